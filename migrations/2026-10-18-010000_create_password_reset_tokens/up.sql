@@ -0,0 +1,9 @@
+-- 重設密碼的 token，只保存雜湊值
+CREATE TABLE IF NOT EXISTS password_reset_tokens (
+    id SERIAL PRIMARY KEY,
+    token_hash BYTEA NOT NULL UNIQUE, -- sha256(token)
+    user_id INTEGER REFERENCES accounts(id) ON DELETE CASCADE NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP, -- NULL 代表尚未使用
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,8 @@
+-- 每個裝置一個 session，記錄建立、最後使用與到期時間
+ALTER TABLE sessions ADD COLUMN id SERIAL UNIQUE;
+ALTER TABLE sessions ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE sessions ADD COLUMN expires_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP; -- 舊的 session 直接視為過期
+ALTER TABLE sessions ADD COLUMN user_agent VARCHAR(255);
+ALTER TABLE sessions ADD COLUMN ip_address VARCHAR(64);
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions (user_id);
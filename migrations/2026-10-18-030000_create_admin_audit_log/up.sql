@@ -0,0 +1,8 @@
+-- 管理員存取非自己擁有的投資組合時留下紀錄
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+    id SERIAL PRIMARY KEY,
+    admin_account_id INTEGER REFERENCES accounts(id) NOT NULL,
+    portfolio_id INTEGER NOT NULL, -- 不加外鍵，被記錄的動作可能會刪除該投資組合
+    action VARCHAR(50) NOT NULL, -- route 名稱
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
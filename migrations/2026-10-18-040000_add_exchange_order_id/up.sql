@@ -0,0 +1,3 @@
+-- 訂單 ID 由資料庫序號產生，交易所的訂單 ID 另外保存
+ALTER TABLE orders ADD COLUMN exchange_order_id VARCHAR(64);
+SELECT setval('orders_id_seq', GREATEST((SELECT COALESCE(MAX(id), 0) FROM orders), 1));
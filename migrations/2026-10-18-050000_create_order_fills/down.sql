@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS order_fills;
+
+ALTER TABLE orders ADD COLUMN state INTEGER NOT NULL DEFAULT 0;
+UPDATE orders SET state = CASE status WHEN 'filled' THEN 1 WHEN 'rejected' THEN 2 WHEN 'cancelled' THEN 2 WHEN 'expired' THEN 2 ELSE 0 END;
+ALTER TABLE orders ALTER COLUMN state DROP DEFAULT;
+ALTER TABLE orders DROP COLUMN status;
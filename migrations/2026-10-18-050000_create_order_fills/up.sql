@@ -0,0 +1,16 @@
+-- 訂單狀態改用名稱保存: new, accepted, partially_filled, filled, cancelled, rejected, expired
+ALTER TABLE orders ADD COLUMN status VARCHAR(20) NOT NULL DEFAULT 'new';
+UPDATE orders SET status = CASE state WHEN 1 THEN 'filled' WHEN 2 THEN 'rejected' ELSE 'new' END;
+ALTER TABLE orders DROP COLUMN state;
+
+-- 訂單成交紀錄，已成交數量與均價由此計算
+CREATE TABLE IF NOT EXISTS order_fills (
+    id SERIAL PRIMARY KEY,
+    order_id INTEGER REFERENCES orders(id) ON DELETE CASCADE NOT NULL,
+    exchange_fill_id VARCHAR(64) NOT NULL, -- 交易所的成交 ID
+    price BIGINT NOT NULL, -- 成交價
+    qty BIGINT NOT NULL, -- 成交數量
+    fee BIGINT NOT NULL, -- 手續費，以報價幣計
+    time_stamp TIMESTAMP NOT NULL, -- 成交時間
+    UNIQUE (order_id, exchange_fill_id)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE orders DROP COLUMN IF EXISTS reserved;
+ALTER TABLE portfolio_balance DROP COLUMN IF EXISTS reserved;
@@ -0,0 +1,4 @@
+-- 掛單時保留的餘額，可用餘額 = quantity - reserved
+ALTER TABLE portfolio_balance ADD COLUMN reserved BIGINT NOT NULL DEFAULT 0 CHECK (reserved >= 0);
+-- 訂單尚未成交部分保留的金額，買單為報價幣，賣單為基礎幣
+ALTER TABLE orders ADD COLUMN reserved BIGINT NOT NULL DEFAULT 0 CHECK (reserved >= 0);
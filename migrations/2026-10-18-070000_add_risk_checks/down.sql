@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS risk_decisions;
+ALTER TABLE risk_management DROP COLUMN IF EXISTS max_notional;
+ALTER TABLE risk_management DROP COLUMN IF EXISTS max_position;
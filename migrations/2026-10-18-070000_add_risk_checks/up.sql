@@ -0,0 +1,17 @@
+-- 風控規則的額外限制，NULL 表示不限制
+ALTER TABLE risk_management ADD COLUMN max_position BIGINT; -- 最大持倉數量 (基礎幣)
+ALTER TABLE risk_management ADD COLUMN max_notional BIGINT; -- 單筆訂單最大金額 (報價幣)
+
+-- 下單前風控檢查的紀錄
+CREATE TABLE IF NOT EXISTS risk_decisions (
+    id SERIAL PRIMARY KEY,
+    portfolio_id INTEGER NOT NULL, -- 不加外鍵，投資組合刪除後仍保留紀錄
+    account_id INTEGER REFERENCES accounts(id) NOT NULL, -- 下單的使用者
+    trading_pair_id INTEGER NOT NULL,
+    buyin BOOLEAN NOT NULL,
+    price BIGINT NOT NULL,
+    qty BIGINT NOT NULL,
+    approved BOOLEAN NOT NULL,
+    violations JSONB NOT NULL DEFAULT '[]', -- 違反的規則
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
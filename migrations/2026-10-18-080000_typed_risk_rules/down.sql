@@ -0,0 +1,9 @@
+-- 規則無法還原成舊的單一欄位格式，只保留資料表結構
+DELETE FROM risk_management;
+ALTER TABLE risk_management
+    ADD COLUMN risk_type VARCHAR(30) NOT NULL DEFAULT 'BUY',
+    ADD COLUMN pnl BIGINT NOT NULL,
+    ADD COLUMN position INTEGER REFERENCES trading_pairs(id) NOT NULL,
+    ADD COLUMN max_position BIGINT,
+    ADD COLUMN max_notional BIGINT;
+ALTER TABLE risk_management DROP COLUMN created_at, DROP COLUMN params, DROP COLUMN rule_type;
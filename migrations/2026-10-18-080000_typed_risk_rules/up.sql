@@ -0,0 +1,43 @@
+-- 風控規則改為類型 + JSON 參數，一個投資組合可以有多條規則
+ALTER TABLE risk_management ADD COLUMN rule_type VARCHAR(30);
+ALTER TABLE risk_management ADD COLUMN params JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE risk_management ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+-- 舊的欄位拆成對應的規則
+INSERT INTO risk_management (risk_type, valid, pnl, position, portfolio_id, rule_type, params)
+SELECT r.risk_type, r.valid, r.pnl, r.position, r.portfolio_id, 'stop_loss',
+       jsonb_build_object('pair', b.code || '/' || q.code, 'max_loss', ABS(r.pnl))
+FROM risk_management r
+JOIN trading_pairs t ON t.id = r.position
+JOIN currencies b ON b.id = t.base_currency_id
+JOIN currencies q ON q.id = t.quote_currency_id
+WHERE r.rule_type IS NULL AND r.pnl <> 0;
+
+INSERT INTO risk_management (risk_type, valid, pnl, position, portfolio_id, rule_type, params)
+SELECT r.risk_type, r.valid, r.pnl, r.position, r.portfolio_id, 'max_position',
+       jsonb_build_object('pair', b.code || '/' || q.code, 'max_qty', r.max_position)
+FROM risk_management r
+JOIN trading_pairs t ON t.id = r.position
+JOIN currencies b ON b.id = t.base_currency_id
+JOIN currencies q ON q.id = t.quote_currency_id
+WHERE r.rule_type IS NULL AND r.max_position > 0;
+
+INSERT INTO risk_management (risk_type, valid, pnl, position, portfolio_id, rule_type, params)
+SELECT r.risk_type, r.valid, r.pnl, r.position, r.portfolio_id, 'max_order_size',
+       jsonb_build_object('max_notional', r.max_notional)
+FROM risk_management r
+WHERE r.rule_type IS NULL AND r.max_notional > 0;
+
+UPDATE risk_management
+SET rule_type = 'allowed_sides', params = jsonb_build_object('sides', jsonb_build_array(LOWER(risk_type)))
+WHERE rule_type IS NULL AND UPPER(risk_type) IN ('BUY', 'SELL');
+
+DELETE FROM risk_management WHERE rule_type IS NULL;
+
+ALTER TABLE risk_management ALTER COLUMN rule_type SET NOT NULL;
+ALTER TABLE risk_management
+    DROP COLUMN risk_type,
+    DROP COLUMN pnl,
+    DROP COLUMN position,
+    DROP COLUMN max_position,
+    DROP COLUMN max_notional;
@@ -0,0 +1,19 @@
+-- 交易暫停 (kill switch)，期間不接受新訂單，只有管理員可以解除
+CREATE TABLE IF NOT EXISTS trading_halts (
+    id SERIAL PRIMARY KEY,
+    portfolio_id INTEGER REFERENCES portfolios(id) ON DELETE CASCADE, -- 暫停單一投資組合
+    account_id INTEGER REFERENCES accounts(id) ON DELETE CASCADE, -- 暫停整個帳戶
+    reason TEXT NOT NULL,
+    triggered_by INTEGER REFERENCES accounts(id), -- 手動暫停的使用者，NULL 表示由風控規則自動觸發
+    risk_rule_id INTEGER, -- 自動觸發的風控規則，不加外鍵，規則刪除後仍保留紀錄
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    lifted_by INTEGER REFERENCES accounts(id), -- 解除暫停的管理員
+    lifted_at TIMESTAMP, -- NULL 表示仍在暫停中
+    CHECK ((portfolio_id IS NULL) <> (account_id IS NULL))
+);
+
+-- 同一個投資組合或帳戶同時只會有一筆進行中的暫停
+CREATE UNIQUE INDEX trading_halts_active_portfolio ON trading_halts (portfolio_id)
+    WHERE lifted_at IS NULL AND portfolio_id IS NOT NULL;
+CREATE UNIQUE INDEX trading_halts_active_account ON trading_halts (account_id)
+    WHERE lifted_at IS NULL AND account_id IS NOT NULL;
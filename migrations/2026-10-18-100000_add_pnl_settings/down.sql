@@ -0,0 +1,2 @@
+ALTER TABLE quotations DROP COLUMN price;
+ALTER TABLE portfolios DROP COLUMN cost_basis;
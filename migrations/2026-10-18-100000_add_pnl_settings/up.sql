@@ -0,0 +1,6 @@
+-- 計算已實現損益時先平倉哪一批 (tax lot)：fifo, lifo, average
+ALTER TABLE portfolios ADD COLUMN cost_basis VARCHAR(10) NOT NULL DEFAULT 'fifo'
+    CHECK (cost_basis IN ('fifo', 'lifo', 'average'));
+
+-- 最新報價，time_stamp 為報價時間，NULL 表示尚未報價
+ALTER TABLE quotations ADD COLUMN price BIGINT;
@@ -0,0 +1,15 @@
+-- 行情 K 線 (OHLCV)，由成交紀錄彙總而成
+CREATE TABLE IF NOT EXISTS market_candles (
+    id SERIAL PRIMARY KEY,
+    trading_pair_id INTEGER REFERENCES trading_pairs(id) ON DELETE CASCADE NOT NULL,
+    resolution VARCHAR(3) NOT NULL, -- 1m, 5m, 1h, 1d
+    open_time TIMESTAMP NOT NULL, -- K 線開始時間 (UTC)
+    open BIGINT NOT NULL,
+    high BIGINT NOT NULL,
+    low BIGINT NOT NULL,
+    close BIGINT NOT NULL,
+    volume BIGINT NOT NULL, -- 成交量 (基礎幣)
+    trades BIGINT NOT NULL, -- 成交筆數
+    last_trade_at TIMESTAMP NOT NULL, -- 最後一筆成交時間，也是下次擷取的起點
+    UNIQUE (trading_pair_id, resolution, open_time)
+);
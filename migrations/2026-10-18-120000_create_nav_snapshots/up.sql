@@ -0,0 +1,12 @@
+-- 投資組合淨值快照，由排程定期寫入，用於計算績效
+CREATE TABLE IF NOT EXISTS nav_snapshots (
+    id SERIAL PRIMARY KEY,
+    portfolio_id INTEGER REFERENCES portfolios(id) ON DELETE CASCADE NOT NULL,
+    currency_id INTEGER REFERENCES currencies(id) NOT NULL, -- 計價幣別
+    nav BIGINT NOT NULL, -- 淨值，無報價的餘額不計入
+    net_flow BIGINT NOT NULL, -- 與上一筆快照之間的入金減出金 (以計價幣別計)
+    balances JSONB NOT NULL, -- 各幣別餘額 {"currency_id": quantity}
+    taken_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS nav_snapshots_portfolio_idx ON nav_snapshots (portfolio_id, taken_at);
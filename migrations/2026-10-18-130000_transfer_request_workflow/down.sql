@@ -0,0 +1,8 @@
+DROP INDEX IF EXISTS intra_account_transfer_requests_status_idx;
+ALTER TABLE intra_account_transfer_requests
+    DROP COLUMN note,
+    DROP COLUMN decided_at,
+    DROP COLUMN expires_at,
+    DROP COLUMN status;
+UPDATE intra_account_transfer_requests SET admin_account_id = trader_account_id WHERE admin_account_id IS NULL;
+ALTER TABLE intra_account_transfer_requests ALTER COLUMN admin_account_id SET NOT NULL;
@@ -0,0 +1,18 @@
+-- 帳戶內轉帳請求的審核流程：交易員送出，管理員核准或駁回，逾期自動失效
+ALTER TABLE intra_account_transfer_requests
+    ALTER COLUMN admin_account_id DROP DEFAULT,
+    ALTER COLUMN admin_account_id DROP NOT NULL, -- 審核前沒有管理員
+    ADD COLUMN status VARCHAR(10) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'rejected', 'expired')),
+    ADD COLUMN expires_at TIMESTAMP,
+    ADD COLUMN decided_at TIMESTAMP, -- 核准、駁回或失效的時間
+    ADD COLUMN note TEXT; -- 駁回原因
+
+UPDATE intra_account_transfer_requests SET status = 'approved' WHERE is_approved;
+UPDATE intra_account_transfer_requests
+SET expires_at = COALESCE(created_at, CURRENT_TIMESTAMP) + INTERVAL '1 day';
+ALTER TABLE intra_account_transfer_requests ALTER COLUMN expires_at SET NOT NULL;
+
+-- 待審核的請求依到期時間查詢
+CREATE INDEX IF NOT EXISTS intra_account_transfer_requests_status_idx
+    ON intra_account_transfer_requests (status, expires_at);
@@ -0,0 +1,41 @@
+-- 複式記帳：每筆餘額異動都是一張傳票，同一傳票內各幣別的分錄加總為零
+CREATE TABLE IF NOT EXISTS ledger_journals (
+    id SERIAL PRIMARY KEY,
+    kind VARCHAR(10) NOT NULL
+        CHECK (kind IN ('opening', 'deposit', 'withdrawal', 'trade', 'fee', 'transfer', 'main')),
+    order_id INTEGER REFERENCES orders(id) ON DELETE SET NULL, -- 來源訂單
+    fill_id INTEGER REFERENCES order_fills(id) ON DELETE SET NULL, -- 來源成交
+    transfer_id INTEGER REFERENCES intra_account_transfer_requests(id) ON DELETE SET NULL, -- 來源轉帳請求
+    created_by INTEGER REFERENCES accounts(id), -- 手動操作的使用者，自動產生為 NULL
+    memo TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- 分錄，正數為轉入。portfolio_id 不設外鍵，投資組合刪除後帳仍保留
+CREATE TABLE IF NOT EXISTS ledger_entries (
+    id SERIAL PRIMARY KEY,
+    journal_id INTEGER REFERENCES ledger_journals(id) ON DELETE CASCADE NOT NULL,
+    account VARCHAR(10) NOT NULL CHECK (account IN ('portfolio', 'external', 'exchange')),
+    portfolio_id INTEGER,
+    currency_id INTEGER REFERENCES currencies(id) NOT NULL,
+    amount BIGINT NOT NULL,
+    CHECK ((account = 'portfolio') = (portfolio_id IS NOT NULL))
+);
+
+CREATE INDEX IF NOT EXISTS ledger_entries_portfolio_idx ON ledger_entries (portfolio_id, currency_id);
+CREATE INDEX IF NOT EXISTS ledger_entries_journal_idx ON ledger_entries (journal_id);
+
+-- 既有餘額以一張期初傳票入帳，對方為外部帳戶
+WITH journal AS (
+    INSERT INTO ledger_journals (kind, memo) VALUES ('opening', 'Balances before the ledger')
+    RETURNING id
+)
+INSERT INTO ledger_entries (journal_id, account, portfolio_id, currency_id, amount)
+SELECT journal.id, 'portfolio', b.portfolio_id, b.currency_id, b.quantity
+FROM journal, portfolio_balance b
+WHERE b.quantity <> 0
+UNION ALL
+SELECT journal.id, 'external', NULL, b.currency_id, -SUM(b.quantity)
+FROM journal, portfolio_balance b
+WHERE b.quantity <> 0
+GROUP BY journal.id, b.currency_id;
@@ -0,0 +1,65 @@
+-- 每種貨幣的小數位數，所有金額都以 10^-scale 為單位存成整數
+ALTER TABLE currencies ADD COLUMN scale INTEGER NOT NULL DEFAULT 8 CHECK (scale BETWEEN 0 AND 18);
+UPDATE currencies SET scale = 6 WHERE code IN ('USDT', 'USDC', 'USD');
+
+-- 既有資料是以整數單位存的，換算成最小單位
+-- 價格以報價幣別計 (每一整個基礎幣)，數量以基礎幣別計
+CREATE TEMPORARY TABLE pair_units AS
+SELECT trading_pairs.id AS trading_pair_id,
+       power(10::NUMERIC, base.scale)::BIGINT AS base_unit,
+       power(10::NUMERIC, quote.scale)::BIGINT AS quote_unit
+FROM trading_pairs
+JOIN currencies base ON base.id = trading_pairs.base_currency_id
+JOIN currencies quote ON quote.id = trading_pairs.quote_currency_id;
+
+CREATE TEMPORARY TABLE currency_units AS
+SELECT id AS currency_id, power(10::NUMERIC, scale)::BIGINT AS unit FROM currencies;
+
+UPDATE portfolio_balance SET quantity = quantity * u.unit, reserved = reserved * u.unit
+FROM currency_units u WHERE u.currency_id = portfolio_balance.currency_id;
+
+UPDATE ledger_entries SET amount = amount * u.unit
+FROM currency_units u WHERE u.currency_id = ledger_entries.currency_id;
+
+-- 買單保留報價幣別，賣單保留基礎幣別
+UPDATE orders SET price = price * p.quote_unit,
+                  qty = qty * p.base_unit,
+                  reserved = reserved * CASE WHEN orders.buyin THEN p.quote_unit ELSE p.base_unit END
+FROM pair_units p WHERE p.trading_pair_id = orders.trading_pair_id;
+
+UPDATE order_fills SET price = order_fills.price * p.quote_unit,
+                       qty = order_fills.qty * p.base_unit,
+                       fee = order_fills.fee * p.quote_unit
+FROM orders, pair_units p
+WHERE orders.id = order_fills.order_id AND p.trading_pair_id = orders.trading_pair_id;
+
+UPDATE intra_account_transfer_requests r SET price = r.price * p.quote_unit,
+                                             quantity = r.quantity * p.base_unit,
+                                             fee = r.fee * p.quote_unit
+FROM positions, pair_units p
+WHERE positions.id = r.position_id AND p.trading_pair_id = positions.trading_pair_id;
+
+UPDATE quotations SET price = price * u.unit
+FROM currency_units u WHERE u.currency_id = quotations.quote_currency_id;
+
+UPDATE risk_decisions SET price = price * p.quote_unit, qty = qty * p.base_unit
+FROM pair_units p WHERE p.trading_pair_id = risk_decisions.trading_pair_id;
+
+UPDATE market_candles SET open = open * p.quote_unit,
+                          high = high * p.quote_unit,
+                          low = low * p.quote_unit,
+                          close = close * p.quote_unit,
+                          volume = volume * p.base_unit
+FROM pair_units p WHERE p.trading_pair_id = market_candles.trading_pair_id;
+
+UPDATE nav_snapshots s SET nav = s.nav * u.unit,
+                           net_flow = s.net_flow * u.unit,
+                           balances = (
+                               SELECT COALESCE(jsonb_object_agg(b.key, (b.value::TEXT::BIGINT) * bu.unit), '{}'::JSONB)
+                               FROM jsonb_each(s.balances) b
+                               JOIN currency_units bu ON bu.currency_id = b.key::INTEGER
+                           )
+FROM currency_units u WHERE u.currency_id = s.currency_id;
+
+DROP TABLE pair_units;
+DROP TABLE currency_units;
@@ -0,0 +1,10 @@
+ALTER TABLE trading_pairs
+    DROP COLUMN tick_size,
+    DROP COLUMN lot_size,
+    DROP COLUMN min_notional,
+    DROP COLUMN price_precision,
+    DROP COLUMN qty_precision,
+    DROP COLUMN enabled;
+
+ALTER TABLE currencies DROP CONSTRAINT currencies_code_key;
+ALTER TABLE currencies DROP COLUMN enabled;
@@ -0,0 +1,19 @@
+-- 停用的貨幣與交易對保留在資料表裡，舊的委託與帳目仍然查得到
+ALTER TABLE currencies ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE currencies ADD CONSTRAINT currencies_code_key UNIQUE (code);
+
+-- 下單的最小跳動單位，價格與數量都以最小單位存
+-- tick_size 以報價幣別計，lot_size 以基礎幣別計，min_notional 以報價幣別計
+-- precision 是下單時允許的小數位數，不超過貨幣本身的 scale
+ALTER TABLE trading_pairs
+    ADD COLUMN tick_size BIGINT NOT NULL DEFAULT 1 CHECK (tick_size > 0),
+    ADD COLUMN lot_size BIGINT NOT NULL DEFAULT 1 CHECK (lot_size > 0),
+    ADD COLUMN min_notional BIGINT NOT NULL DEFAULT 0 CHECK (min_notional >= 0),
+    ADD COLUMN price_precision INTEGER NOT NULL DEFAULT 18 CHECK (price_precision BETWEEN 0 AND 18),
+    ADD COLUMN qty_precision INTEGER NOT NULL DEFAULT 18 CHECK (qty_precision BETWEEN 0 AND 18),
+    ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- 既有交易對的精度跟著貨幣的 scale
+UPDATE trading_pairs SET price_precision = quote.scale, qty_precision = base.scale
+FROM currencies base, currencies quote
+WHERE base.id = trading_pairs.base_currency_id AND quote.id = trading_pairs.quote_currency_id;
@@ -0,0 +1,23 @@
+DROP INDEX orders_expiry_idx;
+DROP INDEX orders_held_idx;
+
+-- 還在等待觸發的委託從未送出，直接取消並退回保留的金額
+UPDATE portfolio_balance b SET reserved = b.reserved - held.reserved
+FROM (
+    SELECT o.portfolio_id,
+           CASE WHEN o.buyin THEN p.quote_currency_id ELSE p.base_currency_id END AS currency_id,
+           SUM(o.reserved) AS reserved
+    FROM orders o
+    JOIN trading_pairs p ON p.id = o.trading_pair_id
+    WHERE o.status = 'held'
+    GROUP BY 1, 2
+) held
+WHERE b.portfolio_id = held.portfolio_id AND b.currency_id = held.currency_id;
+UPDATE orders SET status = 'cancelled', reserved = 0 WHERE status = 'held';
+
+ALTER TABLE orders
+    DROP COLUMN order_type,
+    DROP COLUMN time_in_force,
+    DROP COLUMN stop_price,
+    DROP COLUMN expires_at,
+    DROP COLUMN triggered_at;
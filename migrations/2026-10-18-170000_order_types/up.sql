@@ -0,0 +1,12 @@
+-- 委託的種類與有效期間，舊的委託都是限價單
+-- 停損與停利單在觸發前只存在這裡，價格以報價幣別的最小單位計
+ALTER TABLE orders
+    ADD COLUMN order_type VARCHAR(20) NOT NULL DEFAULT 'limit',
+    ADD COLUMN time_in_force VARCHAR(3) NOT NULL DEFAULT 'gtc',
+    ADD COLUMN stop_price BIGINT,
+    ADD COLUMN expires_at TIMESTAMP,
+    ADD COLUMN triggered_at TIMESTAMP;
+
+-- 觸發與到期時只找還在等待的委託
+CREATE INDEX orders_held_idx ON orders (trading_pair_id) WHERE status = 'held';
+CREATE INDEX orders_expiry_idx ON orders (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS order_idempotency_keys;
+
+DROP INDEX orders_client_order_id;
+ALTER TABLE orders DROP COLUMN client_order_id;
@@ -0,0 +1,17 @@
+-- 使用者自訂的委託編號，同一個投資組合內不可重複
+ALTER TABLE orders ADD COLUMN client_order_id VARCHAR(64);
+CREATE UNIQUE INDEX orders_client_order_id ON orders (portfolio_id, client_order_id)
+    WHERE client_order_id IS NOT NULL;
+
+-- 下單請求的 Idempotency-Key，重送相同的請求時回傳第一次的結果
+CREATE TABLE IF NOT EXISTS order_idempotency_keys (
+    id SERIAL PRIMARY KEY,
+    account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    idempotency_key VARCHAR(255) NOT NULL,
+    request JSONB NOT NULL, -- 第一次的請求內容，同一個 key 只能用在相同的請求
+    order_id INTEGER REFERENCES orders(id) ON DELETE SET NULL,
+    response_status INTEGER, -- NULL 表示第一次的請求還在處理中
+    response JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (account_id, idempotency_key)
+);
@@ -0,0 +1,24 @@
+-- 模擬委託只存在於記憶體中的撮合引擎，移除旗標前先取消並退回保留的金額
+UPDATE portfolio_balance b SET reserved = b.reserved - paper.reserved
+FROM (
+    SELECT o.portfolio_id,
+           CASE WHEN o.buyin THEN p.quote_currency_id ELSE p.base_currency_id END AS currency_id,
+           SUM(o.reserved) AS reserved
+    FROM orders o
+    JOIN trading_pairs p ON p.id = o.trading_pair_id
+    JOIN portfolios f ON f.id = o.portfolio_id
+    WHERE f.paper AND o.status IN ('held', 'new', 'accepted', 'partially_filled')
+    GROUP BY 1, 2
+) paper
+WHERE b.portfolio_id = paper.portfolio_id AND b.currency_id = paper.currency_id;
+UPDATE orders o SET status = 'cancelled', reserved = 0
+FROM portfolios f
+WHERE f.id = o.portfolio_id AND f.paper AND o.status IN ('held', 'new', 'accepted', 'partially_filled');
+
+-- 模擬資金的傳票保留下來，視為入金與出金
+UPDATE ledger_journals SET kind = 'deposit' WHERE kind = 'paper';
+ALTER TABLE ledger_journals DROP CONSTRAINT ledger_journals_kind_check;
+ALTER TABLE ledger_journals ADD CONSTRAINT ledger_journals_kind_check
+    CHECK (kind IN ('opening', 'deposit', 'withdrawal', 'trade', 'fee', 'transfer', 'main'));
+
+ALTER TABLE portfolios DROP COLUMN paper;
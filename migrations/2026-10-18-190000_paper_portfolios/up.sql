@@ -0,0 +1,7 @@
+-- 模擬交易的投資組合，委託只送到模擬撮合引擎，建立後不可更改
+ALTER TABLE portfolios ADD COLUMN paper BOOLEAN NOT NULL DEFAULT false;
+
+-- 模擬資金的存入與取回另立一類傳票，不與真實的出入金混在一起
+ALTER TABLE ledger_journals DROP CONSTRAINT ledger_journals_kind_check;
+ALTER TABLE ledger_journals ADD CONSTRAINT ledger_journals_kind_check
+    CHECK (kind IN ('opening', 'deposit', 'withdrawal', 'trade', 'fee', 'transfer', 'main', 'paper'));
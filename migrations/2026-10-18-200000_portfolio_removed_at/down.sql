@@ -0,0 +1,6 @@
+-- 已刪除的投資組合改名，讓名稱再次全表唯一
+UPDATE portfolios SET name = LEFT(name, 41 - length(id::text)) || '#removed-' || id WHERE removed_at IS NOT NULL;
+DROP INDEX portfolios_name_key;
+ALTER TABLE portfolios ADD CONSTRAINT portfolios_name_key UNIQUE (name);
+
+ALTER TABLE portfolios DROP COLUMN removed_at;
@@ -0,0 +1,6 @@
+-- 刪除投資組合只做標記，委託、成交與淨值快照等歷史仍保留供損益計算
+ALTER TABLE portfolios ADD COLUMN removed_at TIMESTAMP;
+
+-- 名稱只在尚未刪除的投資組合之間唯一，刪除後可再使用
+ALTER TABLE portfolios DROP CONSTRAINT portfolios_name_key;
+CREATE UNIQUE INDEX portfolios_name_key ON portfolios (name) WHERE removed_at IS NULL;
@@ -0,0 +1 @@
+ALTER TABLE orders DROP COLUMN replaced_exchange_order_ids;
@@ -0,0 +1,2 @@
+-- 改單時被交易所換掉的舊委託編號，成交尚未取回前保留，之後同步時再補記
+ALTER TABLE orders ADD COLUMN replaced_exchange_order_ids TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,5 @@
+-- 帳戶層級的紀錄無法放回只記投資組合的表
+DELETE FROM admin_audit_log WHERE portfolio_id IS NULL;
+ALTER TABLE admin_audit_log DROP CONSTRAINT admin_audit_log_target;
+ALTER TABLE admin_audit_log DROP COLUMN account_id;
+ALTER TABLE admin_audit_log ALTER COLUMN portfolio_id SET NOT NULL;
@@ -0,0 +1,4 @@
+-- 管理員查看其他帳戶層級的資料（例如資金紀錄）時，只記錄帳戶，不對應投資組合
+ALTER TABLE admin_audit_log ALTER COLUMN portfolio_id DROP NOT NULL;
+ALTER TABLE admin_audit_log ADD COLUMN account_id INTEGER REFERENCES accounts(id);
+ALTER TABLE admin_audit_log ADD CONSTRAINT admin_audit_log_target CHECK (portfolio_id IS NOT NULL OR account_id IS NOT NULL);
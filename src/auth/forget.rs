@@ -1,55 +1,143 @@
-// use std::env;
+use ::diesel::{ExpressionMethods, OptionalExtension};
+use chrono::{Duration, Utc};
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use rand_core::{OsRng, RngCore};
+use rocket::fairing::{self, AdHoc};
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::Json;
+use rocket::serde::json::{json, Value};
+use rocket::{Build, Rocket, State};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::AsyncConnection;
+use rocket_db_pools::{diesel, Connection};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-// use chrono::{Duration, Utc};
+use crate::auth::mailer::{Mail, Mailer};
+use crate::auth::password::store_password;
+use crate::auth::user_center::get_logged_in_user_id;
+use crate::db_lib::database;
+use crate::db_lib::schema::{accounts, password_reset_tokens, sessions};
 
-// use lettre::transport::smtp::authentication::Credentials;
-// use lettre::{Message, SmtpTransport, Transport};
-// use serde::{Deserialize, Serialize};
-// use urlencoding::encode;
+// The [default.password_reset] table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResetConfig {
+    // the token is appended to this url in the email, it should open static/reset.html
+    pub link_base: String,
+    pub token_minutes: i64,
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    match rocket
+        .figment()
+        .extract_inner::<ResetConfig>("password_reset")
+    {
+        Ok(config) => return Ok(rocket.manage(config)),
+        Err(err) => {
+            println!("Invalid password_reset configuration: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Password reset", init)
+}
+
+// the token sent to the user and the hash of it kept in the database
+fn new_reset_token() -> (String, Vec<u8>) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let token_hash = hash_reset_token(&token);
+    return (token, token_hash);
+}
+
+fn hash_reset_token(token: &str) -> Vec<u8> {
+    return Sha256::digest(token.as_bytes()).to_vec();
+}
 
-// #[derive(Serialize, Deserialize)]
-// pub(crate) struct ForgetPasswordInfo<'r> {
-//     name: &'r str,
-// }
+fn reset_mail(to: String, reset_config: &ResetConfig, reset_token: &str) -> Mail {
+    return Mail {
+        to,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Please click the following link to reset your password:\nLink:{}{}\nThe link will expire in {} minutes.",
+            reset_config.link_base, reset_token, reset_config.token_minutes
+        ),
+    };
+}
 
-/*
+#[derive(Serialize, Deserialize)]
+pub struct ForgetPasswordInfo<'r> {
+    name: &'r str,
+}
+
+// Send a single-use reset link to the email of the account.
+// The response is the same whether or not the account exists, so it cannot be used to probe usernames.
 #[post("/api/auth/forget", data = "<forget_password_info>")]
 pub async fn forget_password(
-    forget_password_info: Form<Strict<ForgetPasswordInfo<'_>>>,
+    forget_password_info: Json<ForgetPasswordInfo<'_>>,
     mut db_conn: Connection<database::PgDb>,
     cookies: &CookieJar<'_>,
+    mailer: &State<Box<dyn Mailer>>,
+    reset_config: &State<ResetConfig>,
 ) -> (Status, Value) {
-    if let Some(_) = get_logged_in_user_id(cookies, &mut db_conn).await {
-        return Err((Status::BadRequest, "Already Logged in."));
+    if get_logged_in_user_id(cookies, &mut db_conn).await.is_some() {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Already logged in."}),
+        );
     }
 
-    let username = forget_password_info.name.to_string();
-
     // Query for user email
-    let fetch_user_email = accounts::table
-        .select(accounts::email)
-        .filter(accounts::username.eq(username))
-        .first::<String>(&mut db_conn)
+    let fetch_user = accounts::table
+        .select((accounts::id, accounts::email))
+        .filter(accounts::username.eq(forget_password_info.name))
+        .first::<(i32, String)>(&mut db_conn)
         .await;
 
-    let user_email = match fetch_user_email {
-        Ok(email) => email,
-        Err(err) => {
-            return (
-                Status::BadRequest,
-                json!({"status":"error", "message": format!("User email not found: {}", err)}),
-            );
-        }
+    let (user_id, user_email) = match fetch_user {
+        Ok(user) => user,
+        Err(_) => return (Status::Ok, json!({"status":"successful"})),
     };
 
-    let reset_token = OsRng.next_u64();
-    let send_email =
-        send_reset_password_email(forget_password_info.user_name, &user_email, &reset_token).await;
+    // only the latest link is usable
+    let remove_old_tokens = diesel::delete(
+        password_reset_tokens::table
+            .filter(password_reset_tokens::user_id.eq(user_id))
+            .filter(password_reset_tokens::used_at.is_null()),
+    )
+    .execute(&mut db_conn)
+    .await;
 
-    match send_email {
+    let (reset_token, token_hash) = new_reset_token();
+    let expires_at = Utc::now().naive_utc() + Duration::minutes(reset_config.token_minutes);
+    let insert_token = diesel::insert_into(password_reset_tokens::table)
+        .values((
+            password_reset_tokens::token_hash.eq(token_hash),
+            password_reset_tokens::user_id.eq(user_id),
+            password_reset_tokens::expires_at.eq(expires_at),
+        ))
+        .execute(&mut db_conn)
+        .await;
+
+    if remove_old_tokens.is_err() || insert_token.is_err() {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Fail to create the reset token."}),
+        );
+    }
+
+    match mailer
+        .send(reset_mail(user_email, reset_config, &reset_token))
+        .await
+    {
         Ok(_) => {
             return (Status::Ok, json!({"status":"successful"}));
-        }Err(_) =>{
+        }
+        Err(_) => {
             return (
                 Status::ServiceUnavailable,
                 json!({"status":"error", "message":"Fail to send the email."}),
@@ -57,89 +145,155 @@ pub async fn forget_password(
         }
     }
 }
-*/
-
-// pub async fn send_reset_password_email(
-//     user_name: &str,
-//     user_email: &str,
-//     reset_token: &u64,
-// ) -> Result<lettre::transport::smtp::response::Response, lettre::transport::smtp::Error> {
-//     let smtp_key: &str = "pA6ZPCjEVv7U0Grz";
-//     let from_email: &str = "testgdscmail@gmail.com";
-//     let to_email: &str = &user_email;
-//     let host: &str = "smtp-relay.sendinblue.com";
-//     let expiration_time = Utc::now() + Duration::minutes(5);
-//     let reset_link = format!(
-//         "{}/api/auth/forget/{}/{}/{}",
-//         env::var("DOMAIN").unwrap_or_default(),
-//         user_name,
-//         reset_token,
-//         encode(&expiration_time.to_rfc3339())
-//     );
-
-//     let email: Message = Message::builder()
-//         .from(from_email.parse().unwrap())
-//         .to(to_email.parse().unwrap())
-//         .subject("Reset your password")
-//         .body(format!(
-//             "Please click the following link to reset your password:\nLink:{}\nThe link will expired in 5 minutes.",
-//             reset_link))
-//         .unwrap();
-
-//     let creds: Credentials = Credentials::new(from_email.to_string(), smtp_key.to_string());
-
-//     // Open a remote connection to gmail
-//     let mailer: SmtpTransport = SmtpTransport::relay(&host)
-//         .unwrap()
-//         .credentials(creds)
-//         .build();
-
-//     // Send the email
-//     return mailer.send(&email);
-// }
-
-// // TO DO post(forget)
-// /*
-// #[derive(FromForm)]
-// pub struct ResetPasswordInfo<'r> {
-//     user_password: &'r str,
-//     confirm_password: &'r str
-// }
-
-// #[post("/api/auth/forget/<username>/<resettoken>/<expiration_timestamp>", data = "<reset_info>")]
-// pub async fn reset_password(
-//     reset_info: Form<Strict<SignupInfo<'_>>>,
-//     mut db_conn: Connection<database::AccountsDb>
-// ) -> Result<Status, (Status, &'static str)> {
-
-//     // confirm the password
-//     if reset_info.user_password != reset_info.confirm_password {
-//         return Err((Status::BadRequest, "The password doesn't match."));
-//     }
-
-//     // hash the password
-//     let salt = pbkdf2::password_hash::SaltString::generate(&mut rand_core::OsRng);
-//     let password_hash = pbkdf2::Pbkdf2.hash_password(signup_info.user_password.as_bytes(), &salt);
-//     let hashed_password = if let Ok(_password) = password_hash {
-//         _password.to_string()
-//     } else {
-//         return Err((Status::BadRequest, "The password is invalid."))
-//     };
-
-//     // update the user's password in the database
-//     let update_result = diesel::update(users::table.filter(users::username.eq(&reset_info.user_name)))
-//         .set(users::password.eq(&hashed_password))
-//         .execute(&mut accounts_db_coon)
-//         .await;
-
-//     match update_result {
-//         Ok(_) => {
-//             return Ok(Status::Ok);
-//         }
-//         Err(_) => {
-//             return Err((Status::InternalServerError, "Failed to update the password."));
-//         }
-//     }
-
-// }
-// */
+
+// Tell the reset page whether the link can still be used, without consuming it
+#[get("/api/auth/forget/<reset_token>")]
+pub async fn check_reset_token(
+    reset_token: &str,
+    mut db_conn: Connection<database::PgDb>,
+) -> (Status, Value) {
+    let fetch_token = password_reset_tokens::table
+        .select(password_reset_tokens::id)
+        .filter(password_reset_tokens::token_hash.eq(hash_reset_token(reset_token)))
+        .filter(password_reset_tokens::used_at.is_null())
+        .filter(password_reset_tokens::expires_at.gt(Utc::now().naive_utc()))
+        .first::<i32>(&mut db_conn)
+        .await;
+
+    match fetch_token {
+        Ok(_) => return (Status::Ok, json!({"status":"successful"})),
+        Err(_) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message":"The link is invalid or has expired."}),
+            )
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ForgetResetInfo<'r> {
+    password: &'r str,
+}
+
+// Why a reset did not go through, nothing of it is kept
+enum ResetError {
+    InvalidToken,
+    Password(&'static str),
+    Database,
+}
+
+impl From<diesel::result::Error> for ResetError {
+    fn from(_: diesel::result::Error) -> Self {
+        ResetError::Database
+    }
+}
+
+// Consume the token and set the new password. Every session of the user is removed afterwards.
+#[post("/api/auth/forget/<reset_token>", data = "<reset_info>")]
+pub async fn reset_forgotten_password(
+    reset_token: &str,
+    reset_info: Json<ForgetResetInfo<'_>>,
+    mut db_conn: Connection<database::PgDb>,
+) -> (Status, Value) {
+    let token_hash = hash_reset_token(reset_token);
+    let password = reset_info.password;
+    // the token is only used up together with the new password
+    let reset = db_conn
+        .transaction::<(), ResetError, _>(|conn| {
+            async move {
+                // mark the token as used in the same statement that checks it, so it cannot be used twice
+                let now = Utc::now().naive_utc();
+                let user_id = diesel::update(
+                    password_reset_tokens::table
+                        .filter(password_reset_tokens::token_hash.eq(token_hash))
+                        .filter(password_reset_tokens::used_at.is_null())
+                        .filter(password_reset_tokens::expires_at.gt(now)),
+                )
+                .set(password_reset_tokens::used_at.eq(now))
+                .returning(password_reset_tokens::user_id)
+                .get_result::<i32>(conn)
+                .await
+                .optional()?
+                .ok_or(ResetError::InvalidToken)?;
+
+                store_password(user_id, password, conn)
+                    .await
+                    .map_err(ResetError::Password)?;
+
+                diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id)))
+                    .execute(conn)
+                    .await?;
+                return Ok(());
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match reset {
+        Ok(_) => return (Status::Ok, json!({"status":"successful"})),
+        Err(ResetError::InvalidToken) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message":"The link is invalid or has expired."}),
+            );
+        }
+        Err(ResetError::Password(err)) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": err}),
+            );
+        }
+        Err(ResetError::Database) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to reset the password."}),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::mailer::MemoryMailer;
+
+    #[test]
+    fn only_the_hash_of_the_token_is_kept() {
+        let (token, token_hash) = new_reset_token();
+        assert_eq!(token.len(), 64);
+        assert!(token.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(token_hash, hash_reset_token(&token));
+        assert_ne!(token_hash, token.as_bytes());
+
+        let (other_token, other_hash) = new_reset_token();
+        assert_ne!(token, other_token);
+        assert_ne!(token_hash, other_hash);
+    }
+
+    #[rocket::async_test]
+    async fn the_reset_mail_carries_the_link() {
+        let config = ResetConfig {
+            link_base: "https://rudrist.local/reset.html#".to_string(),
+            token_minutes: 30,
+        };
+        let mailer = MemoryMailer::default();
+        let (token, _) = new_reset_token();
+        mailer
+            .send(reset_mail(
+                "user@rudrist.local".to_string(),
+                &config,
+                &token,
+            ))
+            .await
+            .unwrap();
+
+        let sent = mailer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "user@rudrist.local");
+        assert!(sent[0]
+            .body
+            .contains(&format!("https://rudrist.local/reset.html#{}", token)));
+        assert!(sent[0].body.contains("30 minutes"));
+    }
+}
@@ -1,19 +1,16 @@
-use crate::db_lib::schema::accounts;
-use crate::db_lib::session::new_session;
-use crate::db_lib::USER_COOKIE_NAME;
+use crate::auth::password::{store_password, verify_password, PasswordCheck};
+use crate::db_lib::schema::{accounts, sessions};
+use crate::db_lib::session::{get_session_token, new_session, ClientInfo};
 use crate::db_lib::{database, RAND};
+use crate::db_lib::{SESSION_ABSOLUTE_HOURS, USER_COOKIE_NAME};
 use ::diesel::ExpressionMethods;
 use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
-// use pbkdf2::password_hash::PasswordHash;
-// use pbkdf2::password_hash::PasswordHasher;
-// use pbkdf2::password_hash::SaltString;
-// use pbkdf2::{password_hash::PasswordVerifier, Pbkdf2};
 use rocket::http::{Cookie, CookieJar, Status};
 use rocket::serde::json::Json;
 use rocket::serde::json::{json, Value};
 use rocket::State;
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
-use rocket_db_pools::Connection;
+use rocket_db_pools::{diesel, Connection};
 use serde::{Deserialize, Serialize};
 #[derive(Serialize, Deserialize)]
 pub struct LoginInfo<'r> {
@@ -29,6 +26,7 @@ pub async fn login(
     mut db_conn: Connection<database::PgDb>,
     cookies: &CookieJar<'_>,
     random: &State<RAND>,
+    client_info: ClientInfo,
 ) -> (Status, Value) {
     // query the id and (hashed)password in the database according to the username
     let login_result: Result<(i32, String, String), _> = accounts::table
@@ -46,38 +44,49 @@ pub async fn login(
             json!({"status":"error", "message":"Login fails. Probably wrong username or password."}),
         );
     };
-    // let salt: SaltString = SaltString::encode_b64(salt.as_bytes()).unwrap();
-    // let password_hash = pbkdf2::Pbkdf2.hash_password(login_info.password.as_bytes(), &salt).unwrap();
-    let password_hash = login_info.password;
+
     // If (hashed)password doesn't match, return badrequest
-    // if let Err(_err) = Pbkdf2.verify_password(
-    //     password.as_bytes(),
-    //     &password_hash,
-    // )
-    if password_hash != password {
-        return (
-            Status::BadRequest,
-            json!({"status":"error", "message":"Wrong password."}),
-        );
+    match verify_password(login_info.password, &password) {
+        PasswordCheck::Valid => {}
+        PasswordCheck::NeedsUpgrade => {
+            // legacy plaintext row, replace it with a real hash now that the password is known
+            if let Err(err) = store_password(user_id, login_info.password, &mut db_conn).await {
+                println!("Fail to upgrade the password of user {}: {}", user_id, err);
+            }
+        }
+        PasswordCheck::Invalid => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message":"Wrong password."}),
+            );
+        }
+    }
+
+    // Rotate the token: the session this client was using before (if any) is dropped
+    if let Some(old_token) = get_session_token(cookies) {
+        let _ = diesel::delete(
+            sessions::table.filter(sessions::session_token.eq(old_token.into_database_value())),
+        )
+        .execute(&mut db_conn)
+        .await;
     }
 
     // Generate a session key. Save it in both the server(database) and the client(cookie).
-    let token = new_session(random.random.clone(), user_id, &mut db_conn).await;
+    let token = new_session(random.random.clone(), user_id, &client_info, &mut db_conn).await;
     match token {
         Ok(token) => {
             let cookie_value = token.into_cookie_value();
-            cookies.add_private(Cookie::build((USER_COOKIE_NAME, cookie_value.clone()))); // default expire time: one week from now
+            cookies.add_private(
+                Cookie::build((USER_COOKIE_NAME, cookie_value.clone()))
+                    .max_age(rocket::time::Duration::hours(SESSION_ABSOLUTE_HOURS)),
+            );
             let fetch_account_type = accounts::table
                 .select(accounts::account_type)
                 .filter(accounts::id.eq(user_id))
                 .first::<Option<i32>>(&mut db_conn)
                 .await
                 .unwrap();
-            let account_type_id = if let Some(account_type_id) = fetch_account_type {
-                account_type_id
-            } else {
-                1
-            };
+            let account_type_id = fetch_account_type.unwrap_or(1);
             cookies.add(Cookie::build(("account_type", account_type_id.to_string())));
 
             return (
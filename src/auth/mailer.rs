@@ -0,0 +1,192 @@
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use rocket::fairing::{self, AdHoc};
+use rocket::figment::providers::Env;
+use rocket::tokio;
+use rocket::{Build, Rocket};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+// An email waiting to be delivered by a Mailer
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[rocket::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), &'static str>;
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    File,
+    Memory,
+}
+
+// The [default.smtp] table of Rocket.toml, every key can be overridden by a SMTP_<KEY> env variable
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    #[serde(default)]
+    pub host: String,
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_outbox")]
+    pub outbox: PathBuf,
+}
+
+fn default_outbox() -> PathBuf {
+    PathBuf::from("./outbox")
+}
+
+fn build_message(from: &Mailbox, mail: &Mail) -> Result<Message, &'static str> {
+    let to: Mailbox = mail.to.parse().map_err(|_| "Invalid recipient address.")?;
+    return Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(mail.subject.clone())
+        .body(mail.body.clone())
+        .map_err(|_| "Fail to build the email.");
+}
+
+// Deliver the emails through a SMTP relay
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> Result<Self, &'static str> {
+        let from = config.from.parse().map_err(|_| "Invalid sender address.")?;
+        let mut builder = SmtpTransport::relay(&config.host).map_err(|_| "Invalid SMTP host.")?;
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if !config.username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                config.username.clone(),
+                config.password.clone(),
+            ));
+        }
+        return Ok(SmtpMailer {
+            from,
+            transport: builder.build(),
+        });
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), &'static str> {
+        let message = build_message(&self.from, &mail)?;
+        let transport = self.transport.clone();
+        // the SMTP transport is blocking, keep it off the async workers
+        match tokio::task::spawn_blocking(move || transport.send(&message)).await {
+            Ok(Ok(_)) => return Ok(()),
+            _ => return Err("Fail to send the email."),
+        }
+    }
+}
+
+// Write every email as an .eml file into the outbox directory, for local development
+pub struct FileMailer {
+    from: Mailbox,
+    outbox: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(config: &MailConfig) -> Result<Self, &'static str> {
+        let from = config.from.parse().map_err(|_| "Invalid sender address.")?;
+        return Ok(FileMailer {
+            from,
+            outbox: config.outbox.clone(),
+        });
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), &'static str> {
+        let message = build_message(&self.from, &mail)?;
+        let file_name = format!(
+            "{}-{}.eml",
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            urlencoding::encode(&mail.to)
+        );
+        if tokio::fs::create_dir_all(&self.outbox).await.is_err() {
+            return Err("Fail to create the outbox directory.");
+        }
+        match tokio::fs::write(self.outbox.join(file_name), message.formatted()).await {
+            Ok(_) => return Ok(()),
+            Err(_) => return Err("Fail to write the email."),
+        }
+    }
+}
+
+// Keep every email in memory, for tests
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Mail>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Mail> {
+        return self.sent.lock().unwrap().clone();
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, mail: Mail) -> Result<(), &'static str> {
+        self.sent.lock().unwrap().push(mail);
+        return Ok(());
+    }
+}
+
+pub fn from_config(config: &MailConfig) -> Result<Box<dyn Mailer>, &'static str> {
+    match config.transport {
+        MailTransport::Smtp => return Ok(Box::new(SmtpMailer::new(config)?)),
+        MailTransport::File => return Ok(Box::new(FileMailer::new(config)?)),
+        MailTransport::Memory => return Ok(Box::<MemoryMailer>::default()),
+    }
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = rocket
+        .figment()
+        .clone()
+        .focus("smtp")
+        .merge(Env::prefixed("SMTP_"))
+        .extract::<MailConfig>();
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Invalid SMTP configuration: {}", err);
+            return Err(rocket);
+        }
+    };
+
+    match from_config(&config) {
+        Ok(mailer) => return Ok(rocket.manage(mailer)),
+        Err(err) => {
+            println!("Fail to set up the mailer: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+// manage a Box<dyn Mailer> built from the [default.smtp] configuration
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Mailer", init)
+}
@@ -1,5 +1,8 @@
 pub mod forget;
 pub mod login;
+pub mod mailer;
+pub mod password;
+pub mod permission;
 pub mod signup;
 pub mod user_center;
 pub mod validation;
@@ -0,0 +1,114 @@
+use ::diesel::ExpressionMethods;
+use diesel::query_dsl::methods::FilterDsl;
+use pbkdf2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use pbkdf2::Pbkdf2;
+use rocket_db_pools::diesel;
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+use subtle::ConstantTimeEq;
+
+use crate::db_lib::schema::accounts;
+
+// The result of checking a password against the value stored in accounts.password
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    // matches a PBKDF2 PHC string
+    Valid,
+    // matches a legacy plaintext row, the caller should store a real hash now
+    NeedsUpgrade,
+    Invalid,
+}
+
+// hash the password with a freshly generated salt, return (PHC string, salt)
+pub fn hash_password(password: &str) -> Result<(String, String), &'static str> {
+    let salt = SaltString::generate(&mut rand_core::OsRng);
+    let hashed_password = hash_with_salt(password, salt.as_str())?;
+    return Ok((hashed_password, salt.to_string()));
+}
+
+// hash the password with a salt in the format stored in accounts.salt
+pub fn hash_with_salt(password: &str, salt: &str) -> Result<String, &'static str> {
+    let salt = SaltString::from_b64(salt).map_err(|_| "The salt is invalid.")?;
+    match Pbkdf2.hash_password(password.as_bytes(), &salt) {
+        Ok(password_hash) => return Ok(password_hash.to_string()),
+        Err(_) => return Err("The password is invalid."),
+    }
+}
+
+// compare the password with the stored value in constant time
+// PHC strings carry their own salt and parameters, anything else is treated as a legacy plaintext row
+pub fn verify_password(password: &str, stored_password: &str) -> PasswordCheck {
+    if let Ok(password_hash) = PasswordHash::new(stored_password) {
+        if Pbkdf2
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok()
+        {
+            return PasswordCheck::Valid;
+        }
+        return PasswordCheck::Invalid;
+    }
+
+    if bool::from(password.as_bytes().ct_eq(stored_password.as_bytes())) {
+        return PasswordCheck::NeedsUpgrade;
+    }
+    return PasswordCheck::Invalid;
+}
+
+// hash the password and store both the PHC string and its salt for the user
+pub async fn store_password(
+    user_id: i32,
+    password: &str,
+    db_conn: &mut AsyncPgConnection,
+) -> Result<(), &'static str> {
+    let (hashed_password, salt) = hash_password(password)?;
+
+    let update_password = diesel::update(accounts::table.filter(accounts::id.eq(user_id)))
+        .set((
+            accounts::password.eq(hashed_password),
+            accounts::salt.eq(salt),
+        ))
+        .execute(db_conn)
+        .await;
+
+    match update_password {
+        Ok(_) => return Ok(()),
+        Err(_) => return Err("Update password fails."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_hashed_password_verifies() {
+        let (hashed_password, salt) = hash_password("hunter22").unwrap();
+        assert!(hashed_password.starts_with("$pbkdf2"));
+        assert_eq!(hash_with_salt("hunter22", &salt).unwrap(), hashed_password);
+        assert_eq!(
+            verify_password("hunter22", &hashed_password),
+            PasswordCheck::Valid
+        );
+        assert_eq!(
+            verify_password("hunter23", &hashed_password),
+            PasswordCheck::Invalid
+        );
+    }
+
+    #[test]
+    fn a_plaintext_row_needs_an_upgrade() {
+        assert_eq!(
+            verify_password("hunter22", "hunter22"),
+            PasswordCheck::NeedsUpgrade
+        );
+        assert_eq!(
+            verify_password("hunter23", "hunter22"),
+            PasswordCheck::Invalid
+        );
+    }
+
+    #[test]
+    fn a_bad_salt_is_rejected() {
+        assert!(hash_with_salt("hunter22", "not a salt!").is_err());
+    }
+}
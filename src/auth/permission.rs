@@ -0,0 +1,297 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome};
+use rocket::serde::json::{json, Value};
+use rocket::Request;
+use std::ops::Deref;
+
+use crate::auth::validation::UserAuth;
+
+// accounts.account_type, 0: admin, 1: trader, 2: customer
+pub const ADMIN_ACCOUNT_TYPE: i32 = 0;
+pub const TRADER_ACCOUNT_TYPE: i32 = 1;
+pub const CUSTOMER_ACCOUNT_TYPE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Admin,
+    Trader,
+    Customer,
+}
+
+impl Role {
+    // unknown account types get the least privileges
+    pub fn from_account_type(account_type: i32) -> Role {
+        match account_type {
+            ADMIN_ACCOUNT_TYPE => Role::Admin,
+            TRADER_ACCOUNT_TYPE => Role::Trader,
+            _ => Role::Customer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ViewPortfolio,
+    ManagePortfolio,
+    ViewOrder,
+    PlaceOrder,
+    ViewRisk,
+    ManageRisk,
+    LiftHalt,
+    ApproveTransfer,
+    ManageLedger,
+    ManageFunds,
+    ManageInstruments,
+    ManageAccounts,
+}
+
+impl Permission {
+    pub fn allows(self, role: Role) -> bool {
+        match self {
+            Permission::ViewPortfolio | Permission::ViewOrder | Permission::ViewRisk => true,
+            Permission::ManagePortfolio | Permission::PlaceOrder | Permission::ManageRisk => {
+                role != Role::Customer
+            }
+            // a halted portfolio stays halted until an admin looks at it
+            Permission::LiftHalt => role == Role::Admin,
+            // moving funds between portfolios needs a second pair of eyes
+            Permission::ApproveTransfer => role == Role::Admin,
+            Permission::ManageLedger => role == Role::Admin,
+            // money only enters or leaves the system through an admin
+            Permission::ManageFunds => role == Role::Admin,
+            // currencies and pairs are shared by every account
+            Permission::ManageInstruments => role == Role::Admin,
+            // roles are granted here and nowhere else, signup always makes a trader
+            Permission::ManageAccounts => role == Role::Admin,
+        }
+    }
+}
+
+// The permission required by every route guarded with `Authorized`, keyed by the handler name.
+// A route that is not listed here is always denied.
+pub const ROUTE_PERMISSIONS: &[(&str, Permission)] = &[
+    // portfolio
+    ("get_portfolio_names", Permission::ViewPortfolio),
+    ("add_portfolio", Permission::ManagePortfolio),
+    ("remove_portfolio", Permission::ManagePortfolio),
+    ("change_portfolio", Permission::ManagePortfolio),
+    ("get_pnl", Permission::ViewPortfolio),
+    ("set_cost_basis", Permission::ManagePortfolio),
+    ("get_valuation", Permission::ViewPortfolio),
+    ("get_performance", Permission::ViewPortfolio),
+    // order
+    ("get_order", Permission::ViewOrder),
+    ("get_order_detail", Permission::ViewOrder),
+    ("get_order_by_client_id", Permission::ViewOrder),
+    ("place_order", Permission::PlaceOrder),
+    ("cancel_order", Permission::PlaceOrder),
+    ("amend_order", Permission::PlaceOrder),
+    // risk
+    ("get_risk_status", Permission::ViewRisk),
+    ("update_risk", Permission::ManageRisk),
+    ("remove_risk_rule", Permission::ManageRisk),
+    ("get_halt_status", Permission::ViewRisk),
+    ("halt_portfolio", Permission::ManageRisk),
+    ("lift_halt", Permission::LiftHalt),
+    // transfer
+    ("submit_transfer", Permission::ManagePortfolio),
+    ("get_transfers", Permission::ViewPortfolio),
+    ("get_all_transfers", Permission::ApproveTransfer),
+    ("approve_transfer", Permission::ApproveTransfer),
+    ("reject_transfer", Permission::ApproveTransfer),
+    // ledger
+    ("get_ledger", Permission::ViewPortfolio),
+    ("check_ledger", Permission::ManageLedger),
+    ("rebuild_ledger", Permission::ManageLedger),
+    // funding
+    ("deposit", Permission::ManageFunds),
+    ("withdraw", Permission::ManageFunds),
+    ("allocate", Permission::ManagePortfolio),
+    ("deallocate", Permission::ManagePortfolio),
+    ("get_funding_history", Permission::ViewPortfolio),
+    // instrument
+    ("list_currencies", Permission::ManageInstruments),
+    ("create_currency", Permission::ManageInstruments),
+    ("update_currency", Permission::ManageInstruments),
+    ("disable_currency", Permission::ManageInstruments),
+    ("list_pairs", Permission::ManageInstruments),
+    ("create_pair", Permission::ManageInstruments),
+    ("update_pair", Permission::ManageInstruments),
+    ("disable_pair", Permission::ManageInstruments),
+    // account
+    ("set_account_type", Permission::ManageAccounts),
+];
+
+pub fn route_permission(route_name: &str) -> Option<Permission> {
+    return ROUTE_PERMISSIONS
+        .iter()
+        .find(|(name, _)| *name == route_name)
+        .map(|(_, permission)| *permission);
+}
+
+pub fn is_authorized(route_name: &str, role: Role) -> bool {
+    match route_permission(route_name) {
+        Some(permission) => return permission.allows(role),
+        None => return false,
+    }
+}
+
+// Admins have to ask for access to portfolios they don't own with this header
+pub const ADMIN_OVERRIDE_HEADER: &str = "X-Admin-Override";
+
+// A logged in user whose role is allowed to call the matched route.
+// Denied requests end in the 403 catcher below.
+#[derive(Debug, Clone)]
+pub struct Authorized {
+    pub user_auth: UserAuth,
+    // set only for admins that sent `X-Admin-Override: true`
+    pub admin_override: bool,
+}
+
+impl Deref for Authorized {
+    type Target = UserAuth;
+
+    fn deref(&self) -> &UserAuth {
+        return &self.user_auth;
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorized {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let user_auth = match req.guard::<UserAuth>().await {
+            Outcome::Success(user_auth) => user_auth,
+            Outcome::Error(err) => return Outcome::Error(err),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let route_name = req
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .unwrap_or_default();
+        let role = Role::from_account_type(user_auth.account_type);
+        if is_authorized(route_name, role) {
+            let admin_override = role == Role::Admin
+                && req
+                    .headers()
+                    .get_one(ADMIN_OVERRIDE_HEADER)
+                    .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+            return Outcome::Success(Authorized {
+                user_auth,
+                admin_override,
+            });
+        } else {
+            return Outcome::Error((Status::Forbidden, ()));
+        }
+    }
+}
+
+#[catch(403)]
+pub fn forbidden() -> Value {
+    return json!({"status":"error", "message":"Permission denied."});
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLES: [Role; 3] = [Role::Admin, Role::Trader, Role::Customer];
+
+    // route name and whether [admin, trader, customer] may call it
+    const EXPECTED: &[(&str, [bool; 3])] = &[
+        ("get_portfolio_names", [true, true, true]),
+        ("add_portfolio", [true, true, false]),
+        ("remove_portfolio", [true, true, false]),
+        ("change_portfolio", [true, true, false]),
+        ("get_pnl", [true, true, true]),
+        ("set_cost_basis", [true, true, false]),
+        ("get_valuation", [true, true, true]),
+        ("get_performance", [true, true, true]),
+        ("get_order", [true, true, true]),
+        ("get_order_detail", [true, true, true]),
+        ("get_order_by_client_id", [true, true, true]),
+        ("place_order", [true, true, false]),
+        ("cancel_order", [true, true, false]),
+        ("amend_order", [true, true, false]),
+        ("get_risk_status", [true, true, true]),
+        ("update_risk", [true, true, false]),
+        ("remove_risk_rule", [true, true, false]),
+        ("get_halt_status", [true, true, true]),
+        ("halt_portfolio", [true, true, false]),
+        ("lift_halt", [true, false, false]),
+        ("submit_transfer", [true, true, false]),
+        ("get_transfers", [true, true, true]),
+        ("get_all_transfers", [true, false, false]),
+        ("approve_transfer", [true, false, false]),
+        ("reject_transfer", [true, false, false]),
+        ("get_ledger", [true, true, true]),
+        ("check_ledger", [true, false, false]),
+        ("rebuild_ledger", [true, false, false]),
+        ("deposit", [true, false, false]),
+        ("withdraw", [true, false, false]),
+        ("allocate", [true, true, false]),
+        ("deallocate", [true, true, false]),
+        ("get_funding_history", [true, true, true]),
+        ("list_currencies", [true, false, false]),
+        ("create_currency", [true, false, false]),
+        ("update_currency", [true, false, false]),
+        ("disable_currency", [true, false, false]),
+        ("list_pairs", [true, false, false]),
+        ("create_pair", [true, false, false]),
+        ("update_pair", [true, false, false]),
+        ("disable_pair", [true, false, false]),
+        ("set_account_type", [true, false, false]),
+    ];
+
+    fn guarded_routes() -> Vec<rocket::Route> {
+        let mut routes = crate::portfolio::routes();
+        routes.extend(crate::order::routes());
+        routes.extend(crate::risk::routes());
+        routes.extend(crate::transfer::routes());
+        routes.extend(crate::ledger::routes());
+        routes.extend(crate::funding::routes());
+        routes.extend(crate::instrument::routes());
+        routes.extend(routes![crate::auth::user_center::set_account_type]);
+        return routes;
+    }
+
+    #[test]
+    fn every_guarded_route_declares_a_permission() {
+        for route in guarded_routes() {
+            let name = route.name.as_deref().unwrap();
+            assert!(
+                route_permission(name).is_some(),
+                "{} has no permission",
+                name
+            );
+            assert!(
+                EXPECTED.iter().any(|(expected, _)| *expected == name),
+                "{} is missing from the expected table",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn route_role_matrix() {
+        for (name, expected) in EXPECTED {
+            for (role, allowed) in ROLES.iter().zip(expected) {
+                assert_eq!(
+                    is_authorized(name, *role),
+                    *allowed,
+                    "{} as {:?}",
+                    name,
+                    role
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_routes_and_account_types_are_denied() {
+        assert!(!is_authorized("not_a_route", Role::Admin));
+        assert_eq!(Role::from_account_type(7), Role::Customer);
+    }
+}
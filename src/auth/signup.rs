@@ -1,3 +1,5 @@
+use crate::auth::password::hash_password;
+use crate::auth::permission::TRADER_ACCOUNT_TYPE;
 use crate::db_lib::database;
 use crate::db_lib::schema;
 use ::diesel::ExpressionMethods;
@@ -14,7 +16,6 @@ pub struct SignupInfo<'r> {
     name: &'r str,
     password: &'r str,
     email: &'r str,
-    user_type: i32,
 }
 
 // TODO, signup is available only when not logged in
@@ -26,26 +27,25 @@ pub async fn signup(
     mut db_conn: Connection<database::PgDb>,
 ) -> (Status, Value) {
     // hash the password
-    let salt = pbkdf2::password_hash::SaltString::generate(&mut rand_core::OsRng);
-    let hashed_password = signup_info.password;
-    // let password_hash = pbkdf2::Pbkdf2.hash_password(signup_info.password.as_bytes(), &salt);
-    // let hashed_password = if let Ok(_password) = password_hash {
-    //     _password.to_string()
-    // } else {
-    //     return (
-    //         Status::BadRequest,
-    //         json!({"status":"error", "message": "The password is invalid."}),
-    //     );
-    // };
+    let (hashed_password, salt) = match hash_password(signup_info.password) {
+        Ok(hashed) => hashed,
+        Err(err) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": err}),
+            );
+        }
+    };
 
     // inser the signup user data into the database
     let signup_user_id = rocket_db_pools::diesel::insert_into(schema::accounts::table)
         .values((
             schema::accounts::username.eq(signup_info.name.to_string()),
-            schema::accounts::salt.eq(salt.to_string()),
+            schema::accounts::salt.eq(salt),
             schema::accounts::email.eq(signup_info.email.to_string()),
             schema::accounts::password.eq(&hashed_password),
-            schema::accounts::account_type.eq(signup_info.user_type),
+            // everyone signs up as a trader, only an admin can grant another role
+            schema::accounts::account_type.eq(TRADER_ACCOUNT_TYPE),
         ))
         .execute(&mut db_conn)
         .await;
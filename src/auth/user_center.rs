@@ -1,6 +1,6 @@
 use ::diesel::ExpressionMethods;
-use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
-use pbkdf2::password_hash::PasswordHasher;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::query_dsl::methods::{FilterDsl, OrderDsl, SelectDsl};
 use rocket::http::{CookieJar, Status};
 use rocket::response::Redirect;
 use rocket::serde::json::Json;
@@ -9,64 +9,37 @@ use rocket_db_pools::diesel::prelude::RunQueryDsl;
 use rocket_db_pools::{diesel, Connection};
 use serde::{Deserialize, Serialize};
 
+use crate::auth::password::store_password;
+use crate::auth::permission::{
+    Authorized, ADMIN_ACCOUNT_TYPE, CUSTOMER_ACCOUNT_TYPE, TRADER_ACCOUNT_TYPE,
+};
 use crate::auth::validation::UserAuth;
 use crate::db_lib::schema::{accounts, sessions};
-use crate::db_lib::session::SessionToken;
-use crate::db_lib::{database, USER_COOKIE_NAME};
+use crate::db_lib::session::{get_session_token, validate_session};
+use crate::db_lib::{database, SESSION_IDLE_MINUTES, USER_COOKIE_NAME};
 
 // return the user_id according to the session token from the client(cookie)
 pub async fn get_logged_in_user_id(
     cookies: &CookieJar<'_>,
-    mut db_conn: &mut Connection<database::PgDb>,
+    db_conn: &mut Connection<database::PgDb>,
 ) -> Option<i32> {
     // get the session token from the client(cookie)
-    let fetch_cookie = cookies
-        .get_private(USER_COOKIE_NAME)
-        .and_then(|cookie| cookie.value().parse::<String>().ok());
-
-    let session_token = if let Some(cookie) = fetch_cookie {
-        SessionToken::to_token(cookie)
-    } else {
-        return None;
-    };
+    let session_token = get_session_token(cookies)?;
 
     // get the user id corresponding to the session token from the database
-    let fetch_user_id = sessions::table
-        .select(sessions::user_id)
-        .filter(sessions::session_token.eq(session_token.into_database_value()))
-        .first::<i32>(&mut db_conn)
-        .await;
-
-    if let Ok(user_id) = fetch_user_id {
-        return Some(user_id);
-    } else {
-        return None;
-    };
+    return validate_session(session_token, db_conn)
+        .await
+        .map(|(_, user_id)| user_id);
 }
 
 // update the (hashed)password on the database
 pub async fn set_new_password(
     user_id: i32,
     new_password: &str,
-    mut db_conn: &mut Connection<database::PgDb>,
+    db_conn: &mut Connection<database::PgDb>,
 ) -> Result<Redirect, (Status, &'static str)> {
-    // hash the new password
-    let salt = pbkdf2::password_hash::SaltString::generate(&mut rand_core::OsRng);
-    let password_hash = pbkdf2::Pbkdf2.hash_password(new_password.as_bytes(), &salt);
-    let new_hashed_password = if let Ok(_password) = password_hash {
-        _password.to_string()
-    } else {
-        return Err((Status::BadRequest, "The new password is invalid."));
-    };
-
-    // update the database
-    let update_password =
-        rocket_db_pools::diesel::update(accounts::table.filter(accounts::id.eq(user_id)))
-            .set(accounts::password.eq(new_hashed_password))
-            .execute(&mut db_conn)
-            .await;
-
-    match update_password {
+    // hash the new password and update the database
+    match store_password(user_id, new_password, db_conn).await {
         Ok(_) => return Ok(Redirect::to(uri!("/index"))),
         Err(_) => return Err((Status::BadRequest, "Update password fails.")),
     }
@@ -103,20 +76,19 @@ pub async fn reset_password(
 }
 
 // remove the session token from both the server(database) and the client(cookie)
+// only the current device is logged out
 #[get("/logout")]
 pub async fn logout(
     mut db_conn: Connection<database::PgDb>,
     cookies: &CookieJar<'_>,
     _user_auth: UserAuth,
 ) -> (Status, Value) {
-    // Ensure the user is logged in
-    let user_id = _user_auth.user_id;
-
     // remove session token from server(database) and client(cookie)
     cookies.remove_private(USER_COOKIE_NAME);
-    let logout_result = diesel::delete(sessions::table.filter(sessions::user_id.eq(user_id)))
-        .execute(&mut db_conn)
-        .await;
+    let logout_result =
+        diesel::delete(sessions::table.filter(sessions::id.eq(_user_auth.session_id)))
+            .execute(&mut db_conn)
+            .await;
 
     match logout_result {
         Ok(_) => {
@@ -130,3 +102,193 @@ pub async fn logout(
         }
     }
 }
+
+// list the active sessions (devices) of the user
+#[get("/api/auth/sessions")]
+pub async fn get_sessions(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: UserAuth,
+) -> (Status, Value) {
+    let now = Utc::now().naive_utc();
+    let fetch_sessions = sessions::table
+        .filter(sessions::user_id.eq(_user_auth.user_id))
+        .filter(sessions::expires_at.gt(now))
+        .filter(sessions::last_seen_at.gt(now - Duration::minutes(SESSION_IDLE_MINUTES)))
+        .select((
+            sessions::id,
+            sessions::user_agent,
+            sessions::ip_address,
+            sessions::created_at,
+            sessions::last_seen_at,
+            sessions::expires_at,
+        ))
+        .order(sessions::last_seen_at.desc())
+        .load::<(
+            i32,
+            Option<String>,
+            Option<String>,
+            NaiveDateTime,
+            NaiveDateTime,
+            NaiveDateTime,
+        )>(&mut db_conn)
+        .await;
+
+    let session_list = if let Ok(session_list) = fetch_sessions {
+        session_list
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Fail to fetch sessions."}),
+        );
+    };
+
+    let mut session_data: Vec<Value> = vec![];
+    for (id, user_agent, ip_address, created_at, last_seen_at, expires_at) in session_list {
+        session_data.push(json!({
+            "id": id,
+            "user_agent": user_agent,
+            "ip": ip_address,
+            "created_at": created_at.to_string(),
+            "last_seen": last_seen_at.to_string(),
+            "expires_at": expires_at.to_string(),
+            "current": id == _user_auth.session_id,
+        }));
+    }
+    let len = session_data.len();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "data": session_data, "len": len}),
+    );
+}
+
+// revoke one session of the user
+#[delete("/api/auth/sessions/<session_id>")]
+pub async fn revoke_session(
+    session_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    cookies: &CookieJar<'_>,
+    _user_auth: UserAuth,
+) -> (Status, Value) {
+    let revoke_result = diesel::delete(
+        sessions::table
+            .filter(sessions::id.eq(session_id))
+            .filter(sessions::user_id.eq(_user_auth.user_id)),
+    )
+    .execute(&mut db_conn)
+    .await;
+
+    match revoke_result {
+        Ok(0) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message":"Session not found."}),
+            );
+        }
+        Ok(_) => {
+            if session_id == _user_auth.session_id {
+                cookies.remove_private(USER_COOKIE_NAME);
+            }
+            return (Status::Ok, json!({"status":"successful"}));
+        }
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to remove session token in the database."}),
+            );
+        }
+    }
+}
+
+// revoke every session of the user except the current one
+#[delete("/api/auth/sessions")]
+pub async fn revoke_other_sessions(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: UserAuth,
+) -> (Status, Value) {
+    let revoke_result = diesel::delete(
+        sessions::table
+            .filter(sessions::user_id.eq(_user_auth.user_id))
+            .filter(sessions::id.ne(_user_auth.session_id)),
+    )
+    .execute(&mut db_conn)
+    .await;
+
+    match revoke_result {
+        Ok(revoked) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "revoked": revoked}),
+            );
+        }
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to remove session token in the database."}),
+            );
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountTypeData {
+    account_type: i32,
+}
+
+// grant a role to an account, the role is read again on every request
+#[put(
+    "/api/auth/user/<account_id>/account_type",
+    data = "<account_type_data>"
+)]
+pub async fn set_account_type(
+    account_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    account_type_data: Json<AccountTypeData>,
+) -> (Status, Value) {
+    let account_type = account_type_data.account_type;
+    if ![
+        ADMIN_ACCOUNT_TYPE,
+        TRADER_ACCOUNT_TYPE,
+        CUSTOMER_ACCOUNT_TYPE,
+    ]
+    .contains(&account_type)
+    {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Unknown account type."}),
+        );
+    }
+    // an admin cannot lock themselves out
+    if account_id == _user_auth.user_id {
+        return (
+            Status::Conflict,
+            json!({"status":"error", "message":"You cannot change your own account type."}),
+        );
+    }
+
+    let update_account = diesel::update(accounts::table.filter(accounts::id.eq(account_id)))
+        .set(accounts::account_type.eq(account_type))
+        .execute(&mut db_conn)
+        .await;
+
+    match update_account {
+        Ok(0) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message":"Account not found."}),
+            );
+        }
+        Ok(_) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "account_type": account_type}),
+            );
+        }
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to update the account type."}),
+            );
+        }
+    }
+}
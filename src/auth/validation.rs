@@ -3,9 +3,8 @@ use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
 
 use crate::db_lib::database;
-use crate::db_lib::schema::{accounts, sessions};
-use crate::db_lib::session::SessionToken;
-use crate::db_lib::USER_COOKIE_NAME;
+use crate::db_lib::schema::accounts;
+use crate::db_lib::session::{get_session_token, validate_session};
 use rocket::{
     http::Status,
     request::{self, FromRequest, Outcome},
@@ -18,6 +17,8 @@ use serde::Deserialize;
 pub struct UserAuth {
     pub user_id: i32,
     pub account_type: i32,
+    // the sessions row this request was authenticated with
+    pub session_id: i32,
 }
 
 #[rocket::async_trait]
@@ -26,43 +27,27 @@ impl<'r> FromRequest<'r> for UserAuth {
 
     async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
         let mut db_conn = req.guard::<Connection<database::PgDb>>().await.unwrap();
-        let cookies = req.cookies();
         // get the session token from the client(cookie)
-        let fetch_cookie = cookies
-            .get_private(USER_COOKIE_NAME)
-            .and_then(|cookie| cookie.value().parse::<String>().ok());
-
-        let session_token = if let Some(cookie) = fetch_cookie {
-            SessionToken::to_token(cookie)
+        let session_token = if let Some(session_token) = get_session_token(req.cookies()) {
+            session_token
         } else {
             return Outcome::Error((Status::Unauthorized, ()));
         };
 
         // get the user id corresponding to the session token from the database
-        let fetch_user_id = sessions::table
-            .select(sessions::user_id)
-            .filter(sessions::session_token.eq(session_token.into_database_value()))
-            .first::<i32>(&mut db_conn)
-            .await;
-
-        if let Ok(user_id) = fetch_user_id {
+        // expired and idle sessions are rejected here
+        if let Some((session_id, user_id)) = validate_session(session_token, &mut db_conn).await {
             let fetch_account_type = accounts::table
                 .select(accounts::account_type)
                 .filter(accounts::id.eq(user_id))
                 .first::<Option<i32>>(&mut db_conn)
                 .await
                 .unwrap();
-            if let Some(account_type) = fetch_account_type {
-                return Outcome::Success(UserAuth {
-                    user_id: user_id,
-                    account_type: account_type,
-                });
-            } else {
-                return Outcome::Success(UserAuth {
-                    user_id: user_id,
-                    account_type: 1,
-                });
-            };
+            return Outcome::Success(UserAuth {
+                user_id,
+                account_type: fetch_account_type.unwrap_or(1),
+                session_id,
+            });
         } else {
             return Outcome::Error((Status::Unauthorized, ()));
         };
@@ -8,6 +8,10 @@ use std::sync::{Arc, Mutex};
 
 pub const USER_COOKIE_NAME: &str = "user_token";
 // const COOKIE_MAX_AGE: &str = "9999999";
+// a session is removed after this long without a request
+pub const SESSION_IDLE_MINUTES: i64 = 120;
+// and after this long no matter what
+pub const SESSION_ABSOLUTE_HOURS: i64 = 24 * 7;
 
 // this structure is used to help session (the name is not fancy at all)
 pub struct RAND {
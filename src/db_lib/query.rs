@@ -1,6 +1,10 @@
 use crate::db_lib::database;
 use crate::db_lib::schema::{currencies, positions, trading_pairs};
+use crate::instrument::spec::PairSpec;
+use crate::order::exchange::Symbol;
+use crate::types::amount::Currencies;
 use rocket_db_pools::diesel::prelude::*;
+use rocket_db_pools::diesel::AsyncPgConnection;
 use rocket_db_pools::Connection;
 pub async fn get_trading_pair(
     db_conn: &mut Connection<database::PgDb>,
@@ -15,12 +19,8 @@ pub async fn get_trading_pair(
         .first::<(i32, i32)>(db_conn)
         .await;
     if let Ok((base, quote)) = fetch_trading_pair {
-        let base = get_currency(db_conn, base)
-            .await
-            .expect("Fetch base failed");
-        let quote = get_currency(db_conn, quote)
-            .await
-            .expect("Fetch quote failed");
+        let base = get_currency(db_conn, base).await?;
+        let quote = get_currency(db_conn, quote).await?;
         return Ok((base, quote));
     } else {
         return Err("Fail to fetch trading pair");
@@ -45,12 +45,8 @@ pub async fn get_trading_pair_id(
     db_conn: &mut Connection<database::PgDb>,
     trading_pair: (&str, &str),
 ) -> Result<(i32, i32, i32), &'static str> {
-    let base = get_currency_id(db_conn, trading_pair.0)
-        .await
-        .expect("Fetch base failed");
-    let quote = get_currency_id(db_conn, trading_pair.1)
-        .await
-        .expect("Fetch quote failed");
+    let base = get_currency_id(db_conn, trading_pair.0).await?;
+    let quote = get_currency_id(db_conn, trading_pair.1).await?;
 
     let fetch_trading_pair = trading_pairs::table
         .filter(trading_pairs::base_currency_id.eq(base))
@@ -107,3 +103,86 @@ pub async fn get_position(
         return Err("Fail to fetch position");
     }
 }
+
+// The pair's symbol with the decimals of its two currencies
+pub async fn get_pair_symbol(
+    conn: &mut AsyncPgConnection,
+    trading_pair_id: i32,
+) -> Result<Symbol, diesel::result::Error> {
+    let (base_id, quote_id) = trading_pairs::table
+        .filter(trading_pairs::id.eq(trading_pair_id))
+        .select((
+            trading_pairs::base_currency_id,
+            trading_pairs::quote_currency_id,
+        ))
+        .first::<(i32, i32)>(conn)
+        .await?;
+    let currency = |id: i32| {
+        currencies::table
+            .filter(currencies::id.eq(id))
+            .select((currencies::code, currencies::scale))
+    };
+    let (base, base_scale) = currency(base_id).first::<(String, i32)>(conn).await?;
+    let (quote, quote_scale) = currency(quote_id).first::<(String, i32)>(conn).await?;
+    return Ok(Symbol {
+        base,
+        quote,
+        base_scale: base_scale as u32,
+        quote_scale: quote_scale as u32,
+    });
+}
+
+// The trading rules of a pair, it only trades while the pair and both its currencies
+// are enabled
+pub async fn get_pair_spec(
+    conn: &mut AsyncPgConnection,
+    trading_pair_id: i32,
+) -> Result<PairSpec, diesel::result::Error> {
+    let (
+        base_id,
+        quote_id,
+        tick_size,
+        lot_size,
+        min_notional,
+        price_precision,
+        qty_precision,
+        enabled,
+    ) = trading_pairs::table
+        .filter(trading_pairs::id.eq(trading_pair_id))
+        .select((
+            trading_pairs::base_currency_id,
+            trading_pairs::quote_currency_id,
+            trading_pairs::tick_size,
+            trading_pairs::lot_size,
+            trading_pairs::min_notional,
+            trading_pairs::price_precision,
+            trading_pairs::qty_precision,
+            trading_pairs::enabled,
+        ))
+        .first::<(i32, i32, i64, i64, i64, i32, i32, bool)>(conn)
+        .await?;
+    let enabled_currencies = currencies::table
+        .filter(currencies::id.eq_any([base_id, quote_id]))
+        .filter(currencies::enabled.eq(true))
+        .count()
+        .get_result::<i64>(conn)
+        .await?;
+    return Ok(PairSpec {
+        tick_size,
+        lot_size,
+        min_notional,
+        price_precision: price_precision as u32,
+        qty_precision: qty_precision as u32,
+        enabled: enabled && enabled_currencies == 2,
+    });
+}
+
+// The code and decimals of every currency
+pub async fn get_currencies(conn: &mut AsyncPgConnection) -> Result<Currencies, &'static str> {
+    let rows = currencies::table
+        .select((currencies::id, currencies::code, currencies::scale))
+        .load::<(i32, String, i32)>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the currencies")?;
+    return Ok(Currencies::new(rows));
+}
@@ -1,12 +1,16 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
 use diesel::ExpressionMethods;
 use rand_core::RngCore;
-use rocket::http::Status;
+use rocket::http::{CookieJar, Status};
+use rocket::request::{self, FromRequest, Outcome, Request};
 use rocket_db_pools::diesel::RunQueryDsl;
 use rocket_db_pools::Connection;
 use std::sync::{Arc, Mutex};
 
 use crate::db_lib::database;
-use crate::db_lib::schema;
+use crate::db_lib::schema::sessions;
+use crate::db_lib::{SESSION_ABSOLUTE_HOURS, SESSION_IDLE_MINUTES, USER_COOKIE_NAME};
 
 type Random = Arc<Mutex<rand_chacha::ChaCha8Rng>>;
 
@@ -28,30 +32,117 @@ impl SessionToken {
     pub fn into_database_value(self) -> Vec<u8> {
         self.0.to_le_bytes().to_vec()
     }
+}
+
+// the user agent and IP address of the client, recorded on the session at login
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
 
-    pub fn to_token(value: String) -> Self {
-        Self(value.parse::<u128>().unwrap())
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let user_agent = req
+            .headers()
+            .get_one("User-Agent")
+            .map(|user_agent| user_agent.chars().take(255).collect());
+        let ip_address = req.client_ip().map(|ip| ip.to_string());
+        return Outcome::Success(ClientInfo {
+            user_agent,
+            ip_address,
+        });
     }
 }
 
+// get the session token from the client(cookie)
+pub fn get_session_token(cookies: &CookieJar<'_>) -> Option<SessionToken> {
+    return cookies
+        .get_private(USER_COOKIE_NAME)
+        .and_then(|cookie| cookie.value().parse::<u128>().ok())
+        .map(SessionToken);
+}
+
 // generate a session token, insert it into database, and return it if successfully otherwise return Status::BadRequest
 pub async fn new_session(
     random: Random,
     user_id: i32,
+    client_info: &ClientInfo,
     mut db_conn: &mut Connection<database::PgDb>,
 ) -> Result<SessionToken, (Status, &'static str)> {
+    let now = Utc::now().naive_utc();
+
+    // drop the sessions of the user that have already expired
+    let _ = rocket_db_pools::diesel::delete(
+        sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::expires_at.le(now)),
+    )
+    .execute(&mut db_conn)
+    .await;
+
     let session_token = SessionToken::generate_new(random);
-    let insert_session = rocket_db_pools::diesel::insert_into(schema::sessions::table)
+    let insert_session = rocket_db_pools::diesel::insert_into(sessions::table)
         .values((
-            schema::sessions::user_id.eq(user_id),
-            schema::sessions::session_token.eq(session_token.into_database_value()),
+            sessions::user_id.eq(user_id),
+            sessions::session_token.eq(session_token.into_database_value()),
+            sessions::created_at.eq(now),
+            sessions::last_seen_at.eq(now),
+            sessions::expires_at.eq(now + Duration::hours(SESSION_ABSOLUTE_HOURS)),
+            sessions::user_agent.eq(&client_info.user_agent),
+            sessions::ip_address.eq(&client_info.ip_address),
         ))
         .execute(&mut db_conn)
         .await;
 
-    if let Ok(_) = insert_session {
+    if insert_session.is_ok() {
         return Ok(session_token);
     } else {
         return Err((Status::BadRequest, "Fail to generate new session"));
     }
 }
+
+// return (session id, user id) if the session is still alive, otherwise remove it
+// last_seen_at is refreshed at most once a minute to keep writes low
+pub async fn validate_session(
+    session_token: SessionToken,
+    mut db_conn: &mut Connection<database::PgDb>,
+) -> Option<(i32, i32)> {
+    let fetch_session = sessions::table
+        .select((
+            sessions::id,
+            sessions::user_id,
+            sessions::last_seen_at,
+            sessions::expires_at,
+        ))
+        .filter(sessions::session_token.eq(session_token.into_database_value()))
+        .first::<(i32, i32, NaiveDateTime, NaiveDateTime)>(&mut db_conn)
+        .await;
+
+    let (session_id, user_id, last_seen_at, expires_at) = if let Ok(session) = fetch_session {
+        session
+    } else {
+        return None;
+    };
+
+    let now = Utc::now().naive_utc();
+    if now >= expires_at || now - last_seen_at >= Duration::minutes(SESSION_IDLE_MINUTES) {
+        let _ =
+            rocket_db_pools::diesel::delete(sessions::table.filter(sessions::id.eq(session_id)))
+                .execute(&mut db_conn)
+                .await;
+        return None;
+    }
+
+    if now - last_seen_at >= Duration::minutes(1) {
+        let _ =
+            rocket_db_pools::diesel::update(sessions::table.filter(sessions::id.eq(session_id)))
+                .set(sessions::last_seen_at.eq(now))
+                .execute(&mut db_conn)
+                .await;
+    }
+
+    return Some((session_id, user_id));
+}
@@ -0,0 +1,273 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::{get_currencies, get_currency_id};
+use crate::funding::movement::{
+    funding_history, move_funds, parse_amount, Funding, FundingError, Movement,
+};
+use crate::portfolio::ownership::{audit_admin_account_access, owned_portfolio, PortfolioKey};
+
+pub mod movement;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![deposit, withdraw, allocate, deallocate, get_funding_history]
+}
+
+fn funding_error(err: FundingError) -> (Status, Value) {
+    let status = match err {
+        FundingError::InvalidAmount | FundingError::Amount(_) | FundingError::Balance(_) => {
+            Status::BadRequest
+        }
+        FundingError::NoMainPortfolio => Status::NotFound,
+        FundingError::Database => Status::InternalServerError,
+    };
+    return (status, json!({"status":"error", "message": err.message()}));
+}
+
+// the currency id and the amount of a request, in the currency's minor units
+async fn currency_amount(
+    currency: &str,
+    amount: &str,
+    db_conn: &mut Connection<database::PgDb>,
+) -> Result<(i32, i64), (Status, Value)> {
+    let currency_id = match get_currency_id(db_conn, currency).await {
+        Ok(currency_id) => currency_id,
+        Err(_) => {
+            return Err((
+                Status::BadRequest,
+                json!({"status":"error", "message": "Currency not found"}),
+            ));
+        }
+    };
+    let currencies = get_currencies(db_conn).await.map_err(|message| {
+        (
+            Status::InternalServerError,
+            json!({"status":"error", "message": message}),
+        )
+    })?;
+    let amount = parse_amount(amount, currencies.scale(currency_id)).map_err(funding_error)?;
+    return Ok((currency_id, amount));
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountFundingData {
+    account_id: i32,
+    currency: String,
+    amount: String,
+    memo: Option<String>,
+}
+
+async fn fund_account(
+    movement: Movement,
+    funding_data: AccountFundingData,
+    db_conn: &mut Connection<database::PgDb>,
+    admin_id: i32,
+) -> (Status, Value) {
+    let (currency_id, amount) =
+        match currency_amount(&funding_data.currency, &funding_data.amount, db_conn).await {
+            Ok(currency_amount) => currency_amount,
+            Err(err) => return err,
+        };
+    let funding = Funding {
+        trader_account_id: funding_data.account_id,
+        movement,
+        currency_id,
+        amount,
+        created_by: admin_id,
+        memo: funding_data.memo,
+    };
+    match move_funds(db_conn, funding).await {
+        Ok(journal_id) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "journal_id": journal_id}),
+            );
+        }
+        Err(err) => return funding_error(err),
+    }
+}
+
+// Bring funds from outside into the main portfolio of an account
+#[post("/api/funding/deposit", data = "<funding_data>")]
+pub async fn deposit(
+    funding_data: Json<AccountFundingData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    return fund_account(
+        Movement::Deposit,
+        funding_data.into_inner(),
+        &mut db_conn,
+        _user_auth.user_id,
+    )
+    .await;
+}
+
+// Send funds of the main portfolio of an account outside, up to what is available
+#[post("/api/funding/withdraw", data = "<funding_data>")]
+pub async fn withdraw(
+    funding_data: Json<AccountFundingData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    return fund_account(
+        Movement::Withdrawal,
+        funding_data.into_inner(),
+        &mut db_conn,
+        _user_auth.user_id,
+    )
+    .await;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AllocationData {
+    currency: String,
+    amount: String,
+}
+
+async fn allocate_funds(
+    id: i32,
+    allocation_data: AllocationData,
+    action: &str,
+    db_conn: &mut Connection<database::PgDb>,
+    _user_auth: &Authorized,
+) -> (Status, Value) {
+    let portfolio = match owned_portfolio(_user_auth, PortfolioKey::Id(id), action, db_conn).await {
+        Ok(portfolio) => portfolio,
+        Err(err) => return err,
+    };
+    // the main(0) and misc(1) portfolios are not allocated to
+    if portfolio.portfolio_type != 2 {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "Only strategy portfolios take allocations"}),
+        );
+    }
+    let (currency_id, amount) =
+        match currency_amount(&allocation_data.currency, &allocation_data.amount, db_conn).await {
+            Ok(currency_amount) => currency_amount,
+            Err(err) => return err,
+        };
+    // a paper portfolio is funded with simulated money, the main portfolio stays out
+    let movement = match (action == "allocate", portfolio.paper) {
+        (true, false) => Movement::Allocate(portfolio.id),
+        (false, false) => Movement::Deallocate(portfolio.id),
+        (true, true) => Movement::PaperAllocate(portfolio.id),
+        (false, true) => Movement::PaperDeallocate(portfolio.id),
+    };
+    let funding = Funding {
+        trader_account_id: portfolio.trader_account_id,
+        movement,
+        currency_id,
+        amount,
+        created_by: _user_auth.user_id,
+        memo: None,
+    };
+    match move_funds(db_conn, funding).await {
+        Ok(journal_id) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "journal_id": journal_id}),
+            );
+        }
+        Err(err) => return funding_error(err),
+    }
+}
+
+// Move funds from the account's main portfolio to one of its strategy portfolios
+#[post("/api/portfolio/<id>/allocate", data = "<allocation_data>")]
+pub async fn allocate(
+    id: i32,
+    allocation_data: Json<AllocationData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    return allocate_funds(
+        id,
+        allocation_data.into_inner(),
+        "allocate",
+        &mut db_conn,
+        &_user_auth,
+    )
+    .await;
+}
+
+// Give funds of a strategy portfolio back to the account's main portfolio
+#[post("/api/portfolio/<id>/deallocate", data = "<allocation_data>")]
+pub async fn deallocate(
+    id: i32,
+    allocation_data: Json<AllocationData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    return allocate_funds(
+        id,
+        allocation_data.into_inner(),
+        "deallocate",
+        &mut db_conn,
+        &_user_auth,
+    )
+    .await;
+}
+
+// The deposits, withdrawals and allocations of the user's account, newest first.
+// Admins may look at another account with the override header, which is audited.
+#[get("/api/funding?<account>&<st>&<len>")]
+pub async fn get_funding_history(
+    account: Option<i32>,
+    st: Option<i64>,
+    len: Option<i64>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let account_id = match account {
+        Some(account_id) if account_id != _user_auth.user_id => {
+            if !_user_auth.admin_override {
+                return (
+                    Status::Forbidden,
+                    json!({"status":"error", "message":"Permission denied."}),
+                );
+            }
+            if let Err(err) = audit_admin_account_access(
+                _user_auth.user_id,
+                account_id,
+                "get_funding_history",
+                &mut db_conn,
+            )
+            .await
+            {
+                return err;
+            }
+            account_id
+        }
+        _ => _user_auth.user_id,
+    };
+    let history =
+        match funding_history(&mut db_conn, account_id, st.unwrap_or(0), len.unwrap_or(50)).await {
+            Ok(history) => history,
+            Err(err) => return funding_error(err),
+        };
+    let currencies = match get_currencies(&mut db_conn).await {
+        Ok(currencies) => currencies,
+        Err(message) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    let data: Vec<Value> = history
+        .iter()
+        .map(|record| record.to_json(&currencies))
+        .collect();
+    let len = data.len();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "data": data, "len": len}),
+    );
+}
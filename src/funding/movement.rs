@@ -0,0 +1,324 @@
+use ::diesel::ExpressionMethods;
+use chrono::NaiveDateTime;
+use diesel::QueryDsl;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::{AsyncConnection, AsyncPgConnection};
+use std::collections::HashMap;
+
+use crate::db_lib::schema::{ledger_entries, ledger_journals};
+use crate::ledger::journal::{record, EntryKind, Journal, LedgerAccount};
+use crate::order::balance::{main_portfolio, BalanceError};
+use crate::types::amount::{Amount, AmountError, Currencies};
+
+// How funds enter, leave or move inside an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    // from outside into the main portfolio
+    Deposit,
+    // from the main portfolio to outside
+    Withdrawal,
+    // from the main portfolio to a strategy portfolio
+    Allocate(i32),
+    // from a strategy portfolio back to the main portfolio
+    Deallocate(i32),
+    // simulated funds into a paper portfolio, real funds are never involved
+    PaperAllocate(i32),
+    // simulated funds out of a paper portfolio
+    PaperDeallocate(i32),
+}
+
+impl Movement {
+    // where the funds leave and where they arrive
+    pub fn accounts(self, main_id: i32) -> (LedgerAccount, LedgerAccount) {
+        let main = LedgerAccount::Portfolio(main_id);
+        match self {
+            Movement::Deposit => return (LedgerAccount::External, main),
+            Movement::Withdrawal => return (main, LedgerAccount::External),
+            Movement::Allocate(strategy_id) => {
+                return (main, LedgerAccount::Portfolio(strategy_id))
+            }
+            Movement::Deallocate(strategy_id) => {
+                return (LedgerAccount::Portfolio(strategy_id), main)
+            }
+            Movement::PaperAllocate(paper_id) => {
+                return (LedgerAccount::External, LedgerAccount::Portfolio(paper_id))
+            }
+            Movement::PaperDeallocate(paper_id) => {
+                return (LedgerAccount::Portfolio(paper_id), LedgerAccount::External)
+            }
+        }
+    }
+
+    pub fn kind(self) -> EntryKind {
+        match self {
+            Movement::Deposit => return EntryKind::Deposit,
+            Movement::Withdrawal => return EntryKind::Withdrawal,
+            Movement::Allocate(_) | Movement::Deallocate(_) => return EntryKind::Main,
+            Movement::PaperAllocate(_) | Movement::PaperDeallocate(_) => return EntryKind::Paper,
+        }
+    }
+
+    // The journal of the movement. It is checked, no portfolio may go below what it
+    // has available.
+    pub fn journal(self, main_id: i32, currency_id: i32, amount: i64) -> Journal {
+        let (from, to) = self.accounts(main_id);
+        return Journal::new(self.kind())
+            .move_funds(from, to, currency_id, amount)
+            .checked();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FundingError {
+    InvalidAmount,
+    Amount(AmountError),
+    NoMainPortfolio,
+    Balance(BalanceError),
+    Database,
+}
+
+impl FundingError {
+    pub fn message(&self) -> String {
+        match self {
+            FundingError::InvalidAmount => "The amount must be positive".to_string(),
+            FundingError::Amount(err) => err.message(),
+            FundingError::NoMainPortfolio => "The account has no main portfolio".to_string(),
+            FundingError::Balance(BalanceError::Insufficient {
+                required,
+                available,
+                ..
+            }) => format!(
+                "Insufficient available balance: {} is needed but only {} is available",
+                required, available
+            ),
+            FundingError::Balance(err) => err.message(),
+            FundingError::Database => "Fail to move the funds".to_string(),
+        }
+    }
+}
+
+impl From<BalanceError> for FundingError {
+    fn from(err: BalanceError) -> Self {
+        match err {
+            BalanceError::Database => FundingError::Database,
+            err => FundingError::Balance(err),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for FundingError {
+    fn from(_: diesel::result::Error) -> Self {
+        FundingError::Database
+    }
+}
+
+// a decimal at the currency's scale, in its minor units
+pub fn parse_amount(amount: &str, scale: u32) -> Result<i64, FundingError> {
+    match Amount::parse(amount, scale) {
+        Ok(amount) if amount.is_positive() => return Ok(amount.units),
+        Ok(_) => return Err(FundingError::InvalidAmount),
+        Err(err) => return Err(FundingError::Amount(err)),
+    }
+}
+
+// A movement requested through the API, booked by whoever asked for it
+pub struct Funding {
+    pub trader_account_id: i32,
+    pub movement: Movement,
+    pub currency_id: i32,
+    pub amount: i64,
+    pub created_by: i32,
+    pub memo: Option<String>,
+}
+
+// Book the movement against the account's main portfolio, returns the journal id
+pub async fn move_funds(
+    conn: &mut AsyncPgConnection,
+    funding: Funding,
+) -> Result<i32, FundingError> {
+    return conn
+        .transaction::<i32, FundingError, _>(|conn| {
+            async move {
+                let main_id = main_portfolio(conn, funding.trader_account_id)
+                    .await?
+                    .ok_or(FundingError::NoMainPortfolio)?;
+                let mut journal = funding
+                    .movement
+                    .journal(main_id, funding.currency_id, funding.amount)
+                    .by(funding.created_by);
+                if let Some(memo) = &funding.memo {
+                    journal = journal.memo(memo);
+                }
+                return Ok(record(conn, &journal).await?);
+            }
+            .scope_boxed()
+        })
+        .await;
+}
+
+// One funding journal seen from the main portfolio
+#[derive(Debug, Clone)]
+pub struct FundingRecord {
+    pub journal_id: i32,
+    pub kind: String,
+    pub currency_id: i32,
+    // positive when the main portfolio received the funds
+    pub amount: i64,
+    // the strategy portfolio of an allocation, none for deposits and withdrawals
+    pub portfolio_id: Option<i32>,
+    pub created_by: Option<i32>,
+    pub memo: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+impl FundingRecord {
+    pub fn to_json(&self, currencies: &Currencies) -> Value {
+        return json!({
+            "journal_id": self.journal_id,
+            "kind": self.kind,
+            "currency": currencies.code(self.currency_id),
+            "amount": currencies.format(self.currency_id, self.amount as i128),
+            "portfolio_id": self.portfolio_id,
+            "created_by": self.created_by,
+            "memo": self.memo,
+            "time": self.created_at.to_string(),
+        });
+    }
+}
+
+// The deposits, withdrawals and allocations of the account, newest first. Every one of
+// them passes through the main portfolio.
+pub async fn funding_history(
+    conn: &mut AsyncPgConnection,
+    trader_account_id: i32,
+    st: i64,
+    len: i64,
+) -> Result<Vec<FundingRecord>, FundingError> {
+    let main_id = main_portfolio(conn, trader_account_id)
+        .await?
+        .ok_or(FundingError::NoMainPortfolio)?;
+    let kinds = [
+        EntryKind::Deposit.as_str(),
+        EntryKind::Withdrawal.as_str(),
+        EntryKind::Main.as_str(),
+    ];
+    let entries = ledger_entries::table
+        .inner_join(ledger_journals::table)
+        .filter(ledger_entries::portfolio_id.eq(main_id))
+        .filter(ledger_journals::kind.eq_any(kinds))
+        .order(ledger_entries::id.desc())
+        .offset(st)
+        .limit(len)
+        .select((
+            ledger_entries::journal_id,
+            ledger_journals::kind,
+            ledger_entries::currency_id,
+            ledger_entries::amount,
+            ledger_journals::created_by,
+            ledger_journals::memo,
+            ledger_journals::created_at,
+        ))
+        .load::<(
+            i32,
+            String,
+            i32,
+            i64,
+            Option<i32>,
+            Option<String>,
+            NaiveDateTime,
+        )>(conn)
+        .await?;
+
+    // the strategy portfolio on the other side of the allocations
+    let journal_ids: Vec<i32> = entries.iter().map(|entry| entry.0).collect();
+    let others = ledger_entries::table
+        .filter(ledger_entries::journal_id.eq_any(journal_ids))
+        .filter(ledger_entries::portfolio_id.ne(main_id))
+        .select((ledger_entries::journal_id, ledger_entries::portfolio_id))
+        .load::<(i32, Option<i32>)>(conn)
+        .await?;
+    let counterparts: HashMap<i32, i32> = others
+        .into_iter()
+        .filter_map(|(journal_id, portfolio_id)| portfolio_id.map(|id| (journal_id, id)))
+        .collect();
+
+    return Ok(entries
+        .into_iter()
+        .map(
+            |(journal_id, kind, currency_id, amount, created_by, memo, created_at)| FundingRecord {
+                journal_id,
+                kind,
+                currency_id,
+                amount,
+                portfolio_id: counterparts.get(&journal_id).copied(),
+                created_by,
+                memo,
+                created_at,
+            },
+        )
+        .collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::journal::Posting;
+
+    #[test]
+    fn movements_go_through_the_main_portfolio_unless_on_paper() {
+        let main = LedgerAccount::Portfolio(1);
+        let strategy = LedgerAccount::Portfolio(5);
+        for (movement, from, to) in [
+            (Movement::Deposit, LedgerAccount::External, main),
+            (Movement::Withdrawal, main, LedgerAccount::External),
+            (Movement::Allocate(5), main, strategy),
+            (Movement::Deallocate(5), strategy, main),
+            (
+                Movement::PaperAllocate(5),
+                LedgerAccount::External,
+                strategy,
+            ),
+            (
+                Movement::PaperDeallocate(5),
+                strategy,
+                LedgerAccount::External,
+            ),
+        ] {
+            let journal = movement.journal(1, 3, 250);
+            assert!(journal.checked && journal.is_balanced());
+            assert_eq!(journal.kind, movement.kind());
+            assert_eq!(
+                journal.postings,
+                vec![
+                    Posting {
+                        account: from,
+                        currency_id: 3,
+                        amount: -250,
+                    },
+                    Posting {
+                        account: to,
+                        currency_id: 3,
+                        amount: 250,
+                    },
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn amounts_must_be_positive() {
+        assert_eq!(parse_amount("100", 0), Ok(100));
+        assert_eq!(parse_amount("1.5", 6), Ok(1_500_000));
+        for amount in ["0", "-5", "0.000"] {
+            assert_eq!(parse_amount(amount, 2), Err(FundingError::InvalidAmount));
+        }
+        for amount in ["1.5", "", "99999999999999999999"] {
+            assert!(matches!(
+                parse_amount(amount, 0),
+                Err(FundingError::Amount(_))
+            ));
+        }
+    }
+}
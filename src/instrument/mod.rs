@@ -0,0 +1,472 @@
+use ::diesel::ExpressionMethods;
+use diesel::QueryDsl;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::{diesel, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::{get_currency_id, get_pair_spec, get_pair_symbol, get_trading_pair_id};
+use crate::db_lib::schema::{currencies, trading_pairs};
+use crate::instrument::spec::{pair_specs, PairSpec, SpecError};
+use crate::order::exchange::Symbol;
+use crate::types::amount::Amount;
+
+pub mod spec;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        list_currencies,
+        create_currency,
+        update_currency,
+        disable_currency,
+        list_pairs,
+        create_pair,
+        update_pair,
+        disable_pair
+    ]
+}
+
+// currencies.scale is capped the same way in the database
+const MAX_SCALE: u32 = 18;
+
+fn database_error(message: &str) -> (Status, Value) {
+    return (
+        Status::InternalServerError,
+        json!({"status":"error", "message": message}),
+    );
+}
+
+fn spec_error(err: SpecError) -> (Status, Value) {
+    return (
+        Status::BadRequest,
+        json!({"status":"error", "message": err.message()}),
+    );
+}
+
+fn is_unique_violation(err: &diesel::result::Error) -> bool {
+    return matches!(
+        err,
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)
+    );
+}
+
+// codes are upper case letters and digits, like the seeded ones
+fn valid_code(code: &str) -> bool {
+    return !code.is_empty()
+        && code.len() <= 10
+        && code
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+}
+
+#[get("/api/instrument/currency")]
+pub async fn list_currencies(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let fetch_currencies = currencies::table
+        .order(currencies::id.asc())
+        .select((
+            currencies::id,
+            currencies::code,
+            currencies::name,
+            currencies::scale,
+            currencies::enabled,
+        ))
+        .load::<(i32, String, String, i32, bool)>(&mut db_conn)
+        .await;
+    let rows = match fetch_currencies {
+        Ok(rows) => rows,
+        Err(_) => return database_error("Fail to fetch the currencies"),
+    };
+    let data: Vec<Value> = rows
+        .into_iter()
+        .map(|(id, code, name, scale, enabled)| {
+            json!({"id": id, "code": code, "name": name, "scale": scale, "enabled": enabled})
+        })
+        .collect();
+    return (Status::Ok, json!({"status":"successful", "data": data}));
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CurrencyData {
+    code: String,
+    name: String,
+    scale: u32,
+}
+#[post("/api/instrument/currency", data = "<currency_data>")]
+pub async fn create_currency(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    currency_data: Json<CurrencyData>,
+) -> (Status, Value) {
+    if !valid_code(&currency_data.code) {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "The code must be 1 to 10 upper case letters or digits"}),
+        );
+    }
+    if currency_data.name.is_empty() || currency_data.name.chars().count() > 50 {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "The name must be 1 to 50 characters"}),
+        );
+    }
+    if currency_data.scale > MAX_SCALE {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": format!("The scale must be at most {}", MAX_SCALE)}),
+        );
+    }
+    let insert_currency = diesel::insert_into(currencies::table)
+        .values((
+            currencies::code.eq(&currency_data.code),
+            currencies::name.eq(&currency_data.name),
+            currencies::scale.eq(currency_data.scale as i32),
+        ))
+        .returning(currencies::id)
+        .get_result::<i32>(&mut db_conn)
+        .await;
+    match insert_currency {
+        Ok(id) => return (Status::Ok, json!({"status":"successful", "id": id})),
+        Err(err) if is_unique_violation(&err) => {
+            return (
+                Status::Conflict,
+                json!({"status":"error", "message": "The currency already exists"}),
+            );
+        }
+        Err(_) => return database_error("Fail to create the currency"),
+    }
+}
+
+// The scale cannot change, every stored amount of the currency is counted in it
+#[derive(Serialize, Deserialize)]
+pub struct CurrencyUpdateData {
+    name: Option<String>,
+    enabled: Option<bool>,
+}
+#[patch("/api/instrument/currency/<code>", data = "<update_data>")]
+pub async fn update_currency(
+    code: &str,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    update_data: Json<CurrencyUpdateData>,
+) -> (Status, Value) {
+    let currency_id = match get_currency_id(&mut db_conn, code).await {
+        Ok(currency_id) => currency_id,
+        Err(_) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message": "Currency not found"}),
+            );
+        }
+    };
+    if let Some(name) = &update_data.name {
+        if name.is_empty() || name.chars().count() > 50 {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": "The name must be 1 to 50 characters"}),
+            );
+        }
+        let update_name = diesel::update(currencies::table.filter(currencies::id.eq(currency_id)))
+            .set(currencies::name.eq(name))
+            .execute(&mut db_conn)
+            .await;
+        if update_name.is_err() {
+            return database_error("Fail to update the currency");
+        }
+    }
+    if let Some(enabled) = update_data.enabled {
+        if set_currency_enabled(currency_id, enabled, &mut db_conn)
+            .await
+            .is_err()
+        {
+            return database_error("Fail to update the currency");
+        }
+    }
+    return (
+        Status::Ok,
+        json!({"status":"successful", "id": currency_id}),
+    );
+}
+
+async fn set_currency_enabled(
+    currency_id: i32,
+    enabled: bool,
+    db_conn: &mut Connection<database::PgDb>,
+) -> Result<usize, diesel::result::Error> {
+    return diesel::update(currencies::table.filter(currencies::id.eq(currency_id)))
+        .set(currencies::enabled.eq(enabled))
+        .execute(db_conn)
+        .await;
+}
+
+// A disabled currency stays in the books, its pairs stop trading
+#[delete("/api/instrument/currency/<code>")]
+pub async fn disable_currency(
+    code: &str,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let currency_id = match get_currency_id(&mut db_conn, code).await {
+        Ok(currency_id) => currency_id,
+        Err(_) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message": "Currency not found"}),
+            );
+        }
+    };
+    match set_currency_enabled(currency_id, false, &mut db_conn).await {
+        Ok(_) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "id": currency_id}),
+            )
+        }
+        Err(_) => return database_error("Fail to disable the currency"),
+    }
+}
+
+// Every pair with its rules, the disabled ones included
+#[get("/api/instrument/pair")]
+pub async fn list_pairs(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let pairs = match pair_specs(&mut db_conn, true).await {
+        Ok(pairs) => pairs,
+        Err(_) => return database_error("Fail to fetch the trading pairs"),
+    };
+    let data: Vec<Value> = pairs
+        .into_iter()
+        .map(|(id, symbol, spec)| {
+            let mut pair = spec.to_json(&symbol);
+            pair["id"] = json!(id);
+            pair
+        })
+        .collect();
+    return (Status::Ok, json!({"status":"successful", "data": data}));
+}
+
+// Increments are decimals in the pair's currencies, a missing field keeps its value
+#[derive(Serialize, Deserialize)]
+pub struct PairSpecData {
+    tick_size: Option<String>,
+    lot_size: Option<String>,
+    min_notional: Option<String>,
+    price_precision: Option<u32>,
+    qty_precision: Option<u32>,
+}
+
+impl PairSpecData {
+    fn apply(&self, spec: &mut PairSpec, symbol: &Symbol) -> Result<(), SpecError> {
+        if let Some(tick_size) = &self.tick_size {
+            spec.tick_size = Amount::parse(tick_size, symbol.quote_scale)?.units;
+        }
+        if let Some(lot_size) = &self.lot_size {
+            spec.lot_size = Amount::parse(lot_size, symbol.base_scale)?.units;
+        }
+        if let Some(min_notional) = &self.min_notional {
+            spec.min_notional = Amount::parse(min_notional, symbol.quote_scale)?.units;
+        }
+        if let Some(price_precision) = self.price_precision {
+            spec.price_precision = price_precision;
+        }
+        if let Some(qty_precision) = self.qty_precision {
+            spec.qty_precision = qty_precision;
+        }
+        return spec.validate(symbol);
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PairData {
+    base: String,
+    quote: String,
+    #[serde(flatten)]
+    spec: PairSpecData,
+}
+#[post("/api/instrument/pair", data = "<pair_data>")]
+pub async fn create_pair(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    pair_data: Json<PairData>,
+) -> (Status, Value) {
+    if pair_data.base == pair_data.quote {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "The base and the quote must differ"}),
+        );
+    }
+    let fetch_currency = |code: &str| {
+        currencies::table
+            .filter(currencies::code.eq(code.to_string()))
+            .select((currencies::id, currencies::scale, currencies::enabled))
+    };
+    let base = fetch_currency(&pair_data.base)
+        .first::<(i32, i32, bool)>(&mut db_conn)
+        .await;
+    let quote = fetch_currency(&pair_data.quote)
+        .first::<(i32, i32, bool)>(&mut db_conn)
+        .await;
+    let ((base_id, base_scale, base_enabled), (quote_id, quote_scale, quote_enabled)) =
+        match (base, quote) {
+            (Ok(base), Ok(quote)) => (base, quote),
+            _ => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message": "Currency not found"}),
+                );
+            }
+        };
+    if !base_enabled || !quote_enabled {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "The currency is disabled"}),
+        );
+    }
+    let symbol = Symbol {
+        base: pair_data.base.clone(),
+        quote: pair_data.quote.clone(),
+        base_scale: base_scale as u32,
+        quote_scale: quote_scale as u32,
+    };
+    let mut spec = PairSpec::unrestricted(&symbol);
+    if let Err(err) = pair_data.spec.apply(&mut spec, &symbol) {
+        return spec_error(err);
+    }
+
+    let insert_pair = diesel::insert_into(trading_pairs::table)
+        .values((
+            trading_pairs::base_currency_id.eq(base_id),
+            trading_pairs::quote_currency_id.eq(quote_id),
+            trading_pairs::tick_size.eq(spec.tick_size),
+            trading_pairs::lot_size.eq(spec.lot_size),
+            trading_pairs::min_notional.eq(spec.min_notional),
+            trading_pairs::price_precision.eq(spec.price_precision as i32),
+            trading_pairs::qty_precision.eq(spec.qty_precision as i32),
+        ))
+        .returning(trading_pairs::id)
+        .get_result::<i32>(&mut db_conn)
+        .await;
+    match insert_pair {
+        Ok(id) => {
+            let mut pair = spec.to_json(&symbol);
+            pair["id"] = json!(id);
+            return (Status::Ok, json!({"status":"successful", "data": pair}));
+        }
+        Err(err) if is_unique_violation(&err) => {
+            return (
+                Status::Conflict,
+                json!({"status":"error", "message": "The trading pair already exists"}),
+            );
+        }
+        Err(_) => return database_error("Fail to create the trading pair"),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PairUpdateData {
+    #[serde(flatten)]
+    spec: PairSpecData,
+    enabled: Option<bool>,
+}
+#[patch("/api/instrument/pair/<base>/<quote>", data = "<update_data>")]
+pub async fn update_pair(
+    base: &str,
+    quote: &str,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    update_data: Json<PairUpdateData>,
+) -> (Status, Value) {
+    let trading_pair_id = match get_trading_pair_id(&mut db_conn, (base, quote)).await {
+        Ok((_, _, id)) => id,
+        Err(message) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    let (symbol, mut spec) = match (
+        get_pair_symbol(&mut db_conn, trading_pair_id).await,
+        get_pair_spec(&mut db_conn, trading_pair_id).await,
+    ) {
+        (Ok(symbol), Ok(spec)) => (symbol, spec),
+        _ => return database_error("Fail to fetch the trading pair"),
+    };
+    if let Err(err) = update_data.spec.apply(&mut spec, &symbol) {
+        return spec_error(err);
+    }
+
+    let pair = trading_pairs::table.filter(trading_pairs::id.eq(trading_pair_id));
+    let update_spec = diesel::update(pair)
+        .set((
+            trading_pairs::tick_size.eq(spec.tick_size),
+            trading_pairs::lot_size.eq(spec.lot_size),
+            trading_pairs::min_notional.eq(spec.min_notional),
+            trading_pairs::price_precision.eq(spec.price_precision as i32),
+            trading_pairs::qty_precision.eq(spec.qty_precision as i32),
+        ))
+        .execute(&mut db_conn)
+        .await;
+    if update_spec.is_err() {
+        return database_error("Fail to update the trading pair");
+    }
+    if let Some(enabled) = update_data.enabled {
+        let update_enabled = diesel::update(pair)
+            .set(trading_pairs::enabled.eq(enabled))
+            .execute(&mut db_conn)
+            .await;
+        if update_enabled.is_err() {
+            return database_error("Fail to update the trading pair");
+        }
+    }
+    // re-read so a disabled currency shows
+    match get_pair_spec(&mut db_conn, trading_pair_id).await {
+        Ok(spec) => {
+            let mut pair = spec.to_json(&symbol);
+            pair["id"] = json!(trading_pair_id);
+            return (Status::Ok, json!({"status":"successful", "data": pair}));
+        }
+        Err(_) => return database_error("Fail to fetch the trading pair"),
+    }
+}
+
+// A disabled pair takes no new orders, the open ones are left to the trader
+#[delete("/api/instrument/pair/<base>/<quote>")]
+pub async fn disable_pair(
+    base: &str,
+    quote: &str,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let trading_pair_id = match get_trading_pair_id(&mut db_conn, (base, quote)).await {
+        Ok((_, _, id)) => id,
+        Err(message) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    let disable =
+        diesel::update(trading_pairs::table.filter(trading_pairs::id.eq(trading_pair_id)))
+            .set(trading_pairs::enabled.eq(false))
+            .execute(&mut db_conn)
+            .await;
+    match disable {
+        Ok(_) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "id": trading_pair_id}),
+            )
+        }
+        Err(_) => return database_error("Fail to disable the trading pair"),
+    }
+}
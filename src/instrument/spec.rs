@@ -0,0 +1,382 @@
+use ::diesel::ExpressionMethods;
+use diesel::QueryDsl;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+
+use crate::db_lib::query::{get_pair_spec, get_pair_symbol};
+use crate::db_lib::schema::trading_pairs;
+use crate::order::exchange::Symbol;
+use crate::order::order_type::OrderTerms;
+use crate::types::amount::{pow10, AmountError, Rounding};
+
+// The trading rules of a pair, the increments are in the minor units of its currencies:
+// tick_size and min_notional in the quote currency, lot_size in the base currency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairSpec {
+    pub tick_size: i64,
+    pub lot_size: i64,
+    pub min_notional: i64,
+    // the decimals an order may carry, capped by the scale of the currency
+    pub price_precision: u32,
+    pub qty_precision: u32,
+    // false when the pair or one of its currencies is disabled
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecError {
+    Disabled,
+    Amount(AmountError),
+    // the increment or limit that is broken, formatted in its currency
+    PricePrecision(u32),
+    QtyPrecision(u32),
+    Tick(String),
+    Lot(String),
+    MinNotional(String),
+    InvalidSpec(&'static str),
+}
+
+impl SpecError {
+    pub fn message(&self) -> String {
+        match self {
+            SpecError::Disabled => "The trading pair is disabled".to_string(),
+            SpecError::Amount(err) => err.message(),
+            SpecError::PricePrecision(precision) => {
+                format!("The price can have at most {} decimals", precision)
+            }
+            SpecError::QtyPrecision(precision) => {
+                format!("The quantity can have at most {} decimals", precision)
+            }
+            SpecError::Tick(tick_size) => {
+                format!(
+                    "The price must be a multiple of the tick size {}",
+                    tick_size
+                )
+            }
+            SpecError::Lot(lot_size) => {
+                format!(
+                    "The quantity must be a multiple of the lot size {}",
+                    lot_size
+                )
+            }
+            SpecError::MinNotional(min_notional) => {
+                format!("The order value must be at least {}", min_notional)
+            }
+            SpecError::InvalidSpec(message) => message.to_string(),
+        }
+    }
+}
+
+impl From<AmountError> for SpecError {
+    fn from(err: AmountError) -> Self {
+        SpecError::Amount(err)
+    }
+}
+
+// the smallest unit an order can use with `precision` decimals of a currency with `scale`
+fn precision_step(scale: u32, precision: u32) -> i64 {
+    let dropped = scale.saturating_sub(precision);
+    return pow10(dropped)
+        .and_then(|step| i64::try_from(step).ok())
+        .unwrap_or(i64::MAX);
+}
+
+impl PairSpec {
+    // the rules a new pair starts with, nothing stricter than its currencies
+    pub fn unrestricted(symbol: &Symbol) -> PairSpec {
+        return PairSpec {
+            tick_size: 1,
+            lot_size: 1,
+            min_notional: 0,
+            price_precision: symbol.quote_scale,
+            qty_precision: symbol.base_scale,
+            enabled: true,
+        };
+    }
+
+    // An admin's spec has to be reachable by orders: the increments are positive and
+    // can be written with the precision of the pair.
+    pub fn validate(&self, symbol: &Symbol) -> Result<(), SpecError> {
+        if self.tick_size <= 0 || self.lot_size <= 0 {
+            return Err(SpecError::InvalidSpec(
+                "The tick size and the lot size must be positive",
+            ));
+        }
+        if self.min_notional < 0 {
+            return Err(SpecError::InvalidSpec(
+                "The min notional must not be negative",
+            ));
+        }
+        if self.price_precision > symbol.quote_scale || self.qty_precision > symbol.base_scale {
+            return Err(SpecError::InvalidSpec(
+                "The precision must not exceed the scale of the currency",
+            ));
+        }
+        if self.tick_size % precision_step(symbol.quote_scale, self.price_precision) != 0 {
+            return Err(SpecError::InvalidSpec(
+                "The tick size has more decimals than the price precision",
+            ));
+        }
+        if self.lot_size % precision_step(symbol.base_scale, self.qty_precision) != 0 {
+            return Err(SpecError::InvalidSpec(
+                "The lot size has more decimals than the quantity precision",
+            ));
+        }
+        return Ok(());
+    }
+
+    fn check_price(&self, symbol: &Symbol, price: i64) -> Result<(), SpecError> {
+        if price % precision_step(symbol.quote_scale, self.price_precision) != 0 {
+            return Err(SpecError::PricePrecision(self.price_precision));
+        }
+        if price % self.tick_size != 0 {
+            return Err(SpecError::Tick(symbol.format_price(self.tick_size)));
+        }
+        return Ok(());
+    }
+
+    fn check_qty(&self, symbol: &Symbol, qty: i64) -> Result<(), SpecError> {
+        if qty % precision_step(symbol.base_scale, self.qty_precision) != 0 {
+            return Err(SpecError::QtyPrecision(self.qty_precision));
+        }
+        if qty % self.lot_size != 0 {
+            return Err(SpecError::Lot(symbol.format_qty(self.lot_size)));
+        }
+        return Ok(());
+    }
+
+    fn check_notional(&self, symbol: &Symbol, price: i64, qty: i64) -> Result<(), SpecError> {
+        // an overflowing notional is certainly above the minimum
+        let notional = symbol
+            .notional(price, qty, Rounding::Down)
+            .unwrap_or(i64::MAX);
+        if notional < self.min_notional {
+            return Err(SpecError::MinNotional(
+                symbol.format_price(self.min_notional),
+            ));
+        }
+        return Ok(());
+    }
+
+    // Check an order's price and quantity, both in minor units, against the pair
+    pub fn check_order(&self, symbol: &Symbol, price: i64, qty: i64) -> Result<(), SpecError> {
+        if !self.enabled {
+            return Err(SpecError::Disabled);
+        }
+        self.check_price(symbol, price)?;
+        self.check_qty(symbol, qty)?;
+        return self.check_notional(symbol, price, qty);
+    }
+
+    // Check an order of any type. The stop price follows the ticks like a limit price,
+    // an order without a limit is held to the minimum at its stop or the market price.
+    pub fn check_terms(
+        &self,
+        symbol: &Symbol,
+        terms: &OrderTerms,
+        qty: i64,
+        market_price: Option<i64>,
+    ) -> Result<(), SpecError> {
+        if let Some(stop_price) = terms.stop_price {
+            self.check_price(symbol, stop_price)?;
+        }
+        if let Some(price) = terms.price {
+            return self.check_order(symbol, price, qty);
+        }
+        if !self.enabled {
+            return Err(SpecError::Disabled);
+        }
+        self.check_qty(symbol, qty)?;
+        match terms.stop_price.or(market_price) {
+            Some(price) => return self.check_notional(symbol, price, qty),
+            None => return Ok(()),
+        }
+    }
+
+    pub fn to_json(&self, symbol: &Symbol) -> Value {
+        return json!({
+            "base": symbol.base,
+            "quote": symbol.quote,
+            "tick_size": symbol.format_price(self.tick_size),
+            "lot_size": symbol.format_qty(self.lot_size),
+            "min_notional": symbol.format_price(self.min_notional),
+            "price_precision": self.price_precision,
+            "qty_precision": self.qty_precision,
+            "enabled": self.enabled,
+        });
+    }
+}
+
+// Every pair with its symbol and rules, the disabled ones only when asked for
+pub async fn pair_specs(
+    conn: &mut AsyncPgConnection,
+    with_disabled: bool,
+) -> Result<Vec<(i32, Symbol, PairSpec)>, diesel::result::Error> {
+    let pair_ids = trading_pairs::table
+        .order(trading_pairs::id.asc())
+        .select(trading_pairs::id)
+        .load::<i32>(conn)
+        .await?;
+    let mut pairs = vec![];
+    for pair_id in pair_ids {
+        let spec = get_pair_spec(conn, pair_id).await?;
+        if spec.enabled || with_disabled {
+            pairs.push((pair_id, get_pair_symbol(conn, pair_id).await?, spec));
+        }
+    }
+    return Ok(pairs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn btc_usdt() -> Symbol {
+        return Symbol {
+            base: "BTC".to_string(),
+            quote: "USDT".to_string(),
+            base_scale: 8,
+            quote_scale: 6,
+        };
+    }
+
+    fn spec() -> PairSpec {
+        // 0.01 USDT ticks, 0.0001 BTC lots, at least 10 USDT per order
+        return PairSpec {
+            tick_size: 10_000,
+            lot_size: 10_000,
+            min_notional: 10_000_000,
+            price_precision: 2,
+            qty_precision: 4,
+            enabled: true,
+        };
+    }
+
+    #[test]
+    fn orders_follow_the_increments() {
+        let symbol = btc_usdt();
+        let spec = spec();
+        let price = symbol.parse_price("30000.01").unwrap();
+        assert_eq!(
+            spec.check_order(&symbol, price, symbol.parse_qty("0.0005").unwrap()),
+            Ok(())
+        );
+        assert_eq!(
+            spec.check_order(&symbol, symbol.parse_price("30000.001").unwrap(), 50_000),
+            Err(SpecError::PricePrecision(2))
+        );
+        assert_eq!(
+            spec.check_order(&symbol, price, symbol.parse_qty("0.00051").unwrap()),
+            Err(SpecError::QtyPrecision(4))
+        );
+        assert_eq!(
+            spec.check_order(&symbol, price, symbol.parse_qty("0.0001").unwrap()),
+            Err(SpecError::MinNotional("10.000000".to_string()))
+        );
+
+        // coarser increments than the precision
+        let coarse = PairSpec {
+            tick_size: 500_000,
+            lot_size: 50_000,
+            ..spec.clone()
+        };
+        assert_eq!(
+            coarse.check_order(&symbol, price, 50_000),
+            Err(SpecError::Tick("0.500000".to_string()))
+        );
+        assert_eq!(
+            coarse.check_order(&symbol, symbol.parse_price("30000.5").unwrap(), 60_000),
+            Err(SpecError::Lot("0.00050000".to_string()))
+        );
+
+        let disabled = PairSpec {
+            enabled: false,
+            ..spec
+        };
+        assert_eq!(
+            disabled.check_order(&symbol, price, 50_000),
+            Err(SpecError::Disabled)
+        );
+    }
+
+    #[test]
+    fn stops_and_market_orders_follow_the_increments() {
+        use crate::order::order_type::{OrderType, TimeInForce};
+
+        let symbol = btc_usdt();
+        let spec = spec();
+        let stop = OrderTerms {
+            order_type: OrderType::StopMarket,
+            time_in_force: TimeInForce::Gtc,
+            price: None,
+            stop_price: Some(symbol.parse_price("29000.5").unwrap()),
+            expires_at: None,
+        };
+        assert_eq!(spec.check_terms(&symbol, &stop, 50_000, None), Ok(()));
+        // 0.0003 BTC at the 29000.5 stop is below 10 USDT
+        assert_eq!(
+            spec.check_terms(&symbol, &stop, 30_000, None),
+            Err(SpecError::MinNotional("10.000000".to_string()))
+        );
+        let off_tick = OrderTerms {
+            stop_price: Some(symbol.parse_price("29000.005").unwrap()),
+            ..stop.clone()
+        };
+        assert_eq!(
+            spec.check_terms(&symbol, &off_tick, 50_000, None),
+            Err(SpecError::PricePrecision(2))
+        );
+
+        let market = OrderTerms {
+            order_type: OrderType::Market,
+            time_in_force: TimeInForce::Ioc,
+            stop_price: None,
+            ..stop
+        };
+        let last = symbol.parse_price("30000.123").unwrap();
+        assert_eq!(
+            spec.check_terms(&symbol, &market, 50_000, Some(last)),
+            Ok(())
+        );
+        assert_eq!(
+            spec.check_terms(&symbol, &market, 55_555, Some(last)),
+            Err(SpecError::QtyPrecision(4))
+        );
+    }
+
+    #[test]
+    fn specs_must_be_reachable() {
+        let symbol = btc_usdt();
+        assert_eq!(spec().validate(&symbol), Ok(()));
+        assert_eq!(PairSpec::unrestricted(&symbol).validate(&symbol), Ok(()));
+        for invalid in [
+            PairSpec {
+                tick_size: 0,
+                ..spec()
+            },
+            PairSpec {
+                min_notional: -1,
+                ..spec()
+            },
+            PairSpec {
+                price_precision: 7,
+                ..spec()
+            },
+            // 0.005 cannot be written with two decimals
+            PairSpec {
+                tick_size: 5_000,
+                ..spec()
+            },
+            PairSpec {
+                lot_size: 1_000,
+                ..spec()
+            },
+        ] {
+            assert!(matches!(
+                invalid.validate(&symbol),
+                Err(SpecError::InvalidSpec(_))
+            ));
+        }
+    }
+}
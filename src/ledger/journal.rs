@@ -0,0 +1,292 @@
+use ::diesel::ExpressionMethods;
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::{self, AsyncPgConnection};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::db_lib::schema::{ledger_entries, ledger_journals};
+use crate::order::balance::{credit, debit, BalanceError};
+
+// Why the balances moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    // the balances that existed before the ledger
+    Opening,
+    Deposit,
+    Withdrawal,
+    Trade,
+    Fee,
+    // an approved intra-account transfer request
+    Transfer,
+    // funds allocated from or given back to the account's main portfolio
+    Main,
+    // simulated funds put into or taken out of a paper portfolio
+    Paper,
+}
+
+impl EntryKind {
+    pub const ALL: [EntryKind; 8] = [
+        EntryKind::Opening,
+        EntryKind::Deposit,
+        EntryKind::Withdrawal,
+        EntryKind::Trade,
+        EntryKind::Fee,
+        EntryKind::Transfer,
+        EntryKind::Main,
+        EntryKind::Paper,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::Opening => "opening",
+            EntryKind::Deposit => "deposit",
+            EntryKind::Withdrawal => "withdrawal",
+            EntryKind::Trade => "trade",
+            EntryKind::Fee => "fee",
+            EntryKind::Transfer => "transfer",
+            EntryKind::Main => "main",
+            EntryKind::Paper => "paper",
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        return EntryKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+            .ok_or_else(|| format!("Unknown entry kind {}", value));
+    }
+}
+
+// Who the funds belong to. Only portfolios have a balance, the outside world and the
+// exchange are the other side of deposits, withdrawals and trades.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    Portfolio(i32),
+    External,
+    Exchange,
+}
+
+impl LedgerAccount {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LedgerAccount::Portfolio(_) => "portfolio",
+            LedgerAccount::External => "external",
+            LedgerAccount::Exchange => "exchange",
+        }
+    }
+
+    pub fn portfolio_id(self) -> Option<i32> {
+        match self {
+            LedgerAccount::Portfolio(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    pub fn from_row(account: &str, portfolio_id: Option<i32>) -> Option<LedgerAccount> {
+        match (account, portfolio_id) {
+            ("portfolio", Some(id)) => Some(LedgerAccount::Portfolio(id)),
+            ("external", None) => Some(LedgerAccount::External),
+            ("exchange", None) => Some(LedgerAccount::Exchange),
+            _ => None,
+        }
+    }
+}
+
+// One side of a movement, positive is money in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub currency_id: i32,
+    pub amount: i64,
+}
+
+// A set of postings booked together, with what caused them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journal {
+    pub kind: EntryKind,
+    pub order_id: Option<i32>,
+    pub fill_id: Option<i32>,
+    pub transfer_id: Option<i32>,
+    pub created_by: Option<i32>,
+    pub memo: Option<String>,
+    pub postings: Vec<Posting>,
+    // portfolios may not go below what they have available
+    pub checked: bool,
+}
+
+impl Journal {
+    pub fn new(kind: EntryKind) -> Journal {
+        return Journal {
+            kind,
+            order_id: None,
+            fill_id: None,
+            transfer_id: None,
+            created_by: None,
+            memo: None,
+            postings: vec![],
+            checked: false,
+        };
+    }
+
+    pub fn order(mut self, order_id: i32, fill_id: Option<i32>) -> Journal {
+        self.order_id = Some(order_id);
+        self.fill_id = fill_id;
+        return self;
+    }
+
+    pub fn transfer(mut self, transfer_id: i32) -> Journal {
+        self.transfer_id = Some(transfer_id);
+        return self;
+    }
+
+    pub fn by(mut self, account_id: i32) -> Journal {
+        self.created_by = Some(account_id);
+        return self;
+    }
+
+    pub fn memo(mut self, memo: &str) -> Journal {
+        self.memo = Some(memo.to_string());
+        return self;
+    }
+
+    pub fn checked(mut self) -> Journal {
+        self.checked = true;
+        return self;
+    }
+
+    pub fn post(mut self, account: LedgerAccount, currency_id: i32, amount: i64) -> Journal {
+        if amount != 0 {
+            self.postings.push(Posting {
+                account,
+                currency_id,
+                amount,
+            });
+        }
+        return self;
+    }
+
+    // `amount` of the currency leaves `from` and arrives in `to`
+    pub fn move_funds(
+        self,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        currency_id: i32,
+        amount: i64,
+    ) -> Journal {
+        return self
+            .post(from, currency_id, -amount)
+            .post(to, currency_id, amount);
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        let mut totals: BTreeMap<i32, i128> = BTreeMap::new();
+        for posting in &self.postings {
+            *totals.entry(posting.currency_id).or_default() += posting.amount as i128;
+        }
+        return totals.values().all(|total| *total == 0);
+    }
+}
+
+// Book the journal and bring the balances of its portfolios along. Run it in the
+// caller's transaction so that a failed posting leaves nothing behind.
+pub async fn record(conn: &mut AsyncPgConnection, journal: &Journal) -> Result<i32, BalanceError> {
+    if !journal.is_balanced() {
+        return Err(BalanceError::Unbalanced);
+    }
+    let journal_id = diesel::insert_into(ledger_journals::table)
+        .values((
+            ledger_journals::kind.eq(journal.kind.as_str()),
+            ledger_journals::order_id.eq(journal.order_id),
+            ledger_journals::fill_id.eq(journal.fill_id),
+            ledger_journals::transfer_id.eq(journal.transfer_id),
+            ledger_journals::created_by.eq(journal.created_by),
+            ledger_journals::memo.eq(&journal.memo),
+        ))
+        .returning(ledger_journals::id)
+        .get_result::<i32>(conn)
+        .await?;
+
+    for posting in &journal.postings {
+        diesel::insert_into(ledger_entries::table)
+            .values((
+                ledger_entries::journal_id.eq(journal_id),
+                ledger_entries::account.eq(posting.account.as_str()),
+                ledger_entries::portfolio_id.eq(posting.account.portfolio_id()),
+                ledger_entries::currency_id.eq(posting.currency_id),
+                ledger_entries::amount.eq(posting.amount),
+            ))
+            .execute(conn)
+            .await?;
+        if let LedgerAccount::Portfolio(portfolio_id) = posting.account {
+            if journal.checked && posting.amount < 0 {
+                debit(conn, portfolio_id, posting.currency_id, -posting.amount).await?;
+            } else {
+                credit(conn, portfolio_id, posting.currency_id, posting.amount).await?;
+            }
+        }
+    }
+    return Ok(journal_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journals_must_balance() {
+        let buy = Journal::new(EntryKind::Trade)
+            .order(7, Some(9))
+            .move_funds(LedgerAccount::Exchange, LedgerAccount::Portfolio(1), 1, 2)
+            .move_funds(
+                LedgerAccount::Portfolio(1),
+                LedgerAccount::Exchange,
+                3,
+                60_000,
+            );
+        assert!(buy.is_balanced());
+        assert_eq!(buy.postings.len(), 4);
+
+        let lopsided = Journal::new(EntryKind::Deposit)
+            .post(LedgerAccount::Portfolio(1), 3, 100)
+            .post(LedgerAccount::External, 3, -99);
+        assert!(!lopsided.is_balanced());
+
+        // zero amounts leave no entry behind
+        let empty = Journal::new(EntryKind::Fee).move_funds(
+            LedgerAccount::Portfolio(1),
+            LedgerAccount::Portfolio(2),
+            3,
+            0,
+        );
+        assert!(empty.postings.is_empty() && empty.is_balanced());
+    }
+
+    #[test]
+    fn accounts_round_trip() {
+        for account in [
+            LedgerAccount::Portfolio(4),
+            LedgerAccount::External,
+            LedgerAccount::Exchange,
+        ] {
+            assert_eq!(
+                LedgerAccount::from_row(account.as_str(), account.portfolio_id()),
+                Some(account)
+            );
+        }
+        assert_eq!(LedgerAccount::from_row("portfolio", None), None);
+        for kind in EntryKind::ALL {
+            assert_eq!(kind.as_str().parse::<EntryKind>(), Ok(kind));
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::get_currencies;
+use crate::db_lib::schema::{ledger_entries, ledger_journals};
+use crate::ledger::journal::{EntryKind, LedgerAccount};
+use crate::ledger::projection::{check, rebuild};
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
+use ::diesel::ExpressionMethods;
+use chrono::NaiveDateTime;
+use diesel::QueryDsl;
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::Connection;
+
+pub mod journal;
+pub mod projection;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_ledger, check_ledger, rebuild_ledger]
+}
+
+// (entry id, journal id, currency id, amount, kind, order id, fill id, transfer id,
+//  created by, memo, created at)
+type EntryRow = (
+    i32,
+    i32,
+    i32,
+    i64,
+    String,
+    Option<i32>,
+    Option<i32>,
+    Option<i32>,
+    Option<i32>,
+    Option<String>,
+    NaiveDateTime,
+);
+
+// The balance history of the portfolio, newest first. Every entry lists the other
+// side of its journal.
+#[get("/api/portfolio/<id>/ledger?<kind>&<st>&<len>")]
+pub async fn get_ledger(
+    id: i32,
+    kind: Option<&str>,
+    st: Option<i64>,
+    len: Option<i64>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    if let Err(err) = owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(id),
+        "get_ledger",
+        &mut db_conn,
+    )
+    .await
+    {
+        return err;
+    }
+    let kind = match kind.map(|kind| kind.parse::<EntryKind>()).transpose() {
+        Ok(kind) => kind,
+        Err(message) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+
+    let mut query = ledger_entries::table
+        .inner_join(ledger_journals::table)
+        .filter(ledger_entries::portfolio_id.eq(id))
+        .into_boxed();
+    if let Some(kind) = kind {
+        query = query.filter(ledger_journals::kind.eq(kind.as_str()));
+    }
+    let fetch_entries = query
+        .order(ledger_entries::id.desc())
+        .offset(st.unwrap_or(0))
+        .limit(len.unwrap_or(50))
+        .select((
+            ledger_entries::id,
+            ledger_entries::journal_id,
+            ledger_entries::currency_id,
+            ledger_entries::amount,
+            ledger_journals::kind,
+            ledger_journals::order_id,
+            ledger_journals::fill_id,
+            ledger_journals::transfer_id,
+            ledger_journals::created_by,
+            ledger_journals::memo,
+            ledger_journals::created_at,
+        ))
+        .load::<EntryRow>(&mut db_conn)
+        .await;
+    let entries = if let Ok(entries) = fetch_entries {
+        entries
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Fail to fetch the ledger"}),
+        );
+    };
+
+    // the other postings of the same journals
+    let journal_ids: Vec<i32> = entries.iter().map(|entry| entry.1).collect();
+    let fetch_others = ledger_entries::table
+        .filter(ledger_entries::journal_id.eq_any(journal_ids))
+        .select((
+            ledger_entries::id,
+            ledger_entries::journal_id,
+            ledger_entries::account,
+            ledger_entries::portfolio_id,
+            ledger_entries::currency_id,
+            ledger_entries::amount,
+        ))
+        .load::<(i32, i32, String, Option<i32>, i32, i64)>(&mut db_conn)
+        .await;
+    let fetch_currencies = get_currencies(&mut db_conn).await;
+    let (others, currencies) = match (fetch_others, fetch_currencies) {
+        (Ok(others), Ok(currencies)) => (others, currencies),
+        _ => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to fetch the ledger"}),
+            );
+        }
+    };
+
+    let data: Vec<Value> = entries
+        .iter()
+        .map(|entry| {
+            let counterparts: Vec<Value> = others
+                .iter()
+                .filter(|other| other.1 == entry.1 && other.0 != entry.0)
+                .map(|(_, _, account, portfolio_id, currency_id, amount)| {
+                    json!({
+                        "account": LedgerAccount::from_row(account, *portfolio_id)
+                            .map(|account| account.as_str()),
+                        "portfolio_id": portfolio_id,
+                        "currency": currencies.code(*currency_id),
+                        "amount": currencies.format(*currency_id, *amount as i128),
+                    })
+                })
+                .collect();
+            json!({
+                "id": entry.0,
+                "journal_id": entry.1,
+                "currency": currencies.code(entry.2),
+                "amount": currencies.format(entry.2, entry.3 as i128),
+                "kind": entry.4,
+                "order_id": entry.5,
+                "fill_id": entry.6,
+                "transfer_id": entry.7,
+                "created_by": entry.8,
+                "memo": entry.9,
+                "time": entry.10.to_string(),
+                "counterparts": counterparts,
+            })
+        })
+        .collect();
+    let len = data.len();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "data": data, "len": len}),
+    );
+}
+
+// Compare every balance with the ledger and look for journals that do not balance
+#[get("/api/ledger/check")]
+pub async fn check_ledger(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let report = match check(&mut db_conn).await {
+        Ok(report) => report,
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": err.message()}),
+            );
+        }
+    };
+    let currencies = match get_currencies(&mut db_conn).await {
+        Ok(currencies) => currencies,
+        Err(message) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    let (mismatches, unbalanced) = report;
+    let mismatches: Vec<Value> = mismatches
+        .iter()
+        .map(|mismatch| mismatch.to_json(&currencies))
+        .collect();
+    return (
+        Status::Ok,
+        json!({
+            "status": "successful",
+            "consistent": mismatches.is_empty() && unbalanced.is_empty(),
+            "mismatches": mismatches,
+            "unbalanced_journals": unbalanced,
+        }),
+    );
+}
+
+// Rebuild the balances from the ledger, the corrected ones are returned
+#[post("/api/ledger/rebuild")]
+pub async fn rebuild_ledger(
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let currencies = match get_currencies(&mut db_conn).await {
+        Ok(currencies) => currencies,
+        Err(message) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    match rebuild(&mut db_conn).await {
+        Ok(corrected) => {
+            let corrected: Vec<Value> = corrected
+                .iter()
+                .map(|mismatch| mismatch.to_json(&currencies))
+                .collect();
+            return (
+                Status::Ok,
+                json!({"status":"successful", "corrected": corrected}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": err.message()}),
+            );
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use ::diesel::ExpressionMethods;
+use diesel::dsl::sql;
+use diesel::sql_types::BigInt;
+use diesel::QueryDsl;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::{self, AsyncConnection, AsyncPgConnection};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::db_lib::schema::{ledger_entries, portfolio_balance, portfolios};
+use crate::order::balance::BalanceError;
+use crate::types::amount::Currencies;
+
+// A balance that does not match the ledger
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub portfolio_id: i32,
+    pub currency_id: i32,
+    pub balance: i64,
+    pub ledger: i64,
+}
+
+impl Mismatch {
+    pub fn to_json(&self, currencies: &Currencies) -> Value {
+        return json!({
+            "portfolio_id": self.portfolio_id,
+            "currency_id": self.currency_id,
+            "currency": currencies.code(self.currency_id),
+            "balance": currencies.format(self.currency_id, self.balance as i128),
+            "ledger": currencies.format(self.currency_id, self.ledger as i128),
+        });
+    }
+}
+
+// Every (portfolio, currency) whose balance differs from the sum of its entries. A
+// currency missing on one side counts as zero there.
+pub fn compare(
+    balances: &BTreeMap<(i32, i32), i64>,
+    ledger: &BTreeMap<(i32, i32), i64>,
+) -> Vec<Mismatch> {
+    let keys: BTreeSet<(i32, i32)> = balances.keys().chain(ledger.keys()).copied().collect();
+    return keys
+        .into_iter()
+        .filter_map(|(portfolio_id, currency_id)| {
+            let balance = balances
+                .get(&(portfolio_id, currency_id))
+                .copied()
+                .unwrap_or(0);
+            let ledger = ledger
+                .get(&(portfolio_id, currency_id))
+                .copied()
+                .unwrap_or(0);
+            (balance != ledger).then_some(Mismatch {
+                portfolio_id,
+                currency_id,
+                balance,
+                ledger,
+            })
+        })
+        .collect();
+}
+
+// the balances and what the ledger says they should be, for the portfolios that
+// still exist
+async fn both_sides(
+    conn: &mut AsyncPgConnection,
+) -> Result<(BTreeMap<(i32, i32), i64>, BTreeMap<(i32, i32), i64>), BalanceError> {
+    let portfolio_ids: BTreeSet<i32> = portfolios::table
+        .select(portfolios::id)
+        .load::<i32>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let mut balances: BTreeMap<(i32, i32), i64> = BTreeMap::new();
+    let rows = portfolio_balance::table
+        .select((
+            portfolio_balance::portfolio_id,
+            portfolio_balance::currency_id,
+            portfolio_balance::quantity,
+        ))
+        .load::<(i32, i32, i64)>(conn)
+        .await?;
+    for (portfolio_id, currency_id, quantity) in rows {
+        *balances.entry((portfolio_id, currency_id)).or_default() += quantity;
+    }
+    let sums = ledger_entries::table
+        .filter(ledger_entries::portfolio_id.is_not_null())
+        .group_by((ledger_entries::portfolio_id, ledger_entries::currency_id))
+        .select((
+            ledger_entries::portfolio_id,
+            ledger_entries::currency_id,
+            sql::<BigInt>("SUM(amount)::BIGINT"),
+        ))
+        .load::<(Option<i32>, i32, i64)>(conn)
+        .await?;
+    let ledger = sums
+        .into_iter()
+        .filter_map(|(portfolio_id, currency_id, total)| {
+            portfolio_id
+                .filter(|id| portfolio_ids.contains(id))
+                .map(|id| ((id, currency_id), total))
+        })
+        .collect();
+    return Ok((balances, ledger));
+}
+
+// journals whose entries do not sum to zero in some currency
+pub async fn unbalanced_journals(conn: &mut AsyncPgConnection) -> Result<Vec<i32>, BalanceError> {
+    let sums = ledger_entries::table
+        .group_by((ledger_entries::journal_id, ledger_entries::currency_id))
+        .select((
+            ledger_entries::journal_id,
+            sql::<BigInt>("SUM(amount)::BIGINT"),
+        ))
+        .load::<(i32, i64)>(conn)
+        .await?;
+    let unbalanced: BTreeSet<i32> = sums
+        .into_iter()
+        .filter(|(_, total)| *total != 0)
+        .map(|(journal_id, _)| journal_id)
+        .collect();
+    return Ok(unbalanced.into_iter().collect());
+}
+
+async fn mismatches(conn: &mut AsyncPgConnection) -> Result<Vec<Mismatch>, BalanceError> {
+    let (balances, ledger) = both_sides(conn).await?;
+    return Ok(compare(&balances, &ledger));
+}
+
+// The balances that differ from the ledger and the journals that do not balance. Both
+// are read from one snapshot, so a journal committed meanwhile is not half seen.
+pub async fn check(
+    conn: &mut AsyncPgConnection,
+) -> Result<(Vec<Mismatch>, Vec<i32>), BalanceError> {
+    return conn
+        .build_transaction()
+        .repeatable_read()
+        .read_only()
+        .run::<_, BalanceError, _>(|conn| {
+            async move {
+                let mismatches = mismatches(conn).await?;
+                let unbalanced = unbalanced_journals(conn).await?;
+                return Ok((mismatches, unbalanced));
+            }
+            .scope_boxed()
+        })
+        .await;
+}
+
+// Set every balance to what the ledger says and return what was off. The
+// reservations are left alone. Writers to the balances and the ledger wait until it
+// is done, so nothing booked meanwhile is overwritten.
+pub async fn rebuild(conn: &mut AsyncPgConnection) -> Result<Vec<Mismatch>, BalanceError> {
+    return conn
+        .transaction::<Vec<Mismatch>, BalanceError, _>(|conn| {
+            async move {
+                diesel::sql_query(
+                    "LOCK TABLE portfolio_balance, ledger_entries IN SHARE ROW EXCLUSIVE MODE",
+                )
+                .execute(conn)
+                .await?;
+                let mismatches = mismatches(conn).await?;
+                for mismatch in &mismatches {
+                    let balance = portfolio_balance::table
+                        .filter(portfolio_balance::portfolio_id.eq(mismatch.portfolio_id))
+                        .filter(portfolio_balance::currency_id.eq(mismatch.currency_id));
+                    let row_ids = balance
+                        .order(portfolio_balance::id.asc())
+                        .select(portfolio_balance::id)
+                        .load::<i32>(conn)
+                        .await?;
+                    // a currency split over several rows is gathered in the first one
+                    match row_ids.split_first() {
+                        Some((first, rest)) => {
+                            diesel::update(
+                                portfolio_balance::table.filter(portfolio_balance::id.eq(*first)),
+                            )
+                            .set(portfolio_balance::quantity.eq(mismatch.ledger))
+                            .execute(conn)
+                            .await?;
+                            diesel::update(
+                                portfolio_balance::table
+                                    .filter(portfolio_balance::id.eq_any(rest.to_vec())),
+                            )
+                            .set(portfolio_balance::quantity.eq(0))
+                            .execute(conn)
+                            .await?;
+                        }
+                        None => {
+                            diesel::insert_into(portfolio_balance::table)
+                                .values((
+                                    portfolio_balance::portfolio_id.eq(mismatch.portfolio_id),
+                                    portfolio_balance::currency_id.eq(mismatch.currency_id),
+                                    portfolio_balance::quantity.eq(mismatch.ledger),
+                                ))
+                                .execute(conn)
+                                .await?;
+                        }
+                    }
+                }
+                return Ok(mismatches);
+            }
+            .scope_boxed()
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatches_on_either_side() {
+        let balances = BTreeMap::from([((1, 3), 100), ((1, 1), 2), ((2, 3), 0)]);
+        let ledger = BTreeMap::from([((1, 3), 100), ((1, 1), 1), ((2, 1), 5)]);
+        assert_eq!(
+            compare(&balances, &ledger),
+            vec![
+                Mismatch {
+                    portfolio_id: 1,
+                    currency_id: 1,
+                    balance: 2,
+                    ledger: 1,
+                },
+                Mismatch {
+                    portfolio_id: 2,
+                    currency_id: 1,
+                    balance: 0,
+                    ledger: 5,
+                },
+            ]
+        );
+    }
+}
@@ -1,7 +1,13 @@
 pub mod auth;
 pub mod db_lib;
+pub mod funding;
+pub mod instrument;
+pub mod ledger;
+pub mod market;
 pub mod order;
 pub mod portfolio;
+pub mod risk;
+pub mod transfer;
 pub mod types;
 
 #[macro_use]
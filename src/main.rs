@@ -11,17 +11,13 @@ use rocket_db_pools::{Connection, Database};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-mod db_lib;
-use db_lib::{database, RAND};
-mod auth;
-use auth::{login, signup, user_center};
-mod portfolio;
 use rocket::http::Method;
 use rocket_cors::{AllowedOrigins, CorsOptions};
-mod order;
-mod risk;
+use rudrist_backend::auth::{self, forget, login, signup, user_center};
+use rudrist_backend::db_lib::schema::accounts;
+use rudrist_backend::db_lib::{database, RAND};
+use rudrist_backend::{funding, instrument, ledger, market, order, portfolio, risk, transfer};
 
-use crate::db_lib::schema::accounts;
 use ::diesel::ExpressionMethods;
 use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
@@ -35,7 +31,10 @@ async fn signup_page(
     mut db_conn: Connection<database::PgDb>,
     cookies: &CookieJar<'_>,
 ) -> (Status, Value) {
-    if let Some(_) = user_center::get_logged_in_user_id(cookies, &mut db_conn).await {
+    if user_center::get_logged_in_user_id(cookies, &mut db_conn)
+        .await
+        .is_some()
+    {
         if let Some(user_id) = user_center::get_logged_in_user_id(cookies, &mut db_conn).await {
             let account_type = accounts::table
                 .select(accounts::account_type)
@@ -58,7 +57,10 @@ async fn login_page(
     mut db_conn: Connection<database::PgDb>,
     cookies: &CookieJar<'_>,
 ) -> Result<RawHtml<&'static str>, (Status, &'static str)> {
-    if let Some(_) = user_center::get_logged_in_user_id(cookies, &mut db_conn).await {
+    if user_center::get_logged_in_user_id(cookies, &mut db_conn)
+        .await
+        .is_some()
+    {
         return Err((Status::BadRequest, "Already logged in."));
     }
     return Ok(RawHtml(include_str!("../static/login.html")));
@@ -69,22 +71,28 @@ async fn user_center_page(
     mut db_conn: Connection<database::PgDb>,
     cookies: &CookieJar<'_>,
 ) -> Result<RawHtml<&'static str>, (Status, &'static str)> {
-    if let None = user_center::get_logged_in_user_id(cookies, &mut db_conn).await {
+    if user_center::get_logged_in_user_id(cookies, &mut db_conn)
+        .await
+        .is_none()
+    {
         return Err((Status::BadRequest, "Not yet logged in."));
     }
     return Ok(RawHtml(include_str!("../static/user_center.html")));
 }
 
-// #[get("/api/auth/forget")]
-// async fn forget_page(
-//     mut db_conn: Connection<database::PgDb>,
-//     cookies: &CookieJar<'_>,
-// ) -> Result<RawHtml<&'static str>, (Status, &'static str)> {
-//     if let Some(_) = user_center::get_logged_in_user_id(cookies, &mut db_conn).await {
-//         return Err((Status::BadRequest, "Already logged in."));
-//     }
-//     return Ok(RawHtml(include_str!("../static/forget.html")));
-// }
+#[get("/api/auth/forget")]
+async fn forget_page(
+    mut db_conn: Connection<database::PgDb>,
+    cookies: &CookieJar<'_>,
+) -> Result<RawHtml<&'static str>, (Status, &'static str)> {
+    if user_center::get_logged_in_user_id(cookies, &mut db_conn)
+        .await
+        .is_some()
+    {
+        return Err((Status::BadRequest, "Already logged in."));
+    }
+    return Ok(RawHtml(include_str!("../static/forget.html")));
+}
 
 /*
 #[get("/api/portfolio")]
@@ -99,33 +107,6 @@ async fn portfolio_page(
 }
 */
 
-//TO DO get(reset_page)
-/*
-#[get("/api/auth/forget/<username>/<resettoken>/<expiration_timestamp>")]
-async fn reset_page(
-    username: String,
-    resettoken: String,
-    expiration_timestamp: String,
-    mut db_conn: Connection<database::PgDb>,
-    cookies: &CookieJar<'_>,
-) -> Result<(Status, &'static str), (Status, &'static str)> {
-    let expiration_time = expiration_timestamp.parse::<DateTime<Utc>>();
-    match expiration_time {
-        Ok(expiration_time) => {
-            let current_time = Utc::now();
-            let duration = expiration_time.signed_duration_since(current_time);
-            if duration.num_minutes() > 5 {
-                return Err((Status::BadRequest, "Link has expired."));
-            }
-            return Ok((Status::Ok, "Successful"));
-        },
-        Err(_) => {
-            return Err((Status::BadRequest, "Invalid expiration timestamp format."));
-        }
-    }
-}
-*/
-
 #[get("/<file..>")]
 async fn files(file: PathBuf) -> Option<NamedFile> {
     println!("{}", Path::new("./static/").join(&file).to_str().unwrap());
@@ -154,6 +135,13 @@ async fn main() {
     rocket::build()
         .attach(database::PgDb::init())
         .attach(cors.to_cors().unwrap())
+        .attach(auth::mailer::stage())
+        .attach(forget::stage())
+        .attach(order::exchange::stage())
+        .attach(order::idempotency::stage())
+        .attach(market::stage())
+        .attach(portfolio::snapshot::stage())
+        .attach(transfer::stage())
         .manage(RAND {
             random: Arc::new(Mutex::new(rand_chacha::ChaCha8Rng::seed_from_u64(
                 rand_core::OsRng.next_u64(),
@@ -169,24 +157,31 @@ async fn main() {
             routes![
                 user_center_page,
                 user_center::reset_password,
-                user_center::logout
+                user_center::logout,
+                user_center::get_sessions,
+                user_center::revoke_session,
+                user_center::revoke_other_sessions,
+                user_center::set_account_type
             ],
         )
-        // .mount("/", routes![forget_page, forget::forget_password])
         .mount(
             "/",
             routes![
-                portfolio::create_portfolio::add_portfolio,
-                portfolio::remove_portfolio::remove_portfolio,
-                portfolio::get_portfolio::get_portfolio_names,
-                portfolio::change_portfolio::change_portfolio
+                forget_page,
+                forget::forget_password,
+                forget::check_reset_token,
+                forget::reset_forgotten_password
             ],
         )
-        .mount("/", routes![risk::get_risk_status, risk::update_risk])
-        .mount(
-            "/",
-            routes![order::route::place_order, order::route::get_order],
-        )
+        .mount("/", portfolio::routes())
+        .mount("/", risk::routes())
+        .mount("/", order::routes())
+        .mount("/", market::routes())
+        .mount("/", transfer::routes())
+        .mount("/", ledger::routes())
+        .mount("/", funding::routes())
+        .mount("/", instrument::routes())
+        .register("/", catchers![auth::permission::forbidden])
         .launch()
         .await
         .expect("Failed to launch rocket");
@@ -0,0 +1,182 @@
+use chrono::{DateTime, NaiveDateTime};
+use std::fmt;
+use std::str::FromStr;
+
+// market_candles.resolution
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interval {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+    OneDay,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 4] = [
+        Interval::OneMinute,
+        Interval::FiveMinutes,
+        Interval::OneHour,
+        Interval::OneDay,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Interval::OneMinute => "1m",
+            Interval::FiveMinutes => "5m",
+            Interval::OneHour => "1h",
+            Interval::OneDay => "1d",
+        }
+    }
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            Interval::OneMinute => 60,
+            Interval::FiveMinutes => 5 * 60,
+            Interval::OneHour => 60 * 60,
+            Interval::OneDay => 24 * 60 * 60,
+        }
+    }
+
+    // the start of the candle `time` falls in, days start at midnight UTC
+    pub fn open_time(self, time: NaiveDateTime) -> NaiveDateTime {
+        let timestamp = time.and_utc().timestamp();
+        let start = timestamp - timestamp.rem_euclid(self.seconds());
+        return DateTime::from_timestamp(start, 0)
+            .map(|start| start.naive_utc())
+            .unwrap_or(time);
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Interval::ALL
+            .into_iter()
+            .find(|interval| interval.as_str() == s)
+            .ok_or_else(|| format!("Unknown interval {}, expected 1m, 5m, 1h or 1d", s));
+    }
+}
+
+// One trade of a pair, in the integers used by orders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tick {
+    pub price: i64,
+    pub qty: i64,
+    pub time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candle {
+    pub open_time: NaiveDateTime,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: i64,
+    pub trades: i64,
+    // time of the last trade in the candle
+    pub last_trade_at: NaiveDateTime,
+}
+
+impl Candle {
+    pub fn from_tick(interval: Interval, tick: &Tick) -> Candle {
+        return Candle {
+            open_time: interval.open_time(tick.time),
+            open: tick.price,
+            high: tick.price,
+            low: tick.price,
+            close: tick.price,
+            volume: tick.qty,
+            trades: 1,
+            last_trade_at: tick.time,
+        };
+    }
+
+    // fold in a candle of the same period made of later trades
+    pub fn merge(&mut self, later: &Candle) {
+        self.high = self.high.max(later.high);
+        self.low = self.low.min(later.low);
+        self.close = later.close;
+        self.volume = self.volume.saturating_add(later.volume);
+        self.trades += later.trades;
+        self.last_trade_at = self.last_trade_at.max(later.last_trade_at);
+    }
+}
+
+// Candles of the ticks, which have to be sorted by time. Periods without a trade
+// have no candle.
+pub fn aggregate(interval: Interval, ticks: &[Tick]) -> Vec<Candle> {
+    let mut candles: Vec<Candle> = vec![];
+    for tick in ticks {
+        let candle = Candle::from_tick(interval, tick);
+        match candles.last_mut() {
+            Some(last) if last.open_time == candle.open_time => last.merge(&candle),
+            _ => candles.push(candle),
+        }
+    }
+    return candles;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        return NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap();
+    }
+
+    fn tick(price: i64, qty: i64, time: NaiveDateTime) -> Tick {
+        return Tick { price, qty, time };
+    }
+
+    #[test]
+    fn open_time_by_interval() {
+        let time = at(13, 47, 12);
+        assert_eq!(Interval::OneMinute.open_time(time), at(13, 47, 0));
+        assert_eq!(Interval::FiveMinutes.open_time(time), at(13, 45, 0));
+        assert_eq!(Interval::OneHour.open_time(time), at(13, 0, 0));
+        assert_eq!(Interval::OneDay.open_time(time), at(0, 0, 0));
+        for interval in Interval::ALL {
+            assert_eq!(interval.as_str().parse::<Interval>(), Ok(interval));
+        }
+    }
+
+    #[test]
+    fn ohlcv_from_ticks() {
+        let ticks = vec![
+            tick(100, 1, at(10, 0, 5)),
+            tick(120, 2, at(10, 0, 30)),
+            tick(90, 3, at(10, 0, 59)),
+            tick(95, 4, at(10, 3, 0)),
+        ];
+        let minutes = aggregate(Interval::OneMinute, &ticks);
+        assert_eq!(minutes.len(), 2);
+        assert_eq!(
+            (
+                minutes[0].open,
+                minutes[0].high,
+                minutes[0].low,
+                minutes[0].close
+            ),
+            (100, 120, 90, 90)
+        );
+        assert_eq!((minutes[0].volume, minutes[0].trades), (6, 3));
+        assert_eq!(minutes[1].open_time, at(10, 3, 0));
+
+        let five = aggregate(Interval::FiveMinutes, &ticks);
+        assert_eq!(five.len(), 1);
+        assert_eq!((five[0].close, five[0].volume), (95, 10));
+        assert_eq!(five[0].last_trade_at, at(10, 3, 0));
+    }
+}
@@ -0,0 +1,246 @@
+use ::diesel::upsert::excluded;
+use ::diesel::{ExpressionMethods, OptionalExtension};
+use chrono::NaiveDateTime;
+use diesel::QueryDsl;
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::{self, AsyncConnection, AsyncPgConnection};
+use std::collections::HashMap;
+
+use crate::db_lib::schema::{currencies, market_candles, positions, quotations, trading_pairs};
+use crate::market::candle::{aggregate, Candle, Interval, Tick};
+use crate::market::source::{MarketError, MarketSource};
+use crate::order::exchange::{Exchange, Symbol};
+use crate::order::paper::{fill_paper_orders, PaperExchange};
+use crate::order::trigger::trigger_orders;
+
+// (open_time, open, high, low, close, volume, trades, last_trade_at)
+pub type CandleRow = (NaiveDateTime, i64, i64, i64, i64, i64, i64, NaiveDateTime);
+
+pub fn candle_from_row(row: CandleRow) -> Candle {
+    let (open_time, open, high, low, close, volume, trades, last_trade_at) = row;
+    return Candle {
+        open_time,
+        open,
+        high,
+        low,
+        close,
+        volume,
+        trades,
+        last_trade_at,
+    };
+}
+
+// The last stored trade of the pair as (time, price), where the next poll starts
+pub async fn last_trade(
+    conn: &mut AsyncPgConnection,
+    trading_pair_id: i32,
+) -> Result<Option<(NaiveDateTime, i64)>, MarketError> {
+    let last = market_candles::table
+        .filter(market_candles::trading_pair_id.eq(trading_pair_id))
+        .filter(market_candles::resolution.eq(Interval::OneMinute.as_str()))
+        .order(market_candles::open_time.desc())
+        .select((market_candles::last_trade_at, market_candles::close))
+        .first::<(NaiveDateTime, i64)>(conn)
+        .await
+        .optional()?;
+    return Ok(last);
+}
+
+// Fold the ticks into the stored candles of every interval and quote the pair's
+// positions at the last price
+pub async fn store_ticks(
+    conn: &mut AsyncPgConnection,
+    trading_pair_id: i32,
+    ticks: &[Tick],
+) -> Result<(), MarketError> {
+    let mut ticks = ticks.to_vec();
+    ticks.sort_by_key(|tick| tick.time);
+    let last = if let Some(last) = ticks.last() {
+        last.clone()
+    } else {
+        return Ok(());
+    };
+
+    conn.transaction::<(), MarketError, _>(|conn| {
+        async move {
+            for interval in Interval::ALL {
+                for candle in aggregate(interval, &ticks) {
+                    let stored = market_candles::table
+                        .filter(market_candles::trading_pair_id.eq(trading_pair_id))
+                        .filter(market_candles::resolution.eq(interval.as_str()))
+                        .filter(market_candles::open_time.eq(candle.open_time))
+                        .select((
+                            market_candles::open_time,
+                            market_candles::open,
+                            market_candles::high,
+                            market_candles::low,
+                            market_candles::close,
+                            market_candles::volume,
+                            market_candles::trades,
+                            market_candles::last_trade_at,
+                        ))
+                        .first::<CandleRow>(conn)
+                        .await
+                        .optional()?;
+                    let candle = match stored {
+                        Some(row) => {
+                            let mut stored = candle_from_row(row);
+                            stored.merge(&candle);
+                            stored
+                        }
+                        None => candle,
+                    };
+                    diesel::insert_into(market_candles::table)
+                        .values((
+                            market_candles::trading_pair_id.eq(trading_pair_id),
+                            market_candles::resolution.eq(interval.as_str()),
+                            market_candles::open_time.eq(candle.open_time),
+                            market_candles::open.eq(candle.open),
+                            market_candles::high.eq(candle.high),
+                            market_candles::low.eq(candle.low),
+                            market_candles::close.eq(candle.close),
+                            market_candles::volume.eq(candle.volume),
+                            market_candles::trades.eq(candle.trades),
+                            market_candles::last_trade_at.eq(candle.last_trade_at),
+                        ))
+                        .on_conflict((
+                            market_candles::trading_pair_id,
+                            market_candles::resolution,
+                            market_candles::open_time,
+                        ))
+                        .do_update()
+                        .set((
+                            market_candles::high.eq(excluded(market_candles::high)),
+                            market_candles::low.eq(excluded(market_candles::low)),
+                            market_candles::close.eq(excluded(market_candles::close)),
+                            market_candles::volume.eq(excluded(market_candles::volume)),
+                            market_candles::trades.eq(excluded(market_candles::trades)),
+                            market_candles::last_trade_at
+                                .eq(excluded(market_candles::last_trade_at)),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+            }
+
+            // the latest quotation of every position on the pair
+            let quote_currency_id = trading_pairs::table
+                .filter(trading_pairs::id.eq(trading_pair_id))
+                .select(trading_pairs::quote_currency_id)
+                .first::<i32>(conn)
+                .await?;
+            let position_ids = positions::table
+                .filter(positions::trading_pair_id.eq(trading_pair_id))
+                .select(positions::id);
+            diesel::update(
+                quotations::table
+                    .filter(quotations::position_id.eq_any(position_ids))
+                    .filter(quotations::quote_currency_id.eq(quote_currency_id)),
+            )
+            .set((
+                quotations::price.eq(last.price),
+                quotations::time_stamp.eq(last.time),
+            ))
+            .execute(conn)
+            .await?;
+            return Ok(());
+        }
+        .scope_boxed()
+    })
+    .await?;
+    return Ok(());
+}
+
+// Every enabled trading pair with its symbol
+pub async fn trading_pair_symbols(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<(i32, Symbol)>, MarketError> {
+    let codes: HashMap<i32, (String, i32)> = currencies::table
+        .select((currencies::id, currencies::code, currencies::scale))
+        .load::<(i32, String, i32)>(conn)
+        .await?
+        .into_iter()
+        .map(|(id, code, scale)| (id, (code, scale)))
+        .collect();
+    // disabled pairs keep their candles but get no new ones
+    let pairs = trading_pairs::table
+        .filter(trading_pairs::enabled.eq(true))
+        .order(trading_pairs::id.asc())
+        .select((
+            trading_pairs::id,
+            trading_pairs::base_currency_id,
+            trading_pairs::quote_currency_id,
+        ))
+        .load::<(i32, i32, i32)>(conn)
+        .await?;
+    return Ok(pairs
+        .into_iter()
+        .filter_map(|(id, base_id, quote_id)| {
+            let (base, base_scale) = codes.get(&base_id)?.clone();
+            let (quote, quote_scale) = codes.get(&quote_id)?.clone();
+            Some((
+                id,
+                Symbol {
+                    base,
+                    quote,
+                    base_scale: base_scale as u32,
+                    quote_scale: quote_scale as u32,
+                },
+            ))
+        })
+        .collect());
+}
+
+// Poll the source once for every pair, fill the paper orders and release the held
+// orders its trades reach. A pair that fails is reported and skipped, the others still
+// get their trades.
+pub async fn ingest_all(
+    conn: &mut AsyncPgConnection,
+    source: &dyn MarketSource,
+    exchange: &Exchange,
+    paper: &PaperExchange,
+    paper_only: bool,
+) -> Result<Vec<(Symbol, MarketError)>, MarketError> {
+    let mut failures = vec![];
+    for (trading_pair_id, symbol) in trading_pair_symbols(conn).await? {
+        let last = last_trade(conn, trading_pair_id).await?;
+        let ticks = match source
+            .ticks(
+                &symbol,
+                last.map(|(time, _)| time),
+                last.map(|(_, price)| price),
+            )
+            .await
+        {
+            Ok(ticks) => ticks,
+            Err(err) => {
+                failures.push((symbol, err));
+                continue;
+            }
+        };
+        if let Err(err) = store_ticks(conn, trading_pair_id, &ticks).await {
+            failures.push((symbol, err));
+            continue;
+        }
+        match fill_paper_orders(conn, exchange, paper, &symbol, &ticks).await {
+            Ok(failed) => {
+                for (exchange_order_id, message) in failed {
+                    let err = MarketError(format!("order {}: {}", exchange_order_id, message));
+                    failures.push((symbol.clone(), err));
+                }
+            }
+            Err(err) => failures.push((symbol.clone(), MarketError(err.0))),
+        }
+        match trigger_orders(conn, exchange, trading_pair_id, &symbol, &ticks, paper_only).await {
+            Ok(report) => {
+                for (order_id, message) in report.failed {
+                    let err = MarketError(format!("order {}: {}", order_id, message));
+                    failures.push((symbol.clone(), err));
+                }
+            }
+            Err(err) => failures.push((symbol, MarketError(err.0))),
+        }
+    }
+    return Ok(failures);
+}
@@ -0,0 +1,184 @@
+use chrono::Utc;
+use rand_core::{OsRng, RngCore};
+use rocket::fairing::{self, AdHoc};
+use rocket::{Build, Orbit, Rocket};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db_lib::database::{self, Database};
+use crate::market::ingest::ingest_all;
+use crate::market::source::{
+    CsvReplaySource, ExchangeSource, Market, RandomWalkConfig, RandomWalkSource,
+};
+use crate::order::exchange::{AdapterKind, Exchange};
+use crate::order::paper::PaperEngine;
+use crate::order::trigger::expire_orders;
+
+pub mod candle;
+pub mod ingest;
+pub mod route;
+pub mod source;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![route::get_candles, route::get_ticker, route::get_pairs]
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    Exchange,
+    Csv,
+    RandomWalk,
+}
+
+impl SourceKind {
+    // made-up or replayed prices, never good enough for real orders
+    pub fn is_synthetic(self) -> bool {
+        return self != SourceKind::Exchange;
+    }
+}
+
+fn default_csv_batch() -> usize {
+    return 100;
+}
+
+// The [default.market] table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketConfig {
+    pub source: SourceKind,
+    // 0 turns the ingestion off, the endpoints still serve what is stored
+    pub poll_seconds: u64,
+    #[serde(default)]
+    pub csv_path: String,
+    // trades replayed per pair and poll
+    #[serde(default = "default_csv_batch")]
+    pub csv_batch: usize,
+    pub random_walk: Option<RandomWalkConfig>,
+}
+
+pub fn from_config(config: &MarketConfig, exchange: Exchange) -> Result<Market, String> {
+    match config.source {
+        SourceKind::Exchange => return Ok(Arc::new(ExchangeSource::new(exchange))),
+        SourceKind::Csv => {
+            let source = CsvReplaySource::open(&config.csv_path, config.csv_batch)
+                .map_err(|err| err.to_string())?;
+            return Ok(Arc::new(source));
+        }
+        SourceKind::RandomWalk => {
+            let walk = config
+                .random_walk
+                .clone()
+                .ok_or("the random_walk source needs a [default.market.random_walk] table")?;
+            return Ok(Arc::new(RandomWalkSource::new(walk, OsRng.next_u64())));
+        }
+    }
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract_inner::<MarketConfig>("market") {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Invalid market configuration: {}", err);
+            return Err(rocket);
+        }
+    };
+    // a live adapter only ever acts on the exchange's own prices
+    match rocket
+        .figment()
+        .extract_inner::<AdapterKind>("exchange.adapter")
+    {
+        Ok(AdapterKind::Bbgo) if config.source.is_synthetic() => {
+            println!("The bbgo adapter needs the exchange market data source");
+            return Err(rocket);
+        }
+        Ok(_) => {}
+        Err(err) => {
+            println!("Invalid exchange configuration: {}", err);
+            return Err(rocket);
+        }
+    }
+    // the exchange stage has to be attached first
+    let exchange = if let Some(exchange) = rocket.state::<Exchange>() {
+        exchange.clone()
+    } else {
+        println!("The market data needs the exchange adapter");
+        return Err(rocket);
+    };
+
+    match from_config(&config, exchange) {
+        Ok(market) => return Ok(rocket.manage(config).manage(market)),
+        Err(err) => {
+            println!("Fail to set up the market data source: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+// poll the source for every pair in the background
+async fn start_ingestion(rocket: &Rocket<Orbit>) {
+    let (config, market, exchange, paper, pool) = match (
+        rocket.state::<MarketConfig>(),
+        rocket.state::<Market>(),
+        rocket.state::<Exchange>(),
+        rocket.state::<PaperEngine>(),
+        database::PgDb::fetch(rocket),
+    ) {
+        (Some(config), Some(market), Some(exchange), Some(paper), Some(db)) => (
+            config,
+            market.clone(),
+            exchange.clone(),
+            paper.clone(),
+            (**db).clone(),
+        ),
+        _ => return,
+    };
+    if config.poll_seconds == 0 {
+        return;
+    }
+    let period = Duration::from_secs(config.poll_seconds);
+    // synthetic trades never release the held orders of live portfolios
+    let paper_only = config.source.is_synthetic();
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let mut conn = match pool.get().await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    println!("Market data: no database connection: {}", err);
+                    continue;
+                }
+            };
+            match ingest_all(&mut conn, market.as_ref(), &exchange, &paper, paper_only).await {
+                Ok(failures) => {
+                    for (symbol, err) in failures {
+                        println!("Market data: fail to ingest {}: {}", symbol, err);
+                    }
+                }
+                Err(err) => println!("Market data: {}", err),
+            }
+            // GTD orders are expired by us, not by the exchange
+            match expire_orders(&mut conn, &exchange, Utc::now().naive_utc()).await {
+                Ok(report) => {
+                    for (order_id, message) in report.failed {
+                        println!("Orders: fail to expire order {}: {}", order_id, message);
+                    }
+                }
+                Err(err) => println!("Orders: {}", err.0),
+            }
+        }
+    });
+}
+
+// manage a `Market` built from the [default.market] configuration and keep the
+// candles of every trading pair up to date
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Market data", |rocket| async {
+        rocket
+            .attach(AdHoc::try_on_ignite("Market data source", init))
+            .attach(AdHoc::on_liftoff("Market data ingestion", |rocket| {
+                Box::pin(start_ingestion(rocket))
+            }))
+    })
+}
@@ -0,0 +1,230 @@
+use ::diesel::ExpressionMethods;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel::QueryDsl;
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::Connection;
+
+use crate::db_lib::database;
+use crate::db_lib::query::{get_pair_symbol, get_trading_pair_id};
+use crate::db_lib::schema::market_candles;
+use crate::instrument::spec::pair_specs;
+use crate::market::candle::{Candle, Interval};
+use crate::market::ingest::{candle_from_row, CandleRow};
+use crate::order::exchange::Symbol;
+
+// at most this many candles per request
+const MAX_CANDLES: i64 = 1000;
+
+// unix seconds or "YYYY-MM-DDTHH:MM:SS" in UTC
+pub fn parse_time(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(seconds) = value.parse::<i64>() {
+        return DateTime::from_timestamp(seconds, 0).map(|time| time.naive_utc());
+    }
+    return NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok();
+}
+
+fn candle_json(interval: Interval, symbol: &Symbol, candle: &Candle) -> Value {
+    return json!({
+        "interval": interval.as_str(),
+        "open_time": candle.open_time.to_string(),
+        "open": symbol.format_price(candle.open),
+        "high": symbol.format_price(candle.high),
+        "low": symbol.format_price(candle.low),
+        "close": symbol.format_price(candle.close),
+        "volume": symbol.format_qty(candle.volume),
+        "trades": candle.trades,
+    });
+}
+
+// the pair's id and its symbol with the decimals of its currencies
+async fn find_pair(
+    base: &str,
+    quote: &str,
+    db_conn: &mut Connection<database::PgDb>,
+) -> Result<(i32, Symbol), (Status, Value)> {
+    let trading_pair_id = match get_trading_pair_id(db_conn, (base, quote)).await {
+        Ok((_, _, id)) => id,
+        Err(message) => {
+            return Err((
+                Status::NotFound,
+                json!({"status":"error", "message": message}),
+            ));
+        }
+    };
+    match get_pair_symbol(db_conn, trading_pair_id).await {
+        Ok(symbol) => return Ok((trading_pair_id, symbol)),
+        Err(_) => {
+            return Err((
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to fetch the trading pair"}),
+            ));
+        }
+    }
+}
+
+#[get("/api/market/<base>/<quote>/candles?<interval>&<from>&<to>")]
+pub async fn get_candles(
+    base: &str,
+    quote: &str,
+    interval: Option<&str>,
+    from: Option<&str>,
+    to: Option<&str>,
+    mut db_conn: Connection<database::PgDb>,
+) -> (Status, Value) {
+    let interval = match interval.unwrap_or("1m").parse::<Interval>() {
+        Ok(interval) => interval,
+        Err(message) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    let (from, to) = match (from.map(parse_time), to.map(parse_time)) {
+        (Some(None), _) | (_, Some(None)) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message":"Invalid time, expected unix seconds or YYYY-MM-DDTHH:MM:SS"}),
+            );
+        }
+        (from, to) => {
+            let to = to.flatten().unwrap_or_else(|| Utc::now().naive_utc());
+            // the latest candles when no start is given
+            let from = from
+                .flatten()
+                .unwrap_or(to - Duration::seconds(interval.seconds() * MAX_CANDLES));
+            (from, to)
+        }
+    };
+    let (trading_pair_id, symbol) = match find_pair(base, quote, &mut db_conn).await {
+        Ok(pair) => pair,
+        Err(err) => return err,
+    };
+
+    let fetch_candles = market_candles::table
+        .filter(market_candles::trading_pair_id.eq(trading_pair_id))
+        .filter(market_candles::resolution.eq(interval.as_str()))
+        .filter(market_candles::open_time.ge(interval.open_time(from)))
+        .filter(market_candles::open_time.le(to))
+        .order(market_candles::open_time.asc())
+        .limit(MAX_CANDLES)
+        .select((
+            market_candles::open_time,
+            market_candles::open,
+            market_candles::high,
+            market_candles::low,
+            market_candles::close,
+            market_candles::volume,
+            market_candles::trades,
+            market_candles::last_trade_at,
+        ))
+        .load::<CandleRow>(&mut db_conn)
+        .await;
+    let candles = if let Ok(candles) = fetch_candles {
+        candles
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Fail to fetch the candles"}),
+        );
+    };
+
+    let data: Vec<Value> = candles
+        .into_iter()
+        .map(|row| candle_json(interval, &symbol, &candle_from_row(row)))
+        .collect();
+    let len = data.len();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "data": data, "len": len}),
+    );
+}
+
+// The last trade and the statistics of the past 24 hours
+#[get("/api/market/<base>/<quote>/ticker")]
+pub async fn get_ticker(
+    base: &str,
+    quote: &str,
+    mut db_conn: Connection<database::PgDb>,
+) -> (Status, Value) {
+    let (trading_pair_id, symbol) = match find_pair(base, quote, &mut db_conn).await {
+        Ok(pair) => pair,
+        Err(err) => return err,
+    };
+
+    let since = Utc::now().naive_utc() - Duration::hours(24);
+    let fetch_candles = market_candles::table
+        .filter(market_candles::trading_pair_id.eq(trading_pair_id))
+        .filter(market_candles::resolution.eq(Interval::OneMinute.as_str()))
+        .filter(market_candles::open_time.ge(Interval::OneMinute.open_time(since)))
+        .order(market_candles::open_time.asc())
+        .select((
+            market_candles::open_time,
+            market_candles::open,
+            market_candles::high,
+            market_candles::low,
+            market_candles::close,
+            market_candles::volume,
+            market_candles::trades,
+            market_candles::last_trade_at,
+        ))
+        .load::<CandleRow>(&mut db_conn)
+        .await;
+    let candles: Vec<Candle> = if let Ok(candles) = fetch_candles {
+        candles.into_iter().map(candle_from_row).collect()
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Fail to fetch the ticker"}),
+        );
+    };
+
+    let (mut day, rest) = if let Some((first, rest)) = candles.split_first() {
+        (first.clone(), rest)
+    } else {
+        return (
+            Status::NotFound,
+            json!({"status":"error", "message":"No trade in the past 24 hours"}),
+        );
+    };
+    for candle in rest {
+        day.merge(candle);
+    }
+    return (
+        Status::Ok,
+        json!({
+            "status": "successful",
+            "base": base,
+            "quote": quote,
+            "price": symbol.format_price(day.close),
+            "time": day.last_trade_at.to_string(),
+            "open_24h": symbol.format_price(day.open),
+            "high_24h": symbol.format_price(day.high),
+            "low_24h": symbol.format_price(day.low),
+            "volume_24h": symbol.format_qty(day.volume),
+            "change_24h": symbol.format_price(day.close.saturating_sub(day.open)),
+            "trades_24h": day.trades,
+        }),
+    );
+}
+
+// The pairs that take orders, with their increments
+#[get("/api/market/pairs")]
+pub async fn get_pairs(mut db_conn: Connection<database::PgDb>) -> (Status, Value) {
+    let pairs = match pair_specs(&mut db_conn, false).await {
+        Ok(pairs) => pairs,
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to fetch the trading pairs"}),
+            );
+        }
+    };
+    let data: Vec<Value> = pairs
+        .into_iter()
+        .map(|(_, symbol, spec)| spec.to_json(&symbol))
+        .collect();
+    return (Status::Ok, json!({"status":"successful", "data": data}));
+}
@@ -0,0 +1,325 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::market::candle::Tick;
+use crate::order::exchange::{Exchange, Symbol};
+use crate::types::amount::{Amount, Rounding};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketError(pub String);
+
+impl fmt::Display for MarketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<rocket_db_pools::diesel::result::Error> for MarketError {
+    fn from(err: rocket_db_pools::diesel::result::Error) -> Self {
+        MarketError(err.to_string())
+    }
+}
+
+// Where the trades of a pair come from. Sources are polled, `since` is the time of
+// the last trade already stored and `last_price` its price.
+#[rocket::async_trait]
+pub trait MarketSource: Send + Sync {
+    // trades after `since`, oldest first
+    async fn ticks(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+        last_price: Option<i64>,
+    ) -> Result<Vec<Tick>, MarketError>;
+}
+
+pub type Market = Arc<dyn MarketSource>;
+
+// The public trades of the exchange adapter
+pub struct ExchangeSource {
+    exchange: Exchange,
+}
+
+impl ExchangeSource {
+    pub fn new(exchange: Exchange) -> Self {
+        return ExchangeSource { exchange };
+    }
+}
+
+#[rocket::async_trait]
+impl MarketSource for ExchangeSource {
+    async fn ticks(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+        _last_price: Option<i64>,
+    ) -> Result<Vec<Tick>, MarketError> {
+        let trades = self
+            .exchange
+            .recent_trades(symbol, since)
+            .await
+            .map_err(|err| MarketError(err.to_string()))?;
+        return Ok(trades
+            .into_iter()
+            .map(|trade| Tick {
+                price: trade.price,
+                qty: trade.quantity,
+                time: trade.time,
+            })
+            .collect());
+    }
+}
+
+// a recorded trade, the decimals are only known once the pair is asked for
+#[derive(Debug, Clone)]
+struct CsvTrade {
+    time: NaiveDateTime,
+    price: Amount,
+    qty: Amount,
+}
+
+// Replays recorded trades from a csv file with the columns time,base,quote,price,qty.
+// The time is either "YYYY-MM-DD HH:MM:SS" in UTC or unix milliseconds, prices and
+// quantities are decimals, a header line is allowed. Every poll hands out at most
+// `batch` trades per pair.
+pub struct CsvReplaySource {
+    trades: HashMap<String, Vec<CsvTrade>>,
+    batch: usize,
+}
+
+fn parse_time(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(millis) = value.parse::<i64>() {
+        return DateTime::from_timestamp_millis(millis).map(|time| time.naive_utc());
+    }
+    return NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").ok();
+}
+
+impl CsvReplaySource {
+    pub fn from_csv(content: &str, batch: usize) -> Result<Self, MarketError> {
+        let mut trades: HashMap<String, Vec<CsvTrade>> = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || (index == 0 && line.starts_with("time")) {
+                continue;
+            }
+            let columns: Vec<&str> = line.split(',').map(|column| column.trim()).collect();
+            let invalid = || MarketError(format!("Invalid csv line {}: {}", index + 1, line));
+            if columns.len() != 5 {
+                return Err(invalid());
+            }
+            let trade = CsvTrade {
+                time: parse_time(columns[0]).ok_or_else(invalid)?,
+                price: columns[3].parse::<Amount>().map_err(|_| invalid())?,
+                qty: columns[4].parse::<Amount>().map_err(|_| invalid())?,
+            };
+            trades
+                .entry(format!("{}{}", columns[1], columns[2]))
+                .or_default()
+                .push(trade);
+        }
+        for trades in trades.values_mut() {
+            trades.sort_by_key(|trade| trade.time);
+        }
+        return Ok(CsvReplaySource {
+            trades,
+            batch: batch.max(1),
+        });
+    }
+
+    pub fn open(path: &str, batch: usize) -> Result<Self, MarketError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| MarketError(format!("Fail to read {}: {}", path, err)))?;
+        return CsvReplaySource::from_csv(&content, batch);
+    }
+}
+
+#[rocket::async_trait]
+impl MarketSource for CsvReplaySource {
+    async fn ticks(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+        _last_price: Option<i64>,
+    ) -> Result<Vec<Tick>, MarketError> {
+        let trades = if let Some(trades) = self.trades.get(&symbol.to_string()) {
+            trades
+        } else {
+            return Ok(vec![]);
+        };
+        // recorded with more decimals than the pair has, the nearest unit is taken
+        let mut ticks = vec![];
+        for trade in trades
+            .iter()
+            .filter(|trade| since.is_none_or(|since| trade.time > since))
+            .take(self.batch)
+        {
+            match (
+                trade.price.rescale(symbol.quote_scale, Rounding::Nearest),
+                trade.qty.rescale(symbol.base_scale, Rounding::Nearest),
+            ) {
+                (Some(price), Some(qty)) => ticks.push(Tick {
+                    price,
+                    qty,
+                    time: trade.time,
+                }),
+                _ => {
+                    return Err(MarketError(format!(
+                        "The trade of {} at {} is too large",
+                        symbol, trade.time
+                    )))
+                }
+            }
+        }
+        return Ok(ticks);
+    }
+}
+
+// Settings of the synthetic source, see [default.market] in Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct RandomWalkConfig {
+    // the first price of a pair without any trade yet, a decimal in the quote currency
+    pub start_price: Amount,
+    // largest move between two trades, in basis points of the price
+    pub step_bps: i64,
+    // a decimal in the base currency
+    pub max_qty: Amount,
+    pub ticks_per_poll: usize,
+}
+
+// Made up trades for local development: each trade moves the price by a random
+// step from the last one
+pub struct RandomWalkSource {
+    config: RandomWalkConfig,
+    rng: Mutex<ChaCha8Rng>,
+}
+
+impl RandomWalkSource {
+    pub fn new(config: RandomWalkConfig, seed: u64) -> Self {
+        return RandomWalkSource {
+            config,
+            rng: Mutex::new(ChaCha8Rng::seed_from_u64(seed)),
+        };
+    }
+
+    pub fn walk(
+        &self,
+        symbol: &Symbol,
+        last_price: Option<i64>,
+        since: Option<NaiveDateTime>,
+        now: NaiveDateTime,
+    ) -> Vec<Tick> {
+        let mut rng = self.rng.lock().unwrap();
+        let start_price = self
+            .config
+            .start_price
+            .rescale(symbol.quote_scale, Rounding::Nearest)
+            .unwrap_or(i64::MAX);
+        let max_qty = self
+            .config
+            .max_qty
+            .rescale(symbol.base_scale, Rounding::Nearest)
+            .unwrap_or(i64::MAX);
+        let mut price = last_price.unwrap_or(start_price).max(1);
+        let count = self.config.ticks_per_poll.max(1);
+        // spread the trades over the time since the last poll
+        let start = since.filter(|since| *since < now).unwrap_or(now);
+        let span = (now - start).num_milliseconds().max(0);
+        let mut ticks = vec![];
+        for index in 1..=count {
+            let max_step = (price as i128 * self.config.step_bps as i128 / 10_000) as i64;
+            let step = if max_step > 0 {
+                rng.gen_range(-max_step..=max_step)
+            } else {
+                rng.gen_range(-1..=1)
+            };
+            price = price.saturating_add(step).max(1);
+            let offset = span * index as i64 / count as i64;
+            ticks.push(Tick {
+                price,
+                qty: rng.gen_range(1..=max_qty.max(1)),
+                time: start + chrono::Duration::milliseconds(offset),
+            });
+        }
+        return ticks;
+    }
+}
+
+#[rocket::async_trait]
+impl MarketSource for RandomWalkSource {
+    async fn ticks(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+        last_price: Option<i64>,
+    ) -> Result<Vec<Tick>, MarketError> {
+        return Ok(self.walk(symbol, last_price, since, Utc::now().naive_utc()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(base: &str) -> Symbol {
+        return Symbol {
+            base: base.to_string(),
+            quote: "USDT".to_string(),
+            base_scale: 8,
+            quote_scale: 6,
+        };
+    }
+
+    #[rocket::async_test]
+    async fn csv_replay_in_batches() {
+        let csv = "time,base,quote,price,qty\n\
+                   2026-10-18 10:00:02,BTC,USDT,101,1\n\
+                   2026-10-18 10:00:01,BTC,USDT,100,2\n\
+                   1792317603000,ETH,USDT,50.5,0.03\n\
+                   2026-10-18 10:00:03,BTC,USDT,102,1\n";
+        let source = CsvReplaySource::from_csv(csv, 2).unwrap();
+        let first = source.ticks(&symbol("BTC"), None, None).await.unwrap();
+        assert_eq!(
+            first.iter().map(|tick| tick.price).collect::<Vec<_>>(),
+            vec![100_000_000, 101_000_000]
+        );
+        let rest = source
+            .ticks(&symbol("BTC"), Some(first[1].time), None)
+            .await
+            .unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].price, 102_000_000);
+        let eth = &source.ticks(&symbol("ETH"), None, None).await.unwrap()[0];
+        assert_eq!((eth.price, eth.qty), (50_500_000, 3_000_000));
+        assert!(CsvReplaySource::from_csv("yesterday,BTC,USDT,1,1", 1).is_err());
+    }
+
+    #[test]
+    fn random_walk_stays_within_steps() {
+        let source = RandomWalkSource::new(
+            RandomWalkConfig {
+                start_price: Amount::new(10_000, 0),
+                step_bps: 100,
+                max_qty: Amount::new(5, 8),
+                ticks_per_poll: 20,
+            },
+            7,
+        );
+        let now = Utc::now().naive_utc();
+        let since = now - chrono::Duration::seconds(10);
+        let ticks = source.walk(&symbol("BTC"), None, Some(since), now);
+        assert_eq!(ticks.len(), 20);
+        let mut last = 10_000_000_000;
+        for tick in &ticks {
+            assert!((tick.price - last).abs() <= last / 100);
+            assert!(tick.qty >= 1 && tick.qty <= 5);
+            assert!(tick.time > since && tick.time <= now);
+            last = tick.price;
+        }
+        assert_eq!(ticks.last().unwrap().time, now);
+    }
+}
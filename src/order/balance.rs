@@ -0,0 +1,402 @@
+use ::diesel::ExpressionMethods;
+use diesel::query_dsl::methods::{FilterDsl, OrderDsl, SelectDsl};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::{self, AsyncPgConnection};
+
+use crate::db_lib::schema::{currencies, orders, portfolio_balance, portfolios, trading_pairs};
+use crate::ledger::journal::{record, EntryKind, Journal, LedgerAccount};
+use crate::order::exchange::{ExchangeFill, Side};
+use crate::types::amount::{notional, Rounding};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceError {
+    Insufficient {
+        currency_id: i32,
+        required: i64,
+        available: i64,
+    },
+    Overflow,
+    // a journal whose entries do not sum to zero in every currency
+    Unbalanced,
+    Database,
+}
+
+impl BalanceError {
+    pub fn message(&self) -> String {
+        match self {
+            BalanceError::Insufficient {
+                required,
+                available,
+                ..
+            } => format!(
+                "Insufficient available balance: the order needs {} but only {} is available",
+                required, available
+            ),
+            BalanceError::Overflow => "The order amount is too large".to_string(),
+            BalanceError::Unbalanced => "The ledger entries do not balance".to_string(),
+            BalanceError::Database => "Fail to update the balance".to_string(),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for BalanceError {
+    fn from(_: diesel::result::Error) -> Self {
+        BalanceError::Database
+    }
+}
+
+// What an open order keeps aside: the quote amount of a buy at its limit price,
+// rounded up so the order is always covered, or the base quantity of a sell
+pub fn reservation_for(side: Side, price: i64, remaining_qty: i64, base_scale: u32) -> Option<i64> {
+    if remaining_qty <= 0 {
+        return Some(0);
+    }
+    match side {
+        Side::Buy => return notional(price, remaining_qty, base_scale, Rounding::Up),
+        Side::Sell => return Some(remaining_qty),
+    }
+}
+
+// The order's side, its portfolio and the currencies it moves
+struct OrderFunds {
+    portfolio_id: i32,
+    trader_account_id: i32,
+    paper: bool,
+    side: Side,
+    price: i64,
+    reserved: i64,
+    base_currency_id: i32,
+    quote_currency_id: i32,
+    base_scale: u32,
+}
+
+impl OrderFunds {
+    fn reserved_currency_id(&self) -> i32 {
+        match self.side {
+            Side::Buy => return self.quote_currency_id,
+            Side::Sell => return self.base_currency_id,
+        }
+    }
+}
+
+async fn order_funds(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+) -> Result<OrderFunds, BalanceError> {
+    let (portfolio_id, trading_pair_id, buyin, price, reserved) = orders::table
+        .filter(orders::id.eq(order_id))
+        .select((
+            orders::portfolio_id,
+            orders::trading_pair_id,
+            orders::buyin,
+            orders::price,
+            orders::reserved,
+        ))
+        .first::<(i32, i32, bool, i64, i64)>(conn)
+        .await?;
+    let (base_currency_id, quote_currency_id) = trading_pairs::table
+        .filter(trading_pairs::id.eq(trading_pair_id))
+        .select((
+            trading_pairs::base_currency_id,
+            trading_pairs::quote_currency_id,
+        ))
+        .first::<(i32, i32)>(conn)
+        .await?;
+    let base_scale = currencies::table
+        .filter(currencies::id.eq(base_currency_id))
+        .select(currencies::scale)
+        .first::<i32>(conn)
+        .await?;
+    let (trader_account_id, paper) = portfolios::table
+        .filter(portfolios::id.eq(portfolio_id))
+        .select((portfolios::trader_account_id, portfolios::paper))
+        .first::<(i32, bool)>(conn)
+        .await?;
+    return Ok(OrderFunds {
+        portfolio_id,
+        trader_account_id,
+        paper,
+        side: if buyin { Side::Buy } else { Side::Sell },
+        price,
+        reserved,
+        base_currency_id,
+        quote_currency_id,
+        base_scale: base_scale as u32,
+    });
+}
+
+pub async fn available_balance(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    currency_id: i32,
+) -> Result<i64, BalanceError> {
+    let balances = portfolio_balance::table
+        .filter(portfolio_balance::portfolio_id.eq(portfolio_id))
+        .filter(portfolio_balance::currency_id.eq(currency_id))
+        .select((portfolio_balance::quantity, portfolio_balance::reserved))
+        .load::<(i64, i64)>(conn)
+        .await?;
+    return Ok(balances
+        .iter()
+        .map(|(quantity, reserved)| quantity - reserved)
+        .sum());
+}
+
+// Add to a balance, creating the row on its first use. The balances are a projection
+// of the ledger, only ledger::record calls this.
+pub async fn credit(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    currency_id: i32,
+    amount: i64,
+) -> Result<(), BalanceError> {
+    let updated = diesel::update(
+        portfolio_balance::table
+            .filter(portfolio_balance::portfolio_id.eq(portfolio_id))
+            .filter(portfolio_balance::currency_id.eq(currency_id)),
+    )
+    .set(portfolio_balance::quantity.eq(portfolio_balance::quantity + amount))
+    .execute(conn)
+    .await?;
+    if updated == 0 {
+        diesel::insert_into(portfolio_balance::table)
+            .values((
+                portfolio_balance::portfolio_id.eq(portfolio_id),
+                portfolio_balance::currency_id.eq(currency_id),
+                portfolio_balance::quantity.eq(amount),
+            ))
+            .execute(conn)
+            .await?;
+    }
+    return Ok(());
+}
+
+// Take from the available balance, fails without touching it when there is not
+// enough. Only ledger::record calls this.
+pub async fn debit(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    currency_id: i32,
+    amount: i64,
+) -> Result<(), BalanceError> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let updated = diesel::update(
+        portfolio_balance::table
+            .filter(portfolio_balance::portfolio_id.eq(portfolio_id))
+            .filter(portfolio_balance::currency_id.eq(currency_id))
+            .filter((portfolio_balance::quantity - portfolio_balance::reserved).ge(amount)),
+    )
+    .set(portfolio_balance::quantity.eq(portfolio_balance::quantity - amount))
+    .execute(conn)
+    .await?;
+    if updated == 0 {
+        return Err(BalanceError::Insufficient {
+            currency_id,
+            required: amount,
+            available: available_balance(conn, portfolio_id, currency_id).await?,
+        });
+    }
+    return Ok(());
+}
+
+// The account's own misc portfolio. There is no house portfolio, an account without
+// one leaves the fee with the other side (the exchange, or outside for transfers).
+pub async fn fee_portfolio(
+    conn: &mut AsyncPgConnection,
+    trader_account_id: i32,
+) -> Result<Option<i32>, BalanceError> {
+    let misc = portfolios::table
+        .filter(portfolios::portfolio_type.eq(1))
+        .filter(portfolios::trader_account_id.eq(trader_account_id))
+        .order(portfolios::id.asc())
+        .select(portfolios::id)
+        .load::<i32>(conn)
+        .await?;
+    return Ok(misc.into_iter().next());
+}
+
+// Where a fee goes and how it is booked: the misc portfolio, or `fallback` without one.
+// The fees of a paper portfolio are simulated money, they leave the books instead of
+// reaching a live balance.
+pub fn fee_collector(
+    paper: bool,
+    misc_id: Option<i32>,
+    fallback: LedgerAccount,
+) -> (EntryKind, LedgerAccount) {
+    match (paper, misc_id) {
+        (true, _) => return (EntryKind::Paper, LedgerAccount::External),
+        (false, Some(misc_id)) => return (EntryKind::Fee, LedgerAccount::Portfolio(misc_id)),
+        (false, None) => return (EntryKind::Fee, fallback),
+    }
+}
+
+// the account's main portfolio, where its funds come from and go back to
+pub async fn main_portfolio(
+    conn: &mut AsyncPgConnection,
+    trader_account_id: i32,
+) -> Result<Option<i32>, BalanceError> {
+    let main = portfolios::table
+        .filter(portfolios::portfolio_type.eq(0))
+        .filter(portfolios::trader_account_id.eq(trader_account_id))
+        .order(portfolios::id.asc())
+        .select(portfolios::id)
+        .load::<i32>(conn)
+        .await?;
+    return Ok(main.into_iter().next());
+}
+
+// Move the order's reservation to `target`. Growing it takes from the available
+// balance and fails when there is not enough, shrinking it gives back.
+pub async fn set_reservation(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    target: i64,
+) -> Result<(), BalanceError> {
+    let funds = order_funds(conn, order_id).await?;
+    let delta = target - funds.reserved;
+    if delta == 0 {
+        return Ok(());
+    }
+    let currency_id = funds.reserved_currency_id();
+    let balance = portfolio_balance::table
+        .filter(portfolio_balance::portfolio_id.eq(funds.portfolio_id))
+        .filter(portfolio_balance::currency_id.eq(currency_id));
+
+    // the availability check and the update are one statement, so concurrent
+    // orders cannot both take the same funds
+    let updated = if delta > 0 {
+        diesel::update(
+            balance.filter((portfolio_balance::quantity - portfolio_balance::reserved).ge(delta)),
+        )
+        .set(portfolio_balance::reserved.eq(portfolio_balance::reserved + delta))
+        .execute(conn)
+        .await?
+    } else {
+        diesel::update(balance.filter(portfolio_balance::reserved.ge(-delta)))
+            .set(portfolio_balance::reserved.eq(portfolio_balance::reserved + delta))
+            .execute(conn)
+            .await?
+    };
+    // giving back never fails, a missing balance row has nothing to release
+    if updated == 0 && delta > 0 {
+        return Err(BalanceError::Insufficient {
+            currency_id,
+            required: delta,
+            available: available_balance(conn, funds.portfolio_id, currency_id).await?,
+        });
+    }
+
+    diesel::update(orders::table.filter(orders::id.eq(order_id)))
+        .set(orders::reserved.eq(target))
+        .execute(conn)
+        .await?;
+    return Ok(());
+}
+
+pub async fn release_reservation(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+) -> Result<(), BalanceError> {
+    return set_reservation(conn, order_id, 0).await;
+}
+
+// Book one execution: the traded amounts move between the base and quote balances,
+// the part of the reservation it used is consumed and the fee goes to the misc portfolio.
+// A fill already happened on the exchange, so it is booked even if it overdraws.
+// `fill_id` is the order_fills row the ledger entries point at.
+pub async fn settle_fill(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    fill_id: Option<i32>,
+    fill: &ExchangeFill,
+) -> Result<(), BalanceError> {
+    let funds = order_funds(conn, order_id).await?;
+    // a buy pays the part of a quote unit it touches, a sell only receives whole ones
+    let rounding = match funds.side {
+        Side::Buy => Rounding::Up,
+        Side::Sell => Rounding::Down,
+    };
+    let notional = notional(fill.price, fill.quantity, funds.base_scale, rounding)
+        .ok_or(BalanceError::Overflow)?;
+    let consumed = reservation_for(funds.side, funds.price, fill.quantity, funds.base_scale)
+        .ok_or(BalanceError::Overflow)?
+        .min(funds.reserved);
+
+    // the exchange is the other side of the trade, the fee goes to the misc portfolio
+    let portfolio = LedgerAccount::Portfolio(funds.portfolio_id);
+    let (base_from, base_to) = match funds.side {
+        Side::Buy => (LedgerAccount::Exchange, portfolio),
+        Side::Sell => (portfolio, LedgerAccount::Exchange),
+    };
+    let trade = Journal::new(EntryKind::Trade)
+        .order(order_id, fill_id)
+        .move_funds(base_from, base_to, funds.base_currency_id, fill.quantity)
+        .move_funds(base_to, base_from, funds.quote_currency_id, notional);
+    record(conn, &trade).await?;
+    if fill.fee != 0 {
+        let misc_id = if funds.paper {
+            None
+        } else {
+            fee_portfolio(conn, funds.trader_account_id).await?
+        };
+        let (kind, collector) = fee_collector(funds.paper, misc_id, LedgerAccount::Exchange);
+        let fee = Journal::new(kind).order(order_id, fill_id).move_funds(
+            portfolio,
+            collector,
+            funds.quote_currency_id,
+            fill.fee,
+        );
+        record(conn, &fee).await?;
+    }
+
+    if consumed > 0 {
+        diesel::update(
+            portfolio_balance::table
+                .filter(portfolio_balance::portfolio_id.eq(funds.portfolio_id))
+                .filter(portfolio_balance::currency_id.eq(funds.reserved_currency_id())),
+        )
+        .set(portfolio_balance::reserved.eq(portfolio_balance::reserved - consumed))
+        .execute(conn)
+        .await?;
+        diesel::update(orders::table.filter(orders::id.eq(order_id)))
+            .set(orders::reserved.eq(orders::reserved - consumed))
+            .execute(conn)
+            .await?;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservation_by_side() {
+        assert_eq!(reservation_for(Side::Buy, 1_000, 3, 0), Some(3_000));
+        assert_eq!(reservation_for(Side::Sell, 1_000, 3, 0), Some(3));
+        assert_eq!(reservation_for(Side::Buy, 1_000, 0, 0), Some(0));
+        assert_eq!(reservation_for(Side::Buy, i64::MAX, 2, 0), None);
+        // 0.00000003 BTC at 30000.000000 USDT rounds up to 0.000900 USDT
+        assert_eq!(reservation_for(Side::Buy, 30_000_000_000, 3, 8), Some(900));
+        assert_eq!(reservation_for(Side::Buy, 10_000_000, 1, 8), Some(1));
+    }
+
+    #[test]
+    fn paper_fees_never_reach_a_live_portfolio() {
+        assert_eq!(
+            fee_collector(false, Some(4), LedgerAccount::Exchange),
+            (EntryKind::Fee, LedgerAccount::Portfolio(4))
+        );
+        assert_eq!(
+            fee_collector(false, None, LedgerAccount::Exchange),
+            (EntryKind::Fee, LedgerAccount::Exchange)
+        );
+        assert_eq!(
+            fee_collector(true, Some(4), LedgerAccount::Exchange),
+            (EntryKind::Paper, LedgerAccount::External)
+        );
+    }
+}
@@ -1,13 +1,356 @@
-use rand::{thread_rng, Rng};
-#[allow(dead_code)]
-pub fn handle_order(
-    _base: &String,
-    _quote: &String,
-    _order_type: &String,
-    _price: &String,
-    _quantity: &String,
-) -> i32 {
-    let mut rng = thread_rng();
-
-    return rng.gen_range(0..1000000);
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::order::exchange::{
+    ExchangeAdapter, ExchangeError, ExchangeFill, ExchangeOrder, ExchangeOrderRequest,
+    ExchangeOrderStatus, ExchangeOrderType, MarketTrade, Side, Symbol,
+};
+use crate::order::order_type::TimeInForce;
+use crate::types::amount::{Amount, Rounding};
+
+// Talks to BBGO's TradingService (SubmitOrder, CancelOrder, QueryOrder, QueryTrades)
+// through its JSON/HTTP gateway. Prices and quantities are sent as decimal strings.
+pub struct BbgoExchange {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl BbgoExchange {
+    // a call that takes longer than `timeout` gives up, so a stuck gateway cannot hold
+    // the handlers or the market data loop
+    pub fn new(base_url: &str, timeout: Duration) -> Result<Self, &'static str> {
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err("bbgo_url should be an http(s) url");
+        }
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|_| "Fail to build the http client")?;
+        return Ok(BbgoExchange {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client,
+        });
+    }
+
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, ExchangeError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|err| ExchangeError::Unavailable(err.to_string()))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(ExchangeError::NotFound);
+        }
+        if status.is_client_error() {
+            let reason = response.text().await.unwrap_or_default();
+            return Err(ExchangeError::Rejected(reason));
+        }
+        if !status.is_success() {
+            return Err(ExchangeError::Unavailable(status.to_string()));
+        }
+        return response
+            .json::<T>()
+            .await
+            .map_err(|err| ExchangeError::Unavailable(err.to_string()));
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BbgoSubmitOrder<'a> {
+    symbol: String,
+    side: &'a str,
+    order_type: &'a str,
+    // market orders go without a price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<String>,
+    quantity: String,
+    time_in_force: &'a str,
+    client_order_id: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BbgoCancelOrder<'a> {
+    symbol: String,
+    id: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BbgoOrder {
+    id: String,
+    status: String,
+    #[serde(default)]
+    executed_quantity: String,
+}
+
+#[derive(Deserialize)]
+struct BbgoOrderResponse {
+    order: BbgoOrder,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BbgoTrade {
+    id: String,
+    price: String,
+    quantity: String,
+    #[serde(default)]
+    fee: String,
+    // milliseconds
+    created_at: i64,
+}
+
+#[derive(Deserialize)]
+struct BbgoTradesResponse {
+    #[serde(default)]
+    trades: Vec<BbgoTrade>,
+}
+
+// BBGO sends decimals, they are taken to the nearest unit of the currency
+fn parse_amount(value: &str, scale: u32) -> Result<i64, ExchangeError> {
+    if value.is_empty() {
+        return Ok(0);
+    }
+    return value
+        .parse::<Amount>()
+        .ok()
+        .and_then(|amount| amount.rescale(scale, Rounding::Nearest))
+        .ok_or_else(|| ExchangeError::Unavailable(format!("unexpected amount {}", value)));
+}
+
+fn into_exchange_order(symbol: &Symbol, order: BbgoOrder) -> Result<ExchangeOrder, ExchangeError> {
+    let status = match order.status.as_str() {
+        "NEW" => ExchangeOrderStatus::New,
+        "PARTIALLY_FILLED" => ExchangeOrderStatus::PartiallyFilled,
+        "FILLED" => ExchangeOrderStatus::Filled,
+        "CANCELED" | "CANCELLED" => ExchangeOrderStatus::Cancelled,
+        "EXPIRED" => ExchangeOrderStatus::Expired,
+        // the order reached the exchange and was turned down there
+        "REJECTED" => ExchangeOrderStatus::Rejected,
+        other => {
+            return Err(ExchangeError::Unavailable(format!(
+                "unknown order status {}",
+                other
+            )))
+        }
+    };
+    return Ok(ExchangeOrder {
+        exchange_order_id: order.id,
+        status,
+        filled_quantity: parse_amount(&order.executed_quantity, symbol.base_scale)?,
+    });
+}
+
+#[rocket::async_trait]
+impl ExchangeAdapter for BbgoExchange {
+    async fn place_order(
+        &self,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let body = BbgoSubmitOrder {
+            symbol: request.symbol.to_string(),
+            side: match request.side {
+                Side::Buy => "BUY",
+                Side::Sell => "SELL",
+            },
+            order_type: match request.order_type {
+                ExchangeOrderType::Market => "MARKET",
+                ExchangeOrderType::Limit => "LIMIT",
+            },
+            price: match request.order_type {
+                ExchangeOrderType::Market => None,
+                ExchangeOrderType::Limit => Some(request.symbol.format_price(request.price)),
+            },
+            quantity: request.symbol.format_qty(request.quantity),
+            // BBGO has no GTD, those orders are expired on our side
+            time_in_force: match request.time_in_force {
+                TimeInForce::Gtc | TimeInForce::Gtd => "GTC",
+                TimeInForce::Ioc => "IOC",
+                TimeInForce::Fok => "FOK",
+            },
+            client_order_id: &request.client_order_id,
+        };
+        let response: BbgoOrderResponse = self
+            .send(
+                self.client
+                    .post(format!("{}/v1/trading/orders", self.base_url))
+                    .json(&body),
+            )
+            .await?;
+        return into_exchange_order(&request.symbol, response.order);
+    }
+
+    async fn cancel_order(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let body = BbgoCancelOrder {
+            symbol: symbol.to_string(),
+            id: exchange_order_id,
+        };
+        let response: BbgoOrderResponse = self
+            .send(
+                self.client
+                    .post(format!("{}/v1/trading/orders/cancel", self.base_url))
+                    .json(&body),
+            )
+            .await?;
+        return into_exchange_order(symbol, response.order);
+    }
+
+    // BBGO has no order edit, the order is cancelled and what remains is submitted again.
+    // A replacement that fails leaves the order cancelled, which is reported with it.
+    async fn amend_order(
+        &self,
+        exchange_order_id: &str,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let cancelled = self
+            .cancel_order(&request.symbol, exchange_order_id)
+            .await?;
+        let remaining = request.quantity - cancelled.filled_quantity;
+        if remaining <= 0 {
+            return Ok(cancelled);
+        }
+        let replacement = ExchangeOrderRequest {
+            client_order_id: format!("{}-{}", request.client_order_id, exchange_order_id),
+            quantity: remaining,
+            ..request.clone()
+        };
+        match self.place_order(&replacement).await {
+            Ok(mut order) => {
+                order.filled_quantity += cancelled.filled_quantity;
+                return Ok(order);
+            }
+            Err(err) => return Err(ExchangeError::ReplaceFailed(cancelled, err.to_string())),
+        }
+    }
+
+    async fn query_order(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let response: BbgoOrderResponse = self
+            .send(
+                self.client
+                    .get(format!(
+                        "{}/v1/trading/orders/{}",
+                        self.base_url, exchange_order_id
+                    ))
+                    .query(&[("symbol", symbol.to_string())]),
+            )
+            .await?;
+        return into_exchange_order(symbol, response.order);
+    }
+
+    async fn list_fills(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<Vec<ExchangeFill>, ExchangeError> {
+        let response: BbgoTradesResponse = self
+            .send(
+                self.client
+                    .get(format!("{}/v1/trading/trades", self.base_url))
+                    .query(&[
+                        ("symbol", symbol.to_string()),
+                        ("orderId", exchange_order_id.to_string()),
+                    ]),
+            )
+            .await?;
+
+        let mut fills = vec![];
+        for trade in response.trades {
+            let time = DateTime::from_timestamp_millis(trade.created_at)
+                .ok_or_else(|| ExchangeError::Unavailable("invalid trade time".to_string()))?
+                .naive_utc();
+            fills.push(ExchangeFill {
+                fill_id: trade.id,
+                exchange_order_id: exchange_order_id.to_string(),
+                price: parse_amount(&trade.price, symbol.quote_scale)?,
+                quantity: parse_amount(&trade.quantity, symbol.base_scale)?,
+                fee: parse_amount(&trade.fee, symbol.quote_scale)?,
+                time,
+            });
+        }
+        return Ok(fills);
+    }
+
+    async fn recent_trades(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<MarketTrade>, ExchangeError> {
+        let mut query = vec![("symbol", symbol.to_string())];
+        if let Some(since) = since {
+            query.push(("startTime", since.and_utc().timestamp_millis().to_string()));
+        }
+        let response: BbgoTradesResponse = self
+            .send(
+                self.client
+                    .get(format!("{}/v1/marketdata/trades", self.base_url))
+                    .query(&query),
+            )
+            .await?;
+
+        let mut trades = vec![];
+        for trade in response.trades {
+            let time = DateTime::from_timestamp_millis(trade.created_at)
+                .ok_or_else(|| ExchangeError::Unavailable("invalid trade time".to_string()))?
+                .naive_utc();
+            // startTime is inclusive on BBGO's side
+            if since.is_some_and(|since| time <= since) {
+                continue;
+            }
+            trades.push(MarketTrade {
+                trade_id: trade.id,
+                price: parse_amount(&trade.price, symbol.quote_scale)?,
+                quantity: parse_amount(&trade.quantity, symbol.base_scale)?,
+                time,
+            });
+        }
+        trades.sort_by_key(|trade| trade.time);
+        return Ok(trades);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejected_orders_are_a_status() {
+        let symbol = Symbol {
+            base: "BTC".to_string(),
+            quote: "USDT".to_string(),
+            base_scale: 8,
+            quote_scale: 6,
+        };
+        let order = BbgoOrder {
+            id: "42".to_string(),
+            status: "REJECTED".to_string(),
+            executed_quantity: String::new(),
+        };
+        let order = into_exchange_order(&symbol, order).unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Rejected);
+        assert_eq!(order.filled_quantity, 0);
+
+        let unknown = BbgoOrder {
+            id: "42".to_string(),
+            status: "PENDING".to_string(),
+            executed_quantity: "0.5".to_string(),
+        };
+        assert!(matches!(
+            into_exchange_order(&symbol, unknown),
+            Err(ExchangeError::Unavailable(_))
+        ));
+    }
 }
@@ -0,0 +1,298 @@
+use chrono::NaiveDateTime;
+use rocket::fairing::{self, AdHoc};
+use rocket::{Build, Orbit, Rocket};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::db_lib::database::{self, Database};
+use crate::order::bbgo::BbgoExchange;
+use crate::order::mock::MockExchange;
+use crate::order::order_type::TimeInForce;
+use crate::order::paper::{
+    close_lost_orders, PaperConfig, PaperEngine, PaperExchange, RoutedExchange,
+};
+use crate::types::amount::{format_units, notional, Amount, AmountError, Rounding};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+// A pair with the decimals of its two currencies. Prices are in quote units for one
+// whole base, quantities in base units.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub base: String,
+    pub quote: String,
+    pub base_scale: u32,
+    pub quote_scale: u32,
+}
+
+impl Symbol {
+    pub fn format_price(&self, price: i64) -> String {
+        return format_units(price as i128, self.quote_scale);
+    }
+
+    pub fn format_qty(&self, qty: i64) -> String {
+        return format_units(qty as i128, self.base_scale);
+    }
+
+    pub fn parse_price(&self, price: &str) -> Result<i64, AmountError> {
+        return Amount::parse(price, self.quote_scale).map(|price| price.units);
+    }
+
+    pub fn parse_qty(&self, qty: &str) -> Result<i64, AmountError> {
+        return Amount::parse(qty, self.base_scale).map(|qty| qty.units);
+    }
+
+    // what `qty` comes to at `price`, in quote units
+    pub fn notional(&self, price: i64, qty: i64, rounding: Rounding) -> Option<i64> {
+        return notional(price, qty, self.base_scale, rounding);
+    }
+
+    // `fee_bps` basis points of the notional in quote units, rounded down
+    // None when it does not fit, the fill has to be rejected then
+    pub fn fee(&self, price: i64, qty: i64, fee_bps: i64) -> Option<i64> {
+        let notional = self.notional(price, qty, Rounding::Down)?;
+        return i64::try_from(notional as i128 * fee_bps as i128 / 10_000).ok();
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.base, self.quote)
+    }
+}
+
+// Stop orders are held on our side, the exchange only ever sees these two
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExchangeOrderType {
+    Market,
+    Limit,
+}
+
+// What we ask the exchange to do, prices and quantities are the scaled integers stored
+// in orders
+#[derive(Debug, Clone)]
+pub struct ExchangeOrderRequest {
+    // orders.id, lets the exchange side be matched back to our row
+    pub client_order_id: String,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub order_type: ExchangeOrderType,
+    // the limit price, for market orders the last trade price as a reference
+    pub price: i64,
+    pub quantity: i64,
+    // GTD is expired by us, the exchange gets it as GTC
+    pub time_in_force: TimeInForce,
+    // the order of a paper portfolio, it only ever goes to the paper exchange
+    pub paper: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExchangeOrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    // an IOC, FOK or market order that found nothing more to fill
+    Expired,
+}
+
+// The exchange's view of an order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExchangeOrder {
+    pub exchange_order_id: String,
+    pub status: ExchangeOrderStatus,
+    pub filled_quantity: i64,
+}
+
+// One execution of an order
+#[derive(Debug, Clone)]
+pub struct ExchangeFill {
+    pub fill_id: String,
+    pub exchange_order_id: String,
+    pub price: i64,
+    pub quantity: i64,
+    // charged in the quote currency
+    pub fee: i64,
+    pub time: NaiveDateTime,
+}
+
+// A public trade of a pair, what the market data is built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketTrade {
+    pub trade_id: String,
+    pub price: i64,
+    pub quantity: i64,
+    pub time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExchangeError {
+    // the exchange refused the request, e.g. insufficient funds or the order is already closed
+    Rejected(String),
+    NotFound,
+    // the exchange could not be reached or answered something we don't understand
+    Unavailable(String),
+    // an amendment cancelled the order but could not place its replacement, the
+    // cancelled order is all there is now
+    ReplaceFailed(ExchangeOrder, String),
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExchangeError::Rejected(reason) => write!(f, "Rejected by the exchange: {}", reason),
+            ExchangeError::NotFound => write!(f, "Order not found on the exchange"),
+            ExchangeError::Unavailable(reason) => write!(f, "Exchange unavailable: {}", reason),
+            ExchangeError::ReplaceFailed(_, reason) => {
+                write!(f, "The order was cancelled but not replaced: {}", reason)
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+pub trait ExchangeAdapter: Send + Sync {
+    async fn place_order(
+        &self,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError>;
+
+    async fn cancel_order(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError>;
+
+    // change the price and total quantity of an open order. Exchanges that cannot
+    // edit orders replace them, so the answer may carry a new exchange order id.
+    async fn amend_order(
+        &self,
+        exchange_order_id: &str,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError>;
+
+    async fn query_order(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError>;
+
+    async fn list_fills(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<Vec<ExchangeFill>, ExchangeError>;
+
+    // trades of everyone on the pair after `since`, oldest first
+    async fn recent_trades(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<MarketTrade>, ExchangeError>;
+}
+
+pub type Exchange = Arc<dyn ExchangeAdapter>;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AdapterKind {
+    Bbgo,
+    Mock,
+}
+
+// The [default.exchange] table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExchangeConfig {
+    pub adapter: AdapterKind,
+    #[serde(default)]
+    pub bbgo_url: String,
+    // how long a call to BBGO may take before it fails as unavailable
+    #[serde(default = "default_bbgo_timeout_secs")]
+    pub bbgo_timeout_secs: u64,
+    // fee charged by the mock exchange, in basis points of the notional
+    #[serde(default)]
+    pub mock_fee_bps: i64,
+    // the simulated exchange of the paper portfolios
+    #[serde(default)]
+    pub paper: PaperConfig,
+}
+
+fn default_bbgo_timeout_secs() -> u64 {
+    return 10;
+}
+
+pub fn from_config(config: &ExchangeConfig) -> Result<Exchange, &'static str> {
+    match config.adapter {
+        AdapterKind::Bbgo => {
+            let timeout = Duration::from_secs(config.bbgo_timeout_secs);
+            return Ok(Arc::new(BbgoExchange::new(&config.bbgo_url, timeout)?));
+        }
+        AdapterKind::Mock => return Ok(Arc::new(MockExchange::new(config.mock_fee_bps))),
+    }
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    let config = match rocket.figment().extract_inner::<ExchangeConfig>("exchange") {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Invalid exchange configuration: {}", err);
+            return Err(rocket);
+        }
+    };
+
+    // paper orders are routed to the paper exchange, never to the live adapter
+    match from_config(&config) {
+        Ok(live) => {
+            let paper: PaperEngine = Arc::new(PaperExchange::new(config.paper.clone()));
+            let exchange: Exchange = Arc::new(RoutedExchange::new(live, paper.clone()));
+            let close_paper_orders = AdHoc::on_liftoff("Paper orders", |rocket| {
+                Box::pin(close_paper_orders(rocket))
+            });
+            return Ok(rocket
+                .manage(exchange)
+                .manage(paper)
+                .attach(close_paper_orders));
+        }
+        Err(err) => {
+            println!("Fail to set up the exchange adapter: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+// close the paper orders a previous run left open
+async fn close_paper_orders(rocket: &Rocket<Orbit>) {
+    let (paper, db) = match (rocket.state::<PaperEngine>(), database::PgDb::fetch(rocket)) {
+        (Some(paper), Some(db)) => (paper, db),
+        _ => return,
+    };
+    let mut conn = match db.get().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            println!("Paper exchange: no database connection: {}", err);
+            return;
+        }
+    };
+    match close_lost_orders(&mut conn, paper).await {
+        Ok(closed) if !closed.is_empty() => {
+            println!("Paper exchange: cancelled the lost orders {:?}", closed);
+        }
+        Ok(_) => {}
+        Err(err) => println!("Paper exchange: fail to close the lost orders: {}", err.0),
+    }
+}
+
+// manage an `Exchange` built from the [default.exchange] configuration, and the
+// `PaperEngine` behind it
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Exchange adapter", init)
+}
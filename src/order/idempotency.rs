@@ -0,0 +1,313 @@
+use ::diesel::{ExpressionMethods, OptionalExtension};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use rocket::fairing::{self, AdHoc};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::serde::json::{json, Value};
+use rocket::{Build, Rocket};
+use rocket_db_pools::diesel;
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+use serde::Deserialize;
+
+use crate::db_lib::schema::{order_idempotency_keys, orders};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+// The Idempotency-Key header of a request, if it has one
+pub struct IdempotencyKey(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let key = req
+            .headers()
+            .get_one(IDEMPOTENCY_KEY_HEADER)
+            .map(|key| key.to_string());
+        return Outcome::Success(IdempotencyKey(key));
+    }
+}
+
+// keys are opaque to us, anything printable that fits the column
+pub fn valid_key(key: &str) -> bool {
+    return !key.is_empty() && key.len() <= 255 && key.bytes().all(|b| b.is_ascii_graphic());
+}
+
+// client order ids are chosen by the user, letters, digits and a few separators
+pub fn valid_client_order_id(client_order_id: &str) -> bool {
+    return !client_order_id.is_empty()
+        && client_order_id.len() <= 64
+        && client_order_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+}
+
+// The [default.idempotency] table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdempotencyConfig {
+    // a key whose first request has not finished after this long is given up on, its
+    // handler died before it could store the result
+    pub stale_seconds: i64,
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    match rocket
+        .figment()
+        .extract_inner::<IdempotencyConfig>("idempotency")
+    {
+        Ok(config) => return Ok(rocket.manage(config)),
+        Err(err) => {
+            println!("Invalid idempotency configuration: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Idempotency keys", init)
+}
+
+// What an Idempotency-Key says about a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Claim {
+    // the first request with the key, it goes ahead and its result is kept under the id
+    New(i32),
+    // the result of the first request
+    Replay(Status, Value),
+    // the first request has not finished yet
+    InProgress,
+    // the key was used for a different request
+    Mismatch,
+}
+
+// What the stored key says before anything is looked up
+#[derive(Debug, Clone, PartialEq)]
+enum Stored {
+    Claim(Claim),
+    // unfinished for longer than the window, the first request is gone
+    Abandoned,
+}
+
+fn stored_claim(
+    stored_request: &Value,
+    request: &Value,
+    response_status: Option<i32>,
+    response: Option<Value>,
+    created_at: NaiveDateTime,
+    now: NaiveDateTime,
+    stale_after: Duration,
+) -> Stored {
+    if stored_request != request {
+        return Stored::Claim(Claim::Mismatch);
+    }
+    match (response_status, response) {
+        (Some(code), Some(response)) => {
+            return Stored::Claim(Claim::Replay(Status::new(code as u16), response));
+        }
+        _ if created_at + stale_after <= now => return Stored::Abandoned,
+        _ => return Stored::Claim(Claim::InProgress),
+    }
+}
+
+// Claim the key for the request, keys are scoped to the account that sends them. A key
+// left unfinished for longer than `stale_after` is taken over, unless the order it was
+// for can be found by its client order id, which is then what the key answers with.
+pub async fn claim_key(
+    conn: &mut AsyncPgConnection,
+    account_id: i32,
+    key: &str,
+    request: &Value,
+    stale_after: Duration,
+) -> Result<Claim, diesel::result::Error> {
+    let claimed = diesel::insert_into(order_idempotency_keys::table)
+        .values((
+            order_idempotency_keys::account_id.eq(account_id),
+            order_idempotency_keys::idempotency_key.eq(key),
+            order_idempotency_keys::request.eq(request),
+        ))
+        .on_conflict_do_nothing()
+        .returning(order_idempotency_keys::id)
+        .get_result::<i32>(conn)
+        .await
+        .optional()?;
+    if let Some(key_id) = claimed {
+        return Ok(Claim::New(key_id));
+    }
+
+    let (key_id, stored_request, response_status, response, created_at) =
+        order_idempotency_keys::table
+            .filter(order_idempotency_keys::account_id.eq(account_id))
+            .filter(order_idempotency_keys::idempotency_key.eq(key))
+            .select((
+                order_idempotency_keys::id,
+                order_idempotency_keys::request,
+                order_idempotency_keys::response_status,
+                order_idempotency_keys::response,
+                order_idempotency_keys::created_at,
+            ))
+            .first::<(i32, Value, Option<i32>, Option<Value>, NaiveDateTime)>(conn)
+            .await?;
+    let now = Utc::now().naive_utc();
+    let stored = stored_claim(
+        &stored_request,
+        request,
+        response_status,
+        response,
+        created_at,
+        now,
+        stale_after,
+    );
+    match stored {
+        Stored::Claim(claim) => return Ok(claim),
+        Stored::Abandoned => {}
+    }
+
+    // the first request may have written its order before it died
+    let client_order = match (
+        request.get("portfolio_id").and_then(Value::as_i64),
+        request.get("client_order_id").and_then(Value::as_str),
+    ) {
+        (Some(portfolio_id), Some(client_order_id)) => match i32::try_from(portfolio_id) {
+            Ok(portfolio_id) => find_client_order(conn, portfolio_id, client_order_id).await?,
+            Err(_) => None,
+        },
+        _ => None,
+    };
+    if let Some((order_id, status)) = client_order {
+        let response = (
+            Status::Ok,
+            json!({"status": "successful", "data": order_id, "order_status": status, "client_order_id": request.get("client_order_id")}),
+        );
+        finish_key(conn, key_id, &response).await?;
+        return Ok(Claim::Replay(response.0, response.1));
+    }
+
+    // only one of the retries takes the key over
+    let taken = diesel::update(
+        order_idempotency_keys::table
+            .filter(order_idempotency_keys::id.eq(key_id))
+            .filter(order_idempotency_keys::created_at.eq(created_at))
+            .filter(order_idempotency_keys::response_status.is_null()),
+    )
+    .set(order_idempotency_keys::created_at.eq(now))
+    .execute(conn)
+    .await?;
+    if taken == 1 {
+        return Ok(Claim::New(key_id));
+    }
+    return Ok(Claim::InProgress);
+}
+
+// Keep the result of the first request under its key. A server error that left no
+// order behind gives the key up, so the retry runs again instead of replaying it.
+pub async fn finish_key(
+    conn: &mut AsyncPgConnection,
+    key_id: i32,
+    response: &(Status, Value),
+) -> Result<(), diesel::result::Error> {
+    let (status, body) = response;
+    let order_id = body
+        .get("data")
+        .and_then(Value::as_i64)
+        .and_then(|order_id| i32::try_from(order_id).ok());
+    if status.code >= 500 && order_id.is_none() {
+        diesel::delete(order_idempotency_keys::table.filter(order_idempotency_keys::id.eq(key_id)))
+            .execute(conn)
+            .await?;
+        return Ok(());
+    }
+    diesel::update(order_idempotency_keys::table.filter(order_idempotency_keys::id.eq(key_id)))
+        .set((
+            order_idempotency_keys::order_id.eq(order_id),
+            order_idempotency_keys::response_status.eq(status.code as i32),
+            order_idempotency_keys::response.eq(body),
+        ))
+        .execute(conn)
+        .await?;
+    return Ok(());
+}
+
+// The order of the portfolio with the client order id, as (id, status)
+pub async fn find_client_order(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    client_order_id: &str,
+) -> Result<Option<(i32, String)>, diesel::result::Error> {
+    return orders::table
+        .filter(orders::portfolio_id.eq(portfolio_id))
+        .filter(orders::client_order_id.eq(client_order_id))
+        .select((orders::id, orders::status))
+        .first::<(i32, String)>(conn)
+        .await
+        .optional();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_and_client_order_ids_are_checked() {
+        assert!(valid_key("5f2b9c1e-retry"));
+        assert!(!valid_key(""));
+        assert!(!valid_key("has space"));
+        assert!(!valid_key(&"k".repeat(256)));
+
+        assert!(valid_client_order_id("grid-42_b.1:x"));
+        assert!(!valid_client_order_id(""));
+        assert!(!valid_client_order_id("a/b"));
+        assert!(!valid_client_order_id(&"c".repeat(65)));
+    }
+
+    #[test]
+    fn unfinished_keys_go_stale() {
+        let created_at =
+            NaiveDateTime::parse_from_str("2026-10-18 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let window = Duration::seconds(300);
+        let request = json!({"portfolio_id": 1, "quantity": "1"});
+        let stored = |response_status, response, now| {
+            stored_claim(
+                &request,
+                &request,
+                response_status,
+                response,
+                created_at,
+                now,
+                window,
+            )
+        };
+
+        assert_eq!(
+            stored(None, None, created_at + Duration::seconds(299)),
+            Stored::Claim(Claim::InProgress)
+        );
+        assert_eq!(
+            stored(None, None, created_at + Duration::seconds(300)),
+            Stored::Abandoned
+        );
+        // a finished key answers with its result however old it is
+        assert_eq!(
+            stored(
+                Some(200),
+                Some(json!({"status": "successful"})),
+                created_at + Duration::days(1)
+            ),
+            Stored::Claim(Claim::Replay(Status::Ok, json!({"status": "successful"})))
+        );
+        assert_eq!(
+            stored_claim(
+                &request,
+                &json!({"portfolio_id": 2}),
+                None,
+                None,
+                created_at,
+                created_at + Duration::days(1),
+                window
+            ),
+            Stored::Claim(Claim::Mismatch)
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use ::diesel::{ExpressionMethods, OptionalExtension};
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use rocket_db_pools::diesel;
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::{AsyncConnection, AsyncPgConnection};
+use std::collections::HashMap;
+
+use crate::db_lib::schema::{order_fills, orders};
+use crate::order::balance::{
+    release_reservation, reservation_for, set_reservation, settle_fill, BalanceError,
+};
+use crate::order::exchange::{
+    Exchange, ExchangeError, ExchangeFill, ExchangeOrder, ExchangeOrderRequest,
+    ExchangeOrderStatus, Side, Symbol,
+};
+use crate::order::status::OrderStatus;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifecycleError(pub String);
+
+impl From<diesel::result::Error> for LifecycleError {
+    fn from(_: diesel::result::Error) -> Self {
+        LifecycleError("Fail to update the order".to_string())
+    }
+}
+
+impl From<BalanceError> for LifecycleError {
+    fn from(err: BalanceError) -> Self {
+        LifecycleError(err.message())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmitError {
+    // the exchange did not take the order, it is rejected on our side too
+    Exchange(ExchangeError),
+    Lifecycle(LifecycleError),
+}
+
+// What the fills of an order add up to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FillSummary {
+    pub filled_qty: i64,
+    // rounded to the nearest price unit, None until the first fill
+    pub avg_price: Option<i64>,
+    pub fee: i64,
+}
+
+impl FillSummary {
+    // fills as (price, qty, fee)
+    pub fn from_fills(fills: &[(i64, i64, i64)]) -> Self {
+        let mut notional: i128 = 0;
+        let mut summary = FillSummary::default();
+        for (price, qty, fee) in fills {
+            notional += *price as i128 * *qty as i128;
+            summary.filled_qty = summary.filled_qty.saturating_add(*qty);
+            summary.fee = summary.fee.saturating_add(*fee);
+        }
+        if summary.filled_qty > 0 {
+            let qty = summary.filled_qty as i128;
+            summary.avg_price = Some(((notional * 2 + qty) / (qty * 2)) as i64);
+        }
+        return summary;
+    }
+}
+
+pub async fn order_status(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+) -> Result<OrderStatus, LifecycleError> {
+    let status = orders::table
+        .filter(orders::id.eq(order_id))
+        .select(orders::status)
+        .first::<String>(conn)
+        .await?;
+    return status
+        .parse::<OrderStatus>()
+        .map_err(|err| LifecycleError(err.to_string()));
+}
+
+// fill summaries of several orders at once, orders without fills are left out
+pub async fn fill_summaries(
+    conn: &mut AsyncPgConnection,
+    order_ids: &[i32],
+) -> Result<HashMap<i32, FillSummary>, LifecycleError> {
+    let fills = order_fills::table
+        .filter(order_fills::order_id.eq_any(order_ids))
+        .select((
+            order_fills::order_id,
+            order_fills::price,
+            order_fills::qty,
+            order_fills::fee,
+        ))
+        .load::<(i32, i64, i64, i64)>(conn)
+        .await?;
+
+    let mut by_order: HashMap<i32, Vec<(i64, i64, i64)>> = HashMap::new();
+    for (order_id, price, qty, fee) in fills {
+        by_order
+            .entry(order_id)
+            .or_default()
+            .push((price, qty, fee));
+    }
+    return Ok(by_order
+        .into_iter()
+        .map(|(order_id, fills)| (order_id, FillSummary::from_fills(&fills)))
+        .collect());
+}
+
+// store the fills we have not seen yet and return them with their row ids
+pub async fn record_fills(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    fills: &[ExchangeFill],
+) -> Result<Vec<(i32, ExchangeFill)>, LifecycleError> {
+    let mut recorded = vec![];
+    for fill in fills {
+        let inserted = diesel::insert_into(order_fills::table)
+            .values((
+                order_fills::order_id.eq(order_id),
+                order_fills::exchange_fill_id.eq(&fill.fill_id),
+                order_fills::price.eq(fill.price),
+                order_fills::qty.eq(fill.quantity),
+                order_fills::fee.eq(fill.fee),
+                order_fills::time_stamp.eq(fill.time),
+            ))
+            .on_conflict((order_fills::order_id, order_fills::exchange_fill_id))
+            .do_nothing()
+            .returning(order_fills::id)
+            .get_result::<i32>(conn)
+            .await
+            .optional()?;
+        if let Some(fill_id) = inserted {
+            recorded.push((fill_id, fill.clone()));
+        }
+    }
+    return Ok(recorded);
+}
+
+// store the fills we have not seen yet and book them into the ledger
+pub async fn record_and_settle_fills(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    fills: &[ExchangeFill],
+) -> Result<(), LifecycleError> {
+    for (fill_id, fill) in record_fills(conn, order_id, fills).await? {
+        settle_fill(conn, order_id, Some(fill_id), &fill).await?;
+    }
+    return Ok(());
+}
+
+// move the order to `next`, failing if the transition is not allowed or the
+// order changed under us. A closed order gives back what it still had reserved.
+pub async fn set_status(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    next: OrderStatus,
+) -> Result<OrderStatus, LifecycleError> {
+    return conn
+        .transaction::<OrderStatus, LifecycleError, _>(|conn| {
+            async move {
+                let current = order_status(conn, order_id).await?;
+                let next = current.transition(next).map_err(LifecycleError)?;
+                let updated = diesel::update(
+                    orders::table
+                        .filter(orders::id.eq(order_id))
+                        .filter(orders::status.eq(current.as_str())),
+                )
+                .set(orders::status.eq(next.as_str()))
+                .execute(conn)
+                .await?;
+                if updated != 1 {
+                    return Err(LifecycleError(
+                        "The order was updated concurrently".to_string(),
+                    ));
+                }
+                if !next.is_open() {
+                    release_reservation(conn, order_id).await?;
+                }
+                return Ok(next);
+            }
+            .scope_boxed()
+        })
+        .await;
+}
+
+// bring our copy of the order in line with the exchange: store and settle the new
+// fills and move the status, all or nothing
+pub async fn apply_exchange_update(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    exchange_order: &ExchangeOrder,
+    fills: &[ExchangeFill],
+) -> Result<OrderStatus, LifecycleError> {
+    let exchange_order = exchange_order.clone();
+    let fills = fills.to_vec();
+    return conn
+        .transaction::<OrderStatus, LifecycleError, _>(|conn| {
+            async move {
+                diesel::update(orders::table.filter(orders::id.eq(order_id)))
+                    .set(orders::exchange_order_id.eq(&exchange_order.exchange_order_id))
+                    .execute(conn)
+                    .await?;
+                record_and_settle_fills(conn, order_id, &fills).await?;
+
+                let current = order_status(conn, order_id).await?;
+                let next = OrderStatus::from(exchange_order.status);
+                if current == next && next != OrderStatus::PartiallyFilled {
+                    return Ok(current);
+                }
+                // an exchange still reporting the order as new is behind us
+                if next == OrderStatus::Accepted && current != OrderStatus::New {
+                    return Ok(current);
+                }
+                return set_status(conn, order_id, next).await;
+            }
+            .scope_boxed()
+        })
+        .await;
+}
+
+// Keep the id of an order the exchange replaced on amend, when its fills could not be
+// fetched. sync_order collects them later.
+pub async fn keep_replaced_order(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    exchange_order_id: &str,
+) -> Result<(), LifecycleError> {
+    let mut replaced = orders::table
+        .filter(orders::id.eq(order_id))
+        .select(orders::replaced_exchange_order_ids)
+        .first::<Vec<String>>(conn)
+        .await?;
+    if !replaced.iter().any(|id| id == exchange_order_id) {
+        replaced.push(exchange_order_id.to_string());
+    }
+    diesel::update(orders::table.filter(orders::id.eq(order_id)))
+        .set(orders::replaced_exchange_order_ids.eq(replaced))
+        .execute(conn)
+        .await?;
+    return Ok(());
+}
+
+// Record the fills of the orders the exchange replaced on amend. An id is dropped once
+// its fills are in, a replaced order takes no new ones.
+async fn collect_replaced_fills(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    order_id: i32,
+    symbol: &Symbol,
+) -> Result<(), LifecycleError> {
+    let replaced = orders::table
+        .filter(orders::id.eq(order_id))
+        .select(orders::replaced_exchange_order_ids)
+        .first::<Vec<String>>(conn)
+        .await?;
+    if replaced.is_empty() {
+        return Ok(());
+    }
+    let mut pending = vec![];
+    let mut fills = vec![];
+    for exchange_order_id in &replaced {
+        match exchange.list_fills(symbol, exchange_order_id).await {
+            Ok(exchange_fills) => fills.extend(exchange_fills),
+            Err(_) => pending.push(exchange_order_id.clone()),
+        }
+    }
+    return conn
+        .transaction::<(), LifecycleError, _>(|conn| {
+            async move {
+                // an amend that replaced the order again in the meantime goes first
+                let updated = diesel::update(
+                    orders::table
+                        .filter(orders::id.eq(order_id))
+                        .filter(orders::replaced_exchange_order_ids.eq(&replaced)),
+                )
+                .set(orders::replaced_exchange_order_ids.eq(&pending))
+                .execute(conn)
+                .await?;
+                if updated == 1 {
+                    record_and_settle_fills(conn, order_id, &fills).await?;
+                }
+                return Ok(());
+            }
+            .scope_boxed()
+        })
+        .await;
+}
+
+// apply what the exchange answered about an order, fetching its fills when some
+// quantity was executed. If the fills cannot be fetched the order is only marked
+// as accepted, so the next refresh picks the execution up again.
+pub async fn sync_order(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    order_id: i32,
+    symbol: &Symbol,
+    mut exchange_order: ExchangeOrder,
+) -> Result<OrderStatus, LifecycleError> {
+    collect_replaced_fills(conn, exchange, order_id, symbol).await?;
+    let mut fills = vec![];
+    if exchange_order.filled_quantity > 0 {
+        match exchange
+            .list_fills(symbol, &exchange_order.exchange_order_id)
+            .await
+        {
+            Ok(exchange_fills) => fills = exchange_fills,
+            Err(_) => exchange_order.status = ExchangeOrderStatus::New,
+        }
+    }
+    return apply_exchange_update(conn, order_id, &exchange_order, &fills).await;
+}
+
+// Send a written order to the exchange and apply the answer, returning its status and
+// the exchange's id. An order the exchange does not take is rejected, which gives its
+// reservation back.
+pub async fn submit_order(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    order_id: i32,
+    request: &ExchangeOrderRequest,
+) -> Result<(OrderStatus, String), SubmitError> {
+    match exchange.place_order(request).await {
+        Ok(exchange_order) => {
+            let exchange_order_id = exchange_order.exchange_order_id.clone();
+            let status = sync_order(conn, exchange, order_id, &request.symbol, exchange_order)
+                .await
+                .map_err(SubmitError::Lifecycle)?;
+            return Ok((status, exchange_order_id));
+        }
+        Err(err) => {
+            // the order never made it to the exchange
+            set_status(conn, order_id, OrderStatus::Rejected)
+                .await
+                .map_err(SubmitError::Lifecycle)?;
+            return Err(SubmitError::Exchange(err));
+        }
+    }
+}
+
+// ask the exchange for the latest state of an open order
+pub async fn refresh_order(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    order_id: i32,
+    symbol: &Symbol,
+    exchange_order_id: &str,
+) -> Result<OrderStatus, LifecycleError> {
+    let exchange_order = exchange
+        .query_order(symbol, exchange_order_id)
+        .await
+        .map_err(|err| LifecycleError(err.to_string()))?;
+    return sync_order(conn, exchange, order_id, symbol, exchange_order).await;
+}
+
+// align an open order's reservation with what is left to fill
+pub async fn reserve_remaining(
+    conn: &mut AsyncPgConnection,
+    order_id: i32,
+    symbol: &Symbol,
+    side: Side,
+    price: i64,
+    qty: i64,
+) -> Result<(), LifecycleError> {
+    let filled_qty = fill_summaries(conn, &[order_id])
+        .await?
+        .get(&order_id)
+        .map(|summary| summary.filled_qty)
+        .unwrap_or(0);
+    let reservation = reservation_for(side, price, qty - filled_qty, symbol.base_scale)
+        .ok_or(BalanceError::Overflow)?;
+    set_reservation(conn, order_id, reservation).await?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_without_fills() {
+        assert_eq!(FillSummary::from_fills(&[]), FillSummary::default());
+    }
+
+    #[test]
+    fn average_price_is_weighted_by_quantity() {
+        let summary = FillSummary::from_fills(&[(100, 1, 1), (103, 2, 2)]);
+        assert_eq!(summary.filled_qty, 3);
+        // 306 / 3 = 102
+        assert_eq!(summary.avg_price, Some(102));
+        assert_eq!(summary.fee, 3);
+
+        // 201 / 2 = 100.5, rounded up
+        let summary = FillSummary::from_fills(&[(100, 1, 0), (101, 1, 0)]);
+        assert_eq!(summary.avg_price, Some(101));
+    }
+}
@@ -0,0 +1,375 @@
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::order::exchange::{
+    ExchangeAdapter, ExchangeError, ExchangeFill, ExchangeOrder, ExchangeOrderRequest,
+    ExchangeOrderStatus, ExchangeOrderType, MarketTrade, Symbol,
+};
+use crate::order::order_type::TimeInForce;
+
+struct MockOrder {
+    request: ExchangeOrderRequest,
+    status: ExchangeOrderStatus,
+    fills: Vec<ExchangeFill>,
+}
+
+impl MockOrder {
+    fn filled_quantity(&self) -> i64 {
+        return self.fills.iter().map(|fill| fill.quantity).sum();
+    }
+
+    fn view(&self, exchange_order_id: &str) -> ExchangeOrder {
+        return ExchangeOrder {
+            exchange_order_id: exchange_order_id.to_string(),
+            status: self.status,
+            filled_quantity: self.filled_quantity(),
+        };
+    }
+}
+
+#[derive(Default)]
+struct MockBook {
+    next_order_id: u64,
+    next_fill_id: u64,
+    orders: HashMap<String, MockOrder>,
+}
+
+// In-process exchange with predictable behaviour, for tests and local development.
+// Orders are filled in full at their own price as soon as they are placed, unless
+// `hold_orders` is set, in which case they stay open until `fill` is called. There is
+// no liquidity while orders are held, so market, IOC and FOK orders expire at once.
+pub struct MockExchange {
+    fee_bps: i64,
+    hold_orders: bool,
+    book: Mutex<MockBook>,
+}
+
+impl MockExchange {
+    pub fn new(fee_bps: i64) -> Self {
+        return MockExchange {
+            fee_bps,
+            hold_orders: false,
+            book: Mutex::new(MockBook::default()),
+        };
+    }
+
+    pub fn holding_orders(fee_bps: i64) -> Self {
+        return MockExchange {
+            hold_orders: true,
+            ..MockExchange::new(fee_bps)
+        };
+    }
+
+    fn fill_locked(
+        &self,
+        book: &mut MockBook,
+        exchange_order_id: &str,
+        price: i64,
+        quantity: i64,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        book.next_fill_id += 1;
+        let fill_id = format!("mock-fill-{}", book.next_fill_id);
+        let order = book
+            .orders
+            .get_mut(exchange_order_id)
+            .ok_or(ExchangeError::NotFound)?;
+        if !matches!(
+            order.status,
+            ExchangeOrderStatus::New | ExchangeOrderStatus::PartiallyFilled
+        ) {
+            return Err(ExchangeError::Rejected("order is closed".to_string()));
+        }
+        let remaining = order.request.quantity - order.filled_quantity();
+        if quantity <= 0 || quantity > remaining {
+            return Err(ExchangeError::Rejected(
+                "fill quantity exceeds the remaining quantity".to_string(),
+            ));
+        }
+        let fee = order
+            .request
+            .symbol
+            .fee(price, quantity, self.fee_bps)
+            .ok_or(ExchangeError::Rejected("the fee overflows".to_string()))?;
+        order.fills.push(ExchangeFill {
+            fill_id,
+            exchange_order_id: exchange_order_id.to_string(),
+            price,
+            quantity,
+            fee,
+            time: Utc::now().naive_utc(),
+        });
+        order.status = if quantity == remaining {
+            ExchangeOrderStatus::Filled
+        } else {
+            ExchangeOrderStatus::PartiallyFilled
+        };
+        return Ok(order.view(exchange_order_id));
+    }
+
+    // execute part or all of an open order, used when orders are held
+    pub fn fill(
+        &self,
+        exchange_order_id: &str,
+        price: i64,
+        quantity: i64,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let mut book = self.book.lock().unwrap();
+        return self.fill_locked(&mut book, exchange_order_id, price, quantity);
+    }
+}
+
+#[rocket::async_trait]
+impl ExchangeAdapter for MockExchange {
+    async fn place_order(
+        &self,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        if request.price <= 0 || request.quantity <= 0 {
+            return Err(ExchangeError::Rejected(
+                "price and quantity must be positive".to_string(),
+            ));
+        }
+
+        let mut book = self.book.lock().unwrap();
+        book.next_order_id += 1;
+        let exchange_order_id = format!("mock-{}", book.next_order_id);
+        book.orders.insert(
+            exchange_order_id.clone(),
+            MockOrder {
+                request: request.clone(),
+                status: ExchangeOrderStatus::New,
+                fills: vec![],
+            },
+        );
+        if self.hold_orders {
+            let order = book.orders.get_mut(&exchange_order_id).unwrap();
+            let resting = request.order_type == ExchangeOrderType::Limit
+                && matches!(request.time_in_force, TimeInForce::Gtc | TimeInForce::Gtd);
+            if !resting {
+                order.status = ExchangeOrderStatus::Expired;
+            }
+            return Ok(order.view(&exchange_order_id));
+        }
+        return self.fill_locked(
+            &mut book,
+            &exchange_order_id,
+            request.price,
+            request.quantity,
+        );
+    }
+
+    async fn cancel_order(
+        &self,
+        _symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let mut book = self.book.lock().unwrap();
+        let order = book
+            .orders
+            .get_mut(exchange_order_id)
+            .ok_or(ExchangeError::NotFound)?;
+        match order.status {
+            ExchangeOrderStatus::New | ExchangeOrderStatus::PartiallyFilled => {
+                order.status = ExchangeOrderStatus::Cancelled;
+                return Ok(order.view(exchange_order_id));
+            }
+            _ => return Err(ExchangeError::Rejected("order is closed".to_string())),
+        }
+    }
+
+    async fn amend_order(
+        &self,
+        exchange_order_id: &str,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let mut book = self.book.lock().unwrap();
+        let order = book
+            .orders
+            .get_mut(exchange_order_id)
+            .ok_or(ExchangeError::NotFound)?;
+        if !matches!(
+            order.status,
+            ExchangeOrderStatus::New | ExchangeOrderStatus::PartiallyFilled
+        ) {
+            return Err(ExchangeError::Rejected("order is closed".to_string()));
+        }
+        let filled = order.filled_quantity();
+        if request.price <= 0 || request.quantity < filled || request.quantity <= 0 {
+            return Err(ExchangeError::Rejected(
+                "quantity is below the filled quantity".to_string(),
+            ));
+        }
+        order.request.price = request.price;
+        order.request.quantity = request.quantity;
+        if request.quantity == filled {
+            order.status = ExchangeOrderStatus::Filled;
+        }
+        return Ok(order.view(exchange_order_id));
+    }
+
+    async fn query_order(
+        &self,
+        _symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let book = self.book.lock().unwrap();
+        match book.orders.get(exchange_order_id) {
+            Some(order) => return Ok(order.view(exchange_order_id)),
+            None => return Err(ExchangeError::NotFound),
+        }
+    }
+
+    async fn list_fills(
+        &self,
+        _symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<Vec<ExchangeFill>, ExchangeError> {
+        let book = self.book.lock().unwrap();
+        match book.orders.get(exchange_order_id) {
+            Some(order) => return Ok(order.fills.clone()),
+            None => return Err(ExchangeError::NotFound),
+        }
+    }
+
+    // the only trades on the mock exchange are the fills of its own orders
+    async fn recent_trades(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<MarketTrade>, ExchangeError> {
+        let book = self.book.lock().unwrap();
+        let mut trades: Vec<MarketTrade> = book
+            .orders
+            .values()
+            .filter(|order| order.request.symbol == *symbol)
+            .flat_map(|order| order.fills.iter())
+            .filter(|fill| since.is_none_or(|since| fill.time > since))
+            .map(|fill| MarketTrade {
+                trade_id: fill.fill_id.clone(),
+                price: fill.price,
+                quantity: fill.quantity,
+                time: fill.time,
+            })
+            .collect();
+        trades.sort_by_key(|trade| trade.time);
+        return Ok(trades);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::exchange::Side;
+
+    fn request(price: i64, quantity: i64) -> ExchangeOrderRequest {
+        return ExchangeOrderRequest {
+            client_order_id: "1".to_string(),
+            symbol: Symbol {
+                base: "BTC".to_string(),
+                quote: "USDT".to_string(),
+                base_scale: 0,
+                quote_scale: 0,
+            },
+            side: Side::Buy,
+            order_type: ExchangeOrderType::Limit,
+            price,
+            quantity,
+            time_in_force: TimeInForce::Gtc,
+            paper: false,
+        };
+    }
+
+    #[rocket::async_test]
+    async fn fills_immediately_with_fee() {
+        let exchange = MockExchange::new(10);
+        let order = exchange.place_order(&request(1_000, 20)).await.unwrap();
+        assert_eq!(order.exchange_order_id, "mock-1");
+        assert_eq!(order.status, ExchangeOrderStatus::Filled);
+
+        let symbol = request(0, 0).symbol;
+        let fills = exchange
+            .list_fills(&symbol, &order.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].fee, 20);
+        assert!(exchange
+            .cancel_order(&symbol, &order.exchange_order_id)
+            .await
+            .is_err());
+    }
+
+    #[rocket::async_test]
+    async fn held_orders_fill_partially_and_cancel() {
+        let exchange = MockExchange::holding_orders(0);
+        let symbol = request(0, 0).symbol;
+        let order = exchange.place_order(&request(1_000, 20)).await.unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::New);
+
+        let order = exchange.fill(&order.exchange_order_id, 990, 5).unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::PartiallyFilled);
+        assert_eq!(order.filled_quantity, 5);
+        assert!(exchange.fill(&order.exchange_order_id, 990, 16).is_err());
+
+        let order = exchange
+            .cancel_order(&symbol, &order.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Cancelled);
+    }
+
+    #[rocket::async_test]
+    async fn orders_that_cannot_rest_expire_while_held() {
+        let exchange = MockExchange::holding_orders(0);
+        let market = ExchangeOrderRequest {
+            order_type: ExchangeOrderType::Market,
+            time_in_force: TimeInForce::Ioc,
+            ..request(1_000, 20)
+        };
+        let order = exchange.place_order(&market).await.unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Expired);
+        assert!(exchange.fill(&order.exchange_order_id, 1_000, 1).is_err());
+
+        let fok = ExchangeOrderRequest {
+            time_in_force: TimeInForce::Fok,
+            ..request(1_000, 20)
+        };
+        let order = exchange.place_order(&fok).await.unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Expired);
+
+        // without holding they fill like any other order
+        let order = MockExchange::new(0).place_order(&market).await.unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Filled);
+    }
+
+    #[rocket::async_test]
+    async fn amends_open_orders_in_place() {
+        let exchange = MockExchange::holding_orders(0);
+        let order = exchange.place_order(&request(1_000, 20)).await.unwrap();
+        exchange.fill(&order.exchange_order_id, 1_000, 5).unwrap();
+
+        assert!(exchange
+            .amend_order(&order.exchange_order_id, &request(1_000, 4))
+            .await
+            .is_err());
+        let amended = exchange
+            .amend_order(&order.exchange_order_id, &request(1_000, 8))
+            .await
+            .unwrap();
+        assert_eq!(amended.exchange_order_id, order.exchange_order_id);
+        assert_eq!(amended.status, ExchangeOrderStatus::PartiallyFilled);
+        assert!(exchange.fill(&order.exchange_order_id, 1_000, 4).is_err());
+        let order = exchange.fill(&order.exchange_order_id, 1_000, 3).unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Filled);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_non_positive_orders() {
+        let exchange = MockExchange::new(0);
+        assert!(matches!(
+            exchange.place_order(&request(0, 1)).await,
+            Err(ExchangeError::Rejected(_))
+        ));
+    }
+}
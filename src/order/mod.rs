@@ -1,2 +1,23 @@
-mod bbgo;
+pub mod balance;
+pub mod bbgo;
+pub mod exchange;
+pub mod idempotency;
+pub mod lifecycle;
+pub mod mock;
+pub mod order_type;
+pub mod ownership;
+pub mod paper;
 pub mod route;
+pub mod status;
+pub mod trigger;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        route::place_order,
+        route::get_order,
+        route::get_order_detail,
+        route::get_order_by_client_id,
+        route::cancel_order,
+        route::amend_order
+    ]
+}
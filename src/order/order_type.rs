@@ -0,0 +1,339 @@
+use chrono::NaiveDateTime;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::order::exchange::{ExchangeOrderType, Side};
+use crate::types::amount::{div_round, Rounding};
+
+// How far a market buy may move above its reference price, in basis points. The order
+// reserves its funds at that price since the execution price is not known up front.
+pub const MARKET_COLLAR_BPS: i64 = 500;
+
+// orders.order_type, stored as the kebab-case name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    Market,
+    Limit,
+    // held until the market reaches the stop price, then sent as a market order
+    StopMarket,
+    // held until the market reaches the stop price, then sent as a limit order
+    StopLimit,
+    // held until the market reaches the target price, then sent as a market order
+    TakeProfit,
+}
+
+impl OrderType {
+    pub const ALL: [OrderType; 5] = [
+        OrderType::Market,
+        OrderType::Limit,
+        OrderType::StopMarket,
+        OrderType::StopLimit,
+        OrderType::TakeProfit,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderType::Market => "market",
+            OrderType::Limit => "limit",
+            OrderType::StopMarket => "stop-market",
+            OrderType::StopLimit => "stop-limit",
+            OrderType::TakeProfit => "take-profit",
+        }
+    }
+
+    // kept on our side until the market data triggers it
+    pub fn is_held(self) -> bool {
+        matches!(
+            self,
+            OrderType::StopMarket | OrderType::StopLimit | OrderType::TakeProfit
+        )
+    }
+
+    // what the exchange gets once the order is released
+    pub fn exchange_type(self) -> ExchangeOrderType {
+        match self {
+            OrderType::Limit | OrderType::StopLimit => return ExchangeOrderType::Limit,
+            OrderType::Market | OrderType::StopMarket | OrderType::TakeProfit => {
+                return ExchangeOrderType::Market
+            }
+        }
+    }
+
+    pub fn default_time_in_force(self) -> TimeInForce {
+        match self {
+            OrderType::Market => return TimeInForce::Ioc,
+            _ => return TimeInForce::Gtc,
+        }
+    }
+
+    // Whether a trade at `price` releases a held order. A stop fires when the market
+    // moves against the position it protects, a take-profit when it moves in favour.
+    pub fn is_triggered(self, side: Side, stop_price: i64, price: i64) -> bool {
+        match (self, side) {
+            (OrderType::StopMarket | OrderType::StopLimit, Side::Buy) => price >= stop_price,
+            (OrderType::StopMarket | OrderType::StopLimit, Side::Sell) => price <= stop_price,
+            (OrderType::TakeProfit, Side::Buy) => price <= stop_price,
+            (OrderType::TakeProfit, Side::Sell) => price >= stop_price,
+            (OrderType::Market | OrderType::Limit, _) => false,
+        }
+    }
+}
+
+impl fmt::Display for OrderType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OrderType {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        return OrderType::ALL
+            .into_iter()
+            .find(|order_type| order_type.as_str() == value)
+            .ok_or("Unknown order type");
+    }
+}
+
+// orders.time_in_force, stored as the lowercase name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    // good till cancelled
+    Gtc,
+    // immediate or cancel, what does not fill at once is cancelled
+    Ioc,
+    // fill or kill, the whole quantity at once or nothing
+    Fok,
+    // good till date, expired by us at orders.expires_at
+    Gtd,
+}
+
+impl TimeInForce {
+    pub const ALL: [TimeInForce; 4] = [
+        TimeInForce::Gtc,
+        TimeInForce::Ioc,
+        TimeInForce::Fok,
+        TimeInForce::Gtd,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimeInForce::Gtc => "gtc",
+            TimeInForce::Ioc => "ioc",
+            TimeInForce::Fok => "fok",
+            TimeInForce::Gtd => "gtd",
+        }
+    }
+}
+
+impl fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TimeInForce {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        return TimeInForce::ALL
+            .into_iter()
+            .find(|time_in_force| time_in_force.as_str() == value.to_lowercase())
+            .ok_or("Unknown time in force");
+    }
+}
+
+// What an order asks for besides its side and quantity, prices in quote units
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderTerms {
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    // the limit price, only for limit and stop-limit orders
+    pub price: Option<i64>,
+    // the trigger of held orders
+    pub stop_price: Option<i64>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl OrderTerms {
+    pub fn validate(&self, now: NaiveDateTime) -> Result<(), &'static str> {
+        let needs_price = matches!(self.order_type, OrderType::Limit | OrderType::StopLimit);
+        match (needs_price, self.price) {
+            (true, None) => return Err("The order needs a price"),
+            (false, Some(_)) => return Err("Only limit and stop-limit orders take a price"),
+            _ => {}
+        }
+        match (self.order_type.is_held(), self.stop_price) {
+            (true, None) => return Err("The order needs a stop price"),
+            (false, Some(_)) => return Err("Only stop and take-profit orders take a stop price"),
+            _ => {}
+        }
+        if self.price.is_some_and(|price| price <= 0)
+            || self.stop_price.is_some_and(|stop_price| stop_price <= 0)
+        {
+            return Err("The prices must be positive");
+        }
+
+        // a market order takes what is there and never rests on the book
+        let resting = matches!(self.time_in_force, TimeInForce::Gtc | TimeInForce::Gtd);
+        if resting && self.order_type == OrderType::Market {
+            return Err("A market order is either IOC or FOK");
+        }
+        // a released stop-market or take-profit is a market order as well, while held
+        // they can only wait or expire
+        if !resting
+            && matches!(
+                self.order_type,
+                OrderType::StopMarket | OrderType::TakeProfit
+            )
+        {
+            return Err("A stop-market or take-profit order is either GTC or GTD");
+        }
+        match (self.time_in_force, self.expires_at) {
+            (TimeInForce::Gtd, None) => return Err("A GTD order needs an expiry"),
+            (TimeInForce::Gtd, Some(expires_at)) if expires_at <= now => {
+                return Err("The expiry is in the past")
+            }
+            (TimeInForce::Gtd, Some(_)) => {}
+            (_, Some(_)) => return Err("Only GTD orders take an expiry"),
+            (_, None) => {}
+        }
+        return Ok(());
+    }
+
+    // The price the order's funds and risk are measured at: the limit price, or for
+    // orders executed at market the reference price (the stop price of held orders,
+    // the last trade otherwise) with room for a buy to move up
+    pub fn working_price(&self, side: Side, last_price: Option<i64>) -> Option<i64> {
+        if let Some(price) = self.price {
+            return Some(price);
+        }
+        let reference = self.stop_price.or(last_price)?;
+        match side {
+            Side::Buy => {
+                let collared = div_round(
+                    reference as i128 * (10_000 + MARKET_COLLAR_BPS) as i128,
+                    10_000,
+                    Rounding::Up,
+                )?;
+                return i64::try_from(collared).ok();
+            }
+            Side::Sell => return Some(reference),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn now() -> NaiveDateTime {
+        return NaiveDateTime::parse_from_str("2026-10-18 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    }
+
+    fn terms(order_type: OrderType) -> OrderTerms {
+        let price = matches!(order_type, OrderType::Limit | OrderType::StopLimit);
+        return OrderTerms {
+            order_type,
+            time_in_force: order_type.default_time_in_force(),
+            price: if price { Some(100) } else { None },
+            stop_price: if order_type.is_held() { Some(90) } else { None },
+            expires_at: None,
+        };
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for order_type in OrderType::ALL {
+            assert_eq!(order_type.as_str().parse::<OrderType>(), Ok(order_type));
+        }
+        for time_in_force in TimeInForce::ALL {
+            assert_eq!(
+                time_in_force.as_str().parse::<TimeInForce>(),
+                Ok(time_in_force)
+            );
+        }
+        assert_eq!("GTC".parse::<TimeInForce>(), Ok(TimeInForce::Gtc));
+        assert!("buy".parse::<OrderType>().is_err());
+    }
+
+    #[test]
+    fn every_type_has_its_own_rules() {
+        for order_type in OrderType::ALL {
+            assert_eq!(terms(order_type).validate(now()), Ok(()), "{}", order_type);
+        }
+
+        let mut market = terms(OrderType::Market);
+        market.price = Some(100);
+        assert!(market.validate(now()).is_err());
+        market.price = None;
+        market.time_in_force = TimeInForce::Gtc;
+        assert!(market.validate(now()).is_err());
+
+        let mut limit = terms(OrderType::Limit);
+        limit.price = None;
+        assert!(limit.validate(now()).is_err());
+        limit.price = Some(100);
+        limit.stop_price = Some(90);
+        assert!(limit.validate(now()).is_err());
+
+        let mut stop_limit = terms(OrderType::StopLimit);
+        stop_limit.stop_price = None;
+        assert!(stop_limit.validate(now()).is_err());
+        stop_limit.stop_price = Some(90);
+        stop_limit.time_in_force = TimeInForce::Ioc;
+        assert_eq!(stop_limit.validate(now()), Ok(()));
+
+        let mut take_profit = terms(OrderType::TakeProfit);
+        take_profit.time_in_force = TimeInForce::Fok;
+        assert!(take_profit.validate(now()).is_err());
+        take_profit.stop_price = Some(0);
+        take_profit.time_in_force = TimeInForce::Gtc;
+        assert!(take_profit.validate(now()).is_err());
+    }
+
+    #[test]
+    fn only_gtd_orders_expire() {
+        let mut limit = terms(OrderType::Limit);
+        limit.time_in_force = TimeInForce::Gtd;
+        assert!(limit.validate(now()).is_err());
+        limit.expires_at = Some(now() - Duration::minutes(1));
+        assert!(limit.validate(now()).is_err());
+        limit.expires_at = Some(now() + Duration::minutes(1));
+        assert_eq!(limit.validate(now()), Ok(()));
+        limit.time_in_force = TimeInForce::Gtc;
+        assert!(limit.validate(now()).is_err());
+    }
+
+    #[test]
+    fn stops_and_take_profits_trigger_in_opposite_directions() {
+        assert!(OrderType::StopMarket.is_triggered(Side::Sell, 90, 89));
+        assert!(OrderType::StopMarket.is_triggered(Side::Sell, 90, 90));
+        assert!(!OrderType::StopMarket.is_triggered(Side::Sell, 90, 91));
+        assert!(OrderType::StopLimit.is_triggered(Side::Buy, 110, 111));
+        assert!(!OrderType::StopLimit.is_triggered(Side::Buy, 110, 109));
+        assert!(OrderType::TakeProfit.is_triggered(Side::Sell, 110, 111));
+        assert!(!OrderType::TakeProfit.is_triggered(Side::Sell, 110, 109));
+        assert!(OrderType::TakeProfit.is_triggered(Side::Buy, 90, 89));
+        assert!(!OrderType::Limit.is_triggered(Side::Buy, 90, 89));
+    }
+
+    #[test]
+    fn market_buys_reserve_above_the_reference() {
+        let market = terms(OrderType::Market);
+        assert_eq!(market.working_price(Side::Buy, Some(1_000)), Some(1_050));
+        assert_eq!(market.working_price(Side::Sell, Some(1_000)), Some(1_000));
+        assert_eq!(market.working_price(Side::Buy, None), None);
+        // 101 * 1.05 = 106.05, rounded up
+        assert_eq!(market.working_price(Side::Buy, Some(101)), Some(107));
+
+        let stop = terms(OrderType::StopMarket);
+        assert_eq!(stop.working_price(Side::Buy, Some(1_000)), Some(95));
+        assert_eq!(
+            terms(OrderType::StopLimit).working_price(Side::Buy, None),
+            Some(100)
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use ::diesel::ExpressionMethods;
+use chrono::NaiveDateTime;
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::{diesel, Connection};
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::get_pair_symbol;
+use crate::db_lib::schema::orders;
+use crate::order::exchange::{Side, Symbol};
+use crate::order::order_type::{OrderTerms, OrderType, TimeInForce};
+use crate::order::status::OrderStatus;
+use crate::portfolio::ownership::{owned_portfolio, OwnedPortfolio, PortfolioKey};
+
+// An order the current user is allowed to act on
+#[derive(Debug, Clone)]
+pub struct OwnedOrder {
+    pub id: i32,
+    pub portfolio: OwnedPortfolio,
+    pub trading_pair_id: i32,
+    pub symbol: Symbol,
+    pub side: Side,
+    pub price: i64,
+    pub qty: i64,
+    pub status: OrderStatus,
+    pub exchange_order_id: Option<String>,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    pub stop_price: Option<i64>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+impl OwnedOrder {
+    // orders.price is the limit only for the types that have one
+    pub fn terms(&self) -> OrderTerms {
+        let limit = matches!(self.order_type, OrderType::Limit | OrderType::StopLimit);
+        return OrderTerms {
+            order_type: self.order_type,
+            time_in_force: self.time_in_force,
+            price: if limit { Some(self.price) } else { None },
+            stop_price: self.stop_price,
+            expires_at: self.expires_at,
+        };
+    }
+}
+
+// (portfolio_id, trading_pair_id, buyin, price, qty, status, exchange_order_id,
+//  (order_type, time_in_force, stop_price, expires_at))
+type OrderRow = (
+    i32,
+    i32,
+    bool,
+    i64,
+    i64,
+    String,
+    Option<String>,
+    (String, String, Option<i64>, Option<NaiveDateTime>),
+);
+
+// Resolve the order and make sure the user owns its portfolio, see `owned_portfolio`
+pub async fn owned_order(
+    user_auth: &Authorized,
+    order_id: i32,
+    action: &str,
+    mut db_conn: &mut Connection<database::PgDb>,
+) -> Result<OwnedOrder, (Status, Value)> {
+    let fetch_order = orders::table
+        .filter(orders::id.eq(order_id))
+        .select((
+            orders::portfolio_id,
+            orders::trading_pair_id,
+            orders::buyin,
+            orders::price,
+            orders::qty,
+            orders::status,
+            orders::exchange_order_id,
+            (
+                orders::order_type,
+                orders::time_in_force,
+                orders::stop_price,
+                orders::expires_at,
+            ),
+        ))
+        .first::<OrderRow>(&mut db_conn)
+        .await;
+    let (portfolio_id, trading_pair_id, buyin, price, qty, status, exchange_order_id, terms) =
+        match fetch_order {
+            Ok(order) => order,
+            Err(_) => {
+                return Err((
+                    Status::NotFound,
+                    json!({"status":"error", "message":"Order not found"}),
+                ));
+            }
+        };
+    let portfolio =
+        owned_portfolio(user_auth, PortfolioKey::Id(portfolio_id), action, db_conn).await?;
+
+    let (order_type, time_in_force, stop_price, expires_at) = terms;
+    let (status, symbol, order_type, time_in_force) = match (
+        status.parse::<OrderStatus>(),
+        get_pair_symbol(db_conn, trading_pair_id).await,
+        order_type.parse::<OrderType>(),
+        time_in_force.parse::<TimeInForce>(),
+    ) {
+        (Ok(status), Ok(symbol), Ok(order_type), Ok(time_in_force)) => {
+            (status, symbol, order_type, time_in_force)
+        }
+        _ => {
+            return Err((
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to fetch the order"}),
+            ));
+        }
+    };
+
+    return Ok(OwnedOrder {
+        id: order_id,
+        portfolio,
+        trading_pair_id,
+        symbol,
+        side: if buyin { Side::Buy } else { Side::Sell },
+        price,
+        qty,
+        status,
+        exchange_order_id,
+        order_type,
+        time_in_force,
+        stop_price,
+        expires_at,
+    });
+}
@@ -0,0 +1,822 @@
+use ::diesel::{ExpressionMethods, TextExpressionMethods};
+use chrono::{NaiveDateTime, Utc};
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::db_lib::schema::orders;
+use crate::market::candle::Tick;
+use crate::order::exchange::{
+    Exchange, ExchangeAdapter, ExchangeError, ExchangeFill, ExchangeOrder, ExchangeOrderRequest,
+    ExchangeOrderStatus, ExchangeOrderType, MarketTrade, Side, Symbol,
+};
+use crate::order::lifecycle::{refresh_order, set_status, LifecycleError};
+use crate::order::order_type::TimeInForce;
+use crate::order::status::OrderStatus;
+use crate::types::amount::{div_round, Rounding};
+
+// every paper order id starts with it, which is how the router tells them apart
+pub const PAPER_ORDER_PREFIX: &str = "paper-";
+
+pub type PaperEngine = Arc<PaperExchange>;
+
+// The [default.exchange.paper] table of Rocket.toml
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PaperConfig {
+    // how long an order takes to reach the simulated exchange
+    #[serde(default)]
+    pub latency_ms: u64,
+    // how far a taker fill moves away from the last price, in basis points
+    #[serde(default)]
+    pub slippage_bps: i64,
+    // fees in basis points of the notional, resting orders pay the maker fee
+    #[serde(default)]
+    pub maker_fee_bps: i64,
+    #[serde(default)]
+    pub taker_fee_bps: i64,
+}
+
+pub fn is_paper_order(exchange_order_id: &str) -> bool {
+    return exchange_order_id.starts_with(PAPER_ORDER_PREFIX);
+}
+
+struct PaperOrder {
+    request: ExchangeOrderRequest,
+    status: ExchangeOrderStatus,
+    fills: Vec<ExchangeFill>,
+}
+
+impl PaperOrder {
+    fn filled_quantity(&self) -> i64 {
+        return self.fills.iter().map(|fill| fill.quantity).sum();
+    }
+
+    fn is_open(&self) -> bool {
+        return matches!(
+            self.status,
+            ExchangeOrderStatus::New | ExchangeOrderStatus::PartiallyFilled
+        );
+    }
+
+    // whether a trade at `price` reaches the limit of the resting order
+    fn crosses(&self, price: i64) -> bool {
+        match self.request.side {
+            Side::Buy => return price <= self.request.price,
+            Side::Sell => return price >= self.request.price,
+        }
+    }
+
+    fn view(&self, exchange_order_id: &str) -> ExchangeOrder {
+        return ExchangeOrder {
+            exchange_order_id: exchange_order_id.to_string(),
+            status: self.status,
+            filled_quantity: self.filled_quantity(),
+        };
+    }
+}
+
+// The resting orders of one pair in arrival order and the last trade seen on it
+#[derive(Default)]
+struct PaperBook {
+    last_price: Option<i64>,
+    resting: Vec<String>,
+}
+
+#[derive(Default)]
+struct PaperState {
+    next_order_id: u64,
+    next_fill_id: u64,
+    orders: HashMap<String, PaperOrder>,
+    // by symbol, one per trading pair
+    books: HashMap<String, PaperBook>,
+}
+
+// Simulated exchange for paper portfolios. Orders that cross the last trade price are
+// filled in full at once as a taker, with slippage. Limit orders that do not cross
+// rest on the pair's book until a trade of the market data reaches them, they are
+// filled at their limit as a maker, up to the traded quantity. Everything lives in
+// memory: orders of a previous run are unknown and reported as cancelled, the ones
+// still open in the database are closed on liftoff.
+pub struct PaperExchange {
+    config: PaperConfig,
+    // tells the order ids of this run apart from those of the previous ones
+    run_id: i64,
+    state: Mutex<PaperState>,
+}
+
+impl PaperExchange {
+    pub fn new(config: PaperConfig) -> Self {
+        return PaperExchange {
+            config,
+            run_id: Utc::now().timestamp_millis(),
+            state: Mutex::new(PaperState::default()),
+        };
+    }
+
+    // the last price moved against the taker, buys round up and sells down
+    fn slipped(&self, side: Side, price: i64) -> i64 {
+        let (bps, rounding) = match side {
+            Side::Buy => (10_000 + self.config.slippage_bps, Rounding::Up),
+            Side::Sell => (10_000 - self.config.slippage_bps, Rounding::Down),
+        };
+        return div_round(price as i128 * bps as i128, 10_000, rounding)
+            .and_then(|price| i64::try_from(price).ok())
+            .unwrap_or(price)
+            .max(1);
+    }
+
+    async fn delay(&self) {
+        if self.config.latency_ms > 0 {
+            rocket::tokio::time::sleep(Duration::from_millis(self.config.latency_ms)).await;
+        }
+    }
+
+    fn push_fill(
+        state: &mut PaperState,
+        exchange_order_id: &str,
+        price: i64,
+        quantity: i64,
+        fee_bps: i64,
+        time: NaiveDateTime,
+    ) -> Result<(), ExchangeError> {
+        let PaperState {
+            orders,
+            next_fill_id,
+            ..
+        } = state;
+        let order = orders
+            .get_mut(exchange_order_id)
+            .ok_or(ExchangeError::NotFound)?;
+        let fee = order
+            .request
+            .symbol
+            .fee(price, quantity, fee_bps)
+            .ok_or(ExchangeError::Rejected("the fee overflows".to_string()))?;
+        *next_fill_id += 1;
+        let fill_id = format!("{}fill-{}", PAPER_ORDER_PREFIX, next_fill_id);
+        order.fills.push(ExchangeFill {
+            fill_id,
+            exchange_order_id: exchange_order_id.to_string(),
+            price,
+            quantity,
+            fee,
+            time,
+        });
+        order.status = if order.filled_quantity() >= order.request.quantity {
+            ExchangeOrderStatus::Filled
+        } else {
+            ExchangeOrderStatus::PartiallyFilled
+        };
+        return Ok(());
+    }
+
+    // Match the resting orders of the pair against the trades of the market data, best
+    // price first and then the oldest. A trade fills up to its quantity on each side.
+    // Returns the orders that got fills.
+    pub fn match_trades(&self, symbol: &Symbol, ticks: &[Tick]) -> Vec<String> {
+        let mut ticks = ticks.to_vec();
+        ticks.sort_by_key(|tick| tick.time);
+        let mut state = self.state.lock().unwrap();
+        let key = symbol.to_string();
+        let mut matched: Vec<String> = vec![];
+        for tick in ticks {
+            let resting = match state.books.get(&key) {
+                Some(book) => book.resting.clone(),
+                None => vec![],
+            };
+            let mut crossing: Vec<(i64, usize, String)> = resting
+                .into_iter()
+                .enumerate()
+                .filter_map(|(arrival, exchange_order_id)| {
+                    let order = state.orders.get(&exchange_order_id)?;
+                    if !order.is_open() || !order.crosses(tick.price) {
+                        return None;
+                    }
+                    let priority = match order.request.side {
+                        Side::Buy => -order.request.price,
+                        Side::Sell => order.request.price,
+                    };
+                    return Some((priority, arrival, exchange_order_id));
+                })
+                .collect();
+            crossing.sort();
+
+            let mut buy_liquidity = tick.qty;
+            let mut sell_liquidity = tick.qty;
+            for (_, _, exchange_order_id) in crossing {
+                let (side, price, remaining) = match state.orders.get(&exchange_order_id) {
+                    Some(order) => (
+                        order.request.side,
+                        order.request.price,
+                        order.request.quantity - order.filled_quantity(),
+                    ),
+                    None => continue,
+                };
+                let liquidity = match side {
+                    Side::Buy => &mut buy_liquidity,
+                    Side::Sell => &mut sell_liquidity,
+                };
+                let quantity = remaining.min(*liquidity);
+                if quantity <= 0 {
+                    continue;
+                }
+                let fee_bps = self.config.maker_fee_bps;
+                // a fill whose fee overflows is skipped and leaves the trade to the others
+                let filled = PaperExchange::push_fill(
+                    &mut state,
+                    &exchange_order_id,
+                    price,
+                    quantity,
+                    fee_bps,
+                    tick.time,
+                );
+                if filled.is_err() {
+                    continue;
+                }
+                *liquidity -= quantity;
+                if !matched.contains(&exchange_order_id) {
+                    matched.push(exchange_order_id);
+                }
+            }
+
+            let PaperState { orders, books, .. } = &mut *state;
+            let book = books.entry(key.clone()).or_default();
+            book.last_price = Some(tick.price);
+            book.resting.retain(|exchange_order_id| {
+                orders
+                    .get(exchange_order_id)
+                    .is_some_and(|order| order.is_open())
+            });
+        }
+        return matched;
+    }
+
+    // whether the order was placed in this run
+    pub fn knows(&self, exchange_order_id: &str) -> bool {
+        return self
+            .state
+            .lock()
+            .unwrap()
+            .orders
+            .contains_key(exchange_order_id);
+    }
+
+    fn lost(exchange_order_id: &str) -> ExchangeOrder {
+        return ExchangeOrder {
+            exchange_order_id: exchange_order_id.to_string(),
+            status: ExchangeOrderStatus::Cancelled,
+            filled_quantity: 0,
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl ExchangeAdapter for PaperExchange {
+    async fn place_order(
+        &self,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        if request.price <= 0 || request.quantity <= 0 {
+            return Err(ExchangeError::Rejected(
+                "price and quantity must be positive".to_string(),
+            ));
+        }
+        self.delay().await;
+
+        let mut state = self.state.lock().unwrap();
+        state.next_order_id += 1;
+        let exchange_order_id = format!(
+            "{}{}-{}",
+            PAPER_ORDER_PREFIX, self.run_id, state.next_order_id
+        );
+        state.orders.insert(
+            exchange_order_id.clone(),
+            PaperOrder {
+                request: request.clone(),
+                status: ExchangeOrderStatus::New,
+                fills: vec![],
+            },
+        );
+
+        // a market order goes by the reference price it carries until the book has
+        // seen a trade
+        let key = request.symbol.to_string();
+        let last_price = state.books.get(&key).and_then(|book| book.last_price);
+        let taker_price = match (request.order_type, last_price) {
+            (ExchangeOrderType::Market, last_price) => {
+                Some(self.slipped(request.side, last_price.unwrap_or(request.price)))
+            }
+            (ExchangeOrderType::Limit, Some(last_price)) => {
+                let slipped = self.slipped(request.side, last_price);
+                match request.side {
+                    Side::Buy if request.price >= last_price => Some(slipped.min(request.price)),
+                    Side::Sell if request.price <= last_price => Some(slipped.max(request.price)),
+                    _ => None,
+                }
+            }
+            (ExchangeOrderType::Limit, None) => None,
+        };
+        let resting = request.order_type == ExchangeOrderType::Limit
+            && matches!(request.time_in_force, TimeInForce::Gtc | TimeInForce::Gtd);
+        match taker_price {
+            Some(price) => {
+                let fee_bps = self.config.taker_fee_bps;
+                let filled = PaperExchange::push_fill(
+                    &mut state,
+                    &exchange_order_id,
+                    price,
+                    request.quantity,
+                    fee_bps,
+                    Utc::now().naive_utc(),
+                );
+                if let Err(error) = filled {
+                    state.orders.remove(&exchange_order_id);
+                    return Err(error);
+                }
+            }
+            None if resting => {
+                state
+                    .books
+                    .entry(key)
+                    .or_default()
+                    .resting
+                    .push(exchange_order_id.clone());
+            }
+            None => {
+                // IOC and FOK orders that do not cross have nothing to fill against
+                if let Some(order) = state.orders.get_mut(&exchange_order_id) {
+                    order.status = ExchangeOrderStatus::Expired;
+                }
+            }
+        }
+        let order = state.orders.get(&exchange_order_id).unwrap();
+        return Ok(order.view(&exchange_order_id));
+    }
+
+    async fn cancel_order(
+        &self,
+        _symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        self.delay().await;
+        let mut state = self.state.lock().unwrap();
+        let order = match state.orders.get_mut(exchange_order_id) {
+            Some(order) => order,
+            None => return Ok(PaperExchange::lost(exchange_order_id)),
+        };
+        if !order.is_open() {
+            return Err(ExchangeError::Rejected("order is closed".to_string()));
+        }
+        order.status = ExchangeOrderStatus::Cancelled;
+        return Ok(order.view(exchange_order_id));
+    }
+
+    // resting orders are amended in place and keep their place in the book
+    async fn amend_order(
+        &self,
+        exchange_order_id: &str,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        self.delay().await;
+        let mut state = self.state.lock().unwrap();
+        // an order of a previous run is gone, as if cancelled
+        let order = state
+            .orders
+            .get_mut(exchange_order_id)
+            .ok_or(ExchangeError::Rejected("order is closed".to_string()))?;
+        if !order.is_open() {
+            return Err(ExchangeError::Rejected("order is closed".to_string()));
+        }
+        let filled = order.filled_quantity();
+        if request.price <= 0 || request.quantity < filled || request.quantity <= 0 {
+            return Err(ExchangeError::Rejected(
+                "quantity is below the filled quantity".to_string(),
+            ));
+        }
+        order.request.price = request.price;
+        order.request.quantity = request.quantity;
+        if request.quantity == filled {
+            order.status = ExchangeOrderStatus::Filled;
+        }
+        return Ok(order.view(exchange_order_id));
+    }
+
+    async fn query_order(
+        &self,
+        _symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        let state = self.state.lock().unwrap();
+        match state.orders.get(exchange_order_id) {
+            Some(order) => return Ok(order.view(exchange_order_id)),
+            None => return Ok(PaperExchange::lost(exchange_order_id)),
+        }
+    }
+
+    async fn list_fills(
+        &self,
+        _symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<Vec<ExchangeFill>, ExchangeError> {
+        let state = self.state.lock().unwrap();
+        match state.orders.get(exchange_order_id) {
+            Some(order) => return Ok(order.fills.clone()),
+            None => return Ok(vec![]),
+        }
+    }
+
+    // the only trades on the paper exchange are the fills of its own orders
+    async fn recent_trades(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<MarketTrade>, ExchangeError> {
+        let state = self.state.lock().unwrap();
+        let mut trades: Vec<MarketTrade> = state
+            .orders
+            .values()
+            .filter(|order| order.request.symbol == *symbol)
+            .flat_map(|order| order.fills.iter())
+            .filter(|fill| since.is_none_or(|since| fill.time > since))
+            .map(|fill| MarketTrade {
+                trade_id: fill.fill_id.clone(),
+                price: fill.price,
+                quantity: fill.quantity,
+                time: fill.time,
+            })
+            .collect();
+        trades.sort_by_key(|trade| trade.time);
+        return Ok(trades);
+    }
+}
+
+// Sends the orders of paper portfolios to the paper exchange and all the others to the
+// live adapter, an order is found again by its id. The market data comes from the
+// live adapter.
+pub struct RoutedExchange {
+    live: Exchange,
+    paper: PaperEngine,
+}
+
+impl RoutedExchange {
+    pub fn new(live: Exchange, paper: PaperEngine) -> Self {
+        return RoutedExchange { live, paper };
+    }
+
+    fn route(&self, exchange_order_id: &str) -> &dyn ExchangeAdapter {
+        if is_paper_order(exchange_order_id) {
+            return self.paper.as_ref();
+        }
+        return self.live.as_ref();
+    }
+}
+
+#[rocket::async_trait]
+impl ExchangeAdapter for RoutedExchange {
+    async fn place_order(
+        &self,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        if request.paper {
+            return self.paper.place_order(request).await;
+        }
+        return self.live.place_order(request).await;
+    }
+
+    async fn cancel_order(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        return self
+            .route(exchange_order_id)
+            .cancel_order(symbol, exchange_order_id)
+            .await;
+    }
+
+    async fn amend_order(
+        &self,
+        exchange_order_id: &str,
+        request: &ExchangeOrderRequest,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        // a replacement must not cross over to the other side
+        if request.paper != is_paper_order(exchange_order_id) {
+            return Err(ExchangeError::Rejected(
+                "the order belongs to the other exchange".to_string(),
+            ));
+        }
+        return self
+            .route(exchange_order_id)
+            .amend_order(exchange_order_id, request)
+            .await;
+    }
+
+    async fn query_order(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<ExchangeOrder, ExchangeError> {
+        return self
+            .route(exchange_order_id)
+            .query_order(symbol, exchange_order_id)
+            .await;
+    }
+
+    async fn list_fills(
+        &self,
+        symbol: &Symbol,
+        exchange_order_id: &str,
+    ) -> Result<Vec<ExchangeFill>, ExchangeError> {
+        return self
+            .route(exchange_order_id)
+            .list_fills(symbol, exchange_order_id)
+            .await;
+    }
+
+    async fn recent_trades(
+        &self,
+        symbol: &Symbol,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<MarketTrade>, ExchangeError> {
+        return self.live.recent_trades(symbol, since).await;
+    }
+}
+
+// The open paper orders of a previous run went with its memory. They are cancelled,
+// which gives their reservations back. Returns the cancelled orders.
+pub async fn close_lost_orders(
+    conn: &mut AsyncPgConnection,
+    paper: &PaperExchange,
+) -> Result<Vec<i32>, LifecycleError> {
+    let open_statuses: Vec<&str> = OrderStatus::ALL
+        .into_iter()
+        .filter(|status| status.is_open())
+        .map(|status| status.as_str())
+        .collect();
+    let open = orders::table
+        .filter(orders::status.eq_any(open_statuses))
+        .filter(orders::exchange_order_id.like(format!("{}%", PAPER_ORDER_PREFIX)))
+        .select((orders::id, orders::exchange_order_id))
+        .load::<(i32, Option<String>)>(conn)
+        .await?;
+    let mut closed = vec![];
+    for (order_id, exchange_order_id) in open {
+        if exchange_order_id.is_some_and(|id| paper.knows(&id)) {
+            continue;
+        }
+        set_status(conn, order_id, OrderStatus::Cancelled).await?;
+        closed.push(order_id);
+    }
+    return Ok(closed);
+}
+
+// Feed the trades of a pair to the paper books and bring the orders that got fills up
+// to date, through the same path as the orders of a live exchange. Returns the orders
+// that could not be synced with the reason.
+pub async fn fill_paper_orders(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    paper: &PaperExchange,
+    symbol: &Symbol,
+    ticks: &[Tick],
+) -> Result<Vec<(String, String)>, LifecycleError> {
+    let matched = paper.match_trades(symbol, ticks);
+    if matched.is_empty() {
+        return Ok(vec![]);
+    }
+    let order_ids = orders::table
+        .filter(orders::exchange_order_id.eq_any(&matched))
+        .select((orders::id, orders::exchange_order_id))
+        .load::<(i32, Option<String>)>(conn)
+        .await?;
+
+    let mut failed = vec![];
+    for exchange_order_id in matched {
+        let order_id = order_ids
+            .iter()
+            .find(|(_, id)| id.as_deref() == Some(exchange_order_id.as_str()))
+            .map(|(order_id, _)| *order_id);
+        let result = match order_id {
+            Some(order_id) => {
+                refresh_order(conn, exchange, order_id, symbol, &exchange_order_id).await
+            }
+            None => Err(LifecycleError("No order has this exchange id".to_string())),
+        };
+        if let Err(err) = result {
+            failed.push((exchange_order_id, err.0));
+        }
+    }
+    return Ok(failed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::mock::MockExchange;
+
+    fn symbol() -> Symbol {
+        return Symbol {
+            base: "BTC".to_string(),
+            quote: "USDT".to_string(),
+            base_scale: 0,
+            quote_scale: 0,
+        };
+    }
+
+    fn request(side: Side, order_type: ExchangeOrderType, price: i64) -> ExchangeOrderRequest {
+        return ExchangeOrderRequest {
+            client_order_id: "1".to_string(),
+            symbol: symbol(),
+            side,
+            order_type,
+            price,
+            quantity: 10,
+            time_in_force: TimeInForce::Gtc,
+            paper: true,
+        };
+    }
+
+    fn ticks(trades: &[(i64, i64)]) -> Vec<Tick> {
+        let start =
+            NaiveDateTime::parse_from_str("2026-10-18 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        return trades
+            .iter()
+            .enumerate()
+            .map(|(i, (price, qty))| Tick {
+                price: *price,
+                qty: *qty,
+                time: start + chrono::Duration::seconds(i as i64),
+            })
+            .collect();
+    }
+
+    fn paper() -> PaperExchange {
+        return PaperExchange::new(PaperConfig {
+            latency_ms: 0,
+            slippage_bps: 100,
+            maker_fee_bps: 10,
+            taker_fee_bps: 50,
+        });
+    }
+
+    #[rocket::async_test]
+    async fn a_fill_whose_fee_overflows_is_rejected() {
+        let exchange = paper();
+        let sell = request(Side::Sell, ExchangeOrderType::Market, i64::MAX / 2);
+        assert!(matches!(
+            exchange.place_order(&sell).await,
+            Err(ExchangeError::Rejected(_))
+        ));
+
+        // the resting order stays open and the trade fills the next one
+        let huge = request(Side::Buy, ExchangeOrderType::Limit, i64::MAX / 2);
+        let huge = exchange.place_order(&huge).await.unwrap();
+        let buy = request(Side::Buy, ExchangeOrderType::Limit, 1_000);
+        let buy = exchange.place_order(&buy).await.unwrap();
+        exchange.match_trades(&symbol(), &ticks(&[(900, 10)]));
+        let huge = exchange
+            .query_order(&symbol(), &huge.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(huge.status, ExchangeOrderStatus::New);
+        let buy = exchange
+            .query_order(&symbol(), &buy.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(buy.status, ExchangeOrderStatus::Filled);
+    }
+
+    #[rocket::async_test]
+    async fn takers_fill_at_once_with_slippage() {
+        let exchange = paper();
+        // no trade seen yet, the market order goes by its reference
+        let buy = request(Side::Buy, ExchangeOrderType::Market, 1_000);
+        let order = exchange.place_order(&buy).await.unwrap();
+        assert!(is_paper_order(&order.exchange_order_id));
+        assert_eq!(order.status, ExchangeOrderStatus::Filled);
+        let fills = exchange
+            .list_fills(&symbol(), &order.exchange_order_id)
+            .await
+            .unwrap();
+        // 1% above the reference, 0.5% of 10 * 1010
+        assert_eq!(
+            (fills[0].price, fills[0].quantity, fills[0].fee),
+            (1_010, 10, 50)
+        );
+
+        exchange.match_trades(&symbol(), &ticks(&[(2_000, 1)]));
+        let sell = request(Side::Sell, ExchangeOrderType::Limit, 1_990);
+        let order = exchange.place_order(&sell).await.unwrap();
+        let fills = exchange
+            .list_fills(&symbol(), &order.exchange_order_id)
+            .await
+            .unwrap();
+        // 1980 after slippage, but not below the limit
+        assert_eq!(fills[0].price, 1_990);
+    }
+
+    #[rocket::async_test]
+    async fn resting_orders_fill_from_the_market_data() {
+        let exchange = paper();
+        exchange.match_trades(&symbol(), &ticks(&[(1_000, 5)]));
+        let cheap = exchange
+            .place_order(&request(Side::Buy, ExchangeOrderType::Limit, 990))
+            .await
+            .unwrap();
+        let cheaper = exchange
+            .place_order(&request(Side::Buy, ExchangeOrderType::Limit, 980))
+            .await
+            .unwrap();
+        assert_eq!(cheap.status, ExchangeOrderStatus::New);
+
+        // the better bid is filled first, at its own limit as a maker
+        let matched = exchange.match_trades(&symbol(), &ticks(&[(990, 3), (985, 8)]));
+        assert_eq!(matched, vec![cheap.exchange_order_id.clone()]);
+        let order = exchange
+            .query_order(&symbol(), &cheap.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Filled);
+        assert_eq!(order.filled_quantity, 10);
+        let fills = exchange
+            .list_fills(&symbol(), &cheap.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(
+            (fills[1].price, fills[1].quantity, fills[1].fee),
+            (990, 7, 6)
+        );
+
+        let matched = exchange.match_trades(&symbol(), &ticks(&[(980, 4)]));
+        assert_eq!(matched, vec![cheaper.exchange_order_id.clone()]);
+        let order = exchange
+            .cancel_order(&symbol(), &cheaper.exchange_order_id)
+            .await
+            .unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Cancelled);
+        assert_eq!(order.filled_quantity, 4);
+    }
+
+    #[rocket::async_test]
+    async fn orders_that_cannot_rest_expire() {
+        let exchange = paper();
+        exchange.match_trades(&symbol(), &ticks(&[(1_000, 5)]));
+        let ioc = ExchangeOrderRequest {
+            time_in_force: TimeInForce::Ioc,
+            ..request(Side::Buy, ExchangeOrderType::Limit, 900)
+        };
+        let order = exchange.place_order(&ioc).await.unwrap();
+        assert_eq!(order.status, ExchangeOrderStatus::Expired);
+        assert!(exchange
+            .match_trades(&symbol(), &ticks(&[(900, 5)]))
+            .is_empty());
+
+        // an order of a previous run is gone
+        let lost = exchange.query_order(&symbol(), "paper-1-1").await.unwrap();
+        assert_eq!(lost.status, ExchangeOrderStatus::Cancelled);
+    }
+
+    #[rocket::async_test]
+    async fn paper_orders_never_reach_the_live_exchange() {
+        let live = Arc::new(MockExchange::new(0));
+        let router = RoutedExchange::new(live.clone(), Arc::new(paper()));
+        let order = router
+            .place_order(&request(Side::Buy, ExchangeOrderType::Market, 1_000))
+            .await
+            .unwrap();
+        assert!(is_paper_order(&order.exchange_order_id));
+        assert!(live
+            .recent_trades(&symbol(), None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            router
+                .list_fills(&symbol(), &order.exchange_order_id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let live_request = ExchangeOrderRequest {
+            paper: false,
+            ..request(Side::Buy, ExchangeOrderType::Limit, 1_000)
+        };
+        let order = router.place_order(&live_request).await.unwrap();
+        assert_eq!(order.exchange_order_id, "mock-1");
+        assert!(matches!(
+            router
+                .amend_order(
+                    &order.exchange_order_id,
+                    &request(Side::Buy, ExchangeOrderType::Limit, 1_000)
+                )
+                .await,
+            Err(ExchangeError::Rejected(_))
+        ));
+    }
+}
@@ -1,14 +1,49 @@
+use chrono::{NaiveDateTime, Utc};
 use rocket::http::Status;
+use rocket::State;
 use rocket_db_pools::Connection;
 
-use crate::auth::validation::UserAuth;
+use crate::auth::permission::Authorized;
 use crate::db_lib::database;
 use crate::db_lib::query::*;
-use crate::db_lib::schema::{orders, portfolios, positions, quotations};
-use crate::order::bbgo;
+use crate::db_lib::schema::{order_fills, orders, portfolios, positions, quotations};
+use crate::market::ingest::last_trade;
+use crate::market::route::parse_time;
+use crate::market::MarketConfig;
+use crate::order::balance::{reservation_for, set_reservation, BalanceError};
+use crate::order::exchange::{
+    Exchange, ExchangeError, ExchangeOrderRequest, ExchangeOrderType, Side, Symbol,
+};
+use crate::order::idempotency::{
+    claim_key, find_client_order, finish_key, valid_client_order_id, valid_key, Claim,
+    IdempotencyConfig, IdempotencyKey,
+};
+use crate::order::lifecycle::{
+    fill_summaries, keep_replaced_order, record_and_settle_fills, refresh_order, reserve_remaining,
+    set_status, submit_order, sync_order, FillSummary, LifecycleError, SubmitError,
+};
+use crate::order::order_type::{OrderTerms, OrderType, TimeInForce};
+use crate::order::ownership::{owned_order, OwnedOrder};
+use crate::order::status::OrderStatus;
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
+use crate::risk::engine::check_order;
+use crate::risk::halt::{active_halt, halt_on_breach};
+use crate::risk::rule::OrderIntent;
 use rocket::serde::json::{json, Json, Value};
 use rocket_db_pools::diesel::prelude::*;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::AsyncPgConnection;
 use serde::{Deserialize, Serialize};
+
+fn side_name(buyin: bool) -> &'static str {
+    if buyin {
+        return "buy";
+    }
+    return "sell";
+}
+
+// price is what the order is reserved at: the limit, or the collared reference of
+// orders executed at market
 #[get("/api/order?<id>&<st>&<len>")]
 pub async fn get_order(
     id: i32,
@@ -16,47 +51,96 @@ pub async fn get_order(
     len: Option<i32>,
     // filter: String,
     mut db_conn: Connection<database::PgDb>,
-    _user_auth: UserAuth,
+    _user_auth: Authorized,
 ) -> (Status, Value) {
+    if let Err(err) =
+        owned_portfolio(&_user_auth, PortfolioKey::Id(id), "get_order", &mut db_conn).await
+    {
+        return err;
+    }
     let st = st.unwrap_or(0);
     let len = len.unwrap_or(10);
     let fetch_order = orders::table
         .inner_join(quotations::table.on(orders::quotation_id.eq(quotations::id)))
         .inner_join(positions::table.on(quotations::position_id.eq(positions::id)))
         .filter(orders::portfolio_id.eq(id))
+        // newest first, so the pages stay put while orders come in
+        .order(orders::id.desc())
         .select((
             orders::id,
             orders::buyin,
-            orders::state,
+            orders::status,
             orders::trading_pair_id,
             orders::qty,
             orders::price,
+            (
+                orders::order_type,
+                orders::time_in_force,
+                orders::stop_price,
+                orders::client_order_id,
+            ),
         ))
         .offset(st.into())
         .limit(len.into())
-        .load::<(i32, bool, i32, i32, i64, i64)>(&mut db_conn)
-        .await
-        .unwrap();
+        .load::<(
+            i32,
+            bool,
+            String,
+            i32,
+            i64,
+            i64,
+            (String, String, Option<i64>, Option<String>),
+        )>(&mut db_conn)
+        .await;
+    let fetch_order = match fetch_order {
+        Ok(fetch_order) => fetch_order,
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to fetch the orders"}),
+            );
+        }
+    };
+
+    let order_ids: Vec<i32> = fetch_order.iter().map(|order| order.0).collect();
+    let summaries = match fill_summaries(&mut db_conn, &order_ids).await {
+        Ok(summaries) => summaries,
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": err.0}),
+            );
+        }
+    };
 
     let mut response_data: Vec<Value> = vec![];
-    for (id, buyin, state, trading_pairs_id, qty, price) in fetch_order {
-        let (base, quote) = get_trading_pair(&mut db_conn, trading_pairs_id)
-            .await
-            .unwrap();
-        let state = match state {
-            0 => "pending",
-            1 => "success",
-            2 => "fail",
-            _ => "unknown",
+    for (id, buyin, status, trading_pairs_id, qty, price, terms) in fetch_order {
+        let (order_type, time_in_force, stop_price, client_order_id) = terms;
+        let symbol = match get_pair_symbol(&mut db_conn, trading_pairs_id).await {
+            Ok(symbol) => symbol,
+            Err(_) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status": "error", "message": "Fail to fetch the trading pair"}),
+                );
+            }
         };
+        let summary = summaries.get(&id).copied().unwrap_or_default();
         response_data.push(json!({
             "id": id,
             "buyin": buyin,
-            "state": state,
-            "base": base,
-            "quote": quote,
-            "qty": qty,
-            "price": price
+            "side": side_name(buyin),
+            "order_type": order_type,
+            "time_in_force": time_in_force,
+            "status": status,
+            "base": symbol.base,
+            "quote": symbol.quote,
+            "qty": symbol.format_qty(qty),
+            "price": symbol.format_price(price),
+            "stop_price": stop_price.map(|price| symbol.format_price(price)),
+            "client_order_id": client_order_id,
+            "filled_qty": symbol.format_qty(summary.filled_qty),
+            "avg_price": summary.avg_price.map(|price| symbol.format_price(price)),
         }));
     }
     let len = response_data.len();
@@ -66,60 +150,1180 @@ pub async fn get_order(
     );
 }
 
+// one order with its fills, open orders are refreshed from the exchange first
+#[get("/api/order/<order_id>")]
+pub async fn get_order_detail(
+    order_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    exchange: &State<Exchange>,
+) -> (Status, Value) {
+    let order = match owned_order(&_user_auth, order_id, "get_order_detail", &mut db_conn).await {
+        Ok(order) => order,
+        Err(err) => return err,
+    };
+    return order_detail(&mut db_conn, &order, exchange).await;
+}
+
+// the same as `get_order_detail`, for the order the user named in the portfolio
+#[get("/api/order/by-client-id/<client_order_id>?<portfolio_id>")]
+pub async fn get_order_by_client_id(
+    client_order_id: String,
+    portfolio_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    exchange: &State<Exchange>,
+) -> (Status, Value) {
+    let order_id = match find_client_order(&mut db_conn, portfolio_id, &client_order_id).await {
+        Ok(Some((order_id, _))) => order_id,
+        Ok(None) => {
+            // the ownership error comes first, so others' ids are not revealed
+            if let Err(err) = owned_portfolio(
+                &_user_auth,
+                PortfolioKey::Id(portfolio_id),
+                "get_order_by_client_id",
+                &mut db_conn,
+            )
+            .await
+            {
+                return err;
+            }
+            return (
+                Status::NotFound,
+                json!({"status": "error", "message": "No order with this client order id"}),
+            );
+        }
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to fetch the order"}),
+            );
+        }
+    };
+    let order = match owned_order(
+        &_user_auth,
+        order_id,
+        "get_order_by_client_id",
+        &mut db_conn,
+    )
+    .await
+    {
+        Ok(order) => order,
+        Err(err) => return err,
+    };
+    return order_detail(&mut db_conn, &order, exchange).await;
+}
+
+async fn order_detail(
+    db_conn: &mut Connection<database::PgDb>,
+    order: &OwnedOrder,
+    exchange: &State<Exchange>,
+) -> (Status, Value) {
+    let order_id = order.id;
+    // a stale answer is better than none, refresh errors are not fatal
+    if let (true, Some(exchange_order_id)) = (order.status.is_open(), &order.exchange_order_id) {
+        let _ = refresh_order(
+            db_conn,
+            exchange.inner(),
+            order_id,
+            &order.symbol,
+            exchange_order_id,
+        )
+        .await;
+    }
+
+    let fetch_order = orders::table
+        .filter(orders::id.eq(order_id))
+        .select((
+            orders::buyin,
+            orders::status,
+            orders::price,
+            orders::qty,
+            orders::time_stamp,
+            orders::exchange_order_id,
+            orders::triggered_at,
+            orders::client_order_id,
+        ))
+        .first::<(
+            bool,
+            String,
+            i64,
+            i64,
+            NaiveDateTime,
+            Option<String>,
+            Option<NaiveDateTime>,
+            Option<String>,
+        )>(db_conn)
+        .await;
+    let fetch_fills = order_fills::table
+        .filter(order_fills::order_id.eq(order_id))
+        .order(order_fills::time_stamp.asc())
+        .select((
+            order_fills::exchange_fill_id,
+            order_fills::price,
+            order_fills::qty,
+            order_fills::fee,
+            order_fills::time_stamp,
+        ))
+        .load::<(String, i64, i64, i64, NaiveDateTime)>(db_conn)
+        .await;
+    let (row, fills) = if let (Ok(row), Ok(fills)) = (fetch_order, fetch_fills) {
+        (row, fills)
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status": "error", "message": "Fail to fetch the order"}),
+        );
+    };
+    let (buyin, status, price, qty, time_stamp, exchange_order_id, triggered_at, client_order_id) =
+        row;
+
+    let summary = FillSummary::from_fills(
+        &fills
+            .iter()
+            .map(|(_, price, qty, fee, _)| (*price, *qty, *fee))
+            .collect::<Vec<_>>(),
+    );
+    let fills: Vec<Value> = fills
+        .into_iter()
+        .map(|(fill_id, price, qty, fee, time_stamp)| {
+            json!({
+                "fill_id": fill_id,
+                "price": order.symbol.format_price(price),
+                "qty": order.symbol.format_qty(qty),
+                "fee": order.symbol.format_price(fee),
+                "time_stamp": time_stamp.to_string(),
+            })
+        })
+        .collect();
+    return (
+        Status::Ok,
+        json!({"status": "successful", "data": {
+            "id": order_id,
+            "portfolio_id": order.portfolio.id,
+            "buyin": buyin,
+            "side": side_name(buyin),
+            "order_type": order.order_type.as_str(),
+            "time_in_force": order.time_in_force.as_str(),
+            "status": status,
+            "base": order.symbol.base,
+            "quote": order.symbol.quote,
+            "qty": order.symbol.format_qty(qty),
+            "price": order.symbol.format_price(price),
+            "stop_price": order.stop_price.map(|price| order.symbol.format_price(price)),
+            "expires_at": order.expires_at.map(|time| time.to_string()),
+            "triggered_at": triggered_at.map(|time| time.to_string()),
+            "time_stamp": time_stamp.to_string(),
+            "exchange_order_id": exchange_order_id,
+            "client_order_id": client_order_id,
+            "filled_qty": order.symbol.format_qty(summary.filled_qty),
+            "avg_price": summary.avg_price.map(|price| order.symbol.format_price(price)),
+            "fee": order.symbol.format_price(summary.fee),
+            "fills": fills,
+        }}),
+    );
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct OrderData {
     base: String,
     quote: String,
-    order_type: String,
-    price: String,
+    side: String,
+    // limit when left out
+    order_type: Option<String>,
+    // the limit of limit and stop-limit orders
+    price: Option<String>,
+    // the trigger of stop and take-profit orders
+    stop_price: Option<String>,
     quantity: String,
+    // IOC for market orders and GTC for the others when left out
+    time_in_force: Option<String>,
+    // required for GTD, unix seconds or "YYYY-MM-DDTHH:MM:SS" in UTC
+    expires_at: Option<String>,
+    // unique within the portfolio, chosen by the user
+    client_order_id: Option<String>,
     portfolio_id: i32,
 }
+
+fn bad_request(message: &str) -> (Status, Value) {
+    return (
+        Status::BadRequest,
+        json!({"status": "error", "message": message}),
+    );
+}
+
+// the decimal of an optional price, in the pair's quote units
+fn parse_optional_price(symbol: &Symbol, price: &Option<String>) -> Result<Option<i64>, String> {
+    match price {
+        Some(price) => {
+            return symbol
+                .parse_price(price)
+                .map(Some)
+                .map_err(|err| err.message())
+        }
+        None => return Ok(None),
+    }
+}
+
+// the side, type and time in force of an order, before any of its amounts
+fn parse_kind(order_data: &OrderData) -> Result<(Side, OrderType, TimeInForce), &'static str> {
+    let side = match order_data.side.as_str() {
+        "buy" => Side::Buy,
+        "sell" => Side::Sell,
+        _ => return Err("The side is either buy or sell"),
+    };
+    let order_type = match &order_data.order_type {
+        Some(order_type) => order_type.parse::<OrderType>()?,
+        None => OrderType::Limit,
+    };
+    let time_in_force = match &order_data.time_in_force {
+        Some(time_in_force) => time_in_force.parse::<TimeInForce>()?,
+        None => order_type.default_time_in_force(),
+    };
+    return Ok((side, order_type, time_in_force));
+}
+
+// A request with an Idempotency-Key places at most one order, a repeat gets the
+// result of the first one back
 #[post("/api/order", data = "<order_data>")]
 pub async fn place_order(
     mut db_conn: Connection<database::PgDb>,
-    _user_auth: UserAuth,
+    _user_auth: Authorized,
+    idempotency_key: IdempotencyKey,
+    idempotency: &State<IdempotencyConfig>,
     order_data: Json<OrderData>,
+    exchange: &State<Exchange>,
+    market: &State<MarketConfig>,
 ) -> (Status, Value) {
-    let user_id = _user_auth.user_id;
-    let order_id = bbgo::handle_order(
-        &order_data.base,
-        &order_data.quote,
-        &order_data.order_type,
-        &order_data.price,
-        &order_data.quantity,
-    );
-    let trading_pairs = get_trading_pair_id(&mut db_conn, (&order_data.base, &order_data.quote))
-        .await
-        .unwrap();
+    let key = match idempotency_key.0 {
+        Some(key) => key,
+        None => {
+            return new_order(&mut db_conn, &_user_auth, &order_data, exchange, market).await;
+        }
+    };
+    if !valid_key(&key) {
+        return bad_request("Invalid Idempotency-Key");
+    }
+    let claim = claim_key(
+        &mut db_conn,
+        _user_auth.user_id,
+        &key,
+        &json!(*order_data),
+        chrono::Duration::seconds(idempotency.stale_seconds),
+    )
+    .await;
+    let key_id = match claim {
+        Ok(Claim::New(key_id)) => key_id,
+        Ok(Claim::Replay(status, response)) => return (status, response),
+        Ok(Claim::InProgress) => {
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": "The first request with this Idempotency-Key is still in progress"}),
+            );
+        }
+        Ok(Claim::Mismatch) => {
+            return (
+                Status::UnprocessableEntity,
+                json!({"status": "error", "message": "The Idempotency-Key was used for a different request"}),
+            );
+        }
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to check the Idempotency-Key"}),
+            );
+        }
+    };
+    let response = new_order(&mut db_conn, &_user_auth, &order_data, exchange, market).await;
+    // the order stands either way, a repeat that misses the result is answered
+    // as still in progress rather than placed twice
+    if let Err(err) = finish_key(&mut db_conn, key_id, &response).await {
+        println!(
+            "Orders: fail to store the result of key {}: {}",
+            key_id, err
+        );
+    }
+    return response;
+}
+
+async fn check_halt(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+) -> Result<(), (Status, Value)> {
+    match active_halt(conn, portfolio_id).await {
+        Ok(None) => return Ok(()),
+        Ok(Some(halt)) => {
+            return Err((
+                Status::Locked,
+                json!({"status": "error", "message": "Trading is halted", "halt": halt.to_json()}),
+            ));
+        }
+        Err(err) => {
+            return Err((
+                Status::InternalServerError,
+                json!({"status": "error", "message": err.0}),
+            ));
+        }
+    }
+}
+
+// pre-trade risk checks, the decision is logged either way
+async fn check_risk(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    intent: &OrderIntent,
+) -> Result<(), (Status, Value)> {
+    match check_order(conn, intent).await {
+        Ok(violations) if violations.is_empty() => return Ok(()),
+        Ok(violations) => {
+            // crossing a loss limit halts the portfolio on top of rejecting the order
+            let halt = match halt_on_breach(conn, exchange, intent.portfolio_id, &violations).await
+            {
+                Ok(Some((halt, report))) => Some(
+                    json!({"halt": halt.to_json(), "cancelled": report.cancelled, "failed": report.failed}),
+                ),
+                Ok(None) => None,
+                Err(err) => {
+                    return Err((
+                        Status::InternalServerError,
+                        json!({"status": "error", "message": err.0, "violations": violations}),
+                    ));
+                }
+            };
+            return Err((
+                Status::BadRequest,
+                json!({"status": "error", "message": "The order breaks the portfolio's risk rules", "violations": violations, "halted": halt}),
+            ));
+        }
+        Err(err) => {
+            return Err((
+                Status::InternalServerError,
+                json!({"status": "error", "message": err.0}),
+            ));
+        }
+    }
+}
+
+async fn new_order(
+    db_conn: &mut Connection<database::PgDb>,
+    _user_auth: &Authorized,
+    order_data: &OrderData,
+    exchange: &State<Exchange>,
+    market: &MarketConfig,
+) -> (Status, Value) {
+    let portfolio = match owned_portfolio(
+        _user_auth,
+        PortfolioKey::Id(order_data.portfolio_id),
+        "place_order",
+        db_conn,
+    )
+    .await
+    {
+        Ok(portfolio) => portfolio,
+        Err(err) => return err,
+    };
+    // a client order id names one order of the portfolio for good
+    let client_order_id = order_data.client_order_id.as_deref();
+    if let Some(client_order_id) = client_order_id {
+        if !valid_client_order_id(client_order_id) {
+            return bad_request("Invalid client order id");
+        }
+        match find_client_order(db_conn, portfolio.id, client_order_id).await {
+            Ok(None) => {}
+            Ok(Some(existing)) => return client_order_id_taken(existing),
+            Err(_) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status": "error", "message": "Fail to fetch the order"}),
+                );
+            }
+        }
+    }
+    let (side, order_type, time_in_force) = match parse_kind(order_data) {
+        Ok(kind) => kind,
+        Err(message) => return bad_request(message),
+    };
+    let trading_pairs =
+        match get_trading_pair_id(db_conn, (&order_data.base, &order_data.quote)).await {
+            Ok(trading_pairs) => trading_pairs,
+            Err(err) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status": "error", "message": err}),
+                );
+            }
+        };
+    let symbol = match get_pair_symbol(db_conn, trading_pairs.2).await {
+        Ok(symbol) => symbol,
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to fetch the trading pair"}),
+            );
+        }
+    };
+    // decimals in the pair's currencies, stored as scaled integers
+    let (price, stop_price, quantity) = match (
+        parse_optional_price(&symbol, &order_data.price),
+        parse_optional_price(&symbol, &order_data.stop_price),
+        symbol.parse_qty(&order_data.quantity),
+    ) {
+        (Ok(price), Ok(stop_price), Ok(quantity)) if quantity > 0 => (price, stop_price, quantity),
+        (Err(message), _, _) | (_, Err(message), _) => return bad_request(&message),
+        (_, _, Err(err)) => return bad_request(&err.message()),
+        _ => return bad_request("Invalid price or quantity"),
+    };
+    let expires_at = match order_data.expires_at.as_deref().map(parse_time) {
+        Some(None) => {
+            return bad_request("Invalid expiry, expected unix seconds or YYYY-MM-DDTHH:MM:SS")
+        }
+        Some(expires_at) => expires_at,
+        None => None,
+    };
+    let terms = OrderTerms {
+        order_type,
+        time_in_force,
+        price,
+        stop_price,
+        expires_at,
+    };
+    if let Err(message) = terms.validate(Utc::now().naive_utc()) {
+        return bad_request(message);
+    }
+
+    // a market order is measured against the last trade of the pair, which only
+    // counts for a live portfolio when it came from the exchange
+    let market_price = if order_type == OrderType::Market {
+        if market.source.is_synthetic() && !portfolio.paper {
+            return bad_request("Market orders of live portfolios need the exchange's market data");
+        }
+        match last_trade(db_conn, trading_pairs.2).await {
+            Ok(Some((_, last_price))) => Some(last_price),
+            Ok(None) => return bad_request("There is no market price for the pair yet"),
+            Err(err) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status": "error", "message": err.0}),
+                );
+            }
+        }
+    } else {
+        None
+    };
+    let working_price = match terms.working_price(side, market_price) {
+        Some(working_price) => working_price,
+        None => return bad_request(&BalanceError::Overflow.message()),
+    };
+
+    // the pair's increments and minimum, a disabled pair takes no orders
+    let spec = match get_pair_spec(db_conn, trading_pairs.2).await {
+        Ok(spec) => spec,
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to fetch the trading pair"}),
+            );
+        }
+    };
+    if let Err(err) = spec.check_terms(&symbol, &terms, quantity, market_price) {
+        return bad_request(&err.message());
+    }
     let fetch_quotation = quotations::table
         .inner_join(positions::table.on(quotations::position_id.eq(positions::id)))
         .inner_join(portfolios::table.on(portfolios::id.eq(positions::portfolio_id)))
         .filter(quotations::quote_currency_id.eq(trading_pairs.1))
-        .filter(quotations::quote_currency_id.eq(trading_pairs.1))
-        .filter(portfolios::trader_account_id.eq(user_id))
-        .filter(portfolios::id.eq(order_data.portfolio_id))
+        .filter(positions::trading_pair_id.eq(trading_pairs.2))
+        .filter(portfolios::id.eq(portfolio.id))
         .select(quotations::id)
-        .first::<i32>(&mut db_conn)
-        .await
-        .unwrap();
-    let _ = rocket_db_pools::diesel::insert_into(orders::table)
-        .values((
-            orders::id.eq(order_id),
-            orders::quotation_id.eq(fetch_quotation),
-            orders::trading_pair_id.eq(trading_pairs.2),
-            orders::state.eq(0),
-            orders::buyin.eq(order_data.order_type == "buy"),
-            orders::price.eq(order_data.price.parse::<i64>().unwrap()),
-            orders::qty.eq(order_data.quantity.parse::<i64>().unwrap()),
-            orders::portfolio_id.eq(order_data.portfolio_id),
-        ))
-        .returning(orders::id)
-        .get_result::<i32>(&mut db_conn)
-        .await
-        .unwrap();
+        .first::<i32>(db_conn)
+        .await;
+    let quotation_id = if let Ok(quotation_id) = fetch_quotation {
+        quotation_id
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"status": "error", "message": "The portfolio has no such position"}),
+        );
+    };
+
+    // nothing new goes out while the portfolio or its owner is halted
+    if let Err(err) = check_halt(db_conn, portfolio.id).await {
+        return err;
+    }
+
+    let intent = OrderIntent {
+        account_id: _user_auth.user_id,
+        portfolio_id: portfolio.id,
+        trading_pair_id: trading_pairs.2,
+        pair: format!("{}/{}", order_data.base, order_data.quote),
+        side,
+        price: working_price,
+        qty: quantity,
+        base_scale: symbol.base_scale,
+        quote_scale: symbol.quote_scale,
+        amends: None,
+    };
+    if let Err(err) = check_risk(db_conn, exchange.inner(), &intent).await {
+        return err;
+    }
+
+    let reservation = if let Some(reservation) =
+        reservation_for(side, working_price, quantity, symbol.base_scale)
+    {
+        reservation
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"status": "error", "message": BalanceError::Overflow.message()}),
+        );
+    };
+
+    // the id comes from the orders sequence, the exchange's id is stored next to it.
+    // The funds are reserved with the insert, so the order only exists if it is covered.
+    // Stop and take-profit orders stay with us until the market data triggers them.
+    let status = if order_type.is_held() {
+        OrderStatus::Held
+    } else {
+        OrderStatus::New
+    };
+    let insert_order = db_conn
+        .transaction::<i32, BalanceError, _>(|conn| {
+            async move {
+                let order_id = rocket_db_pools::diesel::insert_into(orders::table)
+                    .values((
+                        orders::quotation_id.eq(quotation_id),
+                        orders::trading_pair_id.eq(trading_pairs.2),
+                        orders::status.eq(status.as_str()),
+                        orders::buyin.eq(side == Side::Buy),
+                        orders::price.eq(working_price),
+                        orders::qty.eq(quantity),
+                        orders::portfolio_id.eq(portfolio.id),
+                        orders::order_type.eq(order_type.as_str()),
+                        orders::time_in_force.eq(time_in_force.as_str()),
+                        orders::stop_price.eq(stop_price),
+                        orders::expires_at.eq(expires_at),
+                        orders::client_order_id.eq(client_order_id),
+                    ))
+                    .returning(orders::id)
+                    .get_result::<i32>(conn)
+                    .await?;
+                set_reservation(conn, order_id, reservation).await?;
+                return Ok(order_id);
+            }
+            .scope_boxed()
+        })
+        .await;
+    let order_id = match insert_order {
+        Ok(order_id) => order_id,
+        Err(BalanceError::Database) => {
+            // a concurrent request may have taken the client order id in the meantime
+            if let Some(client_order_id) = client_order_id {
+                if let Ok(Some(existing)) =
+                    find_client_order(db_conn, portfolio.id, client_order_id).await
+                {
+                    return client_order_id_taken(existing);
+                }
+            }
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to insert the order"}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::BadRequest,
+                json!({"status": "error", "message": err.message()}),
+            );
+        }
+    };
+
+    if status == OrderStatus::Held {
+        return (
+            Status::Ok,
+            json!({"status": "successful", "data": order_id, "order_status": status.as_str(), "client_order_id": client_order_id}),
+        );
+    }
+
+    // a market order goes out with the last trade as its reference price
+    let request = ExchangeOrderRequest {
+        client_order_id: order_id.to_string(),
+        symbol,
+        side,
+        order_type: order_type.exchange_type(),
+        price: price.or(market_price).unwrap_or(working_price),
+        quantity,
+        time_in_force,
+        paper: portfolio.paper,
+    };
+    let (status, exchange_order_id) =
+        match submit_order(db_conn, exchange.inner(), order_id, &request).await {
+            Ok(submitted) => submitted,
+            Err(SubmitError::Exchange(err)) => {
+                return (
+                    Status::BadGateway,
+                    json!({"status": "error", "message": err.to_string(), "data": order_id}),
+                );
+            }
+            Err(SubmitError::Lifecycle(err)) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status": "error", "message": err.0, "data": order_id}),
+                );
+            }
+        };
+
     return (
         Status::Ok,
-        json!({"status": "successful", "data": order_id}),
+        json!({
+            "status": "successful",
+            "data": order_id,
+            "order_status": status.as_str(),
+            "exchange_order_id": exchange_order_id,
+            "client_order_id": client_order_id
+        }),
+    );
+}
+
+fn client_order_id_taken((order_id, status): (i32, String)) -> (Status, Value) {
+    return (
+        Status::Conflict,
+        json!({"status": "error", "message": "The client order id is already used in the portfolio", "data": order_id, "order_status": status}),
     );
 }
+
+#[delete("/api/order/<order_id>")]
+pub async fn cancel_order(
+    order_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    exchange: &State<Exchange>,
+) -> (Status, Value) {
+    let order = match owned_order(&_user_auth, order_id, "cancel_order", &mut db_conn).await {
+        Ok(order) => order,
+        Err(err) => return err,
+    };
+    if !order.status.is_open() {
+        return (
+            Status::Conflict,
+            json!({"status": "error", "message": "Only open orders can be cancelled", "order_status": order.status.as_str()}),
+        );
+    }
+    // a held order never left us, cancelling it releases its funds right away
+    if order.status == OrderStatus::Held {
+        match set_status(&mut db_conn, order_id, OrderStatus::Cancelled).await {
+            Ok(status) => {
+                return (
+                    Status::Ok,
+                    json!({"status": "successful", "data": order_id, "order_status": status.as_str()}),
+                );
+            }
+            Err(err) => {
+                return (
+                    Status::Conflict,
+                    json!({"status": "error", "message": err.0}),
+                );
+            }
+        }
+    }
+    let exchange_order_id = if let Some(exchange_order_id) = &order.exchange_order_id {
+        exchange_order_id
+    } else {
+        return (
+            Status::Conflict,
+            json!({"status": "error", "message": "The order has not been acknowledged by the exchange yet"}),
+        );
+    };
+
+    let status = match exchange
+        .cancel_order(&order.symbol, exchange_order_id)
+        .await
+    {
+        Ok(exchange_order) => {
+            sync_order(
+                &mut db_conn,
+                exchange.inner(),
+                order_id,
+                &order.symbol,
+                exchange_order,
+            )
+            .await
+        }
+        Err(ExchangeError::Rejected(reason)) => {
+            // most likely closed on the exchange in the meantime, catch up with it
+            let _ = refresh_order(
+                &mut db_conn,
+                exchange.inner(),
+                order_id,
+                &order.symbol,
+                exchange_order_id,
+            )
+            .await;
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": ExchangeError::Rejected(reason).to_string()}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::BadGateway,
+                json!({"status": "error", "message": err.to_string()}),
+            );
+        }
+    };
+    match status {
+        Ok(OrderStatus::Cancelled) => {
+            return (
+                Status::Ok,
+                json!({"status": "successful", "data": order_id, "order_status": OrderStatus::Cancelled.as_str()}),
+            );
+        }
+        Ok(status) => {
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": "The order closed before it could be cancelled", "order_status": status.as_str()}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": err.0}),
+            );
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AmendData {
+    price: Option<String>,
+    // only while the order is held
+    stop_price: Option<String>,
+    quantity: Option<String>,
+}
+#[patch("/api/order/<order_id>", data = "<amend_data>")]
+pub async fn amend_order(
+    order_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    amend_data: Json<AmendData>,
+    exchange: &State<Exchange>,
+) -> (Status, Value) {
+    let order = match owned_order(&_user_auth, order_id, "amend_order", &mut db_conn).await {
+        Ok(order) => order,
+        Err(err) => return err,
+    };
+    // a halted portfolio cannot change its orders either, only cancel them
+    if let Err(err) = check_halt(&mut db_conn, order.portfolio.id).await {
+        return err;
+    }
+    let previous = order.terms();
+    let price = match &amend_data.price {
+        Some(price) => order.symbol.parse_price(price).map(Some),
+        None => Ok(previous.price),
+    };
+    let stop_price = match &amend_data.stop_price {
+        Some(stop_price) => order.symbol.parse_price(stop_price).map(Some),
+        None => Ok(previous.stop_price),
+    };
+    let quantity = match &amend_data.quantity {
+        Some(quantity) => order.symbol.parse_qty(quantity),
+        None => Ok(order.qty),
+    };
+    let (price, stop_price, quantity) = match (price, stop_price, quantity) {
+        (Ok(price), Ok(stop_price), Ok(quantity)) if quantity > 0 => (price, stop_price, quantity),
+        (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => {
+            return bad_request(&err.message());
+        }
+        _ => return bad_request("Invalid price or quantity"),
+    };
+    let terms = OrderTerms {
+        price,
+        stop_price,
+        ..previous.clone()
+    };
+    if let Err(message) = terms.validate(Utc::now().naive_utc()) {
+        return bad_request(message);
+    }
+    let spec = match get_pair_spec(&mut db_conn, order.trading_pair_id).await {
+        Ok(spec) => spec,
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": "Fail to fetch the trading pair"}),
+            );
+        }
+    };
+    if let Err(err) = spec.check_terms(&order.symbol, &terms, quantity, None) {
+        return bad_request(&err.message());
+    }
+    if terms == previous && quantity == order.qty {
+        return bad_request("Nothing to amend");
+    }
+    if order.status == OrderStatus::Held {
+        return amend_held_order(
+            &mut db_conn,
+            exchange.inner(),
+            &_user_auth,
+            &order,
+            &terms,
+            quantity,
+        )
+        .await;
+    }
+    if stop_price != previous.stop_price {
+        return (
+            Status::Conflict,
+            json!({"status": "error", "message": "The stop price can only change before the order is triggered"}),
+        );
+    }
+    // what executes at market is gone before it could be amended
+    let price = match price {
+        Some(price) => price,
+        None => {
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": "Only limit orders can be amended on the exchange"}),
+            );
+        }
+    };
+    let exchange_order_id = match (order.status.is_open(), &order.exchange_order_id) {
+        (true, Some(exchange_order_id)) => exchange_order_id,
+        (true, None) => {
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": "The order has not been acknowledged by the exchange yet"}),
+            );
+        }
+        (false, _) => {
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": "Only open orders can be amended", "order_status": order.status.as_str()}),
+            );
+        }
+    };
+
+    // the fill rules below need the latest executions
+    let status = match refresh_order(
+        &mut db_conn,
+        exchange.inner(),
+        order_id,
+        &order.symbol,
+        exchange_order_id,
+    )
+    .await
+    {
+        Ok(status) => status,
+        Err(err) => {
+            return (
+                Status::BadGateway,
+                json!({"status": "error", "message": err.0}),
+            );
+        }
+    };
+    let filled_qty = match fill_summaries(&mut db_conn, &[order_id]).await {
+        Ok(summaries) => summaries
+            .get(&order_id)
+            .map(|summary| summary.filled_qty)
+            .unwrap_or(0),
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": err.0}),
+            );
+        }
+    };
+    if !status.is_open() {
+        return (
+            Status::Conflict,
+            json!({"status": "error", "message": "Only open orders can be amended", "order_status": status.as_str()}),
+        );
+    }
+    if status == OrderStatus::PartiallyFilled
+        && (price != order.price || quantity >= order.qty || quantity <= filled_qty)
+    {
+        return (
+            Status::Conflict,
+            json!({"status": "error", "message": "A partially filled order can only reduce its remaining quantity", "filled_qty": filled_qty}),
+        );
+    }
+
+    // take the extra funds before asking the exchange, give them back if it refuses
+    let (reservation, previous_reservation) = match (
+        reservation_for(
+            order.side,
+            price,
+            quantity - filled_qty,
+            order.symbol.base_scale,
+        ),
+        reservation_for(
+            order.side,
+            order.price,
+            order.qty - filled_qty,
+            order.symbol.base_scale,
+        ),
+    ) {
+        (Some(reservation), Some(previous)) => (reservation, previous),
+        _ => {
+            return (
+                Status::BadRequest,
+                json!({"status": "error", "message": BalanceError::Overflow.message()}),
+            );
+        }
+    };
+    if reservation > previous_reservation {
+        // a larger order has to pass the risk rules like a new one
+        let intent = amend_intent(&_user_auth, &order, price, quantity - filled_qty);
+        if let Err(err) = check_risk(&mut db_conn, exchange.inner(), &intent).await {
+            return err;
+        }
+        match set_reservation(&mut db_conn, order_id, reservation).await {
+            Ok(_) => {}
+            Err(BalanceError::Database) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status": "error", "message": BalanceError::Database.message()}),
+                );
+            }
+            Err(err) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status": "error", "message": err.message()}),
+                );
+            }
+        }
+    }
+
+    let request = ExchangeOrderRequest {
+        client_order_id: order_id.to_string(),
+        symbol: order.symbol.clone(),
+        side: order.side,
+        order_type: ExchangeOrderType::Limit,
+        price,
+        quantity,
+        time_in_force: order.time_in_force,
+        paper: order.portfolio.paper,
+    };
+    let exchange_order = match exchange.amend_order(exchange_order_id, &request).await {
+        Ok(exchange_order) => exchange_order,
+        Err(ExchangeError::ReplaceFailed(cancelled, reason)) => {
+            // the order is gone on the exchange, close it and release its funds
+            if reservation > previous_reservation {
+                let _ = set_reservation(&mut db_conn, order_id, previous_reservation).await;
+            }
+            let message = ExchangeError::ReplaceFailed(cancelled.clone(), reason).to_string();
+            match sync_order(
+                &mut db_conn,
+                exchange.inner(),
+                order_id,
+                &order.symbol,
+                cancelled,
+            )
+            .await
+            {
+                Ok(status) => {
+                    return (
+                        Status::Conflict,
+                        json!({"status": "error", "message": message, "data": order_id, "order_status": status.as_str()}),
+                    );
+                }
+                Err(err) => {
+                    return (
+                        Status::InternalServerError,
+                        json!({"status": "error", "message": err.0, "data": order_id}),
+                    );
+                }
+            }
+        }
+        Err(err) => {
+            if reservation > previous_reservation {
+                let _ = set_reservation(&mut db_conn, order_id, previous_reservation).await;
+            }
+            if let ExchangeError::Rejected(_) = err {
+                return (
+                    Status::Conflict,
+                    json!({"status": "error", "message": err.to_string()}),
+                );
+            }
+            return (
+                Status::BadGateway,
+                json!({"status": "error", "message": err.to_string()}),
+            );
+        }
+    };
+
+    // fills of a replaced order are only listed under its old id, which is kept on the
+    // order until they can be fetched
+    let mut old_fills = vec![];
+    let mut replaced = None;
+    if &exchange_order.exchange_order_id != exchange_order_id {
+        match exchange.list_fills(&order.symbol, exchange_order_id).await {
+            Ok(fills) => old_fills = fills,
+            Err(_) => replaced = Some(exchange_order_id.to_string()),
+        }
+    }
+    let update_order = db_conn
+        .transaction::<(), LifecycleError, _>(|conn| {
+            async move {
+                record_and_settle_fills(conn, order_id, &old_fills).await?;
+                if let Some(replaced) = replaced {
+                    keep_replaced_order(conn, order_id, &replaced).await?;
+                }
+                rocket_db_pools::diesel::update(orders::table.filter(orders::id.eq(order_id)))
+                    .set((orders::price.eq(price), orders::qty.eq(quantity)))
+                    .execute(conn)
+                    .await?;
+                return Ok(());
+            }
+            .scope_boxed()
+        })
+        .await;
+    if let Err(err) = update_order {
+        return (
+            Status::InternalServerError,
+            json!({"status": "error", "message": err.0, "data": order_id}),
+        );
+    }
+    let sync_result = sync_order(
+        &mut db_conn,
+        exchange.inner(),
+        order_id,
+        &order.symbol,
+        exchange_order,
+    )
+    .await;
+    // what is left open is now reserved at the new price
+    let sync_result = match sync_result {
+        Ok(status) if status.is_open() => reserve_remaining(
+            &mut db_conn,
+            order_id,
+            &order.symbol,
+            order.side,
+            price,
+            quantity,
+        )
+        .await
+        .map(|_| status),
+        other => other,
+    };
+    match sync_result {
+        Ok(status) => {
+            return (
+                Status::Ok,
+                json!({"status": "successful", "data": order_id, "order_status": status.as_str(), "price": order.symbol.format_price(price), "qty": order.symbol.format_qty(quantity)}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": err.0, "data": order_id}),
+            );
+        }
+    }
+}
+
+// what the risk rules see of an amended order, in place of the order itself
+fn amend_intent(
+    user_auth: &Authorized,
+    order: &OwnedOrder,
+    price: i64,
+    quantity: i64,
+) -> OrderIntent {
+    return OrderIntent {
+        account_id: user_auth.user_id,
+        portfolio_id: order.portfolio.id,
+        trading_pair_id: order.trading_pair_id,
+        pair: format!("{}/{}", order.symbol.base, order.symbol.quote),
+        side: order.side,
+        price,
+        qty: quantity,
+        base_scale: order.symbol.base_scale,
+        quote_scale: order.symbol.quote_scale,
+        amends: Some(order.id),
+    };
+}
+
+// A held order is amended on our side only, its funds follow the new terms. The
+// status check makes sure an order triggered in the meantime is left alone.
+async fn amend_held_order(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    user_auth: &Authorized,
+    order: &OwnedOrder,
+    terms: &OrderTerms,
+    quantity: i64,
+) -> (Status, Value) {
+    let order_id = order.id;
+    let (working_price, reservation) = match terms.working_price(order.side, None) {
+        Some(working_price) => (
+            working_price,
+            reservation_for(order.side, working_price, quantity, order.symbol.base_scale),
+        ),
+        None => (0, None),
+    };
+    let previous_reservation =
+        reservation_for(order.side, order.price, order.qty, order.symbol.base_scale);
+    let (reservation, previous_reservation) = match (reservation, previous_reservation) {
+        (Some(reservation), Some(previous)) => (reservation, previous),
+        _ => return bad_request(&BalanceError::Overflow.message()),
+    };
+    if reservation > previous_reservation {
+        let intent = amend_intent(user_auth, order, working_price, quantity);
+        if let Err(err) = check_risk(conn, exchange, &intent).await {
+            return err;
+        }
+    }
+    let stop_price = terms.stop_price;
+    let update_order = conn
+        .transaction::<bool, BalanceError, _>(|conn| {
+            async move {
+                let updated = rocket_db_pools::diesel::update(
+                    orders::table
+                        .filter(orders::id.eq(order_id))
+                        .filter(orders::status.eq(OrderStatus::Held.as_str())),
+                )
+                .set((
+                    orders::price.eq(working_price),
+                    orders::qty.eq(quantity),
+                    orders::stop_price.eq(stop_price),
+                ))
+                .execute(conn)
+                .await?;
+                if updated != 1 {
+                    return Ok(false);
+                }
+                set_reservation(conn, order_id, reservation).await?;
+                return Ok(true);
+            }
+            .scope_boxed()
+        })
+        .await;
+    match update_order {
+        Ok(false) => {
+            return (
+                Status::Conflict,
+                json!({"status": "error", "message": "The order was triggered before it could be amended"}),
+            );
+        }
+        Ok(true) => {
+            return (
+                Status::Ok,
+                json!({
+                    "status": "successful",
+                    "data": order_id,
+                    "order_status": OrderStatus::Held.as_str(),
+                    "price": terms.price.map(|price| order.symbol.format_price(price)),
+                    "stop_price": stop_price.map(|price| order.symbol.format_price(price)),
+                    "qty": order.symbol.format_qty(quantity),
+                }),
+            );
+        }
+        Err(BalanceError::Database) => {
+            return (
+                Status::InternalServerError,
+                json!({"status": "error", "message": BalanceError::Database.message()}),
+            );
+        }
+        Err(err) => {
+            return bad_request(&err.message());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::order::exchange::ExchangeOrderStatus;
+
+// orders.status, stored as the snake_case name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    // a stop or take-profit order waiting for its trigger, never sent to the exchange
+    Held,
+    // written, not yet acknowledged by the exchange
+    New,
+    Accepted,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected,
+    Expired,
+}
+
+impl OrderStatus {
+    pub const ALL: [OrderStatus; 8] = [
+        OrderStatus::Held,
+        OrderStatus::New,
+        OrderStatus::Accepted,
+        OrderStatus::PartiallyFilled,
+        OrderStatus::Filled,
+        OrderStatus::Cancelled,
+        OrderStatus::Rejected,
+        OrderStatus::Expired,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Held => "held",
+            OrderStatus::New => "new",
+            OrderStatus::Accepted => "accepted",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Filled => "filled",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Rejected => "rejected",
+            OrderStatus::Expired => "expired",
+        }
+    }
+
+    // an open order can still be filled, amended or cancelled
+    pub fn is_open(self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Held
+                | OrderStatus::New
+                | OrderStatus::Accepted
+                | OrderStatus::PartiallyFilled
+        )
+    }
+
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        match (self, next) {
+            (OrderStatus::New, OrderStatus::New | OrderStatus::Held) => false,
+            (OrderStatus::New, _) => true,
+            // released to the exchange when triggered, or closed before that
+            (
+                OrderStatus::Held,
+                OrderStatus::New
+                | OrderStatus::Cancelled
+                | OrderStatus::Expired
+                | OrderStatus::Rejected,
+            ) => true,
+            (
+                OrderStatus::Accepted,
+                OrderStatus::PartiallyFilled
+                | OrderStatus::Filled
+                | OrderStatus::Cancelled
+                | OrderStatus::Expired,
+            ) => true,
+            // every new fill of a partially filled order is a transition to itself
+            (
+                OrderStatus::PartiallyFilled,
+                OrderStatus::PartiallyFilled
+                | OrderStatus::Filled
+                | OrderStatus::Cancelled
+                | OrderStatus::Expired,
+            ) => true,
+            _ => false,
+        }
+    }
+
+    pub fn transition(self, next: OrderStatus) -> Result<OrderStatus, String> {
+        if self == next && !self.is_open() {
+            return Ok(self);
+        }
+        if self.can_transition_to(next) {
+            return Ok(next);
+        }
+        return Err(format!(
+            "An order cannot go from {} to {}",
+            self.as_str(),
+            next.as_str()
+        ));
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        return OrderStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == value)
+            .ok_or("Unknown order status");
+    }
+}
+
+impl From<ExchangeOrderStatus> for OrderStatus {
+    fn from(status: ExchangeOrderStatus) -> Self {
+        match status {
+            ExchangeOrderStatus::New => OrderStatus::Accepted,
+            ExchangeOrderStatus::PartiallyFilled => OrderStatus::PartiallyFilled,
+            ExchangeOrderStatus::Filled => OrderStatus::Filled,
+            ExchangeOrderStatus::Cancelled => OrderStatus::Cancelled,
+            ExchangeOrderStatus::Rejected => OrderStatus::Rejected,
+            ExchangeOrderStatus::Expired => OrderStatus::Expired,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for status in OrderStatus::ALL {
+            assert_eq!(status.as_str().parse::<OrderStatus>(), Ok(status));
+        }
+        assert!("pending".parse::<OrderStatus>().is_err());
+    }
+
+    #[test]
+    fn terminal_states_do_not_move() {
+        for from in [
+            OrderStatus::Filled,
+            OrderStatus::Cancelled,
+            OrderStatus::Rejected,
+            OrderStatus::Expired,
+        ] {
+            for to in OrderStatus::ALL {
+                assert!(!from.can_transition_to(to), "{} -> {}", from, to);
+            }
+            // re-applying the same terminal state is harmless
+            assert_eq!(from.transition(from), Ok(from));
+        }
+    }
+
+    #[test]
+    fn open_states() {
+        assert!(OrderStatus::New.can_transition_to(OrderStatus::Filled));
+        assert!(OrderStatus::Accepted.can_transition_to(OrderStatus::PartiallyFilled));
+        assert!(!OrderStatus::Accepted.can_transition_to(OrderStatus::Rejected));
+        assert!(!OrderStatus::PartiallyFilled.can_transition_to(OrderStatus::Accepted));
+        assert!(OrderStatus::PartiallyFilled
+            .transition(OrderStatus::PartiallyFilled)
+            .is_ok());
+        assert!(OrderStatus::Accepted.transition(OrderStatus::New).is_err());
+    }
+
+    #[test]
+    fn held_orders_are_released_or_closed() {
+        assert!(OrderStatus::Held.is_open());
+        assert!(OrderStatus::Held.can_transition_to(OrderStatus::New));
+        assert!(OrderStatus::Held.can_transition_to(OrderStatus::Expired));
+        assert!(!OrderStatus::Held.can_transition_to(OrderStatus::Filled));
+        assert!(!OrderStatus::Held.can_transition_to(OrderStatus::Held));
+        assert!(!OrderStatus::New.can_transition_to(OrderStatus::Held));
+        assert!(!OrderStatus::Accepted.can_transition_to(OrderStatus::Held));
+    }
+}
@@ -0,0 +1,349 @@
+use ::diesel::ExpressionMethods;
+use chrono::NaiveDateTime;
+use diesel::QueryDsl;
+use rocket_db_pools::diesel;
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+
+use crate::db_lib::query::get_pair_symbol;
+use crate::db_lib::schema::{orders, portfolios};
+use crate::market::candle::Tick;
+use crate::order::exchange::{
+    Exchange, ExchangeError, ExchangeOrderRequest, ExchangeOrderStatus, Side, Symbol,
+};
+use crate::order::lifecycle::{
+    refresh_order, set_status, submit_order, sync_order, LifecycleError, SubmitError,
+};
+use crate::order::order_type::{OrderType, TimeInForce};
+use crate::order::status::OrderStatus;
+use crate::risk::halt::active_halt;
+
+// What a pass over the held or expiring orders did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TriggerReport {
+    // sent to the exchange
+    pub released: Vec<i32>,
+    pub expired: Vec<i32>,
+    pub failed: Vec<(i32, String)>,
+}
+
+// The price of the first trade that reaches the stop, the ticks are oldest first
+pub fn trigger_price(
+    order_type: OrderType,
+    side: Side,
+    stop_price: i64,
+    ticks: &[Tick],
+) -> Option<i64> {
+    return ticks
+        .iter()
+        .map(|tick| tick.price)
+        .find(|price| order_type.is_triggered(side, stop_price, *price));
+}
+
+// (id, portfolio_id, buyin, order_type, time_in_force, price, stop_price, qty, paper)
+type HeldRow = (i32, i32, bool, String, String, i64, Option<i64>, i64, bool);
+
+// An order waiting for its trigger, `price` is what its funds are reserved at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldOrder {
+    pub id: i32,
+    pub portfolio_id: i32,
+    pub side: Side,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    pub price: i64,
+    pub stop_price: i64,
+    pub qty: i64,
+    // the order of a paper portfolio
+    pub paper: bool,
+}
+
+impl HeldOrder {
+    fn from_row(row: HeldRow) -> Option<HeldOrder> {
+        let (id, portfolio_id, buyin, order_type, time_in_force, price, stop_price, qty, paper) =
+            row;
+        return Some(HeldOrder {
+            id,
+            portfolio_id,
+            side: if buyin { Side::Buy } else { Side::Sell },
+            order_type: order_type.parse().ok()?,
+            time_in_force: time_in_force.parse().ok()?,
+            price,
+            stop_price: stop_price?,
+            qty,
+            paper,
+        });
+    }
+
+    // The request the order goes out with once triggered. Orders executed at market
+    // carry the trigger price as their reference and are IOC.
+    pub fn release_request(&self, symbol: &Symbol, trigger_price: i64) -> ExchangeOrderRequest {
+        let (price, time_in_force) = match self.order_type {
+            OrderType::Limit | OrderType::StopLimit => (self.price, self.time_in_force),
+            OrderType::Market | OrderType::StopMarket | OrderType::TakeProfit => {
+                (trigger_price, TimeInForce::Ioc)
+            }
+        };
+        return ExchangeOrderRequest {
+            client_order_id: self.id.to_string(),
+            symbol: symbol.clone(),
+            side: self.side,
+            order_type: self.order_type.exchange_type(),
+            price,
+            quantity: self.qty,
+            time_in_force,
+            paper: self.paper,
+        };
+    }
+}
+
+// Release the held orders of the pair that the new trades reached, oldest order first.
+// A halted portfolio's orders are rejected instead, they were checked against the
+// risk rules when they were placed. With `paper_only` the trades are synthetic and
+// the orders of live portfolios keep waiting.
+pub async fn trigger_orders(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    trading_pair_id: i32,
+    symbol: &Symbol,
+    ticks: &[Tick],
+    paper_only: bool,
+) -> Result<TriggerReport, LifecycleError> {
+    let mut ticks = ticks.to_vec();
+    ticks.sort_by_key(|tick| tick.time);
+    let held = orders::table
+        .inner_join(portfolios::table)
+        .filter(orders::trading_pair_id.eq(trading_pair_id))
+        .filter(orders::status.eq(OrderStatus::Held.as_str()))
+        .order(orders::id.asc())
+        .select((
+            orders::id,
+            orders::portfolio_id,
+            orders::buyin,
+            orders::order_type,
+            orders::time_in_force,
+            orders::price,
+            orders::stop_price,
+            orders::qty,
+            portfolios::paper,
+        ))
+        .load::<HeldRow>(conn)
+        .await?;
+
+    let mut report = TriggerReport::default();
+    for row in held {
+        let order_id = row.0;
+        let order = if let Some(order) = HeldOrder::from_row(row) {
+            order
+        } else {
+            report
+                .failed
+                .push((order_id, "The order has no valid trigger".to_string()));
+            continue;
+        };
+        if paper_only && !order.paper {
+            continue;
+        }
+        let trigger = match trigger_price(order.order_type, order.side, order.stop_price, &ticks) {
+            Some(trigger) => trigger,
+            None => continue,
+        };
+
+        match active_halt(conn, order.portfolio_id).await {
+            Ok(None) => {}
+            Ok(Some(_)) => {
+                match set_status(conn, order_id, OrderStatus::Rejected).await {
+                    Ok(_) => report
+                        .failed
+                        .push((order_id, "Trading is halted".to_string())),
+                    Err(err) => report.failed.push((order_id, err.0)),
+                }
+                continue;
+            }
+            Err(err) => {
+                report.failed.push((order_id, err.0.to_string()));
+                continue;
+            }
+        }
+
+        // the status check makes sure a concurrent cancel wins
+        if let Err(err) = set_status(conn, order_id, OrderStatus::New).await {
+            report.failed.push((order_id, err.0));
+            continue;
+        }
+        let triggered_at = ticks
+            .iter()
+            .find(|tick| tick.price == trigger)
+            .map(|tick| tick.time);
+        diesel::update(orders::table.filter(orders::id.eq(order_id)))
+            .set(orders::triggered_at.eq(triggered_at))
+            .execute(conn)
+            .await?;
+
+        let request = order.release_request(symbol, trigger);
+        match submit_order(conn, exchange, order_id, &request).await {
+            Ok(_) => report.released.push(order_id),
+            Err(SubmitError::Exchange(err)) => report.failed.push((order_id, err.to_string())),
+            Err(SubmitError::Lifecycle(err)) => report.failed.push((order_id, err.0)),
+        }
+    }
+    return Ok(report);
+}
+
+// Expire the GTD orders whose time is up. Held orders close on our side, the ones on
+// the exchange are cancelled there first. An order still waiting for the exchange's
+// answer is left for the next pass.
+pub async fn expire_orders(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    now: NaiveDateTime,
+) -> Result<TriggerReport, LifecycleError> {
+    let open_statuses: Vec<&str> = OrderStatus::ALL
+        .into_iter()
+        .filter(|status| status.is_open())
+        .map(|status| status.as_str())
+        .collect();
+    let expiring = orders::table
+        .filter(orders::time_in_force.eq(TimeInForce::Gtd.as_str()))
+        .filter(orders::expires_at.le(now))
+        .filter(orders::status.eq_any(open_statuses))
+        .order(orders::id.asc())
+        .select((
+            orders::id,
+            orders::trading_pair_id,
+            orders::status,
+            orders::exchange_order_id,
+        ))
+        .load::<(i32, i32, String, Option<String>)>(conn)
+        .await?;
+
+    let mut report = TriggerReport::default();
+    for (order_id, trading_pair_id, status, exchange_order_id) in expiring {
+        let exchange_order_id = match (status.parse::<OrderStatus>(), exchange_order_id) {
+            (Ok(OrderStatus::Held), _) => {
+                match set_status(conn, order_id, OrderStatus::Expired).await {
+                    Ok(_) => report.expired.push(order_id),
+                    Err(err) => report.failed.push((order_id, err.0)),
+                }
+                continue;
+            }
+            (Ok(_), Some(exchange_order_id)) => exchange_order_id,
+            _ => continue,
+        };
+        let symbol = match get_pair_symbol(conn, trading_pair_id).await {
+            Ok(symbol) => symbol,
+            Err(_) => {
+                report
+                    .failed
+                    .push((order_id, "Fail to fetch the trading pair".to_string()));
+                continue;
+            }
+        };
+        let status = match exchange.cancel_order(&symbol, &exchange_order_id).await {
+            Ok(mut exchange_order) => {
+                if exchange_order.status == ExchangeOrderStatus::Cancelled {
+                    exchange_order.status = ExchangeOrderStatus::Expired;
+                }
+                sync_order(conn, exchange, order_id, &symbol, exchange_order).await
+            }
+            Err(ExchangeError::Rejected(_)) => {
+                // most likely closed on the exchange in the meantime, catch up with it
+                refresh_order(conn, exchange, order_id, &symbol, &exchange_order_id).await
+            }
+            Err(err) => {
+                report.failed.push((order_id, err.to_string()));
+                continue;
+            }
+        };
+        match status {
+            Ok(OrderStatus::Expired) => report.expired.push(order_id),
+            Ok(status) if !status.is_open() => {}
+            Ok(status) => report
+                .failed
+                .push((order_id, format!("The order is still {}", status.as_str()))),
+            Err(err) => report.failed.push((order_id, err.0)),
+        }
+    }
+    return Ok(report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::exchange::ExchangeOrderType;
+
+    fn ticks(prices: &[i64]) -> Vec<Tick> {
+        let start =
+            NaiveDateTime::parse_from_str("2026-10-18 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        return prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| Tick {
+                price: *price,
+                qty: 1,
+                time: start + chrono::Duration::seconds(i as i64),
+            })
+            .collect();
+    }
+
+    #[test]
+    fn the_first_trade_through_the_stop_triggers() {
+        let ticks = ticks(&[100, 95, 89, 85, 92]);
+        assert_eq!(
+            trigger_price(OrderType::StopMarket, Side::Sell, 90, &ticks),
+            Some(89)
+        );
+        assert_eq!(
+            trigger_price(OrderType::TakeProfit, Side::Sell, 99, &ticks),
+            Some(100)
+        );
+        assert_eq!(
+            trigger_price(OrderType::StopLimit, Side::Buy, 101, &ticks),
+            None
+        );
+        assert_eq!(
+            trigger_price(OrderType::StopMarket, Side::Sell, 90, &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn released_orders_keep_their_limit() {
+        let symbol = Symbol {
+            base: "BTC".to_string(),
+            quote: "USDT".to_string(),
+            base_scale: 0,
+            quote_scale: 0,
+        };
+        let stop_limit = HeldOrder {
+            id: 7,
+            portfolio_id: 1,
+            side: Side::Buy,
+            order_type: OrderType::StopLimit,
+            time_in_force: TimeInForce::Gtd,
+            price: 105,
+            stop_price: 101,
+            qty: 3,
+            paper: false,
+        };
+        let request = stop_limit.release_request(&symbol, 102);
+        assert_eq!(request.order_type, ExchangeOrderType::Limit);
+        assert_eq!(request.price, 105);
+        assert_eq!(request.time_in_force, TimeInForce::Gtd);
+        assert_eq!(request.client_order_id, "7");
+
+        let stop_market = HeldOrder {
+            side: Side::Sell,
+            order_type: OrderType::StopMarket,
+            time_in_force: TimeInForce::Gtc,
+            price: 90,
+            stop_price: 90,
+            ..stop_limit
+        };
+        let request = stop_market.release_request(&symbol, 89);
+        assert_eq!(request.order_type, ExchangeOrderType::Market);
+        assert_eq!(request.price, 89);
+        assert_eq!(request.time_in_force, TimeInForce::Ioc);
+        assert_eq!(request.quantity, 3);
+    }
+}
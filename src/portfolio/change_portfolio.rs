@@ -4,71 +4,152 @@ use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::serde::json::{json, Value};
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
-use rocket_db_pools::{diesel, Connection};
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::AsyncConnection;
+use rocket_db_pools::Connection;
 use serde::{Deserialize, Serialize};
 
-use crate::auth::validation::UserAuth;
+use crate::auth::permission::Authorized;
 use crate::db_lib::database;
-use crate::db_lib::schema::{portfolio_balance, portfolios};
+use crate::db_lib::query::{get_currencies, get_currency_id};
+use crate::db_lib::schema::portfolio_balance;
+use crate::ledger::journal::{record, EntryKind, Journal, LedgerAccount};
+use crate::order::balance::{main_portfolio, BalanceError};
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
 
 #[derive(Serialize, Deserialize)]
 pub struct ChangePortfolioInfo<'r> {
     name: &'r str,
-    amount: i32,
+    // a decimal in the currency
+    amount: &'r str,
     symbol: &'r str,
 }
 
+// Set the balance of `symbol` in a strategy portfolio to `amount`. The difference is
+// taken from or given back to the account's main portfolio.
 #[put("/api/portfolio", data = "<change_portfolio_info>")]
 pub async fn change_portfolio(
     change_portfolio_info: Json<ChangePortfolioInfo<'_>>,
     mut db_conn: Connection<database::PgDb>,
-    _user_auth: UserAuth,
+    _user_auth: Authorized,
 ) -> (Status, Value) {
-    // ensure the user is logged in
-    let _user_id = _user_auth.user_id;
-
-    // get portfolio's id
-    let portfolio_id_result: Result<i32, _> = portfolios::table
-        .filter(portfolios::name.eq(&change_portfolio_info.name))
-        .select(portfolios::id)
-        .first(&mut db_conn)
-        .await;
-
-    let portfolio_id: i32 = match portfolio_id_result {
-        Ok(id) => id,
+    // ensure the user owns the portfolio
+    let portfolio = match owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Name(change_portfolio_info.name),
+        "change_portfolio",
+        &mut db_conn,
+    )
+    .await
+    {
+        Ok(portfolio) => portfolio,
+        Err(err) => return err,
+    };
+    let currency_id = match get_currency_id(&mut db_conn, change_portfolio_info.symbol).await {
+        Ok(currency_id) => currency_id,
         Err(_) => {
             return (
                 Status::BadRequest,
-                json!({"status":"error", "message": "The portfolio does not exist"}),
+                json!({"status":"error", "message": "Currency not found"}),
             );
         }
     };
-    // TODO need to change main account
-    // update portfolio_balance
-    let update_result = diesel::update(
-        portfolio_balance::table.filter(portfolio_balance::portfolio_id.eq(portfolio_id)),
-    )
-    .set(portfolio_balance::quantity.eq(change_portfolio_info.amount as i64))
-    .execute(&mut db_conn)
-    .await;
-
-    match update_result {
-        Ok(updated_records) => {
-            if updated_records == 0 {
-                // not find matched portfolio
+    // funds enter and leave the main portfolio only through the admin deposits and
+    // withdrawals
+    if portfolio.portfolio_type == 0 {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "The main portfolio is funded through deposits and withdrawals"}),
+        );
+    }
+    // where the difference comes from and goes back to, simulated for paper portfolios
+    let (kind, source) = match main_portfolio(&mut db_conn, portfolio.trader_account_id).await {
+        _ if portfolio.paper => (EntryKind::Paper, LedgerAccount::External),
+        Ok(Some(main_id)) => (EntryKind::Main, LedgerAccount::Portfolio(main_id)),
+        Ok(None) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": "The account has no main portfolio to fund it from"}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": err.message()}),
+            );
+        }
+    };
+    let amount = match get_currencies(&mut db_conn).await {
+        Ok(currencies) => match currencies.parse(currency_id, change_portfolio_info.amount) {
+            Ok(amount) if amount >= 0 => amount,
+            Ok(_) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message": "The amount cannot be negative"}),
+                );
+            }
+            Err(err) => {
                 return (
                     Status::BadRequest,
-                    json!({"status":"error", "message": "Portfolio balance not found"}),
+                    json!({"status":"error", "message": err.message()}),
                 );
-            } else {
-                return (Status::Ok, json!({"status":"successful"}));
             }
+        },
+        Err(message) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": message}),
+            );
         }
-        Err(_) => {
+    };
+    let user_id = _user_auth.user_id;
+
+    let change_result = db_conn
+        .transaction::<Option<i32>, BalanceError, _>(|conn| {
+            async move {
+                let current: i64 = portfolio_balance::table
+                    .filter(portfolio_balance::portfolio_id.eq(portfolio.id))
+                    .filter(portfolio_balance::currency_id.eq(currency_id))
+                    .select(portfolio_balance::quantity)
+                    .load::<i64>(conn)
+                    .await?
+                    .iter()
+                    .sum();
+                let delta = amount.checked_sub(current).ok_or(BalanceError::Overflow)?;
+                if delta == 0 {
+                    return Ok(None);
+                }
+                let journal = Journal::new(kind).move_funds(
+                    source,
+                    LedgerAccount::Portfolio(portfolio.id),
+                    currency_id,
+                    delta,
+                );
+                let journal = journal.by(user_id).checked();
+                return Ok(Some(record(conn, &journal).await?));
+            }
+            .scope_boxed()
+        })
+        .await;
+
+    match change_result {
+        Ok(journal_id) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "journal_id": journal_id}),
+            );
+        }
+        Err(BalanceError::Database) => {
             return (
                 Status::InternalServerError,
                 json!({"status":"error", "message": "Failed to update portfolio balance"}),
             );
         }
+        Err(err) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": err.message()}),
+            );
+        }
     }
 }
@@ -1,14 +1,17 @@
-use crate::auth::validation::UserAuth;
+use crate::auth::permission::Authorized;
 use crate::db_lib::database;
 use crate::db_lib::query::*;
 use crate::db_lib::schema::{portfolio_balance, portfolios, positions, quotations};
 use ::diesel::ExpressionMethods;
 use diesel::dsl::sql;
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
 use diesel::sql_types::BigInt;
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use rocket::serde::json::{json, Value};
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::AsyncConnection;
 use rocket_db_pools::{diesel, Connection};
 use serde::{Deserialize, Serialize};
 
@@ -16,138 +19,224 @@ use serde::{Deserialize, Serialize};
 pub struct AddPortfolioInfo<'r> {
     name: &'r str,
     position: Vec<String>,
+    // a paper portfolio trades on the simulated exchange, it cannot be changed later
+    paper: Option<bool>,
 }
 
+// A position of the request that passed validation
+struct NewPosition {
+    base_id: i32,
+    quote_id: i32,
+    trading_pair_id: i32,
+}
+
+// the error names the position that failed so the front end can point at it
+fn invalid_position(index: usize, position: &str, message: &str) -> (Status, Value) {
+    return (
+        Status::BadRequest,
+        json!({"status":"error", "message": message, "position": position, "index": index}),
+    );
+}
+
+// Resolve every "BASE/QUOTE" position before anything is written
+async fn validate_positions(
+    db_conn: &mut Connection<database::PgDb>,
+    position_list: &[String],
+) -> Result<Vec<NewPosition>, (Status, Value)> {
+    let mut new_positions: Vec<NewPosition> = vec![];
+    for (index, pos) in position_list.iter().enumerate() {
+        let position: Vec<&str> = pos.split("/").collect();
+        if position.len() != 2 {
+            return Err(invalid_position(
+                index,
+                pos,
+                "Position should look like BASE/QUOTE",
+            ));
+        }
+        match get_trading_pair_id(db_conn, (position[0], position[1])).await {
+            Ok((base_id, quote_id, trading_pair_id)) => {
+                if new_positions
+                    .iter()
+                    .any(|new_position| new_position.trading_pair_id == trading_pair_id)
+                {
+                    return Err(invalid_position(index, pos, "Duplicated position"));
+                }
+                match get_pair_spec(db_conn, trading_pair_id).await {
+                    Ok(spec) if spec.enabled => {}
+                    Ok(_) => {
+                        return Err(invalid_position(index, pos, "The trading pair is disabled"))
+                    }
+                    Err(_) => return Err(invalid_position(index, pos, "Position not found")),
+                }
+                new_positions.push(NewPosition {
+                    base_id,
+                    quote_id,
+                    trading_pair_id,
+                });
+            }
+            Err(_) => return Err(invalid_position(index, pos, "Position not found")),
+        }
+    }
+    return Ok(new_positions);
+}
+
+// A retry gets the portfolio it created back, a request for the other kind of
+// portfolio must not end up trading in it
+fn existing_portfolio(portfolio_id: i32, existing_paper: bool, paper: bool) -> (Status, Value) {
+    if existing_paper != paper {
+        return (
+            Status::Conflict,
+            json!({"status":"error", "message": "A portfolio with this name exists with a different paper setting", "id": portfolio_id, "paper": existing_paper}),
+        );
+    }
+    return (
+        Status::Ok,
+        json!({"status":"successful", "id": portfolio_id, "created": false, "paper": paper}),
+    );
+}
+
+// Creating a portfolio with a name the user already owns returns the existing portfolio,
+// so a retried request does not fail. Everything else is written in one transaction.
 #[post("/api/portfolio", data = "<add_portfolio_info>")]
 pub async fn add_portfolio<'r>(
     add_portfolio_info: Json<AddPortfolioInfo<'r>>,
     mut db_conn: Connection<database::PgDb>,
-    _user_auth: UserAuth,
+    _user_auth: Authorized,
 ) -> (Status, Value) {
     // ensure the user is logged in
     let user_id = _user_auth.user_id;
+    let name = add_portfolio_info.name.to_string();
+    let paper = add_portfolio_info.paper.unwrap_or(false);
 
-    // insert the portfolio data into the database
-    let portfolio_result = rocket_db_pools::diesel::insert_into(portfolios::table)
-        .values((
-            portfolios::name.eq(add_portfolio_info.name.to_string()),
-            portfolios::trader_account_id.eq(user_id),
-            portfolios::portfolio_type.eq(2),
+    // the name is unique among all the portfolios
+    let fetch_existing = portfolios::table
+        .filter(portfolios::name.eq(&name))
+        .filter(portfolios::removed_at.is_null())
+        .select((
+            portfolios::id,
+            portfolios::trader_account_id,
+            portfolios::paper,
         ))
-        .returning(portfolios::id)
-        .get_result::<i32>(&mut db_conn)
+        .first::<(i32, i32, bool)>(&mut db_conn)
         .await;
-
-    let portfolio_id: i32 = match portfolio_result {
-        Ok(value) => value,
-        Err(_) => {
+    match fetch_existing {
+        Ok((portfolio_id, owner_id, existing_paper)) if owner_id == user_id => {
+            return existing_portfolio(portfolio_id, existing_paper, paper);
+        }
+        Ok(_) => {
             return (
-                // For front end request.
-                Status::Ok,
-                json!({"status":"error", "message": "Failed to insert into portfolios"}),
+                Status::Conflict,
+                json!({"status":"error", "message": "The portfolio name is already taken"}),
             );
         }
-    };
-    for pos in &add_portfolio_info.position {
-        let base_id;
-        let quote_id;
-        let position: Vec<&str> = pos.split("/").collect();
-        if let Ok((_base_id, _quote_id, _)) =
-            get_trading_pair_id(&mut db_conn, (position[0], position[1])).await
-        {
-            base_id = _base_id;
-            quote_id = _quote_id;
-        } else {
-            // TODO add rollback
+        Err(diesel::result::Error::NotFound) => {}
+        Err(_) => {
             return (
-                Status::BadRequest,
-                json!({"status":"error", "message":"Position not found"}),
+                Status::InternalServerError,
+                json!({"status":"error", "message": "Failed to fetch portfolios"}),
             );
         }
-        let mut portfolio_balance_result =
-            rocket_db_pools::diesel::insert_into(portfolio_balance::table)
-                .values((
-                    portfolio_balance::portfolio_id.eq(portfolio_id),
-                    portfolio_balance::currency_id.eq(base_id),
-                    portfolio_balance::quantity.eq(sql::<BigInt>("0")),
-                ))
-                .returning(portfolio_balance::id)
-                .get_result::<i32>(&mut db_conn)
-                .await;
+    }
+
+    let new_positions = match validate_positions(&mut db_conn, &add_portfolio_info.position).await {
+        Ok(new_positions) => new_positions,
+        Err(err) => return err,
+    };
 
-        let _portfolio_balance_id: i32 = match portfolio_balance_result {
-            Ok(value) => value,
-            Err(_) => {
-                return (
-                    Status::BadRequest,
-                    json!({"status":"error", "message": "Failed to insert base into portfolio_balance"}),
-                );
+    // every currency of the positions gets one balance row
+    let mut currency_ids: Vec<i32> = vec![];
+    for new_position in &new_positions {
+        for currency_id in [new_position.base_id, new_position.quote_id] {
+            if !currency_ids.contains(&currency_id) {
+                currency_ids.push(currency_id);
             }
-        };
+        }
+    }
 
-        portfolio_balance_result = rocket_db_pools::diesel::insert_into(portfolio_balance::table)
-            .values((
-                portfolio_balance::portfolio_id.eq(portfolio_id),
-                portfolio_balance::currency_id.eq(quote_id),
-                portfolio_balance::quantity.eq(sql::<BigInt>("0")),
-            ))
-            .returning(portfolio_balance::id)
-            .get_result::<i32>(&mut db_conn)
-            .await;
+    let create_result = db_conn
+        .transaction::<i32, diesel::result::Error, _>(|conn| {
+            async move {
+                // insert the portfolio data into the database
+                let portfolio_id = diesel::insert_into(portfolios::table)
+                    .values((
+                        portfolios::name.eq(name),
+                        portfolios::trader_account_id.eq(user_id),
+                        portfolios::portfolio_type.eq(2),
+                        portfolios::paper.eq(paper),
+                    ))
+                    .returning(portfolios::id)
+                    .get_result::<i32>(conn)
+                    .await?;
 
-        let _portfolio_balance_id: i32 = match portfolio_balance_result {
-            Ok(value) => value,
-            Err(_) => {
-                return (
-                    Status::BadRequest,
-                    json!({"status":"error", "message": "Failed to insert quote into portfolio_balance"}),
-                );
-            }
-        };
-        let trading_pair_id = get_trading_pair_id(&mut db_conn, (position[0], position[1]))
-            .await
-            .unwrap()
-            .2;
-        // Insert into positions table
-        let position_result = rocket_db_pools::diesel::insert_into(positions::table)
-            .values((
-                positions::trading_pair_id.eq(trading_pair_id),
-                positions::portfolio_id.eq(portfolio_id),
-            ))
-            .returning(positions::id)
-            .get_result::<i32>(&mut db_conn)
-            .await;
+                for currency_id in currency_ids {
+                    diesel::insert_into(portfolio_balance::table)
+                        .values((
+                            portfolio_balance::portfolio_id.eq(portfolio_id),
+                            portfolio_balance::currency_id.eq(currency_id),
+                            portfolio_balance::quantity.eq(sql::<BigInt>("0")),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
 
-        let _position_id: i32 = match position_result {
-            Ok(value) => value,
-            Err(_) => {
-                return (
-                    Status::BadRequest,
-                    json!({"status":"error", "message": "Failed to insert into positions"}),
-                );
+                for new_position in new_positions {
+                    // Insert into positions table
+                    let position_id = diesel::insert_into(positions::table)
+                        .values((
+                            positions::trading_pair_id.eq(new_position.trading_pair_id),
+                            positions::portfolio_id.eq(portfolio_id),
+                        ))
+                        .returning(positions::id)
+                        .get_result::<i32>(conn)
+                        .await?;
+
+                    // Insert into quotation table
+                    diesel::insert_into(quotations::table)
+                        .values((
+                            quotations::quote_currency_id.eq(new_position.quote_id),
+                            quotations::position_id.eq(position_id),
+                        ))
+                        .execute(conn)
+                        .await?;
+                }
+                Ok(portfolio_id)
             }
-        };
-        // Insert into quotation table
-        let quotation_result = rocket_db_pools::diesel::insert_into(quotations::table)
-            .values((
-                quotations::quote_currency_id.eq(quote_id),
-                quotations::position_id.eq(_position_id),
-            ))
-            .returning(quotations::id)
-            .get_result::<i32>(&mut db_conn)
-            .await;
-        let _: i32 = match quotation_result {
-            Ok(value) => value,
-            Err(_) => {
-                return (
-                    Status::BadRequest,
-                    json!({"status":"error", "message": "Failed to insert into quotations table"}),
-                );
+            .scope_boxed()
+        })
+        .await;
+
+    match create_result {
+        Ok(portfolio_id) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "id": portfolio_id, "created": true, "paper": paper}),
+            );
+        }
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => {
+            // another request created the same name in the meantime
+            let fetch_existing = portfolios::table
+                .filter(portfolios::name.eq(add_portfolio_info.name))
+                .filter(portfolios::trader_account_id.eq(user_id))
+                .filter(portfolios::removed_at.is_null())
+                .select((portfolios::id, portfolios::paper))
+                .first::<(i32, bool)>(&mut db_conn)
+                .await;
+            if let Ok((portfolio_id, existing_paper)) = fetch_existing {
+                return existing_portfolio(portfolio_id, existing_paper, paper);
             }
-        };
+            return (
+                Status::Conflict,
+                json!({"status":"error", "message": "The portfolio name is already taken"}),
+            );
+        }
+        Err(_) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": "Failed to create the portfolio"}),
+            );
+        }
     }
-    return (
-        Status::Ok,
-        json!({"status":"successful", "id": portfolio_id}),
-    );
 }
@@ -1,60 +1,96 @@
 use ::diesel::ExpressionMethods;
-use diesel::query_dsl::methods::SelectDsl;
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
 use diesel::query_dsl::JoinOnDsl;
 use rocket::http::Status;
 use rocket::serde::json::{json, Value};
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
 use rocket_db_pools::{diesel, Connection};
 
-use crate::auth::validation::UserAuth;
+use crate::auth::permission::Authorized;
 use crate::db_lib::database;
 use crate::db_lib::query::*;
 use crate::db_lib::schema::{portfolio_balance, portfolios};
+use crate::portfolio::ownership::audit_admin_access;
 
 use std::collections::HashMap;
 
 #[get("/api/portfolio")]
 pub async fn get_portfolio_names(
     mut db_conn: Connection<database::PgDb>,
-    _user_auth: UserAuth,
+    _user_auth: Authorized,
 ) -> (Status, Value) {
     // ensure the user is logged in
-    let _user_id = _user_auth.user_id;
+    let user_id = _user_auth.user_id;
 
-    // find all portfolios
-    let portfolio_names_result: Result<Vec<(String, i32)>, _> = portfolios::table
-        .select((portfolios::name, portfolios::id))
-        .load(&mut db_conn)
-        .await;
+    // find the user's portfolios, admins with the override header get every portfolio
+    let portfolio_names_result: Result<Vec<(String, i32, i32, bool)>, _> =
+        if _user_auth.admin_override {
+            FilterDsl::filter(portfolios::table, portfolios::removed_at.is_null())
+                .select((
+                    portfolios::name,
+                    portfolios::id,
+                    portfolios::trader_account_id,
+                    portfolios::paper,
+                ))
+                .load(&mut db_conn)
+                .await
+        } else {
+            FilterDsl::filter(portfolios::table, portfolios::trader_account_id.eq(user_id))
+                .filter(portfolios::removed_at.is_null())
+                .select((
+                    portfolios::name,
+                    portfolios::id,
+                    portfolios::trader_account_id,
+                    portfolios::paper,
+                ))
+                .load(&mut db_conn)
+                .await
+        };
 
     match portfolio_names_result {
         Ok(portfolios) => {
             // HashMap to store portfolio information
-            let mut portfolio_map: HashMap<String, (i32, Vec<Value>)> = HashMap::new();
+            let mut portfolio_map: HashMap<String, (i32, bool, Vec<Value>)> = HashMap::new();
 
             // find each portfolio's balance and positions
-            for (name, id) in portfolios {
+            for (name, id, trader_account_id, paper) in portfolios {
+                if trader_account_id != user_id {
+                    if let Err(err) =
+                        audit_admin_access(user_id, id, "get_portfolio_names", &mut db_conn).await
+                    {
+                        return err;
+                    }
+                }
                 use diesel::QueryDsl;
                 // Query balance
-                let balance_result: Result<Vec<(i64, i32)>, _> = SelectDsl::select(
-                    diesel::QueryDsl::filter(portfolios::table, portfolios::name.eq(&name))
-                        .inner_join(
-                            portfolio_balance::table
-                                .on(portfolios::id.eq(portfolio_balance::portfolio_id)),
-                        ),
-                    (portfolio_balance::quantity, portfolio_balance::currency_id),
+                let balance_result: Result<Vec<(i64, i64, i32)>, _> = SelectDsl::select(
+                    diesel::QueryDsl::filter(portfolios::table, portfolios::id.eq(id)).inner_join(
+                        portfolio_balance::table
+                            .on(portfolios::id.eq(portfolio_balance::portfolio_id)),
+                    ),
+                    (
+                        portfolio_balance::quantity,
+                        portfolio_balance::reserved,
+                        portfolio_balance::currency_id,
+                    ),
                 )
                 .load(&mut db_conn)
                 .await;
 
-                match balance_result {
-                    Ok(positions) => {
+                let currencies = get_currencies(&mut db_conn).await;
+                match (balance_result, currencies) {
+                    (Ok(positions), Ok(currencies)) => {
                         let mut re_positions = vec![];
-                        for (a, b) in positions {
-                            let currency = get_currency(&mut db_conn, b).await.unwrap();
-                            re_positions.push(json!({"balance":a.to_string(), "symbol":currency}));
+                        for (a, reserved, b) in positions {
+                            // reserved is held by open orders, the rest can be traded
+                            re_positions.push(json!({
+                                "balance":currencies.format(b, a as i128),
+                                "reserved":currencies.format(b, reserved as i128),
+                                "available":currencies.format(b, a as i128 - reserved as i128),
+                                "symbol":currencies.code(b)
+                            }));
                         }
-                        portfolio_map.insert(name.clone(), (id, re_positions));
+                        portfolio_map.insert(name.clone(), (id, paper, re_positions));
                     }
                     _ => {
                         return (
@@ -67,8 +103,9 @@ pub async fn get_portfolio_names(
 
             // Convert HashMap values to PortfolioData
             let mut portfolio_data = Vec::new();
-            for (name, (id, positions)) in portfolio_map {
-                portfolio_data.push(json!({"name":name, "id":id, "positions": positions}));
+            for (name, (id, paper, positions)) in portfolio_map {
+                portfolio_data
+                    .push(json!({"name":name, "id":id, "paper": paper, "positions": positions}));
             }
             portfolio_data.sort_by(|a, b| a["id"].as_i64().cmp(&b["id"].as_i64()));
             let num_portfolios = portfolio_data.len();
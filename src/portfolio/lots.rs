@@ -0,0 +1,332 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::order::exchange::Side;
+use crate::types::amount::{div_round, pow10, Rounding};
+
+// portfolios.cost_basis, which lots a sale closes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CostBasis {
+    Fifo,
+    Lifo,
+    // every open lot is merged into one at the average cost
+    Average,
+}
+
+impl CostBasis {
+    pub const ALL: [CostBasis; 3] = [CostBasis::Fifo, CostBasis::Lifo, CostBasis::Average];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CostBasis::Fifo => "fifo",
+            CostBasis::Lifo => "lifo",
+            CostBasis::Average => "average",
+        }
+    }
+}
+
+impl fmt::Display for CostBasis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for CostBasis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return CostBasis::ALL
+            .into_iter()
+            .find(|method| method.as_str() == s)
+            .ok_or_else(|| format!("Unknown cost basis method: {}", s));
+    }
+}
+
+// One execution of the position, in the integers stored in order_fills
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LotFill {
+    pub side: Side,
+    pub price: i64,
+    pub qty: i64,
+    // charged in the quote currency
+    pub fee: i64,
+    pub time: NaiveDateTime,
+}
+
+// A still open part of the position. `cost` is what opening it paid, fees included,
+// or for a short lot what selling it brought in, fees taken off. It is kept in
+// fractions of a quote unit, see `LotBook::lot_cost`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lot {
+    pub qty: i64,
+    pub cost: i128,
+    pub opened_at: NaiveDateTime,
+}
+
+// The open lots of one position and the PnL its closed parts made.
+// All lots are on the same side: a fill first closes lots of the other side, the
+// rest of it opens a new lot.
+// Amounts are kept exact as price * qty, that is in quote units times `unit`, the
+// base units in one whole base. The accessors round them to quote units.
+#[derive(Debug, Clone)]
+pub struct LotBook {
+    method: CostBasis,
+    unit: i128,
+    // the side of the open lots
+    side: Side,
+    lots: VecDeque<Lot>,
+    realized: i128,
+    fees: i128,
+    last_price: Option<i64>,
+}
+
+// cost of `part` out of a lot of `qty` costing `cost`, the rounding is left in the lot
+fn cost_share(cost: i128, part: i64, qty: i64) -> i128 {
+    return cost.saturating_mul(part as i128) / qty as i128;
+}
+
+impl LotBook {
+    // `base_scale` is the decimals of the position's base currency
+    pub fn new(method: CostBasis, base_scale: u32) -> Self {
+        return LotBook {
+            method,
+            unit: pow10(base_scale).unwrap_or(1),
+            side: Side::Buy,
+            lots: VecDeque::new(),
+            realized: 0,
+            fees: 0,
+            last_price: None,
+        };
+    }
+
+    pub fn from_fills(method: CostBasis, base_scale: u32, fills: &[LotFill]) -> Self {
+        let mut book = LotBook::new(method, base_scale);
+        for fill in fills {
+            book.apply(fill);
+        }
+        return book;
+    }
+
+    pub fn apply(&mut self, fill: &LotFill) {
+        if fill.qty <= 0 {
+            return;
+        }
+        self.last_price = Some(fill.price);
+        let fill_fee = (fill.fee as i128).saturating_mul(self.unit);
+        self.fees = self.fees.saturating_add(fill_fee);
+
+        let mut remaining = fill.qty;
+        if fill.side != self.side {
+            while remaining > 0 {
+                let lot = match self.method {
+                    CostBasis::Lifo => self.lots.back_mut(),
+                    CostBasis::Fifo | CostBasis::Average => self.lots.front_mut(),
+                };
+                let lot = if let Some(lot) = lot {
+                    lot
+                } else {
+                    break;
+                };
+                let closed = remaining.min(lot.qty);
+                let basis = cost_share(lot.cost, closed, lot.qty);
+                let fee = cost_share(fill_fee, closed, fill.qty);
+                let amount = fill.price as i128 * closed as i128;
+                // closing a long sells it, closing a short buys it back
+                let realized = match self.side {
+                    Side::Buy => amount.saturating_sub(fee).saturating_sub(basis),
+                    Side::Sell => basis.saturating_sub(amount).saturating_sub(fee),
+                };
+                self.realized = self.realized.saturating_add(realized);
+                lot.qty -= closed;
+                lot.cost = lot.cost.saturating_sub(basis);
+                remaining -= closed;
+                if lot.qty == 0 {
+                    match self.method {
+                        CostBasis::Lifo => self.lots.pop_back(),
+                        CostBasis::Fifo | CostBasis::Average => self.lots.pop_front(),
+                    };
+                }
+            }
+            if remaining == 0 {
+                return;
+            }
+            // the fill went through zero, what is left opens the other side
+            self.side = fill.side;
+        }
+
+        let fee = fill_fee - cost_share(fill_fee, fill.qty - remaining, fill.qty);
+        let amount = fill.price as i128 * remaining as i128;
+        let cost = match fill.side {
+            Side::Buy => amount.saturating_add(fee),
+            Side::Sell => amount.saturating_sub(fee),
+        };
+        match (self.method, self.lots.back_mut()) {
+            (CostBasis::Average, Some(lot)) => {
+                lot.qty = lot.qty.saturating_add(remaining);
+                lot.cost = lot.cost.saturating_add(cost);
+            }
+            _ => self.lots.push_back(Lot {
+                qty: remaining,
+                cost,
+                opened_at: fill.time,
+            }),
+        }
+    }
+
+    pub fn method(&self) -> CostBasis {
+        return self.method;
+    }
+
+    pub fn lots(&self) -> &VecDeque<Lot> {
+        return &self.lots;
+    }
+
+    // to the nearest quote unit
+    fn quote_units(&self, value: i128) -> i128 {
+        return div_round(value, self.unit, Rounding::Nearest).unwrap_or(value);
+    }
+
+    fn exact_cost(&self) -> i128 {
+        return self
+            .lots
+            .iter()
+            .fold(0i128, |cost, lot| cost.saturating_add(lot.cost));
+    }
+
+    // the cost of one of the open lots, in quote units
+    pub fn lot_cost(&self, lot: &Lot) -> i128 {
+        return self.quote_units(lot.cost);
+    }
+
+    // signed, a short position is negative
+    pub fn position(&self) -> i64 {
+        let qty = self
+            .lots
+            .iter()
+            .fold(0i64, |qty, lot| qty.saturating_add(lot.qty));
+        match self.side {
+            Side::Buy => return qty,
+            Side::Sell => return -qty,
+        }
+    }
+
+    pub fn cost(&self) -> i128 {
+        return self.quote_units(self.exact_cost());
+    }
+
+    pub fn realized(&self) -> i128 {
+        return self.quote_units(self.realized);
+    }
+
+    pub fn fees(&self) -> i128 {
+        return self.quote_units(self.fees);
+    }
+
+    pub fn last_price(&self) -> Option<i64> {
+        return self.last_price;
+    }
+
+    // what closing every open lot at `mark_price` would make, exit fees left out
+    pub fn unrealized(&self, mark_price: i64) -> i128 {
+        let value = (mark_price as i128).saturating_mul(self.position().unsigned_abs() as i128);
+        let unrealized = match self.side {
+            Side::Buy => value.saturating_sub(self.exact_cost()),
+            Side::Sell => self.exact_cost().saturating_sub(value),
+        };
+        return self.quote_units(unrealized);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn fill(side: Side, price: i64, qty: i64, fee: i64, minute: u32) -> LotFill {
+        return LotFill {
+            side,
+            price,
+            qty,
+            fee,
+            time: NaiveDate::from_ymd_opt(2026, 10, 18)
+                .unwrap()
+                .and_hms_opt(0, minute, 0)
+                .unwrap(),
+        };
+    }
+
+    fn fills() -> Vec<LotFill> {
+        return vec![
+            fill(Side::Buy, 100, 10, 0, 0),
+            fill(Side::Buy, 200, 10, 0, 1),
+            fill(Side::Sell, 300, 5, 0, 2),
+        ];
+    }
+
+    #[test]
+    fn realized_by_method() {
+        let realized = |method| LotBook::from_fills(method, 0, &fills()).realized();
+        // 5 closed at 100, at 200, at the average 150
+        assert_eq!(realized(CostBasis::Fifo), 1_000);
+        assert_eq!(realized(CostBasis::Lifo), 500);
+        assert_eq!(realized(CostBasis::Average), 750);
+
+        let fifo = LotBook::from_fills(CostBasis::Fifo, 0, &fills());
+        assert_eq!(fifo.position(), 15);
+        assert_eq!(fifo.lots().len(), 2);
+        assert_eq!(fifo.lots()[0].qty, 5);
+        // 5 at 100 and 10 at 200 marked at 250
+        assert_eq!(fifo.unrealized(250), 15 * 250 - 2_500);
+        // realized plus unrealized does not depend on the method
+        for method in CostBasis::ALL {
+            let book = LotBook::from_fills(method, 0, &fills());
+            assert_eq!(book.realized() + book.unrealized(250), 1_000 + 1_250);
+        }
+    }
+
+    #[test]
+    fn fees_and_going_short() {
+        let mut book = LotBook::new(CostBasis::Fifo, 0);
+        book.apply(&fill(Side::Buy, 100, 10, 10, 0));
+        // closes the 10 long and opens 10 short, the fee is split between the two
+        book.apply(&fill(Side::Sell, 120, 20, 20, 1));
+        assert_eq!(book.realized(), 1_200 - 10 - 1_010);
+        assert_eq!(book.position(), -10);
+        assert_eq!(book.cost(), 1_200 - 10);
+        assert_eq!(book.unrealized(110), 1_190 - 1_100);
+        book.apply(&fill(Side::Buy, 110, 10, 0, 2));
+        assert_eq!(book.position(), 0);
+        assert_eq!(book.realized(), 180 + 90);
+        assert_eq!(book.fees(), 30);
+        assert_eq!(book.unrealized(500), 0);
+    }
+
+    #[test]
+    fn decimal_quantities() {
+        // 0.5 and 0.25 BTC bought at 30000 and 31000 USDT, 0.3 sold at 32000
+        let mut book = LotBook::new(CostBasis::Fifo, 8);
+        book.apply(&fill(Side::Buy, 30_000_000_000, 50_000_000, 1_500_000, 0));
+        book.apply(&fill(Side::Buy, 31_000_000_000, 25_000_000, 0, 1));
+        book.apply(&fill(Side::Sell, 32_000_000_000, 30_000_000, 0, 2));
+        assert_eq!(book.position(), 45_000_000);
+        // 0.3 * 2000 less 0.6 of the 1.5 USDT fee
+        assert_eq!(book.realized(), 599_100_000);
+        // 0.2 at 30000 with the rest of the fee and 0.25 at 31000
+        assert_eq!(book.cost(), 13_750_600_000);
+        assert_eq!(book.lot_cost(&book.lots()[0]), 6_000_600_000);
+        assert_eq!(book.unrealized(32_000_000_000), 649_400_000);
+        assert_eq!(book.fees(), 1_500_000);
+    }
+
+    #[test]
+    fn cost_basis_names() {
+        for method in CostBasis::ALL {
+            assert_eq!(method.as_str().parse::<CostBasis>(), Ok(method));
+        }
+        assert!("hifo".parse::<CostBasis>().is_err());
+    }
+}
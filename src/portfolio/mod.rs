@@ -1,4 +1,23 @@
 pub mod change_portfolio;
 pub mod create_portfolio;
 pub mod get_portfolio;
+pub mod lots;
+pub mod ownership;
+pub mod performance;
+pub mod pnl;
 pub mod remove_portfolio;
+pub mod snapshot;
+pub mod valuation;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        create_portfolio::add_portfolio,
+        remove_portfolio::remove_portfolio,
+        get_portfolio::get_portfolio_names,
+        change_portfolio::change_portfolio,
+        pnl::get_pnl,
+        pnl::set_cost_basis,
+        valuation::get_valuation,
+        snapshot::get_performance
+    ]
+}
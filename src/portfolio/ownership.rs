@@ -0,0 +1,143 @@
+use ::diesel::ExpressionMethods;
+use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::{diesel, Connection};
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::schema::{admin_audit_log, portfolios};
+
+// How a route refers to a portfolio
+pub enum PortfolioKey<'a> {
+    Id(i32),
+    Name(&'a str),
+}
+
+// A portfolio the current user is allowed to act on
+#[derive(Debug, Clone)]
+pub struct OwnedPortfolio {
+    pub id: i32,
+    pub name: String,
+    pub trader_account_id: i32,
+    pub portfolio_type: i32,
+    // orders go to the paper exchange only
+    pub paper: bool,
+}
+
+// record an admin acting on a portfolio of another user
+pub async fn audit_admin_access(
+    admin_account_id: i32,
+    portfolio_id: i32,
+    action: &str,
+    mut db_conn: &mut Connection<database::PgDb>,
+) -> Result<(), (Status, Value)> {
+    let insert_audit = diesel::insert_into(admin_audit_log::table)
+        .values((
+            admin_audit_log::admin_account_id.eq(admin_account_id),
+            admin_audit_log::portfolio_id.eq(portfolio_id),
+            admin_audit_log::action.eq(action),
+        ))
+        .execute(&mut db_conn)
+        .await;
+
+    match insert_audit {
+        Ok(_) => return Ok(()),
+        Err(_) => {
+            return Err((
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to write the audit log."}),
+            ))
+        }
+    }
+}
+
+// record an admin looking at account-wide data of another user
+pub async fn audit_admin_account_access(
+    admin_account_id: i32,
+    account_id: i32,
+    action: &str,
+    mut db_conn: &mut Connection<database::PgDb>,
+) -> Result<(), (Status, Value)> {
+    let insert_audit = diesel::insert_into(admin_audit_log::table)
+        .values((
+            admin_audit_log::admin_account_id.eq(admin_account_id),
+            admin_audit_log::account_id.eq(account_id),
+            admin_audit_log::action.eq(action),
+        ))
+        .execute(&mut db_conn)
+        .await;
+
+    match insert_audit {
+        Ok(_) => return Ok(()),
+        Err(_) => {
+            return Err((
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to write the audit log."}),
+            ))
+        }
+    }
+}
+
+// Resolve the portfolio and make sure the user owns it.
+// Admins may act on any portfolio when they send the override header, every such access is audited.
+pub async fn owned_portfolio(
+    user_auth: &Authorized,
+    key: PortfolioKey<'_>,
+    action: &str,
+    mut db_conn: &mut Connection<database::PgDb>,
+) -> Result<OwnedPortfolio, (Status, Value)> {
+    // a removed portfolio only lives on in its history
+    let query = portfolios::table
+        .filter(portfolios::removed_at.is_null())
+        .select((
+            portfolios::id,
+            portfolios::name,
+            portfolios::trader_account_id,
+            portfolios::portfolio_type,
+            portfolios::paper,
+        ));
+    let fetch_portfolio = match key {
+        PortfolioKey::Id(id) => {
+            query
+                .filter(portfolios::id.eq(id))
+                .first::<(i32, String, i32, i32, bool)>(&mut db_conn)
+                .await
+        }
+        PortfolioKey::Name(name) => {
+            query
+                .filter(portfolios::name.eq(name))
+                .first::<(i32, String, i32, i32, bool)>(&mut db_conn)
+                .await
+        }
+    };
+
+    let (id, name, trader_account_id, portfolio_type, paper) = match fetch_portfolio {
+        Ok(portfolio) => portfolio,
+        Err(_) => {
+            return Err((
+                Status::NotFound,
+                json!({"status":"error", "message":"The portfolio does not exist"}),
+            ));
+        }
+    };
+
+    if trader_account_id != user_auth.user_id {
+        if !user_auth.admin_override {
+            return Err((
+                Status::Forbidden,
+                json!({"status":"error", "message":"You do not own this portfolio."}),
+            ));
+        }
+        audit_admin_access(user_auth.user_id, id, action, db_conn).await?;
+    }
+
+    return Ok(OwnedPortfolio {
+        id,
+        name,
+        trader_account_id,
+        portfolio_type,
+        paper,
+    });
+}
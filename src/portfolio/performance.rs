@@ -0,0 +1,212 @@
+use chrono::NaiveDateTime;
+
+const SECONDS_PER_YEAR: f64 = 365.25 * 86_400.0;
+
+// One NAV snapshot. `flow` is what was deposited (positive) or withdrawn (negative)
+// since the previous one, in the same currency as the NAV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NavPoint {
+    pub time: NaiveDateTime,
+    pub nav: i128,
+    pub flow: i128,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Performance {
+    // the return of every period, None when the period started without any value
+    pub returns: Vec<Option<f64>>,
+    // growth of one unit invested at the first snapshot, one value per snapshot
+    pub index: Vec<f64>,
+    // profit over everything put in: the starting NAV and the deposits
+    pub cumulative_return: Option<f64>,
+    // time-weighted return, the deposits and withdrawals do not count as performance
+    pub twr: Option<f64>,
+    pub max_drawdown: f64,
+    // annualized
+    pub volatility: Option<f64>,
+    pub sharpe: Option<f64>,
+    pub sortino: Option<f64>,
+}
+
+// The return of the period ending at `current`, the flow is taken out of the gain
+fn period_return(previous: &NavPoint, current: &NavPoint) -> Option<f64> {
+    if previous.nav <= 0 {
+        return None;
+    }
+    return Some((current.nav - current.flow - previous.nav) as f64 / previous.nav as f64);
+}
+
+fn mean(values: &[f64]) -> f64 {
+    return values.iter().sum::<f64>() / values.len() as f64;
+}
+
+// sample standard deviation
+fn std_dev(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = mean(values);
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (values.len() - 1) as f64;
+    return Some(variance.sqrt());
+}
+
+// How many periods of the series make a year, from the average time between snapshots
+fn periods_per_year(points: &[NavPoint]) -> Option<f64> {
+    let (first, last) = (points.first()?, points.last()?);
+    let seconds = (last.time - first.time).num_seconds() as f64;
+    if points.len() < 2 || seconds <= 0.0 {
+        return None;
+    }
+    return Some(SECONDS_PER_YEAR * (points.len() - 1) as f64 / seconds);
+}
+
+fn ratio(excess: f64, deviation: Option<f64>) -> Option<f64> {
+    return deviation
+        .filter(|deviation| *deviation > 0.0)
+        .map(|deviation| excess / deviation);
+}
+
+// Metrics of the snapshots, oldest first. The flow of the first snapshot is ignored,
+// it is the starting point. `risk_free` is the annual rate the Sharpe and Sortino
+// ratios are measured against.
+pub fn performance(points: &[NavPoint], risk_free: f64) -> Performance {
+    let returns: Vec<Option<f64>> = points
+        .windows(2)
+        .map(|pair| period_return(&pair[0], &pair[1]))
+        .collect();
+    let valid: Vec<f64> = returns.iter().flatten().copied().collect();
+
+    let mut index = vec![];
+    let mut value = 1.0;
+    let mut peak = 1.0;
+    let mut max_drawdown: f64 = 0.0;
+    if !points.is_empty() {
+        index.push(value);
+    }
+    for period in &returns {
+        value *= 1.0 + period.unwrap_or(0.0);
+        peak = f64::max(peak, value);
+        if peak > 0.0 {
+            max_drawdown = max_drawdown.max(1.0 - value / peak);
+        }
+        index.push(value);
+    }
+    let twr = if valid.is_empty() {
+        None
+    } else {
+        Some(value - 1.0)
+    };
+
+    let cumulative_return = match (points.first(), points.last()) {
+        (Some(first), Some(last)) if points.len() > 1 => {
+            let flows = &points[1..];
+            let net_flow: i128 = flows.iter().map(|point| point.flow).sum();
+            let deposits: i128 = flows.iter().map(|point| point.flow.max(0)).sum();
+            let invested = first.nav.max(0) + deposits;
+            if invested > 0 {
+                Some((last.nav - first.nav - net_flow) as f64 / invested as f64)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+
+    let (volatility, sharpe, sortino) = match periods_per_year(points) {
+        Some(periods) if !valid.is_empty() => {
+            let free = risk_free / periods;
+            let excess = (mean(&valid) - free) * periods.sqrt();
+            let deviation = std_dev(&valid);
+            // only the periods below the risk free rate count as risk
+            let downside = (valid
+                .iter()
+                .map(|value| (value - free).min(0.0).powi(2))
+                .sum::<f64>()
+                / valid.len() as f64)
+                .sqrt();
+            (
+                deviation.map(|deviation| deviation * periods.sqrt()),
+                ratio(excess, deviation),
+                ratio(excess, Some(downside)),
+            )
+        }
+        _ => (None, None, None),
+    };
+
+    return Performance {
+        returns,
+        index,
+        cumulative_return,
+        twr,
+        max_drawdown,
+        volatility,
+        sharpe,
+        sortino,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn series(values: &[(i128, i128)]) -> Vec<NavPoint> {
+        let start =
+            NaiveDateTime::parse_from_str("2026-10-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        return values
+            .iter()
+            .enumerate()
+            .map(|(day, (nav, flow))| NavPoint {
+                time: start + Duration::days(day as i64),
+                nav: *nav,
+                flow: *flow,
+            })
+            .collect();
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        return (a - b).abs() < 1e-9;
+    }
+
+    #[test]
+    fn deposits_are_not_returns() {
+        // +10%, then 1000 deposited and flat, then -10%
+        let points = series(&[(1_000, 0), (1_100, 0), (2_100, 1_000), (1_890, 0)]);
+        let result = performance(&points, 0.0);
+        assert_eq!(result.returns.len(), 3);
+        assert!(close(result.returns[0].unwrap(), 0.1));
+        assert!(close(result.returns[1].unwrap(), 0.0));
+        assert!(close(result.returns[2].unwrap(), -0.1));
+        assert!(close(result.twr.unwrap(), 1.1 * 0.9 - 1.0));
+        // profit -110 over 2000 put in
+        assert!(close(result.cumulative_return.unwrap(), -110.0 / 2_000.0));
+        assert!(close(result.max_drawdown, 0.1));
+        assert_eq!(result.index.len(), 4);
+    }
+
+    #[test]
+    fn ratios_need_enough_periods() {
+        let flat = performance(&series(&[(1_000, 0), (1_000, 0), (1_000, 0)]), 0.0);
+        assert_eq!(flat.volatility, Some(0.0));
+        assert_eq!(flat.sharpe, None);
+        assert_eq!(flat.sortino, None);
+
+        let single = performance(&series(&[(1_000, 0)]), 0.0);
+        assert_eq!(single.twr, None);
+        assert_eq!(single.cumulative_return, None);
+
+        // an empty start is skipped instead of dividing by zero
+        let funded = performance(&series(&[(0, 0), (500, 500), (550, 0)]), 0.0);
+        assert_eq!(funded.returns[0], None);
+        assert!(close(funded.twr.unwrap(), 0.1));
+
+        let rising = performance(&series(&[(100, 0), (110, 0), (121, 0), (125, 0)]), 0.0);
+        assert!(rising.sharpe.unwrap() > 0.0);
+        assert_eq!(rising.sortino, None);
+        assert_eq!(rising.max_drawdown, 0.0);
+    }
+}
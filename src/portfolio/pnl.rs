@@ -0,0 +1,394 @@
+use ::diesel::{BoolExpressionMethods, ExpressionMethods};
+use chrono::NaiveDateTime;
+use diesel::QueryDsl;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+use rocket_db_pools::{diesel, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::get_pair_symbol;
+use crate::db_lib::schema::{
+    intra_account_transfer_requests as transfers, order_fills, orders, portfolios, positions,
+    quotations,
+};
+use crate::order::exchange::{Side, Symbol};
+use crate::portfolio::lots::{CostBasis, LotBook, LotFill};
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
+use crate::transfer::request::TransferStatus;
+use crate::types::amount::format_units;
+
+// The lots of one position and the price its open part is marked at
+pub struct PositionPnl {
+    pub position_id: i32,
+    pub trading_pair_id: i32,
+    pub symbol: Symbol,
+    pub book: LotBook,
+    pub mark_price: Option<i64>,
+    // "quotation", or "last_fill" when the position was never quoted
+    pub mark_source: Option<&'static str>,
+}
+
+impl PositionPnl {
+    pub fn unrealized(&self) -> Option<i128> {
+        return self.mark_price.map(|price| self.book.unrealized(price));
+    }
+}
+
+pub async fn cost_basis(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+) -> Result<CostBasis, &'static str> {
+    let method = portfolios::table
+        .filter(portfolios::id.eq(portfolio_id))
+        .select(portfolios::cost_basis)
+        .first::<String>(conn)
+        .await
+        .map_err(|_| "The portfolio does not exist")?;
+    return method
+        .parse::<CostBasis>()
+        .map_err(|_| "Unknown cost basis method");
+}
+
+// (from_portfolio_id, trading_pair_id, price, quantity, fee, decided_at) of an approved transfer
+type TransferRow = (i32, i32, i64, i64, i64, Option<NaiveDateTime>);
+
+// An approved transfer moves the base at its price: the sender sells out of its lots
+// and pays the fee, the receiver opens a lot. Returns the pair and the fill.
+fn transfer_fill(portfolio_id: i32, row: TransferRow) -> Option<(i32, LotFill)> {
+    let (from_portfolio_id, trading_pair_id, price, qty, fee, decided_at) = row;
+    let (side, fee) = if from_portfolio_id == portfolio_id {
+        (Side::Sell, fee)
+    } else {
+        (Side::Buy, 0)
+    };
+    return Some((
+        trading_pair_id,
+        LotFill {
+            side,
+            price,
+            qty,
+            fee,
+            time: decided_at?,
+        },
+    ));
+}
+
+// Replay the portfolio's fills and approved transfers into the lots of each of its positions
+pub async fn position_pnl(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    method: CostBasis,
+) -> Result<Vec<PositionPnl>, &'static str> {
+    let positions = positions::table
+        .filter(positions::portfolio_id.eq(portfolio_id))
+        .order(positions::id.asc())
+        .select((positions::id, positions::trading_pair_id))
+        .load::<(i32, i32)>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the positions")?;
+    let position_ids: Vec<i32> = positions.iter().map(|(id, _)| *id).collect();
+
+    // the latest quoted price of each position
+    let quoted = quotations::table
+        .filter(quotations::position_id.eq_any(position_ids))
+        .filter(quotations::price.is_not_null())
+        .order(quotations::time_stamp.asc())
+        .select((quotations::position_id, quotations::price))
+        .load::<(i32, Option<i64>)>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the quotations")?;
+    let mut quotes: HashMap<i32, i64> = HashMap::new();
+    for (position_id, price) in quoted {
+        if let Some(price) = price {
+            quotes.insert(position_id, price);
+        }
+    }
+
+    let fills = order_fills::table
+        .inner_join(orders::table)
+        .filter(orders::portfolio_id.eq(portfolio_id))
+        .order((order_fills::time_stamp.asc(), order_fills::id.asc()))
+        .select((
+            orders::trading_pair_id,
+            orders::buyin,
+            order_fills::price,
+            order_fills::qty,
+            order_fills::fee,
+            order_fills::time_stamp,
+        ))
+        .load::<(i32, bool, i64, i64, i64, NaiveDateTime)>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the fills")?;
+    let mut pair_fills: HashMap<i32, Vec<LotFill>> = HashMap::new();
+    for (trading_pair_id, buyin, price, qty, fee, time) in fills {
+        pair_fills
+            .entry(trading_pair_id)
+            .or_default()
+            .push(LotFill {
+                side: if buyin { Side::Buy } else { Side::Sell },
+                price,
+                qty,
+                fee,
+                time,
+            });
+    }
+
+    let moved = transfers::table
+        .inner_join(positions::table)
+        .filter(transfers::status.eq(TransferStatus::Approved.as_str()))
+        .filter(
+            transfers::from_portfolio_id
+                .eq(portfolio_id)
+                .or(transfers::to_portfolio_id.eq(portfolio_id)),
+        )
+        .select((
+            transfers::from_portfolio_id,
+            positions::trading_pair_id,
+            transfers::price,
+            transfers::quantity,
+            transfers::fee,
+            transfers::decided_at,
+        ))
+        .load::<TransferRow>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the transfers")?;
+    for row in moved {
+        if let Some((trading_pair_id, fill)) = transfer_fill(portfolio_id, row) {
+            pair_fills.entry(trading_pair_id).or_default().push(fill);
+        }
+    }
+    // the sort is stable, fills at the same time keep their order
+    for fills in pair_fills.values_mut() {
+        fills.sort_by_key(|fill| fill.time);
+    }
+
+    let mut result = vec![];
+    for (position_id, trading_pair_id) in positions {
+        let symbol = get_pair_symbol(conn, trading_pair_id)
+            .await
+            .map_err(|_| "Fail to fetch the trading pair")?;
+        let book = LotBook::from_fills(
+            method,
+            symbol.base_scale,
+            pair_fills
+                .get(&trading_pair_id)
+                .map(|fills| fills.as_slice())
+                .unwrap_or_default(),
+        );
+        let (mark_price, mark_source) = match (quotes.get(&position_id), book.last_price()) {
+            (Some(price), _) => (Some(*price), Some("quotation")),
+            (None, Some(price)) => (Some(price), Some("last_fill")),
+            (None, None) => (None, None),
+        };
+        result.push(PositionPnl {
+            position_id,
+            trading_pair_id,
+            symbol,
+            book,
+            mark_price,
+            mark_source,
+        });
+    }
+    return Ok(result);
+}
+
+#[get("/api/portfolio/<id>/pnl?<method>")]
+pub async fn get_pnl(
+    id: i32,
+    method: Option<String>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    if let Err(err) =
+        owned_portfolio(&_user_auth, PortfolioKey::Id(id), "get_pnl", &mut db_conn).await
+    {
+        return err;
+    }
+
+    // the portfolio's own method unless another one is asked for
+    let method = match method {
+        Some(method) => method.parse::<CostBasis>(),
+        None => cost_basis(&mut db_conn, id)
+            .await
+            .map_err(|err| err.to_string()),
+    };
+    let method = match method {
+        Ok(method) => method,
+        Err(message) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+
+    let positions = match position_pnl(&mut db_conn, id, method).await {
+        Ok(positions) => positions,
+        Err(message) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+
+    // amounts are in each pair's quote currency, so totals are per quote currency
+    let mut totals: BTreeMap<String, (u32, i128, i128, i128)> = BTreeMap::new();
+    let mut data = vec![];
+    for position in positions {
+        let symbol = &position.symbol;
+        let quote_amount = |value: i128| format_units(value, symbol.quote_scale);
+        let book = &position.book;
+        let unrealized = position.unrealized();
+        let total = totals
+            .entry(symbol.quote.clone())
+            .or_insert((symbol.quote_scale, 0, 0, 0));
+        total.1 = total.1.saturating_add(book.realized());
+        total.2 = total.2.saturating_add(unrealized.unwrap_or(0));
+        total.3 = total.3.saturating_add(book.fees());
+
+        let lots: Vec<Value> = book
+            .lots()
+            .iter()
+            .map(|lot| {
+                json!({
+                    "qty": symbol.format_qty(lot.qty),
+                    "cost": quote_amount(book.lot_cost(lot)),
+                    "opened_at": lot.opened_at.to_string(),
+                })
+            })
+            .collect();
+        data.push(json!({
+            "position_id": position.position_id,
+            "base": symbol.base,
+            "quote": symbol.quote,
+            "position": symbol.format_qty(book.position()),
+            "cost": quote_amount(book.cost()),
+            "realized": quote_amount(book.realized()),
+            "unrealized": unrealized.map(quote_amount),
+            "fees": quote_amount(book.fees()),
+            "mark_price": position.mark_price.map(|price| symbol.format_price(price)),
+            "mark_source": position.mark_source,
+            "lots": lots,
+        }));
+    }
+    let totals: Vec<Value> = totals
+        .into_iter()
+        .map(|(quote, (scale, realized, unrealized, fees))| {
+            json!({
+                "quote": quote,
+                "realized": format_units(realized, scale),
+                "unrealized": format_units(unrealized, scale),
+                "fees": format_units(fees, scale),
+            })
+        })
+        .collect();
+
+    return (
+        Status::Ok,
+        json!({"status":"successful", "method": method.as_str(), "data": data, "totals": totals}),
+    );
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CostBasisData {
+    method: String,
+}
+
+#[put("/api/portfolio/<id>/pnl", data = "<cost_basis_data>")]
+pub async fn set_cost_basis(
+    id: i32,
+    cost_basis_data: Json<CostBasisData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    if let Err(err) = owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(id),
+        "set_cost_basis",
+        &mut db_conn,
+    )
+    .await
+    {
+        return err;
+    }
+    let method = match cost_basis_data.method.parse::<CostBasis>() {
+        Ok(method) => method,
+        Err(message) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+
+    let update_method = diesel::update(portfolios::table.filter(portfolios::id.eq(id)))
+        .set(portfolios::cost_basis.eq(method.as_str()))
+        .execute(&mut db_conn)
+        .await;
+    if update_method.is_err() {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Database error."}),
+        );
+    }
+    return (
+        Status::Ok,
+        json!({"status":"successful", "method": method.as_str()}),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        return NaiveDate::from_ymd_opt(2026, 10, 18)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn transfers_move_the_lots_between_portfolios() {
+        // portfolio 1 bought 10 at 100, then 4 went to portfolio 2 at 120 for a fee of 2
+        let transfer: TransferRow = (1, 7, 120, 4, 2, Some(at(11)));
+        let bought = LotFill {
+            side: Side::Buy,
+            price: 100,
+            qty: 10,
+            fee: 0,
+            time: at(10),
+        };
+
+        let (pair, sent) = transfer_fill(1, transfer).unwrap();
+        assert_eq!(pair, 7);
+        let sender = LotBook::from_fills(CostBasis::Fifo, 0, &[bought, sent]);
+        assert_eq!(sender.position(), 6);
+        assert_eq!(sender.realized(), 4 * 20 - 2);
+
+        // what the receiver sells later closes its lot instead of opening a short
+        let (_, received) = transfer_fill(2, transfer).unwrap();
+        let sold = LotFill {
+            side: Side::Sell,
+            price: 130,
+            qty: 4,
+            fee: 0,
+            time: at(12),
+        };
+        let receiver = LotBook::from_fills(CostBasis::Fifo, 0, &[received, sold]);
+        assert_eq!(receiver.position(), 0);
+        assert_eq!(receiver.realized(), 4 * 10);
+        assert_eq!(receiver.fees(), 0);
+
+        // a transfer without a decision time was never approved
+        assert_eq!(transfer_fill(1, (1, 7, 120, 4, 2, None)), None);
+    }
+}
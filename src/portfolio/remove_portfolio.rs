@@ -1,132 +1,190 @@
-use ::diesel::ExpressionMethods;
-use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
+use ::diesel::{BoolExpressionMethods, ExpressionMethods};
+use chrono::Utc;
 use diesel::result::Error;
+use diesel::QueryDsl;
 use rocket::http::Status;
 use rocket::serde::json::{json, Value};
 use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::AsyncConnection;
 use rocket_db_pools::{diesel, Connection};
 
-use crate::auth::validation::UserAuth;
+use crate::auth::permission::Authorized;
 use crate::db_lib::database;
-use crate::db_lib::schema::{orders, portfolio_balance, portfolios, positions, quotations};
+use crate::db_lib::schema::{
+    intra_account_transfer_requests, orders, portfolio_balance, portfolios, risk_management,
+};
+use crate::ledger::journal::{record, EntryKind, Journal, LedgerAccount};
+use crate::order::balance::main_portfolio;
+use crate::order::status::OrderStatus;
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
+use crate::transfer::request::TransferStatus;
 
 #[delete("/api/portfolio?<name>")]
 pub async fn remove_portfolio(
     name: String,
     mut db_conn: Connection<database::PgDb>,
-    _user_auth: UserAuth,
+    _user_auth: Authorized,
 ) -> (Status, Value) {
-    // ensure the user is logged in
-    let _user_id = _user_auth.user_id;
+    // ensure the user owns the portfolio
+    let portfolio = match owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Name(&name),
+        "remove_portfolio",
+        &mut db_conn,
+    )
+    .await
+    {
+        Ok(portfolio) => portfolio,
+        Err(err) => return err,
+    };
+    // the main(0) and misc(1) portfolios hold the account's funds and fees
+    if portfolio.portfolio_type != 2 {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "The main and misc portfolios cannot be removed"}),
+        );
+    }
+    let portfolio_id = portfolio.id;
+    let trader_account_id = portfolio.trader_account_id;
+    let paper = portfolio.paper;
+    let user_id = _user_auth.user_id;
 
-    // get portfolio's id
-    let portfolio_id_result: Result<i32, _> = portfolios::table
-        .filter(portfolios::name.eq(name))
-        .select(portfolios::id)
-        .first(&mut db_conn)
-        .await;
+    // Close the portfolio, all or nothing. Its orders, fills, positions and snapshots stay
+    // for the PnL and performance history, only the removal is marked on the row.
+    let remove_result = db_conn
+        .transaction::<(), RemoveError, _>(|conn| {
+            async move {
+                // orders reserve through the balances, locking them keeps new ones out
+                let reserved: Vec<i64> = portfolio_balance::table
+                    .filter(portfolio_balance::portfolio_id.eq(portfolio_id))
+                    .select(portfolio_balance::reserved)
+                    .for_update()
+                    .load(conn)
+                    .await
+                    .map_err(|_| RemoveError::failed("Error fetching portfolio_balance"))?;
+                let open_statuses: Vec<&str> = OrderStatus::ALL
+                    .into_iter()
+                    .filter(|status| status.is_open())
+                    .map(|status| status.as_str())
+                    .collect();
+                let open_orders: i64 = orders::table
+                    .filter(orders::portfolio_id.eq(portfolio_id))
+                    .filter(orders::status.eq_any(open_statuses))
+                    .count()
+                    .get_result(conn)
+                    .await
+                    .map_err(|_| RemoveError::failed("Error fetching orders"))?;
+                if open_orders > 0 || reserved.iter().any(|reserved| *reserved != 0) {
+                    return Err(RemoveError(
+                        Status::Conflict,
+                        "Cancel the open orders of the portfolio first",
+                    ));
+                }
 
-    let portfolio_id: i32 = match portfolio_id_result {
-        Ok(id) => id,
-        Err(_) => {
-            return (
-                Status::BadRequest,
-                json!({"message": "The portfolio does not exist"}),
-            );
-        }
-    };
+                // the transfer requests still waiting for a decision can no longer go through
+                diesel::update(
+                    intra_account_transfer_requests::table
+                        .filter(
+                            intra_account_transfer_requests::from_portfolio_id
+                                .eq(portfolio_id)
+                                .or(intra_account_transfer_requests::to_portfolio_id
+                                    .eq(portfolio_id)),
+                        )
+                        .filter(
+                            intra_account_transfer_requests::status
+                                .eq(TransferStatus::Pending.as_str()),
+                        ),
+                )
+                .set((
+                    intra_account_transfer_requests::status.eq(TransferStatus::Rejected.as_str()),
+                    intra_account_transfer_requests::decided_at.eq(Utc::now().naive_utc()),
+                    intra_account_transfer_requests::note.eq("The portfolio was removed"),
+                ))
+                .execute(conn)
+                .await
+                .map_err(|_| RemoveError::failed("Error rejecting transfer requests"))?;
 
-    // delete portfolio_balance
-    let portfolio_balance = diesel::delete(
-        portfolio_balance::table.filter(portfolio_balance::portfolio_id.eq(portfolio_id)),
-    )
-    .execute(&mut db_conn)
-    .await;
+                // what is left goes back to the main portfolio before the balances go
+                let balances: Vec<(i32, i64)> = portfolio_balance::table
+                    .filter(portfolio_balance::portfolio_id.eq(portfolio_id))
+                    .select((portfolio_balance::currency_id, portfolio_balance::quantity))
+                    .load(conn)
+                    .await
+                    .map_err(|_| RemoveError::failed("Error fetching portfolio_balance"))?;
+                let main_id = main_portfolio(conn, trader_account_id)
+                    .await
+                    .map_err(|_| RemoveError::failed("Error fetching the main portfolio"))?;
+                // simulated funds go back where they came from
+                let (kind, to) = match main_id {
+                    _ if paper => (EntryKind::Paper, LedgerAccount::External),
+                    Some(main_id) => (EntryKind::Main, LedgerAccount::Portfolio(main_id)),
+                    None => (EntryKind::Withdrawal, LedgerAccount::External),
+                };
+                let mut journal = Journal::new(kind)
+                    .by(user_id)
+                    .memo(&format!("Portfolio {} removed", name));
+                for (currency_id, quantity) in balances {
+                    journal = journal.move_funds(
+                        LedgerAccount::Portfolio(portfolio_id),
+                        to,
+                        currency_id,
+                        quantity,
+                    );
+                }
+                if !journal.postings.is_empty() {
+                    record(conn, &journal)
+                        .await
+                        .map_err(|_| RemoveError::failed("Error moving the remaining balances"))?;
+                }
 
-    match portfolio_balance {
-        Ok(_) => (),
-        Err(_) => {
-            return (
-                Status::InternalServerError,
-                json!({"message": "Error deleting portfolio_balance"}),
-            );
-        }
-    }
+                diesel::delete(
+                    portfolio_balance::table
+                        .filter(portfolio_balance::portfolio_id.eq(portfolio_id)),
+                )
+                .execute(conn)
+                .await
+                .map_err(|_| RemoveError::failed("Error deleting portfolio_balance"))?;
 
-    let position_ids_result: Result<Vec<i32>, Error> = positions::table
-        .filter(positions::portfolio_id.eq(portfolio_id))
-        .select(positions::id)
-        .load(&mut db_conn)
-        .await;
+                diesel::delete(
+                    risk_management::table.filter(risk_management::portfolio_id.eq(portfolio_id)),
+                )
+                .execute(conn)
+                .await
+                .map_err(|_| RemoveError::failed("Error deleting risk management data"))?;
 
-    let positions_ids = match position_ids_result {
-        Ok(ids) => ids,
-        Err(_err) => {
-            return (
-                Status::InternalServerError,
-                json!({"message": "Error fetching positions"}),
-            );
-        }
-    };
-    let delete_order = diesel::delete(orders::table.filter(orders::portfolio_id.eq(portfolio_id)))
-        .execute(&mut db_conn)
+                diesel::update(portfolios::table.filter(portfolios::id.eq(portfolio_id)))
+                    .set(portfolios::removed_at.eq(Utc::now().naive_utc()))
+                    .execute(conn)
+                    .await
+                    .map_err(|_| RemoveError::failed("Error removing portfolio"))?;
+                Ok(())
+            }
+            .scope_boxed()
+        })
         .await;
-    match delete_order {
-        Ok(_) => (),
-        Err(_) => {
-            return (
-                Status::InternalServerError,
-                json!({"message": "Error deleting order"}),
-            );
+
+    match remove_result {
+        Ok(_) => {
+            return (Status::Ok, json!({"status":"successful"}));
         }
-    }
-    // delete quotation
-    for position_id in positions_ids {
-        let deleted_quotation =
-            diesel::delete(quotations::table.filter(quotations::position_id.eq(position_id)))
-                .execute(&mut db_conn)
-                .await;
-        match deleted_quotation {
-            Ok(_) => (),
-            Err(_) => {
-                return (
-                    Status::InternalServerError,
-                    json!({"message": "Error deleting quotation"}),
-                );
-            }
+        Err(RemoveError(status, message)) => {
+            return (status, json!({"status":"error", "message": message}));
         }
     }
+}
 
-    // delete positions
-    let deleted_positions =
-        diesel::delete(positions::table.filter(positions::portfolio_id.eq(portfolio_id)))
-            .execute(&mut db_conn)
-            .await;
+// names the step that failed, the whole removal is rolled back
+struct RemoveError(Status, &'static str);
 
-    match deleted_positions {
-        Ok(_) => (),
-        Err(_) => {
-            return (
-                Status::InternalServerError,
-                json!({"message": "Error deleting positions"}),
-            );
-        }
+impl RemoveError {
+    fn failed(message: &'static str) -> Self {
+        RemoveError(Status::InternalServerError, message)
     }
+}
 
-    // delete portfolio
-    let portfolio = diesel::delete(portfolios::table.filter(portfolios::id.eq(portfolio_id)))
-        .execute(&mut db_conn)
-        .await;
-
-    match portfolio {
-        Ok(_) => {
-            return (Status::Ok, json!({"status":"successful"}));
-        }
-        Err(_) => {
-            return (
-                Status::InternalServerError,
-                json!({"message": "Error deleting portfolio"}),
-            );
-        }
+impl From<Error> for RemoveError {
+    fn from(_: Error) -> Self {
+        RemoveError::failed("Error removing portfolio")
     }
 }
@@ -0,0 +1,458 @@
+use ::diesel::{ExpressionMethods, NullableExpressionMethods, OptionalExtension};
+use chrono::{NaiveDateTime, Utc};
+use diesel::QueryDsl;
+use rocket::fairing::{self, AdHoc};
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket::{Build, Orbit, Rocket};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::{self, AsyncPgConnection};
+use rocket_db_pools::Connection;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database::{self, Database};
+use crate::db_lib::schema::{
+    currencies, ledger_entries, ledger_journals, nav_snapshots, orders, portfolio_balance,
+    portfolios,
+};
+use crate::market::route::parse_time;
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
+use crate::portfolio::performance::{performance, NavPoint};
+use crate::portfolio::valuation::{latest_prices, value_balances, PriceGraph};
+use crate::types::amount::format_units;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotError(pub String);
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<rocket_db_pools::diesel::result::Error> for SnapshotError {
+    fn from(err: rocket_db_pools::diesel::result::Error) -> Self {
+        SnapshotError(err.to_string())
+    }
+}
+
+// The [default.snapshot] table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnapshotConfig {
+    // 0 turns the snapshots off, 86400 takes one a day
+    pub interval_seconds: u64,
+    // the currency the NAV is recorded in
+    pub currency: String,
+}
+
+fn balances_json(balances: &HashMap<i32, i64>) -> Value {
+    let sorted: BTreeMap<String, i64> = balances
+        .iter()
+        .map(|(currency_id, quantity)| (currency_id.to_string(), *quantity))
+        .collect();
+    return json!(sorted);
+}
+
+fn balances_from_json(value: &Value) -> HashMap<i32, i64> {
+    return value
+        .as_object()
+        .map(|balances| {
+            balances
+                .iter()
+                .filter_map(|(currency_id, quantity)| {
+                    Some((currency_id.parse::<i32>().ok()?, quantity.as_i64()?))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+}
+
+// What changed the balances other than trading: the change of every currency since
+// the previous snapshot less what the fills explain. Positive is a deposit.
+pub fn external_flows(
+    previous: &HashMap<i32, i64>,
+    current: &HashMap<i32, i64>,
+    trades: &HashMap<i32, i64>,
+) -> Vec<(i32, i64)> {
+    let mut currency_ids: Vec<i32> = previous
+        .keys()
+        .chain(current.keys())
+        .chain(trades.keys())
+        .copied()
+        .collect();
+    currency_ids.sort();
+    currency_ids.dedup();
+    return currency_ids
+        .into_iter()
+        .filter_map(|currency_id| {
+            let before = previous.get(&currency_id).copied().unwrap_or(0);
+            let after = current.get(&currency_id).copied().unwrap_or(0);
+            let traded = trades.get(&currency_id).copied().unwrap_or(0);
+            let flow = after - before - traded;
+            (flow != 0).then_some((currency_id, flow))
+        })
+        .collect();
+}
+
+// The balance changes booked by the portfolio's fills in (after, until], see settle_fill.
+// They are taken from the ledger by when they were booked, a fill the exchange stamped
+// before the previous snapshot but that was synced after it still counts here.
+async fn traded_changes(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    after: NaiveDateTime,
+    until: NaiveDateTime,
+) -> Result<HashMap<i32, i64>, SnapshotError> {
+    let portfolio_orders = orders::table
+        .filter(orders::portfolio_id.eq(portfolio_id))
+        .select(orders::id.nullable());
+    let entries = ledger_entries::table
+        .inner_join(ledger_journals::table)
+        .filter(ledger_entries::portfolio_id.eq(portfolio_id))
+        .filter(ledger_journals::order_id.eq_any(portfolio_orders))
+        .filter(ledger_journals::created_at.gt(after))
+        .filter(ledger_journals::created_at.le(until))
+        .select((ledger_entries::currency_id, ledger_entries::amount))
+        .load::<(i32, i64)>(conn)
+        .await?;
+
+    let mut changes: HashMap<i32, i64> = HashMap::new();
+    for (currency_id, amount) in entries {
+        let total = changes.entry(currency_id).or_default();
+        *total = total.saturating_add(amount);
+    }
+    return Ok(changes);
+}
+
+// Record the portfolio's balances and NAV at `now`, with the deposits and
+// withdrawals since its previous snapshot in the same currency
+pub async fn take_snapshot(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    reporting: i32,
+    graph: &PriceGraph,
+    now: NaiveDateTime,
+) -> Result<(), SnapshotError> {
+    let balances: HashMap<i32, i64> = portfolio_balance::table
+        .filter(portfolio_balance::portfolio_id.eq(portfolio_id))
+        .select((portfolio_balance::currency_id, portfolio_balance::quantity))
+        .load::<(i32, i64)>(conn)
+        .await?
+        .into_iter()
+        .collect();
+    let previous = nav_snapshots::table
+        .filter(nav_snapshots::portfolio_id.eq(portfolio_id))
+        .filter(nav_snapshots::currency_id.eq(reporting))
+        .order(nav_snapshots::taken_at.desc())
+        .select((nav_snapshots::taken_at, nav_snapshots::balances))
+        .first::<(NaiveDateTime, Value)>(conn)
+        .await
+        .optional()?;
+
+    // the first snapshot is the starting point, nothing flowed in before it
+    let flow = match previous {
+        Some((taken_at, previous)) => {
+            let trades = traded_changes(conn, portfolio_id, taken_at, now).await?;
+            let flows = external_flows(&balances_from_json(&previous), &balances, &trades);
+            value_balances(graph, reporting, &flows).nav
+        }
+        None => 0,
+    };
+
+    let mut held: Vec<(i32, i64)> = balances.iter().map(|(id, qty)| (*id, *qty)).collect();
+    held.sort();
+    let nav = value_balances(graph, reporting, &held).nav;
+    let (nav, flow) = match (i64::try_from(nav), i64::try_from(flow)) {
+        (Ok(nav), Ok(flow)) => (nav, flow),
+        _ => {
+            return Err(SnapshotError(format!(
+                "The NAV of portfolio {} overflows",
+                portfolio_id
+            )));
+        }
+    };
+
+    diesel::insert_into(nav_snapshots::table)
+        .values((
+            nav_snapshots::portfolio_id.eq(portfolio_id),
+            nav_snapshots::currency_id.eq(reporting),
+            nav_snapshots::nav.eq(nav),
+            nav_snapshots::net_flow.eq(flow),
+            nav_snapshots::balances.eq(balances_json(&balances)),
+            nav_snapshots::taken_at.eq(now),
+        ))
+        .execute(conn)
+        .await?;
+    return Ok(());
+}
+
+// Snapshot every portfolio at the latest prices. A portfolio that fails is reported
+// and skipped, the others still get their snapshot.
+pub async fn snapshot_all(
+    conn: &mut AsyncPgConnection,
+    currency: &str,
+) -> Result<Vec<(i32, SnapshotError)>, SnapshotError> {
+    let reporting = currencies::table
+        .filter(currencies::code.eq(currency))
+        .select(currencies::id)
+        .first::<i32>(conn)
+        .await
+        .optional()?
+        .ok_or_else(|| SnapshotError(format!("Unknown currency {}", currency)))?;
+    let graph = PriceGraph::new(
+        &latest_prices(conn)
+            .await
+            .map_err(|err| SnapshotError(err.to_string()))?,
+    );
+    let portfolio_ids = portfolios::table
+        .filter(portfolios::removed_at.is_null())
+        .order(portfolios::id.asc())
+        .select(portfolios::id)
+        .load::<i32>(conn)
+        .await?;
+
+    let now = Utc::now().naive_utc();
+    let mut failures = vec![];
+    for portfolio_id in portfolio_ids {
+        if let Err(err) = take_snapshot(conn, portfolio_id, reporting, &graph, now).await {
+            failures.push((portfolio_id, err));
+        }
+    }
+    return Ok(failures);
+}
+
+fn ratio_json(value: Option<f64>) -> Value {
+    return json!(value.filter(|value| value.is_finite()));
+}
+
+// The NAV series of the portfolio between `from` and `to` with its performance.
+// Deposits and withdrawals are taken out of the returns. The series is in the
+// currency of the latest snapshot unless `currency` asks for another one, `risk_free`
+// is the annual rate the Sharpe and Sortino ratios are measured against, 0 by default.
+#[get("/api/portfolio/<id>/performance?<from>&<to>&<currency>&<risk_free>")]
+pub async fn get_performance(
+    id: i32,
+    from: Option<&str>,
+    to: Option<&str>,
+    currency: Option<&str>,
+    risk_free: Option<f64>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    if let Err(err) = owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(id),
+        "get_performance",
+        &mut db_conn,
+    )
+    .await
+    {
+        return err;
+    }
+
+    let (from, to) = match (from.map(parse_time), to.map(parse_time)) {
+        (Some(None), _) | (_, Some(None)) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message":"Invalid time, expected unix seconds or YYYY-MM-DDTHH:MM:SS"}),
+            );
+        }
+        (from, to) => (from.flatten(), to.flatten()),
+    };
+    // the currency of the latest snapshot unless another one is asked for
+    let fetch_currency = match currency {
+        Some(code) => {
+            currencies::table
+                .filter(currencies::code.eq(code))
+                .select((currencies::id, currencies::code, currencies::scale))
+                .first::<(i32, String, i32)>(&mut db_conn)
+                .await
+        }
+        None => {
+            nav_snapshots::table
+                .inner_join(currencies::table)
+                .filter(nav_snapshots::portfolio_id.eq(id))
+                .order(nav_snapshots::taken_at.desc())
+                .select((currencies::id, currencies::code, currencies::scale))
+                .first::<(i32, String, i32)>(&mut db_conn)
+                .await
+        }
+    };
+    let (reporting, currency, scale) = match (fetch_currency.optional(), currency) {
+        (Ok(Some(reporting)), _) => reporting,
+        (Ok(None), Some(code)) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": format!("Unknown currency {}", code)}),
+            );
+        }
+        (Ok(None), None) => {
+            return (
+                Status::NotFound,
+                json!({"status":"error", "message":"No snapshot of the portfolio yet"}),
+            );
+        }
+        (Err(_), _) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Fail to fetch the snapshots"}),
+            );
+        }
+    };
+
+    let mut query = nav_snapshots::table
+        .filter(nav_snapshots::portfolio_id.eq(id))
+        .filter(nav_snapshots::currency_id.eq(reporting))
+        .into_boxed();
+    if let Some(from) = from {
+        query = query.filter(nav_snapshots::taken_at.ge(from));
+    }
+    if let Some(to) = to {
+        query = query.filter(nav_snapshots::taken_at.le(to));
+    }
+    let fetch_snapshots = query
+        .order(nav_snapshots::taken_at.asc())
+        .select((
+            nav_snapshots::taken_at,
+            nav_snapshots::nav,
+            nav_snapshots::net_flow,
+        ))
+        .load::<(NaiveDateTime, i64, i64)>(&mut db_conn)
+        .await;
+    let points: Vec<NavPoint> = if let Ok(snapshots) = fetch_snapshots {
+        snapshots
+            .into_iter()
+            .map(|(time, nav, flow)| NavPoint {
+                time,
+                nav: nav as i128,
+                flow: flow as i128,
+            })
+            .collect()
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Fail to fetch the snapshots"}),
+        );
+    };
+
+    let result = performance(&points, risk_free.unwrap_or(0.0));
+    let data: Vec<Value> = points
+        .iter()
+        .enumerate()
+        .map(|(index, point)| {
+            // the first snapshot of the range has no period before it
+            let period_return = index
+                .checked_sub(1)
+                .and_then(|period| result.returns[period]);
+            json!({
+                "time": point.time.to_string(),
+                "nav": format_units(point.nav, scale as u32),
+                "net_flow": format_units(point.flow, scale as u32),
+                "return": ratio_json(period_return),
+                "index": result.index[index],
+            })
+        })
+        .collect();
+    let len = data.len();
+    return (
+        Status::Ok,
+        json!({
+            "status": "successful",
+            "currency": currency,
+            "data": data,
+            "len": len,
+            "cumulative_return": ratio_json(result.cumulative_return),
+            "twr": ratio_json(result.twr),
+            "max_drawdown": result.max_drawdown,
+            "volatility": ratio_json(result.volatility),
+            "sharpe": ratio_json(result.sharpe),
+            "sortino": ratio_json(result.sortino),
+        }),
+    );
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    match rocket.figment().extract_inner::<SnapshotConfig>("snapshot") {
+        Ok(config) => return Ok(rocket.manage(config)),
+        Err(err) => {
+            println!("Invalid snapshot configuration: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+// snapshot every portfolio in the background
+async fn start_snapshots(rocket: &Rocket<Orbit>) {
+    let (config, pool) = match (
+        rocket.state::<SnapshotConfig>(),
+        database::PgDb::fetch(rocket),
+    ) {
+        (Some(config), Some(db)) => (config.clone(), (**db).clone()),
+        _ => return,
+    };
+    if config.interval_seconds == 0 {
+        return;
+    }
+    let period = Duration::from_secs(config.interval_seconds);
+    rocket::tokio::spawn(async move {
+        let mut ticker = rocket::tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            let mut conn = match pool.get().await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    println!("NAV snapshots: no database connection: {}", err);
+                    continue;
+                }
+            };
+            match snapshot_all(&mut conn, &config.currency).await {
+                Ok(failures) => {
+                    for (portfolio_id, err) in failures {
+                        println!(
+                            "NAV snapshots: fail to snapshot portfolio {}: {}",
+                            portfolio_id, err
+                        );
+                    }
+                }
+                Err(err) => println!("NAV snapshots: {}", err),
+            }
+        }
+    });
+}
+
+// manage the [default.snapshot] configuration and record the NAV of every
+// portfolio at its interval
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("NAV snapshots", |rocket| async {
+        rocket
+            .attach(AdHoc::try_on_ignite("NAV snapshot settings", init))
+            .attach(AdHoc::on_liftoff("NAV snapshot job", |rocket| {
+                Box::pin(start_snapshots(rocket))
+            }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trades_are_not_flows() {
+        // BTC=1, USDT=3: bought 1 BTC for 100 with a fee of 1, then 500 USDT deposited
+        let previous = HashMap::from([(3, 1_000)]);
+        let current = HashMap::from([(1, 1), (3, 1_399)]);
+        let trades = HashMap::from([(1, 1), (3, -101)]);
+        assert_eq!(external_flows(&previous, &current, &trades), vec![(3, 500)]);
+        // a withdrawal of a currency that is gone entirely
+        assert_eq!(
+            external_flows(&HashMap::from([(1, 2)]), &HashMap::new(), &HashMap::new()),
+            vec![(1, -2)]
+        );
+        let stored = balances_json(&current);
+        assert_eq!(balances_from_json(&stored), current);
+    }
+}
@@ -0,0 +1,483 @@
+use ::diesel::{ExpressionMethods, JoinOnDsl};
+use diesel::QueryDsl;
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::AsyncPgConnection;
+use rocket_db_pools::Connection;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::{get_currencies, get_currency_id};
+use crate::db_lib::schema::{
+    currencies, market_candles, portfolio_balance, portfolios, trading_pairs,
+};
+use crate::market::candle::Interval;
+use crate::portfolio::ownership::audit_admin_access;
+use crate::types::amount::{pow10, Currencies};
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    return a;
+}
+
+// How many minor units of one currency a minor unit of another is worth, kept as a
+// fraction so going from quote to base does not lose precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub num: i128,
+    pub den: i128,
+}
+
+impl Rate {
+    pub const ONE: Rate = Rate { num: 1, den: 1 };
+
+    fn reduced(num: i128, den: i128) -> Rate {
+        let divisor = gcd(num, den).max(1);
+        return Rate {
+            num: num / divisor,
+            den: den / divisor,
+        };
+    }
+
+    fn inverse(self) -> Rate {
+        return Rate {
+            num: self.den,
+            den: self.num,
+        };
+    }
+
+    fn then(self, next: Rate) -> Option<Rate> {
+        let num = self.num.checked_mul(next.num)?;
+        let den = self.den.checked_mul(next.den)?;
+        return Some(Rate::reduced(num, den));
+    }
+
+    // `amount` converted, rounded half away from zero
+    pub fn convert(self, amount: i64) -> Option<i128> {
+        let scaled = (amount as i128).checked_mul(self.num)?;
+        let half = self.den / 2;
+        let rounded = if scaled >= 0 {
+            scaled.checked_add(half)?
+        } else {
+            scaled.checked_sub(half)?
+        };
+        return Some(rounded / self.den);
+    }
+}
+
+// The latest price of every pair as edges between currencies, walked both ways
+#[derive(Debug, Default)]
+pub struct PriceGraph {
+    edges: HashMap<i32, Vec<(i32, Rate)>>,
+}
+
+impl PriceGraph {
+    // (base_currency_id, quote_currency_id, price of one whole base in quote units,
+    // decimals of the base)
+    pub fn new(prices: &[(i32, i32, i64, u32)]) -> Self {
+        let mut graph = PriceGraph::default();
+        for (base, quote, price, base_scale) in prices {
+            let unit = if let Some(unit) = pow10(*base_scale) {
+                unit
+            } else {
+                continue;
+            };
+            if *price <= 0 || base == quote {
+                continue;
+            }
+            let rate = Rate::reduced(*price as i128, unit);
+            graph.edges.entry(*base).or_default().push((*quote, rate));
+            graph
+                .edges
+                .entry(*quote)
+                .or_default()
+                .push((*base, rate.inverse()));
+        }
+        return graph;
+    }
+
+    // The fewest hops from one currency to another, with the currencies on the way
+    pub fn path(&self, from: i32, to: i32) -> Option<(Vec<i32>, Rate)> {
+        if from == to {
+            return Some((vec![from], Rate::ONE));
+        }
+        let mut previous: HashMap<i32, (i32, Rate)> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(currency) = queue.pop_front() {
+            for (next, rate) in self.edges.get(&currency).into_iter().flatten() {
+                if *next == from || previous.contains_key(next) {
+                    continue;
+                }
+                previous.insert(*next, (currency, *rate));
+                if *next == to {
+                    // walk back to the start, then multiply the rates from it
+                    let mut hops = vec![];
+                    let mut at = to;
+                    while at != from {
+                        let (before, rate) = previous[&at];
+                        hops.push((at, rate));
+                        at = before;
+                    }
+                    hops.reverse();
+                    let mut path = vec![from];
+                    let mut total = Rate::ONE;
+                    for (currency, rate) in hops {
+                        path.push(currency);
+                        total = total.then(rate)?;
+                    }
+                    return Some((path, total));
+                }
+                queue.push_back(*next);
+            }
+        }
+        return None;
+    }
+}
+
+// The close of the latest candle of every pair with the decimals of its base
+pub async fn latest_prices(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<(i32, i32, i64, u32)>, &'static str> {
+    let pairs = trading_pairs::table
+        .inner_join(currencies::table.on(currencies::id.eq(trading_pairs::base_currency_id)))
+        .select((
+            trading_pairs::id,
+            trading_pairs::base_currency_id,
+            trading_pairs::quote_currency_id,
+            currencies::scale,
+        ))
+        .load::<(i32, i32, i32, i32)>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the trading pairs")?;
+    // newest first, so the first close seen for a pair is its latest
+    let closes = market_candles::table
+        .filter(market_candles::resolution.eq(Interval::OneMinute.as_str()))
+        .distinct_on(market_candles::trading_pair_id)
+        .order((
+            market_candles::trading_pair_id.asc(),
+            market_candles::open_time.desc(),
+        ))
+        .select((market_candles::trading_pair_id, market_candles::close))
+        .load::<(i32, i64)>(conn)
+        .await
+        .map_err(|_| "Fail to fetch the prices")?;
+    let closes: HashMap<i32, i64> = closes.into_iter().collect();
+    return Ok(pairs
+        .into_iter()
+        .filter_map(|(id, base, quote, scale)| {
+            closes
+                .get(&id)
+                .map(|price| (base, quote, *price, scale as u32))
+        })
+        .collect());
+}
+
+// A balance and what it is worth in the reporting currency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holding {
+    pub currency_id: i32,
+    pub quantity: i64,
+    pub value: i128,
+    // the currencies the price went through, from the held one to the reporting one
+    pub path: Vec<i32>,
+}
+
+// Balances valued in the reporting currency. Those without a price path are kept
+// apart and left out of the NAV.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortfolioValue {
+    pub reporting: i32,
+    pub holdings: Vec<Holding>,
+    // (currency_id, quantity)
+    pub missing: Vec<(i32, i64)>,
+    pub nav: i128,
+}
+
+impl PortfolioValue {
+    pub fn nav_string(&self, currencies: &Currencies) -> String {
+        return currencies.format(self.reporting, self.nav);
+    }
+
+    pub fn holdings_json(&self, currencies: &Currencies) -> Vec<Value> {
+        return self
+            .holdings
+            .iter()
+            .map(|holding| {
+                // share of the NAV, left out when there is nothing to share
+                let weight = if self.nav != 0 {
+                    Some(holding.value as f64 / self.nav as f64)
+                } else {
+                    None
+                };
+                let path: Vec<String> =
+                    holding.path.iter().map(|id| currencies.code(*id)).collect();
+                json!({
+                    "currency": currencies.code(holding.currency_id),
+                    "quantity": currencies.format(holding.currency_id, holding.quantity as i128),
+                    "value": currencies.format(self.reporting, holding.value),
+                    "weight": weight,
+                    "path": path,
+                })
+            })
+            .collect();
+    }
+
+    pub fn missing_json(&self, currencies: &Currencies) -> Vec<Value> {
+        return self
+            .missing
+            .iter()
+            .map(|(currency_id, quantity)| {
+                json!({
+                    "currency": currencies.code(*currency_id),
+                    "quantity": currencies.format(*currency_id, *quantity as i128),
+                    "message": format!(
+                        "No price path from {} to {}",
+                        currencies.code(*currency_id),
+                        currencies.code(self.reporting)
+                    ),
+                })
+            })
+            .collect();
+    }
+}
+
+pub fn value_balances(
+    graph: &PriceGraph,
+    reporting: i32,
+    balances: &[(i32, i64)],
+) -> PortfolioValue {
+    let mut holdings = vec![];
+    let mut missing = vec![];
+    for (currency_id, quantity) in balances {
+        match graph
+            .path(*currency_id, reporting)
+            .and_then(|(path, rate)| Some((path, rate.convert(*quantity)?)))
+        {
+            Some((path, value)) => holdings.push(Holding {
+                currency_id: *currency_id,
+                quantity: *quantity,
+                value,
+                path,
+            }),
+            None => missing.push((*currency_id, *quantity)),
+        }
+    }
+    let nav = holdings
+        .iter()
+        .fold(0i128, |nav, holding| nav.saturating_add(holding.value));
+    return PortfolioValue {
+        reporting,
+        holdings,
+        missing,
+        nav,
+    };
+}
+
+// Every portfolio of the user and the account as a whole, marked to the latest
+// prices in `currency`. Balances that cannot be priced are listed under `missing`
+// and left out of the NAV, and so are paper portfolios from the account's.
+#[get("/api/portfolio/valuation?<currency>")]
+pub async fn get_valuation(
+    currency: Option<&str>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let user_id = _user_auth.user_id;
+    let reporting_code = currency.unwrap_or("USDT");
+    let reporting = match get_currency_id(&mut db_conn, reporting_code).await {
+        Ok(id) => id,
+        Err(_) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": format!("Unknown currency {}", reporting_code)}),
+            );
+        }
+    };
+
+    // admins with the override header get every portfolio, as in get_portfolio_names
+    let fetch_portfolios = if _user_auth.admin_override {
+        portfolios::table
+            .filter(portfolios::removed_at.is_null())
+            .order(portfolios::id.asc())
+            .select((
+                portfolios::id,
+                portfolios::name,
+                portfolios::trader_account_id,
+                portfolios::paper,
+            ))
+            .load::<(i32, String, i32, bool)>(&mut db_conn)
+            .await
+    } else {
+        portfolios::table
+            .filter(portfolios::trader_account_id.eq(user_id))
+            .filter(portfolios::removed_at.is_null())
+            .order(portfolios::id.asc())
+            .select((
+                portfolios::id,
+                portfolios::name,
+                portfolios::trader_account_id,
+                portfolios::paper,
+            ))
+            .load::<(i32, String, i32, bool)>(&mut db_conn)
+            .await
+    };
+    let user_portfolios = if let Ok(user_portfolios) = fetch_portfolios {
+        user_portfolios
+    } else {
+        return (
+            Status::InternalServerError,
+            json!({"status":"error", "message":"Failed to find the portfolio"}),
+        );
+    };
+    let portfolio_ids: Vec<i32> = user_portfolios.iter().map(|(id, ..)| *id).collect();
+
+    let fetch_balances = portfolio_balance::table
+        .filter(portfolio_balance::portfolio_id.eq_any(&portfolio_ids))
+        .select((
+            portfolio_balance::portfolio_id,
+            portfolio_balance::currency_id,
+            portfolio_balance::quantity,
+        ))
+        .load::<(i32, i32, i64)>(&mut db_conn)
+        .await;
+    let fetch_currencies = get_currencies(&mut db_conn).await;
+    let (balances, currencies) = match (fetch_balances, fetch_currencies) {
+        (Ok(balances), Ok(currencies)) => (balances, currencies),
+        _ => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message":"Failed to find the portfolio balance"}),
+            );
+        }
+    };
+    let graph = match latest_prices(&mut db_conn).await {
+        Ok(prices) => PriceGraph::new(&prices),
+        Err(message) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+
+    let mut data = vec![];
+    // per account: currency -> quantity, paper portfolios hold no real funds
+    let mut accounts: BTreeMap<i32, BTreeMap<i32, i64>> = BTreeMap::new();
+    for (id, name, trader_account_id, paper) in user_portfolios {
+        if trader_account_id != user_id {
+            if let Err(err) = audit_admin_access(user_id, id, "get_valuation", &mut db_conn).await {
+                return err;
+            }
+        }
+        let held: Vec<(i32, i64)> = balances
+            .iter()
+            .filter(|(portfolio_id, _, _)| *portfolio_id == id)
+            .map(|(_, currency_id, quantity)| (*currency_id, *quantity))
+            .collect();
+        let account = accounts.entry(trader_account_id).or_default();
+        if !paper {
+            for (currency_id, quantity) in &held {
+                let total = account.entry(*currency_id).or_insert(0);
+                *total = total.saturating_add(*quantity);
+            }
+        }
+        let value = value_balances(&graph, reporting, &held);
+        data.push(json!({
+            "id": id,
+            "name": name,
+            "account_id": trader_account_id,
+            "paper": paper,
+            "nav": value.nav_string(&currencies),
+            "holdings": value.holdings_json(&currencies),
+            "missing": value.missing_json(&currencies),
+        }));
+    }
+    let accounts: Vec<Value> = accounts
+        .into_iter()
+        .map(|(account_id, held)| {
+            let held: Vec<(i32, i64)> = held.into_iter().collect();
+            let value = value_balances(&graph, reporting, &held);
+            json!({
+                "account_id": account_id,
+                "nav": value.nav_string(&currencies),
+                "holdings": value.holdings_json(&currencies),
+                "missing": value.missing_json(&currencies),
+            })
+        })
+        .collect();
+
+    return (
+        Status::Ok,
+        json!({
+            "status": "successful",
+            "currency": reporting_code,
+            "data": data,
+            "accounts": accounts,
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 BTC, 2 ETH, 3 USDT, 4 DOGE, in whole units
+    fn graph() -> PriceGraph {
+        return PriceGraph::new(&[
+            (1, 3, 30_000, 0),
+            (2, 1, 0, 0),
+            (2, 3, 2_000, 0),
+            (4, 5, 7, 0),
+        ]);
+    }
+
+    #[test]
+    fn triangulates_through_pairs() {
+        let graph = graph();
+        let (path, rate) = graph.path(1, 2).unwrap();
+        // BTC -> USDT -> ETH
+        assert_eq!(path, vec![1, 3, 2]);
+        assert_eq!(rate.convert(2), Some(30));
+        // USDT -> BTC keeps the fraction
+        let (_, rate) = graph.path(3, 1).unwrap();
+        assert_eq!(rate.convert(45_000), Some(2));
+        assert_eq!(rate.convert(44_999), Some(1));
+        assert_eq!(graph.path(3, 3), Some((vec![3], Rate::ONE)));
+        // a pair without a price is no path
+        assert_eq!(graph.path(4, 3), None);
+    }
+
+    #[test]
+    fn rates_between_minor_units() {
+        // 30000.000000 USDT for one BTC of 8 decimals
+        let graph = PriceGraph::new(&[(1, 3, 30_000_000_000, 8)]);
+        let (_, rate) = graph.path(1, 3).unwrap();
+        // 0.5 BTC is 15000 USDT
+        assert_eq!(rate.convert(50_000_000), Some(15_000_000_000));
+        let (_, rate) = graph.path(3, 1).unwrap();
+        assert_eq!(rate.convert(3_000_000), Some(10_000));
+    }
+
+    #[test]
+    fn missing_prices_are_reported() {
+        let currencies = Currencies::new(vec![
+            (1, "BTC".to_string(), 0),
+            (3, "USDT".to_string(), 0),
+            (4, "DOGE".to_string(), 0),
+        ]);
+        let value = value_balances(&graph(), 3, &[(1, 1), (3, 10_000), (4, 5)]);
+        assert_eq!(value.nav, 40_000);
+        assert_eq!(value.holdings.len(), 2);
+        assert_eq!(value.holdings_json(&currencies)[0]["weight"], json!(0.75));
+        assert_eq!(value.missing, vec![(4, 5)]);
+        assert_eq!(
+            value.missing_json(&currencies)[0]["currency"],
+            json!("DOGE")
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use ::diesel::ExpressionMethods;
+use chrono::{NaiveDateTime, Utc};
+use diesel::QueryDsl;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::{self, AsyncPgConnection};
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::db_lib::query::get_pair_symbol;
+use crate::db_lib::schema::{
+    order_fills, orders, portfolio_balance, risk_decisions, risk_management, trading_pairs,
+};
+use crate::order::exchange::Side;
+use crate::order::lifecycle::fill_summaries;
+use crate::order::status::OrderStatus;
+use crate::risk::rule::{OrderIntent, RiskContext, RiskRule};
+use crate::types::amount::{div_round, pow10, Rounding};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RiskError(pub &'static str);
+
+impl From<diesel::result::Error> for RiskError {
+    fn from(_: diesel::result::Error) -> Self {
+        RiskError("Fail to evaluate the risk rules")
+    }
+}
+
+// One rule an order broke
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    // risk_management.id
+    pub rule_id: i32,
+    pub rule: String,
+    pub message: String,
+    // a loss limit rather than a limit on the order itself
+    #[serde(skip)]
+    pub loss_limit: bool,
+}
+
+pub fn evaluate(
+    rules: &[(i32, RiskRule)],
+    intent: &OrderIntent,
+    context: &RiskContext,
+) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (rule_id, rule) in rules {
+        if let Err(message) = rule.check(intent, context) {
+            violations.push(Violation {
+                rule_id: *rule_id,
+                rule: rule.rule_type().to_string(),
+                message,
+                loss_limit: rule.is_loss_limit(),
+            });
+        }
+    }
+    return violations;
+}
+
+// A fill of the portfolio as (trading_pair_id, buyin, price, qty, fee, time), oldest first
+pub type PortfolioFill = (i32, bool, i64, i64, i64, NaiveDateTime);
+
+// PnL figures from the fills alone: the cash flow of every fill, with what is still
+// held marked at the last fill price of its pair.
+// Returns the PnL on `pair_id`, the portfolio PnL since `day_start` and the drawdown
+// of the portfolio PnL from its peak. `base_scales` has the base decimals of every
+// pair, a missing one counts as whole units.
+pub fn fill_pnl(
+    fills: &[PortfolioFill],
+    base_scales: &HashMap<i32, u32>,
+    pair_id: i32,
+    day_start: NaiveDateTime,
+) -> (i64, i64, i64) {
+    // per pair: cash, net quantity, last price, base units in one whole base
+    let mut pairs: HashMap<i32, (i128, i128, i128, i128)> = HashMap::new();
+    let value = |(cash, net_qty, last_price, unit): (i128, i128, i128, i128)| {
+        let held = div_round(net_qty.saturating_mul(last_price), unit, Rounding::Nearest);
+        cash.saturating_add(held.unwrap_or(0))
+    };
+    let (mut total, mut peak, mut at_day_start): (i128, i128, Option<i128>) = (0, 0, None);
+
+    for (trading_pair_id, buyin, price, qty, fee, time) in fills {
+        if at_day_start.is_none() && *time >= day_start {
+            at_day_start = Some(total);
+        }
+        let scale = base_scales.get(trading_pair_id).copied().unwrap_or(0);
+        let unit = pow10(scale).unwrap_or(1);
+        let entry = pairs.entry(*trading_pair_id).or_insert((0, 0, 0, unit));
+        total = total.saturating_sub(value(*entry));
+        // rounded the way settle_fill books it
+        let notional =
+            |rounding| div_round(*price as i128 * *qty as i128, unit, rounding).unwrap_or(0);
+        if *buyin {
+            entry.0 = entry.0.saturating_sub(notional(Rounding::Up));
+            entry.1 += *qty as i128;
+        } else {
+            entry.0 = entry.0.saturating_add(notional(Rounding::Down));
+            entry.1 -= *qty as i128;
+        }
+        entry.0 = entry.0.saturating_sub(*fee as i128);
+        entry.2 = *price as i128;
+        total = total.saturating_add(value(*entry));
+        peak = peak.max(total);
+    }
+
+    let clamp = |value: i128| value.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+    let pair_pnl = pairs.get(&pair_id).map(|entry| value(*entry)).unwrap_or(0);
+    return (
+        clamp(pair_pnl),
+        clamp(total.saturating_sub(at_day_start.unwrap_or(total))),
+        clamp(peak.saturating_sub(total)),
+    );
+}
+
+// Everything the rules look at, as of now
+pub async fn risk_context(
+    conn: &mut AsyncPgConnection,
+    intent: &OrderIntent,
+) -> Result<RiskContext, RiskError> {
+    let base_currency_id = trading_pairs::table
+        .filter(trading_pairs::id.eq(intent.trading_pair_id))
+        .select(trading_pairs::base_currency_id)
+        .first::<i32>(conn)
+        .await?;
+    let held: i64 = portfolio_balance::table
+        .filter(portfolio_balance::portfolio_id.eq(intent.portfolio_id))
+        .filter(portfolio_balance::currency_id.eq(base_currency_id))
+        .select(portfolio_balance::quantity)
+        .load::<i64>(conn)
+        .await?
+        .iter()
+        .sum();
+
+    let open_statuses: Vec<&str> = OrderStatus::ALL
+        .into_iter()
+        .filter(|status| status.is_open())
+        .map(|status| status.as_str())
+        .collect();
+    let open_orders = orders::table
+        .filter(orders::portfolio_id.eq(intent.portfolio_id))
+        .filter(orders::status.eq_any(open_statuses))
+        .select((
+            orders::id,
+            orders::trading_pair_id,
+            orders::buyin,
+            orders::qty,
+        ))
+        .load::<(i32, i32, bool, i64)>(conn)
+        .await?
+        .into_iter()
+        .filter(|(id, ..)| Some(*id) != intent.amends)
+        .collect::<Vec<_>>();
+    let open_ids: Vec<i32> = open_orders.iter().map(|order| order.0).collect();
+    let summaries = fill_summaries(conn, &open_ids)
+        .await
+        .map_err(|_| RiskError("Fail to evaluate the risk rules"))?;
+    let pending: i64 = open_orders
+        .iter()
+        .filter(|(_, trading_pair_id, buyin, _)| {
+            *buyin && *trading_pair_id == intent.trading_pair_id
+        })
+        .map(|(id, _, _, qty)| qty - summaries.get(id).map(|s| s.filled_qty).unwrap_or(0))
+        .sum();
+
+    let fills = order_fills::table
+        .inner_join(orders::table)
+        .filter(orders::portfolio_id.eq(intent.portfolio_id))
+        .order(order_fills::time_stamp.asc())
+        .select((
+            orders::trading_pair_id,
+            orders::buyin,
+            order_fills::price,
+            order_fills::qty,
+            order_fills::fee,
+            order_fills::time_stamp,
+        ))
+        .load::<PortfolioFill>(conn)
+        .await?;
+    let mut base_scales: HashMap<i32, u32> = HashMap::new();
+    for (trading_pair_id, ..) in &fills {
+        if let Entry::Vacant(entry) = base_scales.entry(*trading_pair_id) {
+            entry.insert(get_pair_symbol(conn, *trading_pair_id).await?.base_scale);
+        }
+    }
+    let now = Utc::now().naive_utc();
+    let day_start = now.date().and_hms_opt(0, 0, 0).unwrap_or(now);
+    let (pnl, daily_pnl, drawdown) =
+        fill_pnl(&fills, &base_scales, intent.trading_pair_id, day_start);
+
+    return Ok(RiskContext {
+        position: held.saturating_add(pending),
+        pnl,
+        daily_pnl,
+        drawdown,
+        open_orders: open_orders.len() as i64,
+        now: now.time(),
+    });
+}
+
+// the enabled rules of a portfolio, a stored rule that cannot be read anymore is
+// reported as broken rather than skipped
+pub async fn portfolio_rules(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+) -> Result<Vec<(i32, Result<RiskRule, String>)>, RiskError> {
+    let rows = risk_management::table
+        .filter(risk_management::portfolio_id.eq(portfolio_id))
+        .filter(risk_management::valid.eq(true))
+        .order(risk_management::id.asc())
+        .select((
+            risk_management::id,
+            risk_management::rule_type,
+            risk_management::params,
+        ))
+        .load::<(i32, String, Value)>(conn)
+        .await?;
+    return Ok(rows
+        .into_iter()
+        .map(|(id, rule_type, params)| (id, RiskRule::from_parts(&rule_type, &params)))
+        .collect());
+}
+
+// Evaluate every enabled rule of the portfolio and log the decision.
+// An empty list means the order may go through.
+pub async fn check_order(
+    conn: &mut AsyncPgConnection,
+    intent: &OrderIntent,
+) -> Result<Vec<Violation>, RiskError> {
+    let mut violations = vec![];
+    let mut rules = vec![];
+    for (id, rule) in portfolio_rules(conn, intent.portfolio_id).await? {
+        match rule {
+            Ok(rule) => rules.push((id, rule)),
+            Err(message) => violations.push(Violation {
+                rule_id: id,
+                rule: "invalid".to_string(),
+                message,
+                loss_limit: false,
+            }),
+        }
+    }
+
+    if !rules.is_empty() {
+        let context = risk_context(conn, intent).await?;
+        violations.extend(evaluate(&rules, intent, &context));
+    }
+
+    diesel::insert_into(risk_decisions::table)
+        .values((
+            risk_decisions::portfolio_id.eq(intent.portfolio_id),
+            risk_decisions::account_id.eq(intent.account_id),
+            risk_decisions::trading_pair_id.eq(intent.trading_pair_id),
+            risk_decisions::buyin.eq(intent.side == Side::Buy),
+            risk_decisions::price.eq(intent.price),
+            risk_decisions::qty.eq(intent.qty),
+            risk_decisions::approved.eq(violations.is_empty()),
+            risk_decisions::violations.eq(json!(violations)),
+        ))
+        .execute(conn)
+        .await?;
+    return Ok(violations);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::rule::{AllowedSides, MaxOrderSize};
+    use crate::types::amount::Amount;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        return NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn reports_every_broken_rule() {
+        let rules = vec![
+            (
+                1,
+                RiskRule::AllowedSides(AllowedSides {
+                    sides: vec![Side::Sell],
+                }),
+            ),
+            (
+                2,
+                RiskRule::MaxOrderSize(MaxOrderSize {
+                    max_qty: Some(Amount::new(10, 0)),
+                    max_notional: None,
+                }),
+            ),
+        ];
+        let intent = OrderIntent {
+            account_id: 1,
+            portfolio_id: 1,
+            trading_pair_id: 1,
+            pair: "BTC/USDT".to_string(),
+            side: Side::Buy,
+            price: 1,
+            qty: 20,
+            base_scale: 0,
+            quote_scale: 0,
+            amends: None,
+        };
+        let violations = evaluate(&rules, &intent, &RiskContext::default());
+        let broken: Vec<_> = violations
+            .iter()
+            .map(|v| (v.rule_id, v.rule.as_str()))
+            .collect();
+        assert_eq!(broken, vec![(1, "allowed_sides"), (2, "max_order_size")]);
+    }
+
+    #[test]
+    fn pnl_from_fills() {
+        let fills: Vec<PortfolioFill> = vec![
+            // buy 10 at 100, the price then rises to 120 on a partial sell
+            (1, true, 100, 10, 0, at(17, 10)),
+            (1, false, 120, 5, 0, at(17, 11)),
+            // next day pair 1 drops to 90 and pair 2 is bought with a fee
+            (1, false, 90, 1, 0, at(18, 9)),
+            (2, true, 50, 2, 4, at(18, 10)),
+        ];
+        let (pair_pnl, daily_pnl, drawdown) = fill_pnl(&fills, &HashMap::new(), 1, at(18, 0));
+        // cash -1000 + 600 + 90, 4 held at 90
+        assert_eq!(pair_pnl, 50);
+        // at the end of the first day: -400 + 5 * 120 = 200, now 50 - 4
+        assert_eq!(daily_pnl, 46 - 200);
+        assert_eq!(drawdown, 200 - 46);
+        assert_eq!(fill_pnl(&[], &HashMap::new(), 1, at(18, 0)), (0, 0, 0));
+    }
+
+    #[test]
+    fn pnl_with_decimal_quantities() {
+        // 0.5 BTC bought at 30000.000000 USDT and 0.2 sold at 31000.000000
+        let fills: Vec<PortfolioFill> = vec![
+            (1, true, 30_000_000_000, 50_000_000, 0, at(17, 10)),
+            (1, false, 31_000_000_000, 20_000_000, 1_000_000, at(17, 11)),
+        ];
+        let scales = HashMap::from([(1, 8)]);
+        let (pair_pnl, _, _) = fill_pnl(&fills, &scales, 1, at(18, 0));
+        // -15000 + 6200 - 1 + 0.3 * 31000 = 499 USDT
+        assert_eq!(pair_pnl, 499_000_000);
+    }
+}
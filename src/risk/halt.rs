@@ -0,0 +1,411 @@
+use ::diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension};
+use chrono::{NaiveDateTime, Utc};
+use diesel::QueryDsl;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::{self, AsyncPgConnection};
+use serde::Serialize;
+
+use crate::db_lib::query::get_pair_symbol;
+use crate::db_lib::schema::{orders, portfolios, trading_halts};
+use crate::order::exchange::{Exchange, ExchangeError};
+use crate::order::lifecycle::{refresh_order, set_status, sync_order};
+use crate::order::status::OrderStatus;
+use crate::risk::engine::{RiskError, Violation};
+
+// What a halt stops: one portfolio or every portfolio of an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltScope {
+    Portfolio(i32),
+    Account(i32),
+}
+
+// (id, portfolio_id, account_id, reason, triggered_by, risk_rule_id, created_at, lifted_by, lifted_at)
+type HaltRow = (
+    i32,
+    Option<i32>,
+    Option<i32>,
+    String,
+    Option<i32>,
+    Option<i32>,
+    NaiveDateTime,
+    Option<i32>,
+    Option<NaiveDateTime>,
+);
+
+// A row of trading_halts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Halt {
+    pub id: i32,
+    pub scope: HaltScope,
+    pub reason: String,
+    // the user that asked for it, None when a risk rule tripped it
+    pub triggered_by: Option<i32>,
+    pub risk_rule_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+    pub lifted_by: Option<i32>,
+    pub lifted_at: Option<NaiveDateTime>,
+}
+
+impl Halt {
+    fn from_row(row: HaltRow) -> Halt {
+        let (id, portfolio_id, account_id, reason, triggered_by, risk_rule_id, created_at) =
+            (row.0, row.1, row.2, row.3, row.4, row.5, row.6);
+        // the table makes sure exactly one of the two is set
+        let scope = match (portfolio_id, account_id) {
+            (Some(portfolio_id), _) => HaltScope::Portfolio(portfolio_id),
+            (None, account_id) => HaltScope::Account(account_id.unwrap_or_default()),
+        };
+        return Halt {
+            id,
+            scope,
+            reason,
+            triggered_by,
+            risk_rule_id,
+            created_at,
+            lifted_by: row.7,
+            lifted_at: row.8,
+        };
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.lifted_at.is_none();
+    }
+
+    pub fn to_json(&self) -> Value {
+        let (scope, scope_id) = match self.scope {
+            HaltScope::Portfolio(id) => ("portfolio", id),
+            HaltScope::Account(id) => ("account", id),
+        };
+        return json!({
+            "id": self.id,
+            "scope": scope,
+            "scope_id": scope_id,
+            "reason": self.reason,
+            "automatic": self.triggered_by.is_none(),
+            "triggered_by": self.triggered_by,
+            "risk_rule_id": self.risk_rule_id,
+            "created_at": self.created_at.to_string(),
+            "active": self.is_active(),
+            "lifted_by": self.lifted_by,
+            "lifted_at": self.lifted_at.map(|time| time.to_string()),
+        });
+    }
+}
+
+// An open order the halt could not cancel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CancelFailure {
+    pub order_id: i32,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CancelReport {
+    pub cancelled: Vec<i32>,
+    pub failed: Vec<CancelFailure>,
+}
+
+async fn owner_of(conn: &mut AsyncPgConnection, portfolio_id: i32) -> Result<i32, RiskError> {
+    return portfolios::table
+        .filter(portfolios::id.eq(portfolio_id))
+        .select(portfolios::trader_account_id)
+        .first::<i32>(conn)
+        .await
+        .map_err(|_| RiskError("Portfolio not found"));
+}
+
+// The halt stopping orders on the portfolio, either its own or its owner's
+pub async fn active_halt(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+) -> Result<Option<Halt>, RiskError> {
+    let owner = owner_of(conn, portfolio_id).await?;
+    let row = trading_halts::table
+        .filter(trading_halts::lifted_at.is_null())
+        .filter(
+            trading_halts::portfolio_id
+                .eq(portfolio_id)
+                .or(trading_halts::account_id.eq(owner)),
+        )
+        .order(trading_halts::id.asc())
+        .select(trading_halts::all_columns)
+        .first::<HaltRow>(conn)
+        .await
+        .optional()?;
+    return Ok(row.map(Halt::from_row));
+}
+
+// Every halt that ever applied to the portfolio, newest first
+pub async fn halt_history(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+) -> Result<Vec<Halt>, RiskError> {
+    let owner = owner_of(conn, portfolio_id).await?;
+    let rows = trading_halts::table
+        .filter(
+            trading_halts::portfolio_id
+                .eq(portfolio_id)
+                .or(trading_halts::account_id.eq(owner)),
+        )
+        .order(trading_halts::id.desc())
+        .select(trading_halts::all_columns)
+        .load::<HaltRow>(conn)
+        .await?;
+    return Ok(rows.into_iter().map(Halt::from_row).collect());
+}
+
+// Halt the scope. Returns the new halt, or the one already in place and false
+// when the scope was halted before.
+pub async fn halt(
+    conn: &mut AsyncPgConnection,
+    scope: HaltScope,
+    reason: &str,
+    triggered_by: Option<i32>,
+    risk_rule_id: Option<i32>,
+) -> Result<(Halt, bool), RiskError> {
+    let (portfolio_id, account_id) = match scope {
+        HaltScope::Portfolio(id) => (Some(id), None),
+        HaltScope::Account(id) => (None, Some(id)),
+    };
+    // the partial unique indexes keep a single active halt per scope
+    let inserted = diesel::insert_into(trading_halts::table)
+        .values((
+            trading_halts::portfolio_id.eq(portfolio_id),
+            trading_halts::account_id.eq(account_id),
+            trading_halts::reason.eq(reason),
+            trading_halts::triggered_by.eq(triggered_by),
+            trading_halts::risk_rule_id.eq(risk_rule_id),
+        ))
+        .on_conflict_do_nothing()
+        .returning(trading_halts::all_columns)
+        .get_result::<HaltRow>(conn)
+        .await
+        .optional()?;
+    if let Some(row) = inserted {
+        return Ok((Halt::from_row(row), true));
+    }
+
+    let query = trading_halts::table
+        .filter(trading_halts::lifted_at.is_null())
+        .select(trading_halts::all_columns);
+    let existing = match scope {
+        HaltScope::Portfolio(id) => {
+            query
+                .filter(trading_halts::portfolio_id.eq(id))
+                .first::<HaltRow>(conn)
+                .await?
+        }
+        HaltScope::Account(id) => {
+            query
+                .filter(trading_halts::account_id.eq(id))
+                .first::<HaltRow>(conn)
+                .await?
+        }
+    };
+    return Ok((Halt::from_row(existing), false));
+}
+
+// Lift the active halts of the portfolio, and the one of its owner's account
+// when `account` is set. Returns the ids of the lifted halts.
+pub async fn lift(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    account: bool,
+    lifted_by: i32,
+) -> Result<Vec<i32>, RiskError> {
+    let owner = owner_of(conn, portfolio_id).await?;
+    let now = Utc::now().naive_utc();
+    let mut lifted = diesel::update(
+        trading_halts::table
+            .filter(trading_halts::lifted_at.is_null())
+            .filter(trading_halts::portfolio_id.eq(portfolio_id)),
+    )
+    .set((
+        trading_halts::lifted_by.eq(lifted_by),
+        trading_halts::lifted_at.eq(now),
+    ))
+    .returning(trading_halts::id)
+    .get_results::<i32>(conn)
+    .await?;
+    if account {
+        lifted.extend(
+            diesel::update(
+                trading_halts::table
+                    .filter(trading_halts::lifted_at.is_null())
+                    .filter(trading_halts::account_id.eq(owner)),
+            )
+            .set((
+                trading_halts::lifted_by.eq(lifted_by),
+                trading_halts::lifted_at.eq(now),
+            ))
+            .returning(trading_halts::id)
+            .get_results::<i32>(conn)
+            .await?,
+        );
+    }
+    return Ok(lifted);
+}
+
+// Cancel every open order in the scope through the exchange. An order that closed
+// on the exchange in the meantime is neither cancelled nor a failure.
+pub async fn cancel_open_orders(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    scope: HaltScope,
+) -> Result<CancelReport, RiskError> {
+    let portfolio_ids = match scope {
+        HaltScope::Portfolio(id) => vec![id],
+        HaltScope::Account(id) => {
+            portfolios::table
+                .filter(portfolios::trader_account_id.eq(id))
+                .select(portfolios::id)
+                .load::<i32>(conn)
+                .await?
+        }
+    };
+    let open_statuses: Vec<&str> = OrderStatus::ALL
+        .into_iter()
+        .filter(|status| status.is_open())
+        .map(|status| status.as_str())
+        .collect();
+    let open_orders = orders::table
+        .filter(orders::portfolio_id.eq_any(portfolio_ids))
+        .filter(orders::status.eq_any(open_statuses))
+        .order(orders::id.asc())
+        .select((
+            orders::id,
+            orders::trading_pair_id,
+            orders::status,
+            orders::exchange_order_id,
+        ))
+        .load::<(i32, i32, String, Option<String>)>(conn)
+        .await?;
+
+    let mut report = CancelReport::default();
+    for (order_id, trading_pair_id, status, exchange_order_id) in open_orders {
+        let mut fail = |message: String| {
+            report.failed.push(CancelFailure { order_id, message });
+        };
+        // held orders never reached the exchange
+        if status == OrderStatus::Held.as_str() {
+            match set_status(conn, order_id, OrderStatus::Cancelled).await {
+                Ok(_) => report.cancelled.push(order_id),
+                Err(err) => fail(err.0),
+            }
+            continue;
+        }
+        let exchange_order_id = if let Some(exchange_order_id) = exchange_order_id {
+            exchange_order_id
+        } else {
+            fail("The order has not been acknowledged by the exchange yet".to_string());
+            continue;
+        };
+        let symbol = if let Ok(symbol) = get_pair_symbol(conn, trading_pair_id).await {
+            symbol
+        } else {
+            fail("Fail to fetch the trading pair".to_string());
+            continue;
+        };
+
+        let status = match exchange.cancel_order(&symbol, &exchange_order_id).await {
+            Ok(exchange_order) => {
+                sync_order(conn, exchange, order_id, &symbol, exchange_order).await
+            }
+            Err(ExchangeError::Rejected(_)) => {
+                // most likely closed on the exchange in the meantime, catch up with it
+                refresh_order(conn, exchange, order_id, &symbol, &exchange_order_id).await
+            }
+            Err(err) => {
+                fail(err.to_string());
+                continue;
+            }
+        };
+        match status {
+            Ok(OrderStatus::Cancelled) => report.cancelled.push(order_id),
+            Ok(status) if !status.is_open() => {}
+            Ok(status) => fail(format!("The order is still {}", status.as_str())),
+            Err(err) => fail(err.0),
+        }
+    }
+    return Ok(report);
+}
+
+// The loss limit to halt the portfolio for, if the order crossed one
+pub fn tripped_loss_limit(violations: &[Violation]) -> Option<&Violation> {
+    return violations.iter().find(|violation| violation.loss_limit);
+}
+
+// Halt the portfolio when a loss limit was crossed and cancel what is still open.
+// Nothing is cancelled again when the portfolio was already halted.
+pub async fn halt_on_breach(
+    conn: &mut AsyncPgConnection,
+    exchange: &Exchange,
+    portfolio_id: i32,
+    violations: &[Violation],
+) -> Result<Option<(Halt, CancelReport)>, RiskError> {
+    let violation = if let Some(violation) = tripped_loss_limit(violations) {
+        violation
+    } else {
+        return Ok(None);
+    };
+    let reason = format!("{} breached: {}", violation.rule, violation.message);
+    let scope = HaltScope::Portfolio(portfolio_id);
+    let (halt, created) = halt(conn, scope, &reason, None, Some(violation.rule_id)).await?;
+    let report = if created {
+        cancel_open_orders(conn, exchange, scope).await?
+    } else {
+        CancelReport::default()
+    };
+    return Ok(Some((halt, report)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violation(rule_id: i32, rule: &str, loss_limit: bool) -> Violation {
+        return Violation {
+            rule_id,
+            rule: rule.to_string(),
+            message: String::new(),
+            loss_limit,
+        };
+    }
+
+    #[test]
+    fn only_loss_limits_halt() {
+        let violations = vec![
+            violation(1, "max_order_size", false),
+            violation(2, "max_daily_loss", true),
+        ];
+        assert_eq!(tripped_loss_limit(&violations).map(|v| v.rule_id), Some(2));
+        assert_eq!(tripped_loss_limit(&violations[..1]), None);
+    }
+
+    #[test]
+    fn halt_scope_from_row() {
+        let created_at = NaiveDateTime::default();
+        let row = |portfolio_id, account_id| {
+            (
+                1,
+                portfolio_id,
+                account_id,
+                String::new(),
+                None,
+                None,
+                created_at,
+                None,
+                None,
+            )
+        };
+        let halt = Halt::from_row(row(Some(3), None));
+        assert_eq!(halt.scope, HaltScope::Portfolio(3));
+        assert!(halt.is_active());
+        assert_eq!(halt.to_json()["automatic"], json!(true));
+        assert_eq!(
+            Halt::from_row(row(None, Some(7))).scope,
+            HaltScope::Account(7)
+        );
+    }
+}
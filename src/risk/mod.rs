@@ -0,0 +1,397 @@
+use rocket::http::Status;
+use rocket_db_pools::diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use rocket_db_pools::Connection;
+// use rudrist_backend::db_lib::schema::risk_management::position;
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::schema::{portfolios, risk_management};
+use crate::order::exchange::Exchange;
+use crate::portfolio::ownership::{audit_admin_access, owned_portfolio, PortfolioKey};
+use crate::risk::halt::{active_halt, cancel_open_orders, halt, halt_history, lift, HaltScope};
+use crate::risk::rule::RiskRule;
+use rocket::serde::json::Json;
+use rocket::serde::json::{json, Value};
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+pub mod engine;
+pub mod halt;
+pub mod rule;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        get_risk_status,
+        update_risk,
+        remove_risk_rule,
+        get_halt_status,
+        halt_portfolio,
+        lift_halt
+    ]
+}
+
+#[get("/api/risk")]
+pub async fn get_risk_status(
+    mut db_conn: Connection<database::PgDb>,
+    // cookies: &CookieJar<'_>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let user_id = _user_auth.user_id;
+
+    // fetch the rules of every portfolio of the user
+    let fetch_risk_status = risk_management::table
+        .inner_join(portfolios::table)
+        .filter(portfolios::trader_account_id.eq(user_id))
+        .order(risk_management::id.asc())
+        .select((
+            risk_management::id,
+            risk_management::rule_type,
+            risk_management::params,
+            risk_management::valid,
+            risk_management::portfolio_id,
+        ))
+        .load::<(i32, String, Value, bool, i32)>(&mut db_conn)
+        .await;
+
+    let risk_status = if let Ok(risk_status) = fetch_risk_status {
+        risk_status
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Risk staqtus not found"}),
+        );
+    };
+
+    // return risk status
+    let mut risk_data: Vec<Value> = vec![];
+    let len = risk_status.len();
+    for (id, rule_type, params, valid, portfolio_id) in risk_status {
+        risk_data.push(json!({"id": id, "type": rule_type, "params": params, "on": valid, "portfolio_id": portfolio_id}));
+    }
+    return (
+        Status::Ok,
+        json!({"status": "successful", "data":Value::from(risk_data).to_string(), "len": len}),
+    );
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RiskData {
+    pid: i32,
+    // the rule to change, a new rule is added to the portfolio when left out
+    id: Option<i32>,
+    #[serde(rename = "type")]
+    rule_type: String,
+    params: Value,
+    on: bool,
+}
+
+#[post("/api/risk", data = "<risk_data>")]
+pub async fn update_risk(
+    risk_data: Json<RiskData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    // check the ownership
+    if let Err(err) = owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(risk_data.pid),
+        "update_risk",
+        &mut db_conn,
+    )
+    .await
+    {
+        return err;
+    }
+
+    // every rule type has its own parameters
+    let rule = match RiskRule::from_parts(&risk_data.rule_type, &risk_data.params) {
+        Ok(rule) => rule,
+        Err(message) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+
+    if let Some(rule_id) = risk_data.id {
+        // update the rule, it has to belong to the portfolio
+        let update_risk_info = diesel::update(
+            risk_management::table
+                .filter(risk_management::id.eq(rule_id))
+                .filter(risk_management::portfolio_id.eq(risk_data.pid)),
+        )
+        .set((
+            risk_management::rule_type.eq(rule.rule_type()),
+            risk_management::params.eq(rule.params()),
+            risk_management::valid.eq(risk_data.on),
+        ))
+        .execute(&mut db_conn)
+        .await;
+
+        match update_risk_info {
+            Ok(0) => {
+                return (
+                    Status::NotFound,
+                    json!({"status":"error", "message":"Risk rule not found"}),
+                );
+            }
+            Ok(_) => {
+                return (
+                    Status::Ok,
+                    json!({"status":"successful", "message":"Risk management data updated.", "id": rule_id}),
+                );
+            }
+            Err(_) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message":"Database error."}),
+                );
+            }
+        }
+    }
+
+    let insert_risk_info = diesel::insert_into(risk_management::table)
+        .values((
+            risk_management::portfolio_id.eq(risk_data.pid),
+            risk_management::rule_type.eq(rule.rule_type()),
+            risk_management::params.eq(rule.params()),
+            risk_management::valid.eq(risk_data.on),
+        ))
+        .returning(risk_management::id)
+        .get_result::<i32>(&mut db_conn)
+        .await;
+
+    if let Ok(rule_id) = insert_risk_info {
+        return (
+            Status::Ok,
+            json!({"status":"successful", "message":"Risk management data inserted.", "id": rule_id}),
+        );
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message": "Database error."}),
+        );
+    }
+}
+
+#[delete("/api/risk/<rule_id>")]
+pub async fn remove_risk_rule(
+    rule_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let fetch_portfolio_id = risk_management::table
+        .filter(risk_management::id.eq(rule_id))
+        .select(risk_management::portfolio_id)
+        .first::<i32>(&mut db_conn)
+        .await;
+    let portfolio_id = if let Ok(portfolio_id) = fetch_portfolio_id {
+        portfolio_id
+    } else {
+        return (
+            Status::NotFound,
+            json!({"status":"error", "message":"Risk rule not found"}),
+        );
+    };
+    if let Err(err) = owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(portfolio_id),
+        "remove_risk_rule",
+        &mut db_conn,
+    )
+    .await
+    {
+        return err;
+    }
+
+    let delete_rule =
+        diesel::delete(risk_management::table.filter(risk_management::id.eq(rule_id)))
+            .execute(&mut db_conn)
+            .await;
+    if delete_rule.is_err() {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Database error."}),
+        );
+    }
+    return (
+        Status::Ok,
+        json!({"status":"successful", "message":"Risk rule removed.", "id": rule_id}),
+    );
+}
+
+#[get("/api/risk/<portfolio_id>/halt")]
+pub async fn get_halt_status(
+    portfolio_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    if let Err(err) = owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(portfolio_id),
+        "get_halt_status",
+        &mut db_conn,
+    )
+    .await
+    {
+        return err;
+    }
+
+    let history = match halt_history(&mut db_conn, portfolio_id).await {
+        Ok(history) => history,
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": err.0}),
+            );
+        }
+    };
+    let halted = history.iter().any(|halt| halt.is_active());
+    let data: Vec<Value> = history.iter().map(|halt| halt.to_json()).collect();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "halted": halted, "data": data}),
+    );
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HaltData {
+    reason: String,
+    // halt every portfolio of the owner instead of this one only
+    account: Option<bool>,
+    // cancel the open orders, on unless turned off
+    cancel_orders: Option<bool>,
+}
+
+#[post("/api/risk/<portfolio_id>/halt", data = "<halt_data>")]
+pub async fn halt_portfolio(
+    portfolio_id: i32,
+    halt_data: Json<HaltData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+    exchange: &State<Exchange>,
+) -> (Status, Value) {
+    let portfolio = match owned_portfolio(
+        &_user_auth,
+        PortfolioKey::Id(portfolio_id),
+        "halt_portfolio",
+        &mut db_conn,
+    )
+    .await
+    {
+        Ok(portfolio) => portfolio,
+        Err(err) => return err,
+    };
+    let reason = halt_data.reason.trim();
+    if reason.is_empty() {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"A reason is required"}),
+        );
+    }
+
+    let scope = if halt_data.account.unwrap_or(false) {
+        HaltScope::Account(portfolio.trader_account_id)
+    } else {
+        HaltScope::Portfolio(portfolio.id)
+    };
+    let (halt, created) =
+        match halt(&mut db_conn, scope, reason, Some(_user_auth.user_id), None).await {
+            Ok(halt) => halt,
+            Err(err) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status":"error", "message": err.0}),
+                );
+            }
+        };
+    if !created {
+        return (
+            Status::Conflict,
+            json!({"status":"error", "message":"Trading is already halted", "halt": halt.to_json()}),
+        );
+    }
+
+    if !halt_data.cancel_orders.unwrap_or(true) {
+        return (
+            Status::Ok,
+            json!({"status":"successful", "halt": halt.to_json()}),
+        );
+    }
+    match cancel_open_orders(&mut db_conn, exchange.inner(), scope).await {
+        Ok(report) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "halt": halt.to_json(), "cancelled": report.cancelled, "failed": report.failed}),
+            );
+        }
+        Err(err) => {
+            // the halt itself is in place, only the cancellation failed
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": err.0, "halt": halt.to_json()}),
+            );
+        }
+    }
+}
+
+// admins only, see Permission::LiftHalt
+#[delete("/api/risk/<portfolio_id>/halt?<account>")]
+pub async fn lift_halt(
+    portfolio_id: i32,
+    account: Option<bool>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let fetch_owner = portfolios::table
+        .filter(portfolios::id.eq(portfolio_id))
+        .select(portfolios::trader_account_id)
+        .first::<i32>(&mut db_conn)
+        .await;
+    let owner = if let Ok(owner) = fetch_owner {
+        owner
+    } else {
+        return (
+            Status::NotFound,
+            json!({"status":"error", "message":"The portfolio does not exist"}),
+        );
+    };
+    if owner != _user_auth.user_id {
+        if let Err(err) =
+            audit_admin_access(_user_auth.user_id, portfolio_id, "lift_halt", &mut db_conn).await
+        {
+            return err;
+        }
+    }
+
+    let lifted = match lift(
+        &mut db_conn,
+        portfolio_id,
+        account.unwrap_or(false),
+        _user_auth.user_id,
+    )
+    .await
+    {
+        Ok(lifted) => lifted,
+        Err(err) => {
+            return (
+                Status::InternalServerError,
+                json!({"status":"error", "message": err.0}),
+            );
+        }
+    };
+    if lifted.is_empty() {
+        return (
+            Status::NotFound,
+            json!({"status":"error", "message":"No active halt to lift"}),
+        );
+    }
+
+    // an account wide halt may still be in place
+    let still_halted = matches!(active_halt(&mut db_conn, portfolio_id).await, Ok(Some(_)));
+    return (
+        Status::Ok,
+        json!({"status":"successful", "lifted": lifted, "halted": still_halted}),
+    );
+}
@@ -0,0 +1,540 @@
+use chrono::NaiveTime;
+use rocket::serde::json::{from_value, json, Value};
+use serde::{Deserialize, Serialize};
+
+use crate::order::exchange::Side;
+use crate::types::amount::{format_units, notional, Amount, Rounding};
+
+// The order being checked
+#[derive(Debug, Clone)]
+pub struct OrderIntent {
+    pub account_id: i32,
+    pub portfolio_id: i32,
+    pub trading_pair_id: i32,
+    // "BASE/QUOTE"
+    pub pair: String,
+    pub side: Side,
+    pub price: i64,
+    pub qty: i64,
+    // decimals of the pair's currencies
+    pub base_scale: u32,
+    pub quote_scale: u32,
+    // the open order this one replaces, it is left out of the context
+    pub amends: Option<i32>,
+}
+
+impl OrderIntent {
+    pub fn notional(&self) -> i64 {
+        return notional(self.price, self.qty, self.base_scale, Rounding::Up).unwrap_or(i64::MAX);
+    }
+}
+
+// Where the portfolio stands when the order comes in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RiskContext {
+    // base currency held on the order's pair plus what open buys may still add
+    pub position: i64,
+    // PnL on the order's pair, in its quote currency
+    pub pnl: i64,
+    // PnL of the portfolio since midnight UTC
+    pub daily_pnl: i64,
+    // how far the portfolio's PnL is below its peak
+    pub drawdown: i64,
+    pub open_orders: i64,
+    // UTC
+    pub now: NaiveTime,
+}
+
+impl Default for RiskContext {
+    fn default() -> Self {
+        RiskContext {
+            position: 0,
+            pnl: 0,
+            daily_pnl: 0,
+            drawdown: 0,
+            open_orders: 0,
+            now: NaiveTime::MIN,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxPosition {
+    // every pair when left out
+    #[serde(default)]
+    pub pair: Option<String>,
+    pub max_qty: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxDailyLoss {
+    pub max_loss: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxDrawdown {
+    pub max_drawdown: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxOrderSize {
+    #[serde(default)]
+    pub max_qty: Option<Amount>,
+    // price * qty, in the quote currency
+    #[serde(default)]
+    pub max_notional: Option<Amount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxOpenOrders {
+    pub max: i64,
+}
+
+// "HH:MM" in UTC, a window ending before it starts runs over midnight
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TradingHours {
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PairWhitelist {
+    pub pairs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllowedSides {
+    pub sides: Vec<Side>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StopLoss {
+    #[serde(default)]
+    pub pair: Option<String>,
+    pub max_loss: Amount,
+}
+
+// risk_management.rule_type and its params
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "params", rename_all = "snake_case")]
+pub enum RiskRule {
+    MaxPosition(MaxPosition),
+    MaxDailyLoss(MaxDailyLoss),
+    MaxDrawdown(MaxDrawdown),
+    MaxOrderSize(MaxOrderSize),
+    MaxOpenOrders(MaxOpenOrders),
+    TradingHours(TradingHours),
+    PairWhitelist(PairWhitelist),
+    AllowedSides(AllowedSides),
+    StopLoss(StopLoss),
+}
+
+fn positive(name: &str, value: i64) -> Result<(), String> {
+    if value <= 0 {
+        return Err(format!("{} should be positive", name));
+    }
+    return Ok(());
+}
+
+fn positive_amount(name: &str, value: Amount) -> Result<(), String> {
+    return positive(name, value.units);
+}
+
+// A limit in units of the currency it is compared in. The decimals it has beyond the
+// currency's are dropped, one too large for it is no limit at all.
+fn limit(value: Amount, scale: u32) -> i64 {
+    return value.rescale(scale, Rounding::Down).unwrap_or(i64::MAX);
+}
+
+fn valid_pair(pair: &str) -> Result<(), String> {
+    match pair.split_once('/') {
+        Some((base, quote)) if !base.is_empty() && !quote.is_empty() && !quote.contains('/') => {
+            return Ok(())
+        }
+        _ => return Err(format!("{} is not a BASE/QUOTE pair", pair)),
+    }
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    return NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| format!("{} is not a HH:MM time", value));
+}
+
+// loss limits stop new buys, sells that reduce the position still go through
+fn loss_check(intent: &OrderIntent, loss: i64, max_loss: Amount, what: &str) -> Result<(), String> {
+    if intent.side == Side::Buy && loss >= limit(max_loss, intent.quote_scale) {
+        return Err(format!(
+            "The {} of {} has reached the limit of {}",
+            what,
+            format_units(loss as i128, intent.quote_scale),
+            max_loss
+        ));
+    }
+    return Ok(());
+}
+
+impl RiskRule {
+    // build a rule from what is stored or sent, rejecting unknown types and bad params
+    pub fn from_parts(rule_type: &str, params: &Value) -> Result<RiskRule, String> {
+        let rule = from_value::<RiskRule>(json!({"type": rule_type, "params": params}))
+            .map_err(|err| format!("Invalid {} rule: {}", rule_type, err))?;
+        rule.validate()?;
+        return Ok(rule);
+    }
+
+    pub fn rule_type(&self) -> &'static str {
+        match self {
+            RiskRule::MaxPosition(_) => "max_position",
+            RiskRule::MaxDailyLoss(_) => "max_daily_loss",
+            RiskRule::MaxDrawdown(_) => "max_drawdown",
+            RiskRule::MaxOrderSize(_) => "max_order_size",
+            RiskRule::MaxOpenOrders(_) => "max_open_orders",
+            RiskRule::TradingHours(_) => "trading_hours",
+            RiskRule::PairWhitelist(_) => "pair_whitelist",
+            RiskRule::AllowedSides(_) => "allowed_sides",
+            RiskRule::StopLoss(_) => "stop_loss",
+        }
+    }
+
+    pub fn params(&self) -> Value {
+        let mut value = json!(self);
+        return value["params"].take();
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            RiskRule::MaxPosition(rule) => {
+                if let Some(pair) = &rule.pair {
+                    valid_pair(pair)?;
+                }
+                return positive_amount("max_qty", rule.max_qty);
+            }
+            RiskRule::MaxDailyLoss(rule) => return positive_amount("max_loss", rule.max_loss),
+            RiskRule::MaxDrawdown(rule) => {
+                return positive_amount("max_drawdown", rule.max_drawdown)
+            }
+            RiskRule::MaxOrderSize(rule) => {
+                if rule.max_qty.is_none() && rule.max_notional.is_none() {
+                    return Err("max_qty or max_notional is required".to_string());
+                }
+                if let Some(max_qty) = rule.max_qty {
+                    positive_amount("max_qty", max_qty)?;
+                }
+                if let Some(max_notional) = rule.max_notional {
+                    positive_amount("max_notional", max_notional)?;
+                }
+                return Ok(());
+            }
+            RiskRule::MaxOpenOrders(rule) => return positive("max", rule.max),
+            RiskRule::TradingHours(rule) => {
+                if parse_time(&rule.start)? == parse_time(&rule.end)? {
+                    return Err("start and end should differ".to_string());
+                }
+                return Ok(());
+            }
+            RiskRule::PairWhitelist(rule) => {
+                if rule.pairs.is_empty() {
+                    return Err("pairs should not be empty".to_string());
+                }
+                for pair in &rule.pairs {
+                    valid_pair(pair)?;
+                }
+                return Ok(());
+            }
+            RiskRule::AllowedSides(rule) => {
+                if rule.sides.is_empty() {
+                    return Err("sides should not be empty".to_string());
+                }
+                return Ok(());
+            }
+            RiskRule::StopLoss(rule) => {
+                if let Some(pair) = &rule.pair {
+                    valid_pair(pair)?;
+                }
+                return positive_amount("max_loss", rule.max_loss);
+            }
+        }
+    }
+
+    // whether the rule looks at the loss of the portfolio, breaking one of these
+    // means the portfolio is losing money rather than the order being malformed
+    pub fn is_loss_limit(&self) -> bool {
+        matches!(
+            self,
+            RiskRule::MaxDailyLoss(_) | RiskRule::MaxDrawdown(_) | RiskRule::StopLoss(_)
+        )
+    }
+
+    // Ok when the order passes, otherwise why it does not
+    pub fn check(&self, intent: &OrderIntent, context: &RiskContext) -> Result<(), String> {
+        match self {
+            RiskRule::MaxPosition(rule) => {
+                if rule.pair.as_ref().is_some_and(|pair| *pair != intent.pair) {
+                    return Ok(());
+                }
+                let position = match intent.side {
+                    Side::Buy => context.position.saturating_add(intent.qty),
+                    Side::Sell => context.position,
+                };
+                if position > limit(rule.max_qty, intent.base_scale) {
+                    return Err(format!(
+                        "The position would reach {}, above the limit of {}",
+                        format_units(position as i128, intent.base_scale),
+                        rule.max_qty
+                    ));
+                }
+            }
+            RiskRule::MaxDailyLoss(rule) => loss_check(
+                intent,
+                context.daily_pnl.saturating_neg(),
+                rule.max_loss,
+                "daily loss",
+            )?,
+            RiskRule::MaxDrawdown(rule) => {
+                loss_check(intent, context.drawdown, rule.max_drawdown, "drawdown")?
+            }
+            RiskRule::MaxOrderSize(rule) => {
+                if let Some(max_qty) = rule.max_qty {
+                    if intent.qty > limit(max_qty, intent.base_scale) {
+                        return Err(format!(
+                            "The order quantity {} is above the limit of {}",
+                            format_units(intent.qty as i128, intent.base_scale),
+                            max_qty
+                        ));
+                    }
+                }
+                if let Some(max_notional) = rule.max_notional {
+                    let notional = intent.notional();
+                    if notional > limit(max_notional, intent.quote_scale) {
+                        return Err(format!(
+                            "The order notional {} is above the limit of {}",
+                            format_units(notional as i128, intent.quote_scale),
+                            max_notional
+                        ));
+                    }
+                }
+            }
+            RiskRule::MaxOpenOrders(rule) => {
+                if context.open_orders >= rule.max {
+                    return Err(format!(
+                        "The portfolio already has {} open orders, the limit is {}",
+                        context.open_orders, rule.max
+                    ));
+                }
+            }
+            RiskRule::TradingHours(rule) => {
+                let (start, end) = (parse_time(&rule.start)?, parse_time(&rule.end)?);
+                let open = if start < end {
+                    start <= context.now && context.now < end
+                } else {
+                    context.now >= start || context.now < end
+                };
+                if !open {
+                    return Err(format!(
+                        "Trading is only allowed between {} and {} UTC",
+                        rule.start, rule.end
+                    ));
+                }
+            }
+            RiskRule::PairWhitelist(rule) => {
+                if !rule.pairs.contains(&intent.pair) {
+                    return Err(format!("{} is not in the allowed pairs", intent.pair));
+                }
+            }
+            RiskRule::AllowedSides(rule) => {
+                if !rule.sides.contains(&intent.side) {
+                    return Err(format!("{:?} orders are not allowed", intent.side));
+                }
+            }
+            RiskRule::StopLoss(rule) => {
+                if rule.pair.as_ref().is_some_and(|pair| *pair != intent.pair) {
+                    return Ok(());
+                }
+                loss_check(intent, context.pnl.saturating_neg(), rule.max_loss, "loss")?
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intent(side: Side, price: i64, qty: i64) -> OrderIntent {
+        return OrderIntent {
+            account_id: 1,
+            portfolio_id: 1,
+            trading_pair_id: 1,
+            pair: "BTC/USDT".to_string(),
+            side,
+            price,
+            qty,
+            base_scale: 0,
+            quote_scale: 0,
+            amends: None,
+        };
+    }
+
+    #[test]
+    fn params_are_validated_per_type() {
+        assert!(RiskRule::from_parts("max_open_orders", &json!({"max": 3})).is_ok());
+        assert!(RiskRule::from_parts("max_open_orders", &json!({"max": 0})).is_err());
+        assert!(RiskRule::from_parts("max_open_orders", &json!({"max": 3, "x": 1})).is_err());
+        assert!(RiskRule::from_parts("max_order_size", &json!({})).is_err());
+        assert!(RiskRule::from_parts("pair_whitelist", &json!({"pairs": ["BTCUSDT"]})).is_err());
+        assert!(
+            RiskRule::from_parts("trading_hours", &json!({"start": "25:00", "end": "01:00"}))
+                .is_err()
+        );
+        assert!(RiskRule::from_parts("leverage", &json!({})).is_err());
+    }
+
+    #[test]
+    fn params_round_trip() {
+        let rule =
+            RiskRule::from_parts("allowed_sides", &json!({"sides": ["buy", "sell"]})).unwrap();
+        assert_eq!(rule.rule_type(), "allowed_sides");
+        assert_eq!(rule.params(), json!({"sides": ["buy", "sell"]}));
+        assert_eq!(
+            RiskRule::from_parts(rule.rule_type(), &rule.params()),
+            Ok(rule)
+        );
+    }
+
+    #[test]
+    fn order_size_and_position() {
+        let context = RiskContext {
+            position: 95,
+            ..RiskContext::default()
+        };
+        let size = RiskRule::MaxOrderSize(MaxOrderSize {
+            max_qty: None,
+            max_notional: Some(Amount::new(10_000, 0)),
+        });
+        assert!(size.check(&intent(Side::Buy, 1_000, 10), &context).is_ok());
+        assert!(size.check(&intent(Side::Buy, 1_000, 11), &context).is_err());
+
+        let position = RiskRule::MaxPosition(MaxPosition {
+            pair: None,
+            max_qty: Amount::new(100, 0),
+        });
+        assert!(position.check(&intent(Side::Buy, 1, 6), &context).is_err());
+        assert!(position.check(&intent(Side::Sell, 1, 6), &context).is_ok());
+        let other_pair = RiskRule::MaxPosition(MaxPosition {
+            pair: Some("ETH/USDT".to_string()),
+            max_qty: Amount::new(1, 0),
+        });
+        assert!(other_pair.check(&intent(Side::Buy, 1, 6), &context).is_ok());
+    }
+
+    #[test]
+    fn decimal_limits_follow_the_pair_scales() {
+        // 0.5 BTC at 30000 USDT is 15000 USDT
+        let order = OrderIntent {
+            base_scale: 8,
+            quote_scale: 6,
+            ..intent(Side::Buy, 30_000_000_000, 50_000_000)
+        };
+        let rule = RiskRule::from_parts(
+            "max_order_size",
+            &json!({"max_qty": "0.5", "max_notional": 15_000}),
+        )
+        .unwrap();
+        assert!(rule.check(&order, &RiskContext::default()).is_ok());
+        let order = OrderIntent {
+            qty: 50_000_001,
+            ..order
+        };
+        let message = rule.check(&order, &RiskContext::default()).unwrap_err();
+        assert_eq!(
+            message,
+            "The order quantity 0.50000001 is above the limit of 0.5"
+        );
+        assert_eq!(
+            rule.params(),
+            json!({"max_qty": "0.5", "max_notional": "15000"})
+        );
+        assert!(RiskRule::from_parts("max_daily_loss", &json!({"max_loss": 1.5})).is_err());
+    }
+
+    #[test]
+    fn loss_limits_only_block_buys() {
+        let context = RiskContext {
+            pnl: -500,
+            daily_pnl: -200,
+            drawdown: 700,
+            ..RiskContext::default()
+        };
+        for rule in [
+            RiskRule::StopLoss(StopLoss {
+                pair: None,
+                max_loss: Amount::new(500, 0),
+            }),
+            RiskRule::MaxDailyLoss(MaxDailyLoss {
+                max_loss: Amount::new(200, 0),
+            }),
+            RiskRule::MaxDrawdown(MaxDrawdown {
+                max_drawdown: Amount::new(700, 0),
+            }),
+        ] {
+            assert!(rule.is_loss_limit());
+            assert!(rule.check(&intent(Side::Buy, 1, 1), &context).is_err());
+            assert!(rule.check(&intent(Side::Sell, 1, 1), &context).is_ok());
+        }
+        let relaxed = RiskRule::MaxDailyLoss(MaxDailyLoss {
+            max_loss: Amount::new(201, 0),
+        });
+        assert!(relaxed.check(&intent(Side::Buy, 1, 1), &context).is_ok());
+    }
+
+    #[test]
+    fn trading_hours_window() {
+        let day = RiskRule::TradingHours(TradingHours {
+            start: "09:00".to_string(),
+            end: "17:00".to_string(),
+        });
+        let night = RiskRule::TradingHours(TradingHours {
+            start: "22:00".to_string(),
+            end: "02:00".to_string(),
+        });
+        let at = |hour| RiskContext {
+            now: NaiveTime::from_hms_opt(hour, 0, 0).unwrap(),
+            ..RiskContext::default()
+        };
+        let order = intent(Side::Buy, 1, 1);
+        assert!(day.check(&order, &at(9)).is_ok());
+        assert!(day.check(&order, &at(17)).is_err());
+        assert!(night.check(&order, &at(23)).is_ok());
+        assert!(night.check(&order, &at(1)).is_ok());
+        assert!(night.check(&order, &at(12)).is_err());
+    }
+
+    #[test]
+    fn whitelist_sides_and_open_orders() {
+        let context = RiskContext {
+            open_orders: 2,
+            ..RiskContext::default()
+        };
+        let order = intent(Side::Sell, 1, 1);
+        let whitelist = RiskRule::PairWhitelist(PairWhitelist {
+            pairs: vec!["ETH/USDT".to_string()],
+        });
+        assert!(whitelist.check(&order, &context).is_err());
+        let sides = RiskRule::AllowedSides(AllowedSides {
+            sides: vec![Side::Buy],
+        });
+        assert!(sides.check(&order, &context).is_err());
+        let open = RiskRule::MaxOpenOrders(MaxOpenOrders { max: 2 });
+        assert!(open.check(&order, &context).is_err());
+    }
+}
@@ -16,6 +16,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    admin_audit_log (id) {
+        id -> Int4,
+        admin_account_id -> Int4,
+        portfolio_id -> Nullable<Int4>,
+        #[max_length = 50]
+        action -> Varchar,
+        created_at -> Timestamp,
+        account_id -> Nullable<Int4>,
+    }
+}
+
 diesel::table! {
     currencies (id) {
         id -> Int4,
@@ -23,6 +35,8 @@ diesel::table! {
         code -> Varchar,
         #[max_length = 50]
         name -> Varchar,
+        scale -> Int4,
+        enabled -> Bool,
     }
 }
 
@@ -39,7 +53,7 @@ diesel::table! {
 diesel::table! {
     intra_account_transfer_requests (id) {
         id -> Int4,
-        admin_account_id -> Int4,
+        admin_account_id -> Nullable<Int4>,
         trader_account_id -> Int4,
         position_id -> Int4,
         from_portfolio_id -> Int4,
@@ -49,6 +63,93 @@ diesel::table! {
         fee -> Int8,
         is_approved -> Bool,
         created_at -> Nullable<Timestamp>,
+        #[max_length = 10]
+        status -> Varchar,
+        expires_at -> Timestamp,
+        decided_at -> Nullable<Timestamp>,
+        note -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    ledger_entries (id) {
+        id -> Int4,
+        journal_id -> Int4,
+        #[max_length = 10]
+        account -> Varchar,
+        portfolio_id -> Nullable<Int4>,
+        currency_id -> Int4,
+        amount -> Int8,
+    }
+}
+
+diesel::table! {
+    ledger_journals (id) {
+        id -> Int4,
+        #[max_length = 10]
+        kind -> Varchar,
+        order_id -> Nullable<Int4>,
+        fill_id -> Nullable<Int4>,
+        transfer_id -> Nullable<Int4>,
+        created_by -> Nullable<Int4>,
+        memo -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    market_candles (id) {
+        id -> Int4,
+        trading_pair_id -> Int4,
+        #[max_length = 3]
+        resolution -> Varchar,
+        open_time -> Timestamp,
+        open -> Int8,
+        high -> Int8,
+        low -> Int8,
+        close -> Int8,
+        volume -> Int8,
+        trades -> Int8,
+        last_trade_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    nav_snapshots (id) {
+        id -> Int4,
+        portfolio_id -> Int4,
+        currency_id -> Int4,
+        nav -> Int8,
+        net_flow -> Int8,
+        balances -> Jsonb,
+        taken_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    order_fills (id) {
+        id -> Int4,
+        order_id -> Int4,
+        #[max_length = 64]
+        exchange_fill_id -> Varchar,
+        price -> Int8,
+        qty -> Int8,
+        fee -> Int8,
+        time_stamp -> Timestamp,
+    }
+}
+
+diesel::table! {
+    order_idempotency_keys (id) {
+        id -> Int4,
+        account_id -> Int4,
+        #[max_length = 255]
+        idempotency_key -> Varchar,
+        request -> Jsonb,
+        order_id -> Nullable<Int4>,
+        response_status -> Nullable<Int4>,
+        response -> Nullable<Jsonb>,
+        created_at -> Timestamp,
     }
 }
 
@@ -57,12 +158,37 @@ diesel::table! {
         id -> Int4,
         portfolio_id -> Int4,
         time_stamp -> Timestamp,
-        state -> Int4,
         buyin -> Bool,
         trading_pair_id -> Int4,
         quotation_id -> Int4,
         price -> Int8,
         qty -> Int8,
+        #[max_length = 64]
+        exchange_order_id -> Nullable<Varchar>,
+        #[max_length = 20]
+        status -> Varchar,
+        reserved -> Int8,
+        #[max_length = 20]
+        order_type -> Varchar,
+        #[max_length = 3]
+        time_in_force -> Varchar,
+        stop_price -> Nullable<Int8>,
+        expires_at -> Nullable<Timestamp>,
+        triggered_at -> Nullable<Timestamp>,
+        #[max_length = 64]
+        client_order_id -> Nullable<Varchar>,
+        replaced_exchange_order_ids -> Array<Text>,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        token_hash -> Bytea,
+        user_id -> Int4,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
     }
 }
 
@@ -72,6 +198,7 @@ diesel::table! {
         portfolio_id -> Int4,
         quantity -> Int8,
         currency_id -> Int4,
+        reserved -> Int8,
     }
 }
 
@@ -83,6 +210,10 @@ diesel::table! {
         time_stamp -> Timestamp,
         trader_account_id -> Int4,
         portfolio_type -> Int4,
+        #[max_length = 10]
+        cost_basis -> Varchar,
+        paper -> Bool,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -100,18 +231,34 @@ diesel::table! {
         time_stamp -> Timestamp,
         quote_currency_id -> Int4,
         position_id -> Int4,
+        price -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    risk_decisions (id) {
+        id -> Int4,
+        portfolio_id -> Int4,
+        account_id -> Int4,
+        trading_pair_id -> Int4,
+        buyin -> Bool,
+        price -> Int8,
+        qty -> Int8,
+        approved -> Bool,
+        violations -> Jsonb,
+        created_at -> Timestamp,
     }
 }
 
 diesel::table! {
     risk_management (id) {
         id -> Int4,
-        #[max_length = 30]
-        risk_type -> Varchar,
         valid -> Bool,
-        pnl -> Int8,
-        position -> Int4,
         portfolio_id -> Int4,
+        #[max_length = 30]
+        rule_type -> Varchar,
+        params -> Jsonb,
+        created_at -> Timestamp,
     }
 }
 
@@ -119,6 +266,28 @@ diesel::table! {
     sessions (session_token) {
         session_token -> Bytea,
         user_id -> Int4,
+        id -> Int4,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+        expires_at -> Timestamp,
+        #[max_length = 255]
+        user_agent -> Nullable<Varchar>,
+        #[max_length = 64]
+        ip_address -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    trading_halts (id) {
+        id -> Int4,
+        portfolio_id -> Nullable<Int4>,
+        account_id -> Nullable<Int4>,
+        reason -> Text,
+        triggered_by -> Nullable<Int4>,
+        risk_rule_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+        lifted_by -> Nullable<Int4>,
+        lifted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -127,12 +296,31 @@ diesel::table! {
         id -> Int4,
         base_currency_id -> Int4,
         quote_currency_id -> Int4,
+        tick_size -> Int8,
+        lot_size -> Int8,
+        min_notional -> Int8,
+        price_precision -> Int4,
+        qty_precision -> Int4,
+        enabled -> Bool,
     }
 }
 
 diesel::joinable!(intra_account_transfer_requests -> positions (position_id));
+diesel::joinable!(ledger_entries -> currencies (currency_id));
+diesel::joinable!(ledger_entries -> ledger_journals (journal_id));
+diesel::joinable!(ledger_journals -> accounts (created_by));
+diesel::joinable!(ledger_journals -> intra_account_transfer_requests (transfer_id));
+diesel::joinable!(ledger_journals -> order_fills (fill_id));
+diesel::joinable!(ledger_journals -> orders (order_id));
+diesel::joinable!(market_candles -> trading_pairs (trading_pair_id));
+diesel::joinable!(nav_snapshots -> currencies (currency_id));
+diesel::joinable!(nav_snapshots -> portfolios (portfolio_id));
+diesel::joinable!(order_fills -> orders (order_id));
+diesel::joinable!(order_idempotency_keys -> accounts (account_id));
+diesel::joinable!(order_idempotency_keys -> orders (order_id));
 diesel::joinable!(orders -> portfolios (portfolio_id));
 diesel::joinable!(orders -> quotations (quotation_id));
+diesel::joinable!(password_reset_tokens -> accounts (user_id));
 diesel::joinable!(portfolio_balance -> currencies (currency_id));
 diesel::joinable!(portfolio_balance -> portfolios (portfolio_id));
 diesel::joinable!(portfolios -> accounts (trader_account_id));
@@ -140,21 +328,32 @@ diesel::joinable!(positions -> portfolios (portfolio_id));
 diesel::joinable!(positions -> trading_pairs (trading_pair_id));
 diesel::joinable!(quotations -> currencies (quote_currency_id));
 diesel::joinable!(quotations -> positions (position_id));
+diesel::joinable!(risk_decisions -> accounts (account_id));
 diesel::joinable!(risk_management -> portfolios (portfolio_id));
-diesel::joinable!(risk_management -> trading_pairs (position));
 diesel::joinable!(sessions -> accounts (user_id));
+diesel::joinable!(trading_halts -> portfolios (portfolio_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     accounts,
+    admin_audit_log,
     currencies,
     exchange_api_keys,
     intra_account_transfer_requests,
+    ledger_entries,
+    ledger_journals,
+    market_candles,
+    nav_snapshots,
+    order_fills,
+    order_idempotency_keys,
     orders,
+    password_reset_tokens,
     portfolio_balance,
     portfolios,
     positions,
     quotations,
+    risk_decisions,
     risk_management,
     sessions,
+    trading_halts,
     trading_pairs,
 );
@@ -0,0 +1,433 @@
+use ::diesel::ExpressionMethods;
+use chrono::{Duration, Utc};
+use diesel::QueryDsl;
+use rocket::fairing::{self, AdHoc};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::serde::json::{json, Value};
+use rocket::{Build, Rocket, State};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::auth::permission::Authorized;
+use crate::db_lib::database;
+use crate::db_lib::query::{get_pair_symbol, get_trading_pair_id};
+use crate::db_lib::schema::positions;
+use crate::market::ingest::last_trade;
+use crate::order::balance::available_balance;
+use crate::order::exchange::Symbol;
+use crate::portfolio::ownership::{owned_portfolio, PortfolioKey};
+use crate::transfer::request::{
+    approve, expire_requests, list_requests, reject, submit, transfer_fee, NewTransfer,
+    TransferError, TransferRequest, TransferStatus,
+};
+use crate::types::amount::format_units;
+
+pub mod request;
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        submit_transfer,
+        get_transfers,
+        get_all_transfers,
+        approve_transfer,
+        reject_transfer
+    ]
+}
+
+// The [default.transfer] table of Rocket.toml
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferConfig {
+    // charged on the notional of every approved transfer
+    pub fee_bps: i64,
+    // how long a request waits for an admin
+    pub expiry_minutes: i64,
+}
+
+async fn init(rocket: Rocket<Build>) -> fairing::Result {
+    match rocket.figment().extract_inner::<TransferConfig>("transfer") {
+        Ok(config) => return Ok(rocket.manage(config)),
+        Err(err) => {
+            println!("Invalid transfer configuration: {}", err);
+            return Err(rocket);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Transfer requests", init)
+}
+
+fn transfer_error(err: TransferError) -> (Status, Value) {
+    let status = match err {
+        TransferError::NotFound => Status::NotFound,
+        TransferError::NotPending(_) => Status::Conflict,
+        TransferError::Balance(_) => Status::BadRequest,
+        TransferError::Database => Status::InternalServerError,
+    };
+    return (status, json!({"status":"error", "message": err.message()}));
+}
+
+fn parse_status(status: Option<&str>) -> Result<Option<TransferStatus>, (Status, Value)> {
+    return status
+        .map(|status| status.parse::<TransferStatus>())
+        .transpose()
+        .map_err(|message| {
+            (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            )
+        });
+}
+
+// the requests with their pair as "BASE/QUOTE" and its decimals
+async fn requests_json(
+    requests: &[TransferRequest],
+    db_conn: &mut Connection<database::PgDb>,
+) -> Result<Vec<Value>, TransferError> {
+    let mut pairs: HashMap<i32, Symbol> = HashMap::new();
+    let mut data = vec![];
+    for request in requests {
+        if let Entry::Vacant(entry) = pairs.entry(request.trading_pair_id) {
+            entry.insert(get_pair_symbol(db_conn, request.trading_pair_id).await?);
+        }
+        data.push(request.to_json(&pairs[&request.trading_pair_id]));
+    }
+    return Ok(data);
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferData {
+    from_portfolio_id: i32,
+    to_portfolio_id: i32,
+    base: String,
+    quote: String,
+    quantity: String,
+    // the latest trade price of the pair when not given
+    price: Option<String>,
+}
+
+// Ask for `quantity` of the base currency to move between two portfolios of the same
+// trader. Nothing moves until an admin approves it.
+#[post("/api/transfer", data = "<transfer_data>")]
+pub async fn submit_transfer(
+    transfer_data: Json<TransferData>,
+    config: &State<TransferConfig>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    if transfer_data.from_portfolio_id == transfer_data.to_portfolio_id {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"The two portfolios must differ"}),
+        );
+    }
+    let mut portfolios = vec![];
+    for portfolio_id in [
+        transfer_data.from_portfolio_id,
+        transfer_data.to_portfolio_id,
+    ] {
+        match owned_portfolio(
+            &_user_auth,
+            PortfolioKey::Id(portfolio_id),
+            "submit_transfer",
+            &mut db_conn,
+        )
+        .await
+        {
+            Ok(portfolio) => portfolios.push(portfolio),
+            Err(err) => return err,
+        }
+    }
+    let (from, to) = (&portfolios[0], &portfolios[1]);
+    if from.trader_account_id != to.trader_account_id {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Both portfolios must belong to the same account"}),
+        );
+    }
+    // simulated balances never mix with real ones
+    if from.paper != to.paper {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"Funds do not move between paper and live portfolios"}),
+        );
+    }
+
+    let (base_id, quote_id, trading_pair_id) = match get_trading_pair_id(
+        &mut db_conn,
+        (&transfer_data.base, &transfer_data.quote),
+    )
+    .await
+    {
+        Ok(trading_pair) => trading_pair,
+        Err(message) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": message}),
+            );
+        }
+    };
+    let fetch_position = positions::table
+        .filter(positions::portfolio_id.eq(from.id))
+        .filter(positions::trading_pair_id.eq(trading_pair_id))
+        .select(positions::id)
+        .first::<i32>(&mut db_conn)
+        .await;
+    let position_id = if let Ok(position_id) = fetch_position {
+        position_id
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"The portfolio has no such position"}),
+        );
+    };
+
+    let symbol = match get_pair_symbol(&mut db_conn, trading_pair_id).await {
+        Ok(symbol) => symbol,
+        Err(err) => return transfer_error(err.into()),
+    };
+    let quantity = match symbol.parse_qty(&transfer_data.quantity) {
+        Ok(quantity) if quantity > 0 => quantity,
+        Ok(_) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message":"Invalid quantity"}),
+            );
+        }
+        Err(err) => {
+            return (
+                Status::BadRequest,
+                json!({"status":"error", "message": err.message()}),
+            );
+        }
+    };
+    let price = match &transfer_data.price {
+        Some(price) => match symbol.parse_price(price) {
+            Ok(price) if price > 0 => price,
+            Ok(_) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message":"Invalid price"}),
+                );
+            }
+            Err(err) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message": err.message()}),
+                );
+            }
+        },
+        None => match last_trade(&mut db_conn, trading_pair_id).await {
+            Ok(Some((_, price))) => price,
+            Ok(None) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message":"The pair has no trade to price the transfer, give a price"}),
+                );
+            }
+            Err(_) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status":"error", "message":"Fail to fetch the price"}),
+                );
+            }
+        },
+    };
+    let fee = if let Some(fee) = transfer_fee(price, quantity, symbol.base_scale, config.fee_bps) {
+        fee
+    } else {
+        return (
+            Status::BadRequest,
+            json!({"status":"error", "message":"The transfer amount is too large"}),
+        );
+    };
+
+    // checked again on approval, this only turns away requests that cannot succeed now
+    for (currency_id, scale, required) in [
+        (base_id, symbol.base_scale, quantity),
+        (quote_id, symbol.quote_scale, fee),
+    ] {
+        match available_balance(&mut db_conn, from.id, currency_id).await {
+            Ok(available) if available >= required => {}
+            Ok(available) => {
+                return (
+                    Status::BadRequest,
+                    json!({"status":"error", "message": format!(
+                        "Insufficient available balance: the transfer needs {} but only {} is available",
+                        format_units(required as i128, scale),
+                        format_units(available as i128, scale)
+                    )}),
+                );
+            }
+            Err(err) => {
+                return (
+                    Status::InternalServerError,
+                    json!({"status":"error", "message": err.message()}),
+                );
+            }
+        }
+    }
+
+    let new_transfer = NewTransfer {
+        trader_account_id: from.trader_account_id,
+        position_id,
+        from_portfolio_id: from.id,
+        to_portfolio_id: to.id,
+        price,
+        quantity,
+        fee,
+        expires_at: Utc::now().naive_utc() + Duration::minutes(config.expiry_minutes),
+    };
+    match submit(&mut db_conn, new_transfer).await {
+        Ok(request) => {
+            return (
+                Status::Ok,
+                json!({"status":"successful", "data": request.to_json(&symbol)}),
+            );
+        }
+        Err(err) => return transfer_error(err),
+    }
+}
+
+// The user's own requests, newest first
+#[get("/api/transfer?<status>")]
+pub async fn get_transfers(
+    status: Option<&str>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let status = match parse_status(status) {
+        Ok(status) => status,
+        Err(err) => return err,
+    };
+    if let Err(err) = expire_requests(&mut db_conn, Utc::now().naive_utc()).await {
+        return transfer_error(err);
+    }
+    let requests = match list_requests(&mut db_conn, Some(_user_auth.user_id), status).await {
+        Ok(requests) => requests,
+        Err(err) => return transfer_error(err),
+    };
+    let data = match requests_json(&requests, &mut db_conn).await {
+        Ok(data) => data,
+        Err(err) => return transfer_error(err),
+    };
+    let len = data.len();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "data": data, "len": len}),
+    );
+}
+
+// Every trader's requests for the admins, optionally of one trader
+#[get("/api/transfer/all?<status>&<trader>")]
+pub async fn get_all_transfers(
+    status: Option<&str>,
+    trader: Option<i32>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let status = match parse_status(status) {
+        Ok(status) => status,
+        Err(err) => return err,
+    };
+    if let Err(err) = expire_requests(&mut db_conn, Utc::now().naive_utc()).await {
+        return transfer_error(err);
+    }
+    let requests = match list_requests(&mut db_conn, trader, status).await {
+        Ok(requests) => requests,
+        Err(err) => return transfer_error(err),
+    };
+    let data = match requests_json(&requests, &mut db_conn).await {
+        Ok(data) => data,
+        Err(err) => return transfer_error(err),
+    };
+    let len = data.len();
+    return (
+        Status::Ok,
+        json!({"status":"successful", "data": data, "len": len}),
+    );
+}
+
+// admins do not decide their own requests
+async fn check_decider(
+    request_id: i32,
+    admin_id: i32,
+    db_conn: &mut Connection<database::PgDb>,
+) -> Result<(), (Status, Value)> {
+    let now = Utc::now().naive_utc();
+    expire_requests(db_conn, now)
+        .await
+        .map_err(transfer_error)?;
+    let request = request::find_request(db_conn, request_id)
+        .await
+        .map_err(transfer_error)?;
+    if request.trader_account_id == admin_id {
+        return Err((
+            Status::Forbidden,
+            json!({"status":"error", "message":"A transfer request needs another admin to decide it"}),
+        ));
+    }
+    return Ok(());
+}
+
+// Approve a pending request, the balances move and the fee is booked at once
+#[post("/api/transfer/<request_id>/approve")]
+pub async fn approve_transfer(
+    request_id: i32,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let admin_id = _user_auth.user_id;
+    if let Err(err) = check_decider(request_id, admin_id, &mut db_conn).await {
+        return err;
+    }
+    match approve(&mut db_conn, request_id, admin_id, Utc::now().naive_utc()).await {
+        Ok(request) => match requests_json(&[request], &mut db_conn).await {
+            Ok(data) => {
+                return (Status::Ok, json!({"status":"successful", "data": data[0]}));
+            }
+            Err(err) => return transfer_error(err),
+        },
+        Err(err) => return transfer_error(err),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RejectData {
+    reason: Option<String>,
+}
+
+#[post("/api/transfer/<request_id>/reject", data = "<reject_data>")]
+pub async fn reject_transfer(
+    request_id: i32,
+    reject_data: Json<RejectData>,
+    mut db_conn: Connection<database::PgDb>,
+    _user_auth: Authorized,
+) -> (Status, Value) {
+    let admin_id = _user_auth.user_id;
+    if let Err(err) = check_decider(request_id, admin_id, &mut db_conn).await {
+        return err;
+    }
+    let note = reject_data.into_inner().reason;
+    match reject(
+        &mut db_conn,
+        request_id,
+        admin_id,
+        note,
+        Utc::now().naive_utc(),
+    )
+    .await
+    {
+        Ok(request) => match requests_json(&[request], &mut db_conn).await {
+            Ok(data) => {
+                return (Status::Ok, json!({"status":"successful", "data": data[0]}));
+            }
+            Err(err) => return transfer_error(err),
+        },
+        Err(err) => return transfer_error(err),
+    }
+}
@@ -0,0 +1,519 @@
+use ::diesel::{ExpressionMethods, OptionalExtension};
+use chrono::NaiveDateTime;
+use diesel::QueryDsl;
+use rocket::serde::json::{json, Value};
+use rocket_db_pools::diesel::prelude::RunQueryDsl;
+use rocket_db_pools::diesel::scoped_futures::ScopedFutureExt;
+use rocket_db_pools::diesel::{self, AsyncConnection, AsyncPgConnection};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::db_lib::schema::{
+    intra_account_transfer_requests as transfers, portfolios, positions, quotations, trading_pairs,
+};
+use crate::ledger::journal::{record, EntryKind, Journal, LedgerAccount};
+use crate::order::balance::{fee_collector, fee_portfolio, BalanceError};
+use crate::order::exchange::Symbol;
+use crate::types::amount::{div_round, pow10, Rounding};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Expired,
+}
+
+impl TransferStatus {
+    pub const ALL: [TransferStatus; 4] = [
+        TransferStatus::Pending,
+        TransferStatus::Approved,
+        TransferStatus::Rejected,
+        TransferStatus::Expired,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransferStatus::Pending => "pending",
+            TransferStatus::Approved => "approved",
+            TransferStatus::Rejected => "rejected",
+            TransferStatus::Expired => "expired",
+        }
+    }
+}
+
+impl fmt::Display for TransferStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TransferStatus {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        return TransferStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == value)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown transfer status {}, expected pending, approved, rejected or expired",
+                    value
+                )
+            });
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    NotFound,
+    // the request was already decided or expired
+    NotPending(TransferStatus),
+    Balance(BalanceError),
+    Database,
+}
+
+impl TransferError {
+    pub fn message(&self) -> String {
+        match self {
+            TransferError::NotFound => "Transfer request not found".to_string(),
+            TransferError::NotPending(status) => {
+                format!("The transfer request is already {}", status)
+            }
+            TransferError::Balance(BalanceError::Insufficient {
+                required,
+                available,
+                ..
+            }) => format!(
+                "Insufficient available balance: the transfer needs {} but only {} is available",
+                required, available
+            ),
+            TransferError::Balance(err) => err.message(),
+            TransferError::Database => "Fail to update the transfer request".to_string(),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for TransferError {
+    fn from(_: diesel::result::Error) -> Self {
+        TransferError::Database
+    }
+}
+
+impl From<BalanceError> for TransferError {
+    fn from(err: BalanceError) -> Self {
+        TransferError::Balance(err)
+    }
+}
+
+// The fee of moving `quantity` at `price`, in basis points of the notional and
+// rounded up so that small transfers are not free
+pub fn transfer_fee(price: i64, quantity: i64, base_scale: u32, fee_bps: i64) -> Option<i64> {
+    let notional = (price as i128).checked_mul(quantity as i128)?;
+    let divisor = pow10(base_scale)?.checked_mul(10_000)?;
+    let fee = div_round(
+        notional.checked_mul(fee_bps as i128)?,
+        divisor,
+        Rounding::Up,
+    )?;
+    return i64::try_from(fee).ok();
+}
+
+// (id, admin_account_id, trader_account_id, position_id, from_portfolio_id,
+//  to_portfolio_id, price, quantity, fee, status, created_at, expires_at, decided_at,
+//  note, trading_pair_id)
+type TransferRow = (
+    i32,
+    Option<i32>,
+    i32,
+    i32,
+    i32,
+    i32,
+    i64,
+    i64,
+    i64,
+    String,
+    Option<NaiveDateTime>,
+    NaiveDateTime,
+    Option<NaiveDateTime>,
+    Option<String>,
+    i32,
+);
+
+// A row of intra_account_transfer_requests: `quantity` of the position's base currency
+// moves from one portfolio of the trader to another, valued at `price`. The fee is
+// taken in the quote currency from the sending portfolio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferRequest {
+    pub id: i32,
+    // the admin that decided it
+    pub admin_account_id: Option<i32>,
+    pub trader_account_id: i32,
+    pub position_id: i32,
+    pub trading_pair_id: i32,
+    pub from_portfolio_id: i32,
+    pub to_portfolio_id: i32,
+    pub price: i64,
+    pub quantity: i64,
+    pub fee: i64,
+    pub status: TransferStatus,
+    pub created_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+    pub note: Option<String>,
+}
+
+impl TransferRequest {
+    fn from_row(row: TransferRow) -> TransferRequest {
+        return TransferRequest {
+            id: row.0,
+            admin_account_id: row.1,
+            trader_account_id: row.2,
+            position_id: row.3,
+            from_portfolio_id: row.4,
+            to_portfolio_id: row.5,
+            price: row.6,
+            quantity: row.7,
+            fee: row.8,
+            // the table only allows the four statuses
+            status: row.9.parse().unwrap_or(TransferStatus::Pending),
+            created_at: row.10,
+            expires_at: row.11,
+            decided_at: row.12,
+            note: row.13,
+            trading_pair_id: row.14,
+        };
+    }
+
+    pub fn to_json(&self, symbol: &Symbol) -> Value {
+        return json!({
+            "id": self.id,
+            "status": self.status.as_str(),
+            "trader_account_id": self.trader_account_id,
+            "admin_account_id": self.admin_account_id,
+            "pair": format!("{}/{}", symbol.base, symbol.quote),
+            "from_portfolio_id": self.from_portfolio_id,
+            "to_portfolio_id": self.to_portfolio_id,
+            "price": symbol.format_price(self.price),
+            "quantity": symbol.format_qty(self.quantity),
+            "fee": symbol.format_price(self.fee),
+            "created_at": self.created_at.map(|time| time.to_string()),
+            "expires_at": self.expires_at.to_string(),
+            "decided_at": self.decided_at.map(|time| time.to_string()),
+            "note": self.note,
+        });
+    }
+}
+
+// A request that passed validation
+pub struct NewTransfer {
+    pub trader_account_id: i32,
+    pub position_id: i32,
+    pub from_portfolio_id: i32,
+    pub to_portfolio_id: i32,
+    pub price: i64,
+    pub quantity: i64,
+    pub fee: i64,
+    pub expires_at: NaiveDateTime,
+}
+
+pub async fn find_request(
+    conn: &mut AsyncPgConnection,
+    request_id: i32,
+) -> Result<TransferRequest, TransferError> {
+    let row = transfers::table
+        .inner_join(positions::table)
+        .filter(transfers::id.eq(request_id))
+        .select((
+            transfers::id,
+            transfers::admin_account_id,
+            transfers::trader_account_id,
+            transfers::position_id,
+            transfers::from_portfolio_id,
+            transfers::to_portfolio_id,
+            transfers::price,
+            transfers::quantity,
+            transfers::fee,
+            transfers::status,
+            transfers::created_at,
+            transfers::expires_at,
+            transfers::decided_at,
+            transfers::note,
+            positions::trading_pair_id,
+        ))
+        .first::<TransferRow>(conn)
+        .await
+        .optional()?
+        .ok_or(TransferError::NotFound)?;
+    return Ok(TransferRequest::from_row(row));
+}
+
+// Pending requests nobody acted on in time expire, run before reading or deciding them
+pub async fn expire_requests(
+    conn: &mut AsyncPgConnection,
+    now: NaiveDateTime,
+) -> Result<usize, TransferError> {
+    let expired = diesel::update(
+        transfers::table
+            .filter(transfers::status.eq(TransferStatus::Pending.as_str()))
+            .filter(transfers::expires_at.le(now)),
+    )
+    .set((
+        transfers::status.eq(TransferStatus::Expired.as_str()),
+        transfers::decided_at.eq(now),
+    ))
+    .execute(conn)
+    .await?;
+    return Ok(expired);
+}
+
+pub async fn submit(
+    conn: &mut AsyncPgConnection,
+    new_transfer: NewTransfer,
+) -> Result<TransferRequest, TransferError> {
+    let request_id = diesel::insert_into(transfers::table)
+        .values((
+            transfers::trader_account_id.eq(new_transfer.trader_account_id),
+            transfers::position_id.eq(new_transfer.position_id),
+            transfers::from_portfolio_id.eq(new_transfer.from_portfolio_id),
+            transfers::to_portfolio_id.eq(new_transfer.to_portfolio_id),
+            transfers::price.eq(new_transfer.price),
+            transfers::quantity.eq(new_transfer.quantity),
+            transfers::fee.eq(new_transfer.fee),
+            transfers::is_approved.eq(false),
+            transfers::expires_at.eq(new_transfer.expires_at),
+        ))
+        .returning(transfers::id)
+        .get_result::<i32>(conn)
+        .await?;
+    return find_request(conn, request_id).await;
+}
+
+// The requests of one trader, or of everyone, newest first
+pub async fn list_requests(
+    conn: &mut AsyncPgConnection,
+    trader_account_id: Option<i32>,
+    status: Option<TransferStatus>,
+) -> Result<Vec<TransferRequest>, TransferError> {
+    let mut query = transfers::table
+        .inner_join(positions::table)
+        .select((
+            transfers::id,
+            transfers::admin_account_id,
+            transfers::trader_account_id,
+            transfers::position_id,
+            transfers::from_portfolio_id,
+            transfers::to_portfolio_id,
+            transfers::price,
+            transfers::quantity,
+            transfers::fee,
+            transfers::status,
+            transfers::created_at,
+            transfers::expires_at,
+            transfers::decided_at,
+            transfers::note,
+            positions::trading_pair_id,
+        ))
+        .into_boxed();
+    if let Some(trader_account_id) = trader_account_id {
+        query = query.filter(transfers::trader_account_id.eq(trader_account_id));
+    }
+    if let Some(status) = status {
+        query = query.filter(transfers::status.eq(status.as_str()));
+    }
+    let rows = query
+        .order(transfers::id.desc())
+        .load::<TransferRow>(conn)
+        .await?;
+    return Ok(rows.into_iter().map(TransferRequest::from_row).collect());
+}
+
+// Mark a pending request as decided. The status check and the update are one
+// statement, so a request is decided only once.
+async fn decide(
+    conn: &mut AsyncPgConnection,
+    request_id: i32,
+    admin_account_id: i32,
+    status: TransferStatus,
+    note: Option<String>,
+    now: NaiveDateTime,
+) -> Result<(), TransferError> {
+    let updated = diesel::update(
+        transfers::table
+            .filter(transfers::id.eq(request_id))
+            .filter(transfers::status.eq(TransferStatus::Pending.as_str()))
+            .filter(transfers::expires_at.gt(now)),
+    )
+    .set((
+        transfers::status.eq(status.as_str()),
+        transfers::is_approved.eq(status == TransferStatus::Approved),
+        transfers::admin_account_id.eq(admin_account_id),
+        transfers::decided_at.eq(now),
+        transfers::note.eq(note),
+    ))
+    .execute(conn)
+    .await?;
+    if updated == 0 {
+        let request = find_request(conn, request_id).await?;
+        // an overdue request that was not swept yet
+        if request.status == TransferStatus::Pending {
+            return Err(TransferError::NotPending(TransferStatus::Expired));
+        }
+        return Err(TransferError::NotPending(request.status));
+    }
+    return Ok(());
+}
+
+// the receiving portfolio gets the position when it does not hold the pair yet
+async fn ensure_position(
+    conn: &mut AsyncPgConnection,
+    portfolio_id: i32,
+    trading_pair_id: i32,
+    quote_currency_id: i32,
+) -> Result<(), TransferError> {
+    let existing = positions::table
+        .filter(positions::portfolio_id.eq(portfolio_id))
+        .filter(positions::trading_pair_id.eq(trading_pair_id))
+        .select(positions::id)
+        .first::<i32>(conn)
+        .await
+        .optional()?;
+    if existing.is_some() {
+        return Ok(());
+    }
+    let position_id = diesel::insert_into(positions::table)
+        .values((
+            positions::trading_pair_id.eq(trading_pair_id),
+            positions::portfolio_id.eq(portfolio_id),
+        ))
+        .returning(positions::id)
+        .get_result::<i32>(conn)
+        .await?;
+    diesel::insert_into(quotations::table)
+        .values((
+            quotations::quote_currency_id.eq(quote_currency_id),
+            quotations::position_id.eq(position_id),
+        ))
+        .execute(conn)
+        .await?;
+    return Ok(());
+}
+
+// Approve the request and book it in the ledger, all or nothing: the base currency
+// goes from one portfolio to the other and the fee goes to the misc portfolio
+pub async fn approve(
+    conn: &mut AsyncPgConnection,
+    request_id: i32,
+    admin_account_id: i32,
+    now: NaiveDateTime,
+) -> Result<TransferRequest, TransferError> {
+    conn.transaction::<(), TransferError, _>(|conn| {
+        async move {
+            decide(
+                conn,
+                request_id,
+                admin_account_id,
+                TransferStatus::Approved,
+                None,
+                now,
+            )
+            .await?;
+            let request = find_request(conn, request_id).await?;
+            let (base_currency_id, quote_currency_id) = trading_pairs::table
+                .filter(trading_pairs::id.eq(request.trading_pair_id))
+                .select((
+                    trading_pairs::base_currency_id,
+                    trading_pairs::quote_currency_id,
+                ))
+                .first::<(i32, i32)>(conn)
+                .await?;
+
+            let from = LedgerAccount::Portfolio(request.from_portfolio_id);
+            let transfer = Journal::new(EntryKind::Transfer)
+                .transfer(request_id)
+                .by(admin_account_id)
+                .checked()
+                .move_funds(
+                    from,
+                    LedgerAccount::Portfolio(request.to_portfolio_id),
+                    base_currency_id,
+                    request.quantity,
+                );
+            record(conn, &transfer).await?;
+            if request.fee != 0 {
+                // paper portfolios only ever transfer between themselves
+                let paper = portfolios::table
+                    .filter(portfolios::id.eq(request.from_portfolio_id))
+                    .select(portfolios::paper)
+                    .first::<bool>(conn)
+                    .await?;
+                let misc_id = if paper {
+                    None
+                } else {
+                    fee_portfolio(conn, request.trader_account_id).await?
+                };
+                let (kind, collector) = fee_collector(paper, misc_id, LedgerAccount::External);
+                let fee = Journal::new(kind)
+                    .transfer(request_id)
+                    .by(admin_account_id)
+                    .checked()
+                    .move_funds(from, collector, quote_currency_id, request.fee);
+                record(conn, &fee).await?;
+            }
+            ensure_position(
+                conn,
+                request.to_portfolio_id,
+                request.trading_pair_id,
+                quote_currency_id,
+            )
+            .await?;
+            return Ok(());
+        }
+        .scope_boxed()
+    })
+    .await?;
+    return find_request(conn, request_id).await;
+}
+
+pub async fn reject(
+    conn: &mut AsyncPgConnection,
+    request_id: i32,
+    admin_account_id: i32,
+    note: Option<String>,
+    now: NaiveDateTime,
+) -> Result<TransferRequest, TransferError> {
+    decide(
+        conn,
+        request_id,
+        admin_account_id,
+        TransferStatus::Rejected,
+        note,
+        now,
+    )
+    .await?;
+    return find_request(conn, request_id).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_round_trip() {
+        for status in TransferStatus::ALL {
+            assert_eq!(status.as_str().parse::<TransferStatus>(), Ok(status));
+        }
+        assert!("done".parse::<TransferStatus>().is_err());
+    }
+
+    #[test]
+    fn fee_rounds_up() {
+        assert_eq!(transfer_fee(30_000, 2, 0, 10), Some(60));
+        assert_eq!(transfer_fee(1, 1, 0, 10), Some(1));
+        assert_eq!(transfer_fee(100, 3, 0, 0), Some(0));
+        assert_eq!(transfer_fee(i64::MAX, i64::MAX, 0, 10), None);
+        // 0.001 BTC at 30000.000000 USDT is 30 USDT, 10 bps of it 0.03 USDT
+        assert_eq!(transfer_fee(30_000_000_000, 100_000, 8, 10), Some(30_000));
+        assert_eq!(transfer_fee(30_000_000_000, 1, 8, 10), Some(1));
+    }
+}
@@ -0,0 +1,397 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+// currencies.scale is at most this, 10^18 still fits in an i64
+pub const MAX_SCALE: u32 = 18;
+
+// What to do with the digits a division leaves over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    // toward zero
+    Down,
+    // away from zero
+    Up,
+    // half away from zero
+    Nearest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    Invalid(String),
+    TooPrecise { value: String, scale: u32 },
+    Overflow,
+}
+
+impl AmountError {
+    pub fn message(&self) -> String {
+        match self {
+            AmountError::Invalid(value) => format!("{} is not a decimal number", value),
+            AmountError::TooPrecise { value, scale } => {
+                format!("{} has more than {} decimals", value, scale)
+            }
+            AmountError::Overflow => "The amount is too large".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+pub fn pow10(scale: u32) -> Option<i128> {
+    return 10i128.checked_pow(scale);
+}
+
+// `n / d` for a positive `d`, the remainder rounded as asked
+pub fn div_round(n: i128, d: i128, rounding: Rounding) -> Option<i128> {
+    if d <= 0 {
+        return None;
+    }
+    let (quotient, remainder) = (n / d, n % d);
+    if remainder == 0 {
+        return Some(quotient);
+    }
+    let away = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => true,
+        Rounding::Nearest => remainder.unsigned_abs().checked_mul(2)? >= d.unsigned_abs(),
+    };
+    if away {
+        return quotient.checked_add(n.signum());
+    }
+    return Some(quotient);
+}
+
+// `units` of 10^-scale as a decimal string, every decimal of the scale is written out
+pub fn format_units(units: i128, scale: u32) -> String {
+    let digits = units.unsigned_abs().to_string();
+    let sign = if units < 0 { "-" } else { "" };
+    if scale == 0 {
+        return format!("{}{}", sign, digits);
+    }
+    let scale = scale as usize;
+    let digits = format!("{:0>width$}", digits, width = scale + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - scale);
+    return format!("{}{}.{}", sign, whole, fraction);
+}
+
+// the sign, the digits before the point and those after it
+fn split(value: &str) -> Result<(bool, &str, &str), AmountError> {
+    let invalid = || AmountError::Invalid(value.to_string());
+    let (negative, unsigned) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (whole, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(invalid());
+    }
+    if !whole
+        .bytes()
+        .chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    return Ok((negative, whole, fraction));
+}
+
+// A fixed-point decimal, `units` of 10^-scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Amount {
+    pub units: i64,
+    pub scale: u32,
+}
+
+impl Amount {
+    pub fn new(units: i64, scale: u32) -> Amount {
+        return Amount { units, scale };
+    }
+
+    // Read a decimal string at exactly `scale` decimals. Zeros past the scale are
+    // fine, any other digit there is an error rather than being rounded away.
+    pub fn parse(value: &str, scale: u32) -> Result<Amount, AmountError> {
+        let (negative, whole, fraction) = split(value)?;
+        let kept = fraction.len().min(scale as usize);
+        if fraction[kept..].bytes().any(|b| b != b'0') {
+            return Err(AmountError::TooPrecise {
+                value: value.to_string(),
+                scale,
+            });
+        }
+        let digits = format!(
+            "{}{}{}",
+            whole,
+            &fraction[..kept],
+            "0".repeat(scale as usize - kept)
+        );
+        let units = digits
+            .trim_start_matches('0')
+            .parse::<i128>()
+            .or_else(|err| match err.kind() {
+                std::num::IntErrorKind::Empty => Ok(0),
+                _ => Err(AmountError::Overflow),
+            })?;
+        let units = if negative { -units } else { units };
+        let units = i64::try_from(units).map_err(|_| AmountError::Overflow)?;
+        return Ok(Amount { units, scale });
+    }
+
+    // the same value in units of another scale
+    pub fn rescale(self, scale: u32, rounding: Rounding) -> Option<i64> {
+        let units = self.units as i128;
+        let value = if scale >= self.scale {
+            units.checked_mul(pow10(scale - self.scale)?)?
+        } else {
+            div_round(units, pow10(self.scale - scale)?, rounding)?
+        };
+        return i64::try_from(value).ok();
+    }
+
+    fn aligned(self, other: Amount) -> Option<(i64, i64, u32)> {
+        let scale = self.scale.max(other.scale);
+        let left = self.rescale(scale, Rounding::Down)?;
+        let right = other.rescale(scale, Rounding::Down)?;
+        return Some((left, right, scale));
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        let (left, right, scale) = self.aligned(other)?;
+        return Some(Amount::new(left.checked_add(right)?, scale));
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        let (left, right, scale) = self.aligned(other)?;
+        return Some(Amount::new(left.checked_sub(right)?, scale));
+    }
+
+    pub fn is_positive(self) -> bool {
+        return self.units > 0;
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_units(self.units as i128, self.scale))
+    }
+}
+
+// at the scale it is written with, "1.50" is 150 at scale 2
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (_, _, fraction) = split(value)?;
+        let scale = fraction.len() as u32;
+        if scale > MAX_SCALE {
+            return Err(AmountError::TooPrecise {
+                value: value.to_string(),
+                scale: MAX_SCALE,
+            });
+        }
+        return Amount::parse(value, scale);
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal string or an integer")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Amount, E> {
+        return value.parse::<Amount>().map_err(E::custom);
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Amount, E> {
+        return Ok(Amount::new(value, 0));
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Amount, E> {
+        return i64::try_from(value)
+            .map(|value| Amount::new(value, 0))
+            .map_err(|_| E::custom(AmountError::Overflow));
+    }
+}
+
+// floats are refused, they cannot hold most decimals exactly
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+// `qty` base units at `price` quote units for one whole base, in quote units
+pub fn notional(price: i64, qty: i64, base_scale: u32, rounding: Rounding) -> Option<i64> {
+    let value = (price as i128).checked_mul(qty as i128)?;
+    let value = div_round(value, pow10(base_scale)?, rounding)?;
+    return i64::try_from(value).ok();
+}
+
+// The code and scale of every currency, to read and write their amounts
+#[derive(Debug, Clone, Default)]
+pub struct Currencies {
+    currencies: HashMap<i32, (String, u32)>,
+}
+
+impl Currencies {
+    // (id, code, scale) rows of currencies
+    pub fn new(rows: Vec<(i32, String, i32)>) -> Currencies {
+        let currencies = rows
+            .into_iter()
+            .map(|(id, code, scale)| (id, (code, scale.clamp(0, MAX_SCALE as i32) as u32)))
+            .collect();
+        return Currencies { currencies };
+    }
+
+    // the id when the currency is unknown
+    pub fn code(&self, id: i32) -> String {
+        return self
+            .currencies
+            .get(&id)
+            .map(|(code, _)| code.clone())
+            .unwrap_or_else(|| id.to_string());
+    }
+
+    pub fn scale(&self, id: i32) -> u32 {
+        return self
+            .currencies
+            .get(&id)
+            .map(|(_, scale)| *scale)
+            .unwrap_or(0);
+    }
+
+    pub fn format(&self, id: i32, units: i128) -> String {
+        return format_units(units, self.scale(id));
+    }
+
+    pub fn parse(&self, id: i32, value: &str) -> Result<i64, AmountError> {
+        return Amount::parse(value, self.scale(id)).map(|amount| amount.units);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_exactly_at_the_scale() {
+        assert_eq!(Amount::parse("0.5", 8), Ok(Amount::new(50_000_000, 8)));
+        assert_eq!(Amount::parse("12", 6), Ok(Amount::new(12_000_000, 6)));
+        assert_eq!(Amount::parse("-.25", 2), Ok(Amount::new(-25, 2)));
+        assert_eq!(Amount::parse("1.2300", 2), Ok(Amount::new(123, 2)));
+        assert_eq!(Amount::parse("0", 8), Ok(Amount::new(0, 8)));
+        assert!(matches!(
+            Amount::parse("0.123", 2),
+            Err(AmountError::TooPrecise { .. })
+        ));
+        for invalid in ["", ".", "1e5", "1.2.3", "abc", "--1", " 1"] {
+            assert!(
+                matches!(Amount::parse(invalid, 2), Err(AmountError::Invalid(_))),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            Amount::parse("92233720368.54775808", 8),
+            Err(AmountError::Overflow)
+        );
+        assert_eq!(
+            Amount::parse("92233720368.54775807", 8),
+            Ok(Amount::new(i64::MAX, 8))
+        );
+    }
+
+    #[test]
+    fn formats_every_decimal() {
+        assert_eq!(Amount::new(50_000_000, 8).to_string(), "0.50000000");
+        assert_eq!(Amount::new(-5, 3).to_string(), "-0.005");
+        assert_eq!(Amount::new(1_234, 0).to_string(), "1234");
+        assert_eq!(
+            format_units(i128::from(i64::MIN) * 4, 2),
+            "-368934881474191032.32"
+        );
+        for (value, scale) in [("0.00000001", 8), ("60000.000000", 6), ("-7.10", 2)] {
+            assert_eq!(Amount::parse(value, scale).unwrap().to_string(), value);
+        }
+    }
+
+    #[test]
+    fn rounds_and_rescales() {
+        assert_eq!(div_round(7, 2, Rounding::Down), Some(3));
+        assert_eq!(div_round(7, 2, Rounding::Up), Some(4));
+        assert_eq!(div_round(7, 2, Rounding::Nearest), Some(4));
+        assert_eq!(div_round(-7, 2, Rounding::Nearest), Some(-4));
+        assert_eq!(div_round(-5, 3, Rounding::Nearest), Some(-2));
+        assert_eq!(div_round(5, 3, Rounding::Down), Some(1));
+        let half = "0.5".parse::<Amount>().unwrap();
+        assert_eq!(half, Amount::new(5, 1));
+        assert_eq!(half.rescale(8, Rounding::Down), Some(50_000_000));
+        assert_eq!(half.rescale(0, Rounding::Down), Some(0));
+        assert_eq!(half.rescale(0, Rounding::Nearest), Some(1));
+        assert_eq!(Amount::new(i64::MAX, 0).rescale(1, Rounding::Down), None);
+        assert_eq!(
+            half.checked_add(Amount::new(125, 2)),
+            Some(Amount::new(175, 2))
+        );
+        assert_eq!(
+            Amount::new(i64::MIN, 0).checked_sub(Amount::new(1, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn notional_of_scaled_amounts() {
+        // 0.5 BTC at 60000 USDT, BTC has 8 decimals and USDT 6
+        assert_eq!(
+            notional(60_000_000_000, 50_000_000, 8, Rounding::Down),
+            Some(30_000_000_000)
+        );
+        // 1 satoshi at 1.000001 USDT is a fraction of the smallest USDT unit
+        assert_eq!(notional(1_000_001, 1, 8, Rounding::Down), Some(0));
+        assert_eq!(notional(1_000_001, 1, 8, Rounding::Up), Some(1));
+        assert_eq!(notional(i64::MAX, i64::MAX, 0, Rounding::Down), None);
+    }
+
+    #[test]
+    fn amounts_from_json() {
+        let parse = |value| rocket::serde::json::from_value::<Amount>(value);
+        assert_eq!(
+            parse(rocket::serde::json::json!("0.25")).unwrap(),
+            Amount::new(25, 2)
+        );
+        assert_eq!(
+            parse(rocket::serde::json::json!(3)).unwrap(),
+            Amount::new(3, 0)
+        );
+        assert!(parse(rocket::serde::json::json!(0.1)).is_err());
+        assert_eq!(
+            rocket::serde::json::json!(Amount::new(25, 2)),
+            rocket::serde::json::json!("0.25")
+        );
+    }
+
+    #[test]
+    fn currencies_name_and_scale() {
+        let currencies =
+            Currencies::new(vec![(1, "BTC".to_string(), 8), (3, "USDT".to_string(), 6)]);
+        assert_eq!(currencies.code(1), "BTC");
+        assert_eq!(currencies.code(9), "9");
+        assert_eq!(currencies.format(3, 1_500_000), "1.500000");
+        assert_eq!(currencies.parse(1, "0.5"), Ok(50_000_000));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod amount;
+
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -0,0 +1,37 @@
+import requests as re
+import json
+import os
+import email
+import config
+
+# run from the repository root while the server writes its mails to ./outbox
+outbox = "./outbox"
+
+# a fresh session, the flow is for users who are not logged in
+res = re.post(config.url + 'auth/forget', json={"name": "test"})
+print(res)
+print(json.loads(res.text))
+
+# the reset mail just sent, the token is the fragment of the reset page link
+latest = max(os.listdir(outbox), key=lambda name: os.path.getmtime(os.path.join(outbox, name)))
+with open(os.path.join(outbox, latest), "rb") as eml:
+    body = email.message_from_bytes(eml.read()).get_payload(decode=True).decode()
+token = body.split("Link:")[1].split("\n")[0].strip().split("#")[-1]
+print(token)
+
+res = re.get(config.url + 'auth/forget/' + token)
+print(res)
+print(json.loads(res.text))
+
+data = {
+  "password": "123456",
+}
+
+res = re.post(config.url + 'auth/forget/' + token, json=data)
+print(res)
+print(json.loads(res.text))
+
+# the link can only be used once, expect 400
+res = re.post(config.url + 'auth/forget/' + token, json=data)
+print(res)
+print(json.loads(res.text))
@@ -6,12 +6,18 @@ from config import session_requests
 data = {
   "base": "BTC",
   "quote": "USDT",
-  "order_type": "buy",
+  "side": "buy",
+  "order_type": "limit",
   "price": "0",
   "quantity": "0",
+  "client_order_id": "test-1",
   "portfolio_id": 4
 }
-res = session_requests.post(config.url + 'order', json=data)
+res = session_requests.post(config.url + 'order', json=data, headers={"Idempotency-Key": "test-order-1"})
+print(res)
+print(json.loads(res.text))
+
+res = session_requests.get(config.url + 'order/by-client-id/test-1?portfolio_id=4')
 print(res)
 print(json.loads(res.text))
 
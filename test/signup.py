@@ -6,7 +6,6 @@ from config import session_requests
 data = {
   "name": "test",
   "password": "123456",
-  "user_type": 0,
   "email": "admin@localhost",
 }
 